@@ -59,3 +59,20 @@ pub enum LinkError {
 #[derive(Clone, Debug, Error)]
 #[error("'{0}' is not a valid Solidity type")]
 pub struct ParseParamTypeError(pub String);
+
+/// An error hashing EIP-712 typed structured data.
+#[derive(Clone, Debug, Error)]
+pub enum TypedDataError {
+    /// A type is neither a known Solidity type nor a struct type defined in
+    /// the typed data.
+    #[error("unknown EIP-712 type '{0}'")]
+    UnknownType(String),
+
+    /// A value does not match its declared type.
+    #[error("invalid value for EIP-712 type '{0}'")]
+    InvalidValue(String),
+
+    /// A struct value is missing one of its type's members.
+    #[error("missing EIP-712 struct member '{0}'")]
+    MissingField(String),
+}
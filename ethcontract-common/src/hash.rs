@@ -1,5 +1,9 @@
-//! Keccak256 hash utilities.
+//! Keccak256 hash utilities, as well as EIP-191 and EIP-712 message hashing.
 
+pub mod typed_data;
+
+pub use self::typed_data::TypedData;
+use crate::errors::TypedDataError;
 use tiny_keccak::{Hasher, Keccak};
 
 /// Perform a Keccak256 hash of data and return its 32-byte result.
@@ -33,6 +37,28 @@ where
     selector
 }
 
+/// Computes the EIP-191 hash of a personal message. This is the hash that gets
+/// signed by `personal_sign` and `eth_sign` and is defined as the Keccak256
+/// hash of `"\x19Ethereum Signed Message:\n" ‖ len(message) ‖ message`.
+pub fn hash_message<M>(message: M) -> [u8; 32]
+where
+    M: AsRef<[u8]>,
+{
+    let message = message.as_ref();
+    let mut data = format!("\x19Ethereum Signed Message:\n{}", message.len()).into_bytes();
+    data.extend_from_slice(message);
+    keccak256(data)
+}
+
+/// Computes the EIP-712 signing hash of typed structured data. This is defined
+/// as the Keccak256 hash of `"\x19\x01" ‖ domainSeparator ‖ hashStruct(message)`.
+pub fn hash_typed_data(data: &TypedData) -> Result<[u8; 32], TypedDataError> {
+    let mut encoded = vec![0x19, 0x01];
+    encoded.extend_from_slice(&data.domain_separator()?);
+    encoded.extend_from_slice(&data.message_hash()?);
+    Ok(keccak256(encoded))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -58,6 +84,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn personal_message_hash() {
+        // test vector retrieved from
+        // https://web3js.readthedocs.io/en/v1.2.4/web3-eth-accounts.html#hashmessage
+        assert_eq!(
+            &hash_message("Hello World"),
+            b"\xa1\xde\x98\x86\x00\xa4\x2c\x4b\x4a\xb0\x89\xb6\x19\x29\x7c\x17\
+              \xd5\x3c\xff\xae\x5d\x51\x20\xd8\x2d\x8a\x92\xd0\xbb\x3b\x78\xf2"
+        );
+    }
+
     #[test]
     fn revert_function_signature() {
         assert_eq!(function_selector("Error(string)"), [0x08, 0xc3, 0x79, 0xa0]);
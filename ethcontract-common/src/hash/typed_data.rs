@@ -0,0 +1,479 @@
+//! Implementation of EIP-712 typed structured data hashing.
+//!
+//! Typed data uses the same JSON representation as the `eth_signTypedData_v4`
+//! RPC method, so payloads produced by wallets and dapps can be loaded with
+//! `serde_json` as is.
+
+use crate::errors::TypedDataError;
+use crate::hash::keccak256;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use web3::types::{Address, H256, U256};
+
+/// The name of the type used for the EIP-712 domain separator.
+pub const EIP712_DOMAIN_TYPE: &str = "EIP712Domain";
+
+/// A typed structured data payload as specified by EIP-712.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypedData {
+    /// The struct type definitions by type name. This may optionally include
+    /// the `EIP712Domain` type, in which case it is used for hashing the
+    /// domain.
+    pub types: BTreeMap<String, Vec<TypedDataField>>,
+    /// The name of the type of `message`.
+    pub primary_type: String,
+    /// The signing domain.
+    pub domain: Eip712Domain,
+    /// The message to sign, an object of type `primary_type`.
+    pub message: Value,
+}
+
+/// A member of an EIP-712 struct type.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TypedDataField {
+    /// The name of the member.
+    pub name: String,
+    /// The Solidity type of the member, or the name of another struct type.
+    #[serde(rename = "type")]
+    pub r#type: String,
+}
+
+/// The EIP-712 domain used for computing the domain separator. Only the fields
+/// that are specified are included in the domain.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Eip712Domain {
+    /// The user readable name of the signing domain.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The current major version of the signing domain.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// The EIP-155 chain ID.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_chain_id"
+    )]
+    pub chain_id: Option<U256>,
+    /// The address of the contract that will verify the signature.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verifying_contract: Option<Address>,
+    /// A disambiguating salt for the protocol.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub salt: Option<H256>,
+}
+
+impl TypedData {
+    /// Computes the domain separator for the typed data.
+    ///
+    /// If the `EIP712Domain` type is not part of the type definitions, then it
+    /// is derived from the domain fields that are set.
+    pub fn domain_separator(&self) -> Result<[u8; 32], TypedDataError> {
+        let domain = serde_json::to_value(&self.domain)
+            .map_err(|_| TypedDataError::InvalidValue(EIP712_DOMAIN_TYPE.to_owned()))?;
+
+        if self.types.contains_key(EIP712_DOMAIN_TYPE) {
+            return self.struct_hash(EIP712_DOMAIN_TYPE, &domain);
+        }
+
+        let mut types = self.clone();
+        types
+            .types
+            .insert(EIP712_DOMAIN_TYPE.to_owned(), self.domain.fields());
+        types.struct_hash(EIP712_DOMAIN_TYPE, &domain)
+    }
+
+    /// Computes the struct hash of the typed data message.
+    pub fn message_hash(&self) -> Result<[u8; 32], TypedDataError> {
+        self.struct_hash(&self.primary_type, &self.message)
+    }
+
+    /// Returns the encoded type string for a struct type, for example
+    /// `Mail(Person from,Person to,string contents)Person(string name,address wallet)`.
+    pub fn encode_type(&self, name: &str) -> Result<String, TypedDataError> {
+        let fields = self.fields(name)?;
+
+        let mut dependencies = BTreeSet::new();
+        self.collect_dependencies(name, &mut dependencies)?;
+        dependencies.remove(name);
+
+        let mut encoded = encode_struct_type(name, fields);
+        for dependency in dependencies {
+            encoded.push_str(&encode_struct_type(dependency, self.fields(dependency)?));
+        }
+
+        Ok(encoded)
+    }
+
+    /// Returns the type hash of a struct type.
+    pub fn type_hash(&self, name: &str) -> Result<[u8; 32], TypedDataError> {
+        Ok(keccak256(self.encode_type(name)?))
+    }
+
+    /// Computes the struct hash, `hashStruct(s) = keccak256(typeHash ‖
+    /// encodeData(s))`, of a value with the specified struct type.
+    pub fn struct_hash(&self, name: &str, value: &Value) -> Result<[u8; 32], TypedDataError> {
+        let object = value
+            .as_object()
+            .ok_or_else(|| TypedDataError::InvalidValue(name.to_owned()))?;
+
+        let mut encoded = self.type_hash(name)?.to_vec();
+        for field in self.fields(name)? {
+            let value = object
+                .get(&field.name)
+                .ok_or_else(|| TypedDataError::MissingField(format!("{}.{}", name, field.name)))?;
+            encoded.extend_from_slice(&self.encode_value(&field.r#type, value)?);
+        }
+
+        Ok(keccak256(encoded))
+    }
+
+    fn fields(&self, name: &str) -> Result<&[TypedDataField], TypedDataError> {
+        self.types
+            .get(name)
+            .map(Vec::as_slice)
+            .ok_or_else(|| TypedDataError::UnknownType(name.to_owned()))
+    }
+
+    fn collect_dependencies<'a>(
+        &'a self,
+        name: &'a str,
+        found: &mut BTreeSet<&'a str>,
+    ) -> Result<(), TypedDataError> {
+        if !found.insert(name) {
+            return Ok(());
+        }
+        for field in self.fields(name)? {
+            let base = base_type(&field.r#type);
+            if self.types.contains_key(base) {
+                self.collect_dependencies(base, found)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Encodes a single value into its 32-byte `encodeData` representation.
+    fn encode_value(&self, ty: &str, value: &Value) -> Result<[u8; 32], TypedDataError> {
+        let invalid = || TypedDataError::InvalidValue(ty.to_owned());
+
+        if let Some((inner, len)) = array_type(ty) {
+            let items = value.as_array().ok_or_else(invalid)?;
+            if matches!(len, Some(len) if len != items.len()) {
+                return Err(invalid());
+            }
+            let mut encoded = Vec::with_capacity(32 * items.len());
+            for item in items {
+                encoded.extend_from_slice(&self.encode_value(inner, item)?);
+            }
+            return Ok(keccak256(encoded));
+        }
+
+        if self.types.contains_key(ty) {
+            return self.struct_hash(ty, value);
+        }
+
+        let mut word = [0u8; 32];
+        match ty {
+            "string" => return Ok(keccak256(value.as_str().ok_or_else(invalid)?)),
+            "bytes" => return Ok(keccak256(parse_bytes(value).ok_or_else(invalid)?)),
+            "bool" => word[31] = value.as_bool().ok_or_else(invalid)? as u8,
+            "address" => {
+                let address = value
+                    .as_str()
+                    .and_then(|s| s.strip_prefix("0x").unwrap_or(s).parse::<Address>().ok())
+                    .ok_or_else(invalid)?;
+                word[12..].copy_from_slice(address.as_bytes());
+            }
+            _ if ty.starts_with("bytes") => {
+                let size = parse_size(&ty[5..], 32).ok_or_else(|| unknown(ty))?;
+                let bytes = parse_bytes(value)
+                    .filter(|bytes| bytes.len() == size)
+                    .ok_or_else(invalid)?;
+                word[..size].copy_from_slice(&bytes);
+            }
+            _ if ty.starts_with("uint") => {
+                let bits = parse_bits(&ty[4..]).ok_or_else(|| unknown(ty))?;
+                let (negative, value) = parse_uint(value).ok_or_else(invalid)?;
+                if negative || value.bits() > bits {
+                    return Err(invalid());
+                }
+                value.to_big_endian(&mut word);
+            }
+            _ if ty.starts_with("int") => {
+                let bits = parse_bits(&ty[3..]).ok_or_else(|| unknown(ty))?;
+                let (negative, value) = parse_uint(value).ok_or_else(invalid)?;
+                // NOTE: `intN` values are in the range `-2^(N-1)..2^(N-1)`.
+                let limit = U256::one() << (bits - 1);
+                let value = match negative {
+                    true if value <= limit => (!value).overflowing_add(1.into()).0,
+                    false if value < limit => value,
+                    _ => return Err(invalid()),
+                };
+                value.to_big_endian(&mut word);
+            }
+            _ => return Err(unknown(ty)),
+        }
+
+        Ok(word)
+    }
+}
+
+impl Eip712Domain {
+    /// Returns the `EIP712Domain` struct type fields for the domain fields that
+    /// are set, in the order specified by EIP-712.
+    pub fn fields(&self) -> Vec<TypedDataField> {
+        let field = |name: &str, ty: &str| TypedDataField {
+            name: name.to_owned(),
+            r#type: ty.to_owned(),
+        };
+
+        let mut fields = Vec::new();
+        if self.name.is_some() {
+            fields.push(field("name", "string"));
+        }
+        if self.version.is_some() {
+            fields.push(field("version", "string"));
+        }
+        if self.chain_id.is_some() {
+            fields.push(field("chainId", "uint256"));
+        }
+        if self.verifying_contract.is_some() {
+            fields.push(field("verifyingContract", "address"));
+        }
+        if self.salt.is_some() {
+            fields.push(field("salt", "bytes32"));
+        }
+        fields
+    }
+}
+
+fn unknown(ty: &str) -> TypedDataError {
+    TypedDataError::UnknownType(ty.to_owned())
+}
+
+fn encode_struct_type(name: &str, fields: &[TypedDataField]) -> String {
+    let members = fields
+        .iter()
+        .map(|field| format!("{} {}", field.r#type, field.name))
+        .collect::<Vec<_>>();
+    format!("{}({})", name, members.join(","))
+}
+
+/// Strips all array suffixes from a type, for example `Person[][2]` becomes
+/// `Person`.
+fn base_type(ty: &str) -> &str {
+    ty.find('[').map(|index| &ty[..index]).unwrap_or(ty)
+}
+
+/// Splits an array type into its item type and optional fixed length.
+fn array_type(ty: &str) -> Option<(&str, Option<usize>)> {
+    let inner = ty.strip_suffix(']')?;
+    let index = inner.rfind('[')?;
+    let len = match &inner[index + 1..] {
+        "" => None,
+        len => Some(len.parse().ok()?),
+    };
+    Some((&inner[..index], len))
+}
+
+fn parse_size(size: &str, max: usize) -> Option<usize> {
+    let size = size.parse().ok()?;
+    (1..=max).contains(&size).then_some(size)
+}
+
+fn parse_bits(bits: &str) -> Option<usize> {
+    if bits.is_empty() {
+        return Some(256);
+    }
+    let bits = parse_size(bits, 256)?;
+    (bits % 8 == 0).then_some(bits)
+}
+
+fn parse_bytes(value: &Value) -> Option<Vec<u8>> {
+    let s = value.as_str()?;
+    hex::decode(s.strip_prefix("0x").unwrap_or(s)).ok()
+}
+
+/// Parses an integer from a JSON number or a decimal or `0x` prefixed hex
+/// string, returning its sign and magnitude.
+fn parse_uint(value: &Value) -> Option<(bool, U256)> {
+    match value {
+        Value::Number(number) => {
+            if let Some(value) = number.as_u64() {
+                Some((false, value.into()))
+            } else {
+                let value = number.as_i64()?;
+                Some((value < 0, value.unsigned_abs().into()))
+            }
+        }
+        Value::String(s) => {
+            let (negative, s) = match s.strip_prefix('-') {
+                Some(s) => (true, s),
+                None => (false, s.as_str()),
+            };
+            let value = match s.strip_prefix("0x") {
+                Some(hex) => U256::from_str_radix(hex, 16).ok()?,
+                None => U256::from_dec_str(s).ok()?,
+            };
+            Some((negative && !value.is_zero(), value))
+        }
+        _ => None,
+    }
+}
+
+fn deserialize_chain_id<'de, D>(deserializer: D) -> Result<Option<U256>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<Value>::deserialize(deserializer)? {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => match parse_uint(&value) {
+            Some((false, chain_id)) => Ok(Some(chain_id)),
+            _ => Err(D::Error::custom(format!("invalid chain ID {}", value))),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn mail() -> TypedData {
+        // example from the EIP-712 specification:
+        // https://github.com/ethereum/EIPs/blob/master/assets/eip-712/Example.js
+        serde_json::from_str(include_str!("eip712_example.json")).unwrap()
+    }
+
+    #[test]
+    fn mail_encode_type() {
+        assert_eq!(
+            mail().encode_type("Mail").unwrap(),
+            "Mail(Person from,Person to,string contents)Person(string name,address wallet)",
+        );
+    }
+
+    #[test]
+    fn mail_hashes() {
+        let data = mail();
+        assert_eq!(
+            H256(data.domain_separator().unwrap()),
+            "f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f"
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(
+            H256(data.message_hash().unwrap()),
+            "c52c0ee5d84264471806290a3f2c4cecfc5490626bf912d01f240d7a274b371e"
+                .parse()
+                .unwrap(),
+        );
+    }
+
+    #[test]
+    fn mail_typed_data_hash() {
+        assert_eq!(
+            H256(crate::hash::hash_typed_data(&mail()).unwrap()),
+            "be609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2"
+                .parse()
+                .unwrap(),
+        );
+    }
+
+    #[test]
+    fn derives_domain_type() {
+        let mut data = mail();
+        let expected = data.domain_separator().unwrap();
+        data.types.remove(EIP712_DOMAIN_TYPE);
+        assert_eq!(data.domain_separator().unwrap(), expected);
+    }
+
+    #[test]
+    fn encodes_atomic_values() {
+        let data = TypedData::default();
+        assert_eq!(data.encode_value("bool", &json!(true)).unwrap()[31], 1);
+        assert_eq!(data.encode_value("int8", &json!(-1)).unwrap(), [0xff; 32],);
+        assert_eq!(
+            data.encode_value("uint256", &json!("0x2a")).unwrap(),
+            data.encode_value("uint256", &json!("42")).unwrap(),
+        );
+        assert_eq!(
+            data.encode_value("bytes2", &json!("0x1337")).unwrap()[..3],
+            [0x13, 0x37, 0x00],
+        );
+        assert!(matches!(
+            data.encode_value("bytes2", &json!("0x13")),
+            Err(TypedDataError::InvalidValue(_)),
+        ));
+        assert!(matches!(
+            data.encode_value("bytes2", &json!("0x133700")),
+            Err(TypedDataError::InvalidValue(_)),
+        ));
+        assert!(matches!(
+            data.encode_value("uint256", &json!(-1)),
+            Err(TypedDataError::InvalidValue(_)),
+        ));
+        assert!(matches!(
+            data.encode_value("Unknown", &json!({})),
+            Err(TypedDataError::UnknownType(_)),
+        ));
+    }
+
+    #[test]
+    fn rejects_out_of_range_integers() {
+        let data = TypedData::default();
+        let encoded = |ty: &str, value: Value| data.encode_value(ty, &value).ok();
+        let min_int256 = U256::one() << 255;
+
+        assert!(encoded("uint8", json!(255)).is_some());
+        assert!(encoded("uint8", json!(256)).is_none());
+        assert!(encoded("uint8", json!(300)).is_none());
+        assert!(encoded("uint16", json!("0x10000")).is_none());
+
+        assert!(encoded("int8", json!(127)).is_some());
+        assert!(encoded("int8", json!(128)).is_none());
+        assert_eq!(
+            encoded("int8", json!(-128)).unwrap(),
+            encoded("int256", json!(-128)).unwrap(),
+        );
+        assert!(encoded("int8", json!(-129)).is_none());
+        assert!(encoded("int8", json!(-1000)).is_none());
+        assert!(encoded("int256", json!(format!("-{}", min_int256))).is_some());
+        assert!(encoded("int256", json!(format!("-{}", min_int256 + 1))).is_none());
+        assert!(encoded("int256", json!(min_int256.to_string())).is_none());
+    }
+
+    #[test]
+    fn encodes_arrays() {
+        let data = TypedData::default();
+        let items = json!(["0x01", "0x02"]);
+        let mut encoded = Vec::new();
+        for item in items.as_array().unwrap() {
+            encoded.extend_from_slice(&data.encode_value("uint256", item).unwrap());
+        }
+
+        assert_eq!(
+            data.encode_value("uint256[]", &items).unwrap(),
+            keccak256(&encoded),
+        );
+        assert_eq!(
+            data.encode_value("uint256[2]", &items).unwrap(),
+            keccak256(&encoded),
+        );
+        assert!(data.encode_value("uint256[3]", &items).is_err());
+    }
+
+    #[test]
+    fn missing_field() {
+        let mut data = mail();
+        data.message.as_object_mut().unwrap().remove("contents");
+        assert!(matches!(
+            data.message_hash(),
+            Err(TypedDataError::MissingField(field)) if field == "Mail.contents",
+        ));
+    }
+}
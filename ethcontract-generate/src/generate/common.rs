@@ -145,9 +145,10 @@ pub(crate) fn expand(cx: &Context) -> TokenStream {
                 use self::ethcontract::web3::api::Web3;
 
                 let transport = DynTransport::new(web3.transport().clone());
-                let web3 = Web3::new(transport);
+                let web3 = Web3::new(transport.clone());
                 let interface = Self::raw_contract().interface.clone();
-                let instance = Instance::with_deployment_info(web3, interface, address, deployment_information);
+                let instance = Instance::with_deployment_info(web3, interface, address, deployment_information)
+                    .with_subscription_transport(transport);
 
                 Contract::from_raw(instance)
             }
@@ -169,6 +170,20 @@ pub(crate) fn expand(cx: &Context) -> TokenStream {
                 self.raw_instance().deployment_information()
             }
 
+            /// Returns a copy of this contract that is pinned to the specified
+            /// block, for reading a consistent snapshot of the contract state.
+            /// View method calls and event queries of the returned contract
+            /// are pinned to the block. See
+            /// [`Instance::at_block`](self::ethcontract::Instance::at_block)
+            /// for more details.
+            pub async fn at_block(
+                &self,
+                block: self::ethcontract::BlockId,
+            ) -> Result<Self, self::ethcontract::errors::ExecutionError> {
+                let instance = self.raw_instance().at_block(block).await?;
+                Ok(Contract::from_raw(instance))
+            }
+
             /// Returns a reference to the default method options used by this
             /// contract.
             pub fn defaults(&self) -> &self::ethcontract::contract::MethodDefaults {
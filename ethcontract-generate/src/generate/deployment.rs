@@ -61,8 +61,10 @@ fn expand_deployed(cx: &Context) -> TokenStream {
                 use self::ethcontract::transport::DynTransport;
 
                 let transport = DynTransport::new(web3.transport().clone());
-                let web3 = Web3::new(transport);
-                let instance = Instance::deployed(web3, Contract::raw_contract().clone()).await?;
+                let web3 = Web3::new(transport.clone());
+                let instance = Instance::deployed(web3, Contract::raw_contract().clone())
+                    .await?
+                    .with_subscription_transport(transport);
 
                 Ok(Contract::from_raw(instance))
             }
@@ -173,12 +175,14 @@ fn expand_deploy(cx: &Context) -> Result<TokenStream> {
                 use self::ethcontract::web3::api::Web3;
 
                 let transport = DynTransport::new(web3.transport().clone());
-                let web3 = Web3::new(transport);
+                let web3 = Web3::new(transport.clone());
 
                 let bytecode = Self::raw_contract().bytecode.clone();
                 #link
 
-                DeployBuilder::new(web3, bytecode, #arg).expect("valid deployment args")
+                DeployBuilder::new(web3, bytecode, #arg)
+                    .expect("valid deployment args")
+                    .subscription_transport(transport)
             }
         }
 
@@ -299,6 +299,15 @@ fn expand_builder_type(event: &Event) -> Result<TokenStream> {
                 self
             }
 
+            /// Retrieves events emitted by any contract in the address set
+            /// instead of the builder's contract. The address set can be
+            /// updated while the events are being streamed, and the emitting
+            /// contract of each event is available in its metadata.
+            pub fn address_set(mut self, addresses: self::ethcontract::log::AddressSet) -> Self {
+                self.0 = (self.0).address_set(addresses);
+                self
+            }
+
             #topic_filters
 
             /// Returns a future that resolves with a collection of all existing
@@ -384,11 +393,30 @@ fn expand_all_events(cx: &Context) -> TokenStream {
         impl Contract {
             /// Returns a log stream with all events.
             pub fn all_events(&self) -> self::ethcontract::dyns::DynAllEventsBuilder<Event> {
-                self::ethcontract::dyns::DynAllEventsBuilder::new(
+                let mut builder = self::ethcontract::dyns::DynAllEventsBuilder::new(
                     self.raw_instance().web3(),
                     self.address(),
                     self.deployment_information(),
-                )
+                );
+                if let Some(transport) = self.raw_instance().subscription_transport() {
+                    builder = builder.subscription_transport(transport);
+                }
+                match self.raw_instance().pinned_block() {
+                    Some(block) => builder.pinned_block(block),
+                    None => builder,
+                }
+            }
+
+            /// Decodes the events emitted by this contract from the logs of a
+            /// transaction receipt, ignoring logs from other contracts.
+            pub fn events_from_receipt(
+                &self,
+                receipt: &self::ethcontract::web3::types::TransactionReceipt,
+            ) -> Result<
+                std::vec::Vec<self::ethcontract::Event<Event>>,
+                self::ethcontract::errors::ExecutionError,
+            > {
+                self.raw_instance().parse_receipt_logs(receipt)
             }
         }
 
@@ -762,6 +790,37 @@ mod tests {
         });
     }
 
+    #[test]
+    fn expand_all_events_with_subscription_transport() {
+        let mut events = BTreeMap::<String, _>::default();
+        events.insert(
+            "Foo".into(),
+            vec![Event {
+                name: "Foo".into(),
+                inputs: vec![],
+                anonymous: false,
+            }],
+        );
+        let abi = Abi {
+            events,
+            ..Default::default()
+        };
+        let mut contract = Contract::with_name("Contract");
+        contract.interface = Arc::new(abi.into());
+
+        let context = Context::from_builder(&contract, ContractBuilder::new()).unwrap();
+        let expanded = expand_all_events(&context).to_string();
+
+        assert!(expanded.contains(
+            &quote! {
+                if let Some(transport) = self.raw_instance().subscription_transport() {
+                    builder = builder.subscription_transport(transport);
+                }
+            }
+            .to_string()
+        ));
+    }
+
     #[test]
     fn expand_parse_log_impl_for_all_events() {
         let mut events = BTreeMap::<String, _>::default();
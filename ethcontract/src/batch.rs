@@ -1,22 +1,218 @@
-//! Module containing components to batch multiple contract calls
-//! into a single request to the Node.
+//! Module containing components to batch multiple contract calls and other
+//! typed RPC requests into a single request to the Node.
 
+use crate::state_override::{self, StateOverrides};
 use futures::channel::oneshot::{channel, Sender};
+use futures::stream::{self, StreamExt as _};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
 use web3::{
     error::{Error as Web3Error, TransportError},
     helpers::{self},
-    types::{BlockId, BlockNumber, Bytes, CallRequest},
+    types::{
+        Address, BlockId, BlockNumber, Bytes, CallRequest, Filter, Log, TransactionReceipt, H256,
+        U256,
+    },
     BatchTransport as Web3BatchTransport,
 };
 
+/// A typed JSON RPC request that can be added to a [`CallBatch`].
+pub trait BatchRequest {
+    /// The type of the response.
+    type Output: DeserializeOwned + Send + 'static;
+
+    /// The JSON RPC method name.
+    fn method(&self) -> &'static str;
+
+    /// The JSON RPC parameters.
+    fn params(&self) -> Vec<Value>;
+}
+
+/// An `eth_call` request.
+#[derive(Clone, Debug, Default)]
+pub struct EthCall {
+    /// The call to execute.
+    pub call: CallRequest,
+    /// The block to execute the call on, defaults to the latest block.
+    pub block: Option<BlockId>,
+    /// Whether the block, if specified by hash, is required to be part of the
+    /// canonical chain.
+    pub require_canonical: bool,
+    /// State overrides for the call.
+    pub overrides: Option<StateOverrides>,
+}
+
+impl BatchRequest for EthCall {
+    type Output = Bytes;
+
+    fn method(&self) -> &'static str {
+        "eth_call"
+    }
+
+    fn params(&self) -> Vec<Value> {
+        state_override::call_params(
+            &self.call,
+            self.block,
+            self.require_canonical,
+            self.overrides.as_ref(),
+        )
+    }
+}
+
+/// An `eth_getBalance` request.
+#[derive(Clone, Debug, Default)]
+pub struct GetBalance {
+    /// The account to get the balance of.
+    pub address: Address,
+    /// The block to get the balance at, defaults to the latest block.
+    pub block: Option<BlockNumber>,
+}
+
+impl BatchRequest for GetBalance {
+    type Output = U256;
+
+    fn method(&self) -> &'static str {
+        "eth_getBalance"
+    }
+
+    fn params(&self) -> Vec<Value> {
+        let block = self.block.unwrap_or(BlockNumber::Latest);
+        vec![
+            helpers::serialize(&self.address),
+            helpers::serialize(&block),
+        ]
+    }
+}
+
+/// An `eth_getCode` request.
+#[derive(Clone, Debug, Default)]
+pub struct GetCode {
+    /// The account to get the code of.
+    pub address: Address,
+    /// The block to get the code at, defaults to the latest block.
+    pub block: Option<BlockNumber>,
+}
+
+impl BatchRequest for GetCode {
+    type Output = Bytes;
+
+    fn method(&self) -> &'static str {
+        "eth_getCode"
+    }
+
+    fn params(&self) -> Vec<Value> {
+        let block = self.block.unwrap_or(BlockNumber::Latest);
+        vec![
+            helpers::serialize(&self.address),
+            helpers::serialize(&block),
+        ]
+    }
+}
+
+/// An `eth_getStorageAt` request.
+#[derive(Clone, Debug, Default)]
+pub struct GetStorageAt {
+    /// The account to read the storage of.
+    pub address: Address,
+    /// The storage slot to read.
+    pub slot: U256,
+    /// The block to read the storage at, defaults to the latest block.
+    pub block: Option<BlockNumber>,
+}
+
+impl BatchRequest for GetStorageAt {
+    type Output = H256;
+
+    fn method(&self) -> &'static str {
+        "eth_getStorageAt"
+    }
+
+    fn params(&self) -> Vec<Value> {
+        let block = self.block.unwrap_or(BlockNumber::Latest);
+        vec![
+            helpers::serialize(&self.address),
+            helpers::serialize(&self.slot),
+            helpers::serialize(&block),
+        ]
+    }
+}
+
+/// An `eth_estimateGas` request.
+#[derive(Clone, Debug, Default)]
+pub struct EstimateGas {
+    /// The call to estimate gas for.
+    pub call: CallRequest,
+    /// The block to estimate gas on, omitted from the request if not
+    /// specified.
+    pub block: Option<BlockNumber>,
+    /// State overrides for the estimate.
+    pub overrides: Option<StateOverrides>,
+}
+
+impl BatchRequest for EstimateGas {
+    type Output = U256;
+
+    fn method(&self) -> &'static str {
+        "eth_estimateGas"
+    }
+
+    fn params(&self) -> Vec<Value> {
+        state_override::estimate_gas_params(&self.call, self.block, self.overrides.as_ref())
+    }
+}
+
+/// An `eth_getLogs` request.
+#[derive(Clone, Debug)]
+pub struct GetLogs {
+    /// The filter to get logs for.
+    pub filter: Filter,
+}
+
+impl BatchRequest for GetLogs {
+    type Output = Vec<Log>;
+
+    fn method(&self) -> &'static str {
+        "eth_getLogs"
+    }
+
+    fn params(&self) -> Vec<Value> {
+        vec![helpers::serialize(&self.filter)]
+    }
+}
+
+/// An `eth_getTransactionReceipt` request. Resolves to `None` for unknown or
+/// pending transactions.
+#[derive(Clone, Debug, Default)]
+pub struct GetTransactionReceipt {
+    /// The hash of the transaction.
+    pub hash: H256,
+}
+
+impl BatchRequest for GetTransactionReceipt {
+    type Output = Option<TransactionReceipt>;
+
+    fn method(&self) -> &'static str {
+        "eth_getTransactionReceipt"
+    }
+
+    fn params(&self) -> Vec<Value> {
+        vec![helpers::serialize(&self.hash)]
+    }
+}
+
 /// Struct allowing to batch multiple calls into a single Node request
 pub struct CallBatch<T: Web3BatchTransport> {
     inner: T,
-    requests: Vec<(Request, CompletionHandler)>,
+    requests: Vec<PendingRequest>,
 }
 
-type Request = (CallRequest, Option<BlockId>);
-type CompletionHandler = Sender<Result<Bytes, Web3Error>>;
+struct PendingRequest {
+    method: &'static str,
+    params: Vec<Value>,
+    handler: CompletionHandler,
+}
+
+type CompletionHandler = Box<dyn FnOnce(Result<Value, Web3Error>) + Send>;
 
 impl<T: Web3BatchTransport> CallBatch<T> {
     /// Create a new instance from a BatchTransport
@@ -27,6 +223,16 @@ impl<T: Web3BatchTransport> CallBatch<T> {
         }
     }
 
+    /// Returns the number of requests in the batch.
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    /// Returns true if the batch does not contain any requests.
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
     /// Adds a call request to the current batch. The resulting future can only resolve after
     /// the batch has been resolved via `execute_all`.
     /// Explicitly returns a Future instead of being declared `async` so that we can split the
@@ -38,8 +244,29 @@ impl<T: Web3BatchTransport> CallBatch<T> {
         call: CallRequest,
         block: Option<BlockId>,
     ) -> impl std::future::Future<Output = Result<Bytes, Web3Error>> {
-        let (tx, rx) = channel();
-        self.requests.push(((call, block), tx));
+        self.push_request(EthCall {
+            call,
+            block,
+            require_canonical: false,
+            overrides: None,
+        })
+    }
+
+    /// Adds a typed RPC request to the current batch. The resulting future
+    /// resolves to the decoded response, or the error of this individual
+    /// request, after the batch has been resolved via `execute_all`.
+    pub fn push_request<R: BatchRequest>(
+        &mut self,
+        request: R,
+    ) -> impl std::future::Future<Output = Result<R::Output, Web3Error>> {
+        let (tx, rx): (Sender<Result<R::Output, Web3Error>>, _) = channel();
+        self.requests.push(PendingRequest {
+            method: request.method(),
+            params: request.params(),
+            handler: Box::new(move |result| {
+                let _ = tx.send(result.and_then(helpers::decode));
+            }),
+        });
         async move {
             rx.await.unwrap_or_else(|_| {
                 Err(Web3Error::Transport(TransportError::Message(
@@ -49,41 +276,71 @@ impl<T: Web3BatchTransport> CallBatch<T> {
         }
     }
 
-    /// Execute and resolve all enqueued CallRequests in a batched RPC call, `chunk_size` requests per roundtrip.
+    /// Execute and resolve all enqueued requests in batched RPC calls, `batch_size` requests per roundtrip.
     /// Top level request failures will be forwarded to the individual requests.
     pub async fn execute_all(self, batch_size: usize) {
+        self.execute_all_concurrently(batch_size, 1).await
+    }
+
+    /// Execute and resolve all enqueued requests in batched RPC calls,
+    /// `batch_size` requests per roundtrip and with up to `concurrency`
+    /// roundtrips in flight at the same time.
+    /// Top level request failures will be forwarded to the individual requests.
+    pub async fn execute_all_concurrently(self, batch_size: usize, concurrency: usize) {
         let Self { inner, requests } = self;
         let mut iterator = requests.into_iter().peekable();
+        let mut chunks = Vec::new();
         while iterator.peek().is_some() {
-            let (requests, senders): (Vec<_>, Vec<_>) = iterator.by_ref().take(batch_size).unzip();
-
-            // Send requests in a single call
-            let batch_result = inner
-                .send_batch(requests.iter().map(|(request, block)| {
-                    let req = helpers::serialize(request);
-                    let block =
-                        helpers::serialize(&block.unwrap_or_else(|| BlockNumber::Latest.into()));
-                    let (id, request) = inner.prepare("eth_call", vec![req, block]);
-                    (id, request)
-                }))
-                .await;
-
-            // Process results
-            for (i, sender) in senders.into_iter().enumerate() {
-                let _ = match &batch_result {
-                    Ok(results) => sender.send(
-                        results
-                            .get(i)
-                            .unwrap_or(&Err(Web3Error::Decoder(
-                                "Batch result did not contain enough responses".to_owned(),
-                            )))
-                            .clone()
-                            .and_then(helpers::decode),
-                    ),
-                    Err(err) => sender.send(Err(Web3Error::Transport(TransportError::Message(
-                        format!("Batch failed with: {}", err),
-                    )))),
-                };
+            chunks.push(
+                iterator
+                    .by_ref()
+                    .take(batch_size.max(1))
+                    .collect::<Vec<_>>(),
+            );
+        }
+
+        stream::iter(chunks)
+            .map(|chunk| execute_chunk(&inner, chunk))
+            .buffer_unordered(concurrency.max(1))
+            .collect::<()>()
+            .await;
+    }
+}
+
+/// Sends a chunk of requests in a single call and resolves them.
+async fn execute_chunk<T: Web3BatchTransport>(inner: &T, chunk: Vec<PendingRequest>) {
+    let (requests, handlers): (Vec<_>, Vec<_>) = chunk
+        .into_iter()
+        .map(|request| ((request.method, request.params), request.handler))
+        .unzip();
+
+    // Send requests in a single call
+    let batch_result = inner
+        .send_batch(
+            requests
+                .into_iter()
+                .map(|(method, params)| inner.prepare(method, params)),
+        )
+        .await;
+
+    // Process results
+    match batch_result {
+        Ok(results) => {
+            let mut results = results.into_iter();
+            for handler in handlers {
+                handler(results.next().unwrap_or_else(|| {
+                    Err(Web3Error::Decoder(
+                        "Batch result did not contain enough responses".to_owned(),
+                    ))
+                }));
+            }
+        }
+        Err(err) => {
+            for handler in handlers {
+                handler(Err(Web3Error::Transport(TransportError::Message(format!(
+                    "Batch failed with: {}",
+                    err
+                )))));
             }
         }
     }
@@ -95,8 +352,7 @@ mod tests {
     use serde_json::json;
 
     use super::*;
-    use crate::test::prelude::FutureTestExt;
-    use crate::test::transport::TestTransport;
+    use crate::testing::{FutureTestExt, TestTransport};
 
     #[test]
     fn batches_calls() {
@@ -167,4 +423,173 @@ mod tests {
         assert_eq!(results[1].clone().unwrap().0, vec![2u8]);
         assert_eq!(results[2].clone().unwrap().0, vec![3u8]);
     }
+
+    #[test]
+    fn batches_typed_requests() {
+        let mut transport = TestTransport::new();
+        transport.add_batch_response(vec![
+            Ok(json!("0x2a")),
+            Ok(json!("0x6001")),
+            Ok(json!(
+                "0x0000000000000000000000000000000000000000000000000000000000000001"
+            )),
+            Ok(json!("0x5208")),
+            Ok(json!([])),
+            Ok(json!(null)),
+        ]);
+
+        let mut batch = CallBatch::new(transport.clone());
+        let balance = batch.push_request(GetBalance {
+            address: addr!("0x0101010101010101010101010101010101010101"),
+            block: Some(BlockNumber::Number(1.into())),
+        });
+        let code = batch.push_request(GetCode::default());
+        let storage = batch.push_request(GetStorageAt {
+            slot: 1.into(),
+            ..Default::default()
+        });
+        let gas = batch.push_request(EstimateGas::default());
+        let logs = batch.push_request(GetLogs {
+            filter: Default::default(),
+        });
+        let receipt = batch.push_request(GetTransactionReceipt::default());
+        assert_eq!(batch.len(), 6);
+
+        batch.execute_all(usize::MAX).immediate();
+
+        assert_eq!(balance.immediate().unwrap(), 42.into());
+        assert_eq!(code.immediate().unwrap().0, vec![0x60, 0x01]);
+        assert_eq!(storage.immediate().unwrap(), H256::from_low_u64_be(1));
+        assert_eq!(gas.immediate().unwrap(), 21000.into());
+        assert!(logs.immediate().unwrap().is_empty());
+        assert!(receipt.immediate().unwrap().is_none());
+
+        transport.assert_request(
+            "eth_getBalance",
+            &[
+                json!("0x0101010101010101010101010101010101010101"),
+                json!("0x1"),
+            ],
+        );
+        transport.assert_request(
+            "eth_getCode",
+            &[
+                json!("0x0000000000000000000000000000000000000000"),
+                json!("latest"),
+            ],
+        );
+        transport.assert_request(
+            "eth_getStorageAt",
+            &[
+                json!("0x0000000000000000000000000000000000000000"),
+                json!("0x1"),
+                json!("latest"),
+            ],
+        );
+        transport.assert_request("eth_estimateGas", &[json!({})]);
+        transport.assert_request("eth_getLogs", &[json!({})]);
+        transport.assert_request(
+            "eth_getTransactionReceipt",
+            &[json!(
+                "0x0000000000000000000000000000000000000000000000000000000000000000"
+            )],
+        );
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn batches_calls_with_state_overrides() {
+        let mut transport = TestTransport::new();
+        transport.add_response(json!([json!("0x01"), json!("0x5208")]));
+
+        let account = addr!("0x0101010101010101010101010101010101010101");
+        let mut batch = CallBatch::new(transport.clone());
+        let call = batch.push_request(EthCall {
+            call: CallRequest::default(),
+            block: Some(BlockNumber::Pending.into()),
+            require_canonical: false,
+            overrides: Some(
+                StateOverrides::new()
+                    .balance(account, 1.into())
+                    .nonce(account, 2.into()),
+            ),
+        });
+
+        let gas = batch.push_request(EstimateGas {
+            overrides: Some(StateOverrides::new().balance(account, 1.into())),
+            ..Default::default()
+        });
+
+        batch.execute_all(usize::MAX).immediate();
+
+        assert_eq!(call.immediate().unwrap().0, vec![1u8]);
+        assert_eq!(gas.immediate().unwrap(), 21000.into());
+        transport.assert_request(
+            "eth_call",
+            &[
+                json!({}),
+                json!("pending"),
+                json!({
+                    "0x0101010101010101010101010101010101010101": {
+                        "balance": "0x1",
+                        "nonce": "0x2",
+                    },
+                }),
+            ],
+        );
+        transport.assert_request(
+            "eth_estimateGas",
+            &[
+                json!({}),
+                json!("latest"),
+                json!({ "0x0101010101010101010101010101010101010101": { "balance": "0x1" } }),
+            ],
+        );
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn propagates_errors_per_request() {
+        let mut transport = TestTransport::new();
+        transport.add_batch_response(vec![
+            Ok(json!("0x01")),
+            Err(Web3Error::Rpc(jsonrpc_core::Error::invalid_params("bad"))),
+            Ok(json!("not a number")),
+        ]);
+
+        let mut batch = CallBatch::new(transport);
+        let call = batch.push(CallRequest::default(), None);
+        let failed = batch.push_request(GetBalance::default());
+        let invalid = batch.push_request(GetBalance::default());
+        let missing = batch.push_request(GetCode::default());
+
+        batch.execute_all(usize::MAX).immediate();
+
+        assert_eq!(call.immediate().unwrap().0, vec![1u8]);
+        assert!(matches!(failed.immediate(), Err(Web3Error::Rpc(_))));
+        assert!(matches!(invalid.immediate(), Err(Web3Error::Decoder(_))));
+        assert!(matches!(missing.immediate(), Err(Web3Error::Decoder(_))));
+    }
+
+    #[test]
+    fn executes_chunks_concurrently() {
+        let mut transport = TestTransport::new();
+        transport.add_response(json!([json!("0x01"), json!("0x02")]));
+        transport.add_error(Web3Error::Unreachable);
+        transport.add_response(json!([json!("0x05")]));
+
+        let mut batch = CallBatch::new(transport);
+        let results = (0..5)
+            .map(|_| batch.push(CallRequest::default(), None))
+            .collect::<Vec<_>>();
+
+        batch.execute_all_concurrently(2, 3).immediate();
+
+        let results = join_all(results).immediate();
+        assert_eq!(results[0].clone().unwrap().0, vec![1u8]);
+        assert_eq!(results[1].clone().unwrap().0, vec![2u8]);
+        assert!(results[2].is_err());
+        assert!(results[3].is_err());
+        assert_eq!(results[4].clone().unwrap().0, vec![5u8]);
+    }
 }
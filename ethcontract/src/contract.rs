@@ -7,8 +7,9 @@ mod event;
 mod method;
 
 use crate::{
-    errors::{DeployError, LinkError},
+    errors::{DeployError, ExecutionError, LinkError},
     tokens::Tokenize,
+    transport::DynTransport,
 };
 use ethcontract_common::hash::H32;
 use ethcontract_common::{
@@ -19,14 +20,16 @@ use ethcontract_common::{Abi, Bytecode, Contract, DeploymentInformation};
 use std::hash::Hash;
 use std::sync::Arc;
 use web3::api::Web3;
-use web3::types::{Address, Bytes, H256};
+use web3::types::{Address, BlockId, Bytes, TransactionReceipt, H256, U64};
 use web3::Transport;
 
 pub use self::deploy::{Deploy, DeployBuilder};
+pub(crate) use self::event::block_number_from_transaction_hash;
 pub use self::event::{
     AllEventsBuilder, Event, EventBuilder, EventMetadata, EventStatus, ParseLog, RawLog,
     StreamEvent, Topic,
 };
+pub(crate) use self::method::decode_response;
 pub use self::method::{MethodBuilder, MethodDefaults, ViewMethodBuilder};
 use std::marker::PhantomData;
 
@@ -69,6 +72,17 @@ pub struct Instance<T: Transport> {
     /// querying method calls.
     pub defaults: MethodDefaults,
     interface: Arc<Interface>,
+    pinned_block: Option<PinnedBlock>,
+    subscription_transport: Option<DynTransport>,
+}
+
+/// A block that an [`Instance`] is pinned to with [`Instance::at_block`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PinnedBlock {
+    /// The number of the block.
+    pub number: U64,
+    /// The hash of the block.
+    pub hash: H256,
 }
 
 impl<T: Transport> Instance<T> {
@@ -101,6 +115,8 @@ impl<T: Transport> Instance<T> {
             address,
             deployment_information,
             defaults: Default::default(),
+            pinned_block: None,
+            subscription_transport: None,
         }
     }
 
@@ -179,6 +195,54 @@ impl<T: Transport> Instance<T> {
         self.deployment_information
     }
 
+    /// Returns a copy of this instance that is pinned to the specified block,
+    /// for reading a consistent snapshot of the contract state.
+    ///
+    /// The block is resolved to its hash, and method calls, including batched
+    /// calls, are executed on that hash with `requireCanonical` set
+    /// (EIP-1898), so that they fail instead of silently reading state of a
+    /// block that was reorged out of the chain. Event queries only return
+    /// events up to the pinned block, and fail if it is no longer canonical
+    /// once the events are retrieved. Event streams are only limited by the
+    /// block number. Transactions are not affected.
+    pub async fn at_block(&self, block: BlockId) -> Result<Self, ExecutionError> {
+        let resolved = self.web3.eth().block(block).await?;
+        let pinned_block = resolved
+            .and_then(|block| {
+                Some(PinnedBlock {
+                    number: block.number?,
+                    hash: block.hash?,
+                })
+            })
+            .ok_or(ExecutionError::UnknownBlock(block))?;
+
+        Ok(Instance {
+            pinned_block: Some(pinned_block),
+            ..self.clone()
+        })
+    }
+
+    /// Returns a copy of this instance that uses the specified transport for
+    /// `eth_subscribe` based event streams and transaction confirmations. It
+    /// is only used if it supports subscriptions.
+    pub fn with_subscription_transport(self, transport: DynTransport) -> Self {
+        Instance {
+            subscription_transport: Some(transport),
+            ..self
+        }
+    }
+
+    /// Returns the transport used for `eth_subscribe` based event streams and
+    /// transaction confirmations, if any.
+    pub fn subscription_transport(&self) -> Option<DynTransport> {
+        self.subscription_transport.clone()
+    }
+
+    /// Returns the block this instance is pinned to, if any.
+    pub fn pinned_block(&self) -> Option<PinnedBlock> {
+        self.pinned_block
+    }
+
     /// Returns a method builder to setup a call or transaction on a smart
     /// contract method. Note that calls just get evaluated on a node but do not
     /// actually commit anything to the block chain.
@@ -211,10 +275,9 @@ impl<T: Transport> Instance<T> {
         let function = function.clone();
         let data = Bytes(data);
 
-        Ok(
-            MethodBuilder::new(self.web3(), function, self.address, data)
-                .with_defaults(&self.defaults),
-        )
+        let builder = MethodBuilder::new(self.web3(), function, self.address, data)
+            .with_defaults(&self.defaults);
+        Ok(self.configure_method(builder))
     }
 
     /// Returns a view method builder to setup a call to a smart contract. View
@@ -245,11 +308,23 @@ impl<T: Transport> Instance<T> {
             return Err(AbiError::InvalidName("fallback".into()));
         }
 
-        Ok(MethodBuilder::fallback(
-            self.web3(),
-            self.address,
-            Bytes(data.into()),
-        ))
+        let builder = MethodBuilder::fallback(self.web3(), self.address, Bytes(data.into()));
+        Ok(self.configure_method(builder))
+    }
+
+    /// Applies the subscription transport and pinned block of this instance to
+    /// a method builder.
+    fn configure_method<R>(&self, mut builder: MethodBuilder<T, R>) -> MethodBuilder<T, R>
+    where
+        R: Tokenize,
+    {
+        if let Some(transport) = &self.subscription_transport {
+            builder = builder.subscription_transport(transport.clone());
+        }
+        match self.pinned_block {
+            Some(block) => builder.pinned_block(block),
+            None => builder,
+        }
     }
 
     /// Returns a event builder to setup an event stream for a smart contract
@@ -265,17 +340,45 @@ impl<T: Transport> Instance<T> {
             .map(|(name, index)| &self.interface.abi.events[name][*index])
             .ok_or_else(|| AbiError::InvalidName(hex::encode(signature)))?;
 
-        Ok(EventBuilder::new(
-            self.web3(),
-            event.clone(),
-            self.address(),
-        ))
+        let mut builder = EventBuilder::new(self.web3(), event.clone(), self.address());
+        if let Some(transport) = &self.subscription_transport {
+            builder = builder.subscription_transport(transport.clone());
+        }
+        Ok(match self.pinned_block {
+            Some(block) => builder.pinned_block(block),
+            None => builder,
+        })
     }
 
     /// Returns a log stream that emits a log for every new event emitted after
     /// the stream was created for this contract instance.
     pub fn all_events(&self) -> AllEventsBuilder<T, RawLog> {
-        AllEventsBuilder::new(self.web3(), self.address(), self.deployment_information())
+        let mut builder =
+            AllEventsBuilder::new(self.web3(), self.address(), self.deployment_information());
+        if let Some(transport) = &self.subscription_transport {
+            builder = builder.subscription_transport(transport.clone());
+        }
+        match self.pinned_block {
+            Some(block) => builder.pinned_block(block),
+            None => builder,
+        }
+    }
+
+    /// Decodes the events emitted by this contract instance from the logs of
+    /// a transaction receipt. Logs emitted by other contracts are ignored.
+    pub fn parse_receipt_logs<E>(
+        &self,
+        receipt: &TransactionReceipt,
+    ) -> Result<Vec<Event<E>>, ExecutionError>
+    where
+        E: ParseLog,
+    {
+        receipt
+            .logs
+            .iter()
+            .filter(|log| log.address == self.address)
+            .map(|log| Event::from_past_log(log.clone(), E::parse_log))
+            .collect()
     }
 }
 
@@ -359,6 +462,8 @@ mod tests {
     use super::*;
     use crate::test::prelude::*;
     use ethcontract_common::contract::Network;
+    use ethcontract_common::hash;
+    use web3::types::{BlockNumber, U256};
 
     #[test]
     fn deployed() {
@@ -417,4 +522,288 @@ mod tests {
             err
         );
     }
+
+    fn pinning_instance(transport: &TestTransport) -> Instance<TestTransport> {
+        let abi: Abi = serde_json::from_str(
+            r#"[
+                {
+                    "type": "function",
+                    "name": "value",
+                    "inputs": [],
+                    "outputs": [{ "name": "", "type": "uint256" }],
+                    "stateMutability": "view"
+                },
+                {
+                    "type": "event",
+                    "name": "Changed",
+                    "inputs": [],
+                    "anonymous": false
+                },
+                {
+                    "type": "fallback",
+                    "stateMutability": "nonpayable"
+                }
+            ]"#,
+        )
+        .unwrap();
+        Instance::at(
+            Web3::new(transport.clone()),
+            Arc::new(abi.into()),
+            addr!("0x0102030405060708091011121314151617181920"),
+        )
+    }
+
+    #[test]
+    fn at_block_pins_view_calls_to_canonical_block_hash() {
+        let mut transport = TestTransport::new();
+        let instance = pinning_instance(&transport);
+        let hash = H256::repeat_byte(0x42);
+
+        transport.add_response(
+            serde_json::to_value(web3::types::Block::<H256> {
+                hash: Some(hash),
+                number: Some(42.into()),
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        let pinned = instance
+            .at_block(BlockNumber::Number(42.into()).into())
+            .immediate()
+            .expect("block is known");
+        assert_eq!(
+            pinned.pinned_block(),
+            Some(PinnedBlock {
+                number: 42.into(),
+                hash,
+            })
+        );
+        assert_eq!(instance.pinned_block(), None);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x2a"), json!(false)]);
+
+        let signature = hash::function_selector("value()");
+        transport.add_response(json!(
+            "0x000000000000000000000000000000000000000000000000000000000000002a"
+        ));
+        let value = pinned
+            .view_method::<_, U256>(signature, ())
+            .unwrap()
+            .call()
+            .immediate()
+            .unwrap();
+        assert_eq!(value, 42.into());
+        transport.assert_request(
+            "eth_call",
+            &[
+                json!({
+                    "to": "0x0102030405060708091011121314151617181920",
+                    "data": "0x3fa4f245",
+                }),
+                json!({
+                    "blockHash": hash,
+                    "requireCanonical": true,
+                }),
+            ],
+        );
+
+        transport.add_response(json!(
+            "0x0000000000000000000000000000000000000000000000000000000000000007"
+        ));
+        let value = pinned
+            .method::<_, U256>(signature, ())
+            .unwrap()
+            .call()
+            .immediate()
+            .unwrap();
+        assert_eq!(value, 7.into());
+        transport.assert_request(
+            "eth_call",
+            &[
+                json!({
+                    "to": "0x0102030405060708091011121314151617181920",
+                    "data": "0x3fa4f245",
+                }),
+                json!({
+                    "blockHash": hash,
+                    "requireCanonical": true,
+                }),
+            ],
+        );
+
+        transport.add_response(json!("0x"));
+        pinned
+            .fallback(vec![0x13, 0x37])
+            .unwrap()
+            .call()
+            .immediate()
+            .unwrap();
+        transport.assert_request(
+            "eth_call",
+            &[
+                json!({
+                    "to": "0x0102030405060708091011121314151617181920",
+                    "data": "0x1337",
+                }),
+                json!({
+                    "blockHash": hash,
+                    "requireCanonical": true,
+                }),
+            ],
+        );
+
+        let mut batch = crate::batch::CallBatch::new(transport.clone());
+        let call = pinned
+            .view_method::<_, U256>(signature, ())
+            .unwrap()
+            .batch_call(&mut batch);
+        transport.add_response(json!([
+            "0x0000000000000000000000000000000000000000000000000000000000000001"
+        ]));
+        batch.execute_all(usize::MAX).immediate();
+        assert_eq!(call.immediate().unwrap(), 1.into());
+        transport.assert_request(
+            "eth_call",
+            &[
+                json!({
+                    "to": "0x0102030405060708091011121314151617181920",
+                    "data": "0x3fa4f245",
+                }),
+                json!({
+                    "blockHash": hash,
+                    "requireCanonical": true,
+                }),
+            ],
+        );
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn at_block_limits_event_queries() {
+        let mut transport = TestTransport::new();
+        let instance = pinning_instance(&transport);
+
+        transport.add_response(
+            serde_json::to_value(web3::types::Block::<H256> {
+                hash: Some(H256::repeat_byte(0x42)),
+                number: Some(42.into()),
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        let pinned = instance
+            .at_block(BlockId::Hash(H256::repeat_byte(0x42)))
+            .immediate()
+            .unwrap();
+        transport.assert_request(
+            "eth_getBlockByHash",
+            &[json!(H256::repeat_byte(0x42)), json!(false)],
+        );
+
+        transport.add_response(json!([]));
+        transport.add_response(
+            serde_json::to_value(web3::types::Block::<H256> {
+                hash: Some(H256::repeat_byte(0x42)),
+                number: Some(42.into()),
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        let events = pinned
+            .event::<()>(hash::keccak256("Changed()").into())
+            .unwrap()
+            .from_block(BlockNumber::Earliest)
+            .query()
+            .immediate()
+            .unwrap();
+        assert!(events.is_empty());
+        transport.assert_request_matches("eth_getLogs", |params| {
+            params[0]["toBlock"] == json!("0x2a")
+        });
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x2a"), json!(false)]);
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn at_block_event_queries_fail_after_reorg() {
+        let mut transport = TestTransport::new();
+        let instance = pinning_instance(&transport);
+        let block = |hash: H256| {
+            serde_json::to_value(web3::types::Block::<H256> {
+                hash: Some(hash),
+                number: Some(42.into()),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+
+        transport.add_response(block(H256::repeat_byte(0x42)));
+        let pinned = instance
+            .at_block(BlockNumber::Number(42.into()).into())
+            .immediate()
+            .unwrap();
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x2a"), json!(false)]);
+
+        transport.add_response(json!([]));
+        transport.add_response(block(H256::repeat_byte(0x43)));
+        let err = pinned.all_events().query().immediate().unwrap_err();
+
+        assert!(
+            matches!(err, ExecutionError::NonCanonicalBlock(hash) if hash == H256::repeat_byte(0x42))
+        );
+        transport.assert_request_matches("eth_getLogs", |params| {
+            params[0]["toBlock"] == json!("0x2a")
+        });
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x2a"), json!(false)]);
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn at_block_fails_for_unknown_block() {
+        let mut transport = TestTransport::new();
+        let instance = pinning_instance(&transport);
+
+        transport.add_response(json!(null));
+        let err = instance
+            .at_block(BlockNumber::Number(1000.into()).into())
+            .immediate()
+            .unwrap_err();
+
+        assert!(matches!(err, ExecutionError::UnknownBlock(_)));
+    }
+
+    #[test]
+    fn parse_receipt_logs_ignores_other_contracts() {
+        let transport = TestTransport::new();
+        let instance = pinning_instance(&transport);
+        let changed = H256::from(hash::keccak256("Changed()"));
+        let log = |address: Address, log_index: u64| {
+            serde_json::from_value(json!({
+                "address": address,
+                "topics": [changed],
+                "data": "0x",
+                "blockHash": H256::repeat_byte(0x42),
+                "blockNumber": "0x2a",
+                "transactionHash": H256::repeat_byte(0x01),
+                "transactionIndex": "0x0",
+                "logIndex": U256::from(log_index),
+            }))
+            .unwrap()
+        };
+        let receipt = TransactionReceipt {
+            logs: vec![
+                log(addr!("0x2020202020202020202020202020202020202020"), 0),
+                log(instance.address(), 1),
+            ],
+            ..Default::default()
+        };
+
+        let events = instance.parse_receipt_logs::<RawLog>(&receipt).unwrap();
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].data.topics, [changed]);
+        let meta = events[0].meta.as_ref().unwrap();
+        assert_eq!(meta.address, instance.address());
+        assert_eq!(meta.log_index, 1);
+        transport.assert_no_more_requests();
+    }
 }
@@ -3,7 +3,9 @@
 
 use crate::errors::{DeployError, ExecutionError};
 use crate::tokens::Tokenize;
-use crate::transaction::{Account, GasPrice, TransactionBuilder, TransactionResult};
+use crate::trace;
+use crate::transaction::{Account, GasPolicy, GasPrice, TransactionBuilder, TransactionResult};
+use crate::transport::DynTransport;
 use ethcontract_common::abi::Error as AbiError;
 use ethcontract_common::{Abi, Bytecode};
 use std::marker::PhantomData;
@@ -113,6 +115,13 @@ where
         self
     }
 
+    /// Specify the gas policy to use for padding the gas estimate and limiting
+    /// the transaction fee.
+    pub fn gas_policy(mut self, value: GasPolicy) -> Self {
+        self.tx = self.tx.gas_policy(value);
+        self
+    }
+
     /// Specify what how much ETH to transfer with the transaction, if not
     /// specified then no ETH will be sent.
     pub fn value(mut self, value: U256) -> Self {
@@ -135,6 +144,13 @@ where
         self
     }
 
+    /// Specify the transport used for waiting for confirmations with a
+    /// `newHeads` subscription instead of polling.
+    pub fn subscription_transport(mut self, transport: DynTransport) -> Self {
+        self.tx = self.tx.subscription_transport(transport);
+        self
+    }
+
     /// Extract inner `TransactionBuilder` from this `DeployBuilder`. This
     /// exposes `TransactionBuilder` only APIs.
     pub fn into_inner(self) -> TransactionBuilder<T> {
@@ -144,7 +160,8 @@ where
     /// Sign (if required) and execute the transaction. Returns the transaction
     /// hash that can be used to retrieve transaction information.
     pub async fn deploy(self) -> Result<I, DeployError> {
-        let tx = match self.tx.send().await? {
+        let span = trace::span!("deploy", address = ::tracing::field::Empty);
+        let tx = match trace::instrument(span.clone(), self.tx.send()).await? {
             TransactionResult::Receipt(tx) => tx,
             TransactionResult::Hash(tx) => return Err(DeployError::Pending(tx)),
         };
@@ -153,6 +170,7 @@ where
         let address = tx
             .contract_address
             .ok_or_else(|| ExecutionError::Failure(Box::new(tx)))?;
+        trace::record!(span, "address", ?address);
 
         Ok(I::from_deployment(
             self.web3,
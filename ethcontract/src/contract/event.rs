@@ -4,9 +4,12 @@
 mod data;
 
 pub use self::data::{Event, EventMetadata, EventStatus, ParseLog, RawLog, StreamEvent};
+use crate::contract::PinnedBlock;
 use crate::errors::{EventError, ExecutionError};
-use crate::log::LogFilterBuilder;
+use crate::indexer::{CheckpointStore, EventIndexer, LogIndexer};
+use crate::log::{AdaptivePaging, AddressSet, LogFilterBuilder, LogStreamMode};
 use crate::tokens::Tokenize;
+use crate::transport::DynTransport;
 pub use ethcontract_common::abi::Topic;
 use ethcontract_common::{
     abi::{Event as AbiEvent, RawTopicFilter, Token},
@@ -64,6 +67,15 @@ impl<T: Transport, E: Tokenize> EventBuilder<T, E> {
         self
     }
 
+    /// Pins queries to the specified block, so that they only return events
+    /// up to the block and fail if the block was reorged out of the chain by
+    /// the time the events are retrieved. Event streams are only limited by
+    /// the block number.
+    pub fn pinned_block(mut self, block: PinnedBlock) -> Self {
+        self.filter = self.filter.pinned_block(block);
+        self
+    }
+
     /// Adds a filter for the first indexed topic.
     ///
     /// This corresponds to the first indexed property, which for anonymous
@@ -103,6 +115,27 @@ impl<T: Transport, E: Tokenize> EventBuilder<T, E> {
         self
     }
 
+    /// The page size in blocks to use when doing a paginated query on past
+    /// events.
+    pub fn block_page_size(mut self, value: u64) -> Self {
+        self.filter = self.filter.block_page_size(value);
+        self
+    }
+
+    /// The configuration for adapting the block page size of paginated queries
+    /// to node limits.
+    pub fn adaptive_paging(mut self, value: AdaptivePaging) -> Self {
+        self.filter = self.filter.adaptive_paging(value);
+        self
+    }
+
+    /// The number of pages to query concurrently when doing a paginated query
+    /// on past events. Events are still returned in block order.
+    pub fn page_concurrency(mut self, value: usize) -> Self {
+        self.filter = self.filter.page_concurrency(value);
+        self
+    }
+
     /// The polling interval. This is used as the interval between consecutive
     /// `eth_getFilterChanges` calls to get filter updates.
     pub fn poll_interval(mut self, value: Duration) -> Self {
@@ -110,6 +143,31 @@ impl<T: Transport, E: Tokenize> EventBuilder<T, E> {
         self
     }
 
+    /// Sets the strategy used for streaming new events. Use
+    /// [`LogStreamMode::Polling`] for nodes that do not support filters.
+    pub fn stream_mode(mut self, value: LogStreamMode) -> Self {
+        self.filter = self.filter.stream_mode(value);
+        self
+    }
+
+    /// Sets the transport used for subscribing to new events with
+    /// `eth_subscribe`, usually the [`DynTransport`] of the web3 provider.
+    /// It is only used if it supports subscriptions.
+    pub fn subscription_transport(mut self, transport: DynTransport) -> Self {
+        self.filter = self.filter.subscription_transport(transport);
+        self
+    }
+
+    /// Retrieves events emitted by any contract in the address set instead of
+    /// the builder's contract, for querying the same event across many
+    /// deployments of a contract. The address set can be updated while the
+    /// events are being streamed, and the emitting contract of each event is
+    /// available in its [`EventMetadata::address`].
+    pub fn address_set(mut self, addresses: AddressSet) -> Self {
+        self.filter = self.filter.address_set(addresses);
+        self
+    }
+
     /// Returns a `LogFilterBuilder` instance for the current builder.
     pub fn into_inner(self) -> Result<(AbiEvent, LogFilterBuilder<T>), EventError> {
         let EventBuilder {
@@ -142,6 +200,24 @@ impl<T: Transport, E: Tokenize> EventBuilder<T, E> {
             .collect()
     }
 
+    /// Returns a stream of all existing events matching the builder
+    /// parameters. This method is similar to `query` with the notable
+    /// difference that the logs are fetched in pages by querying smaller block
+    /// ranges specified by `block_page_size` instead of using a single query.
+    pub fn query_paginated(self) -> impl Stream<Item = Result<Event<E>, EventError>> {
+        future::ready(self.into_inner().map(|(event, filter)| {
+            filter
+                .past_logs_pages()
+                .map_ok(|logs| stream::iter(logs).map(Ok))
+                .try_flatten()
+                .map(move |log| {
+                    log.and_then(|log| Event::from_past_log(log, |raw| raw.decode(&event)))
+                        .map_err(|err| EventError::new(&event, err))
+                })
+        }))
+        .try_flatten_stream()
+    }
+
     /// Creates an event stream from the current event builder that emits new
     /// events.
     pub fn stream(self) -> impl Stream<Item = Result<StreamEvent<E>, EventError>> {
@@ -213,6 +289,15 @@ impl<T: Transport, E: ParseLog> AllEventsBuilder<T, E> {
         self
     }
 
+    /// Pins queries to the specified block, so that they only return events
+    /// up to the block and fail if the block was reorged out of the chain by
+    /// the time the events are retrieved. Event streams are only limited by
+    /// the block number.
+    pub fn pinned_block(mut self, block: PinnedBlock) -> Self {
+        self.filter = self.filter.pinned_block(block);
+        self
+    }
+
     /// Sets `block_hash`. The field `block_hash` and the pair `from_block` and
     /// `to_block` are mutually exclusive.
     pub fn block_hash(mut self, hash: H256) -> Self {
@@ -262,6 +347,20 @@ impl<T: Transport, E: ParseLog> AllEventsBuilder<T, E> {
         self
     }
 
+    /// The configuration for adapting the block page size of paginated queries
+    /// to node limits.
+    pub fn adaptive_paging(mut self, value: AdaptivePaging) -> Self {
+        self.filter = self.filter.adaptive_paging(value);
+        self
+    }
+
+    /// The number of pages to query concurrently when doing a paginated query
+    /// on past events. Events are still returned in block order.
+    pub fn page_concurrency(mut self, value: usize) -> Self {
+        self.filter = self.filter.page_concurrency(value);
+        self
+    }
+
     /// The polling interval. This is used as the interval between consecutive
     /// `eth_getLogs` calls to get log updates.
     pub fn poll_interval(mut self, value: Duration) -> Self {
@@ -269,6 +368,34 @@ impl<T: Transport, E: ParseLog> AllEventsBuilder<T, E> {
         self
     }
 
+    /// Sets the strategy used for streaming new events. Use
+    /// [`LogStreamMode::Polling`] for nodes that do not support filters.
+    pub fn stream_mode(mut self, value: LogStreamMode) -> Self {
+        self.filter = self.filter.stream_mode(value);
+        self
+    }
+
+    /// Sets the transport used for subscribing to new events with
+    /// `eth_subscribe`, usually the [`DynTransport`] of the web3 provider.
+    /// It is only used if it supports subscriptions.
+    pub fn subscription_transport(mut self, transport: DynTransport) -> Self {
+        self.filter = self.filter.subscription_transport(transport);
+        self
+    }
+
+    /// Retrieves events emitted by any contract in the address set instead of
+    /// the builder's contract. The address set can be updated while the events
+    /// are being streamed, and the emitting contract of each event is
+    /// available in its [`EventMetadata::address`].
+    ///
+    /// Note that this clears the deployment information, as the contracts in
+    /// the set may have been deployed at different blocks.
+    pub fn address_set(mut self, addresses: AddressSet) -> Self {
+        self.filter = self.filter.address_set(addresses);
+        self.deployment_information = None;
+        self
+    }
+
     /// Returns a future that resolves into a collection of events matching the
     /// event builder's parameters.
     pub async fn query(self) -> Result<Vec<Event<E>>, ExecutionError> {
@@ -317,6 +444,14 @@ impl<T: Transport, E: ParseLog> AllEventsBuilder<T, E> {
         Ok(events)
     }
 
+    /// Creates a resumable indexer for the events matching the builder's
+    /// parameters, persisting its progress in the specified checkpoint store.
+    pub fn indexer<S: CheckpointStore>(self, store: S) -> EventIndexer<T, E, S> {
+        EventIndexer::new(
+            LogIndexer::new(self.filter, store).deployment_information(self.deployment_information),
+        )
+    }
+
     /// Creates an event stream from the current event builder.
     pub fn stream(self) -> impl Stream<Item = Result<StreamEvent<E>, ExecutionError>> {
         self.filter
@@ -326,7 +461,7 @@ impl<T: Transport, E: ParseLog> AllEventsBuilder<T, E> {
 }
 
 /// Retrieves a block number for the specified transaction hash.
-async fn block_number_from_transaction_hash<T: Transport>(
+pub(crate) async fn block_number_from_transaction_hash<T: Transport>(
     web3: Web3<T>,
     tx_hash: H256,
 ) -> Result<u64, ExecutionError> {
@@ -433,6 +568,78 @@ mod tests {
         transport.assert_no_more_requests();
     }
 
+    #[test]
+    fn event_query_address_set() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+        let (event, mut log) = test_abi_event();
+
+        let pool = Address::repeat_byte(0x02);
+        log["address"] = json!(pool);
+        transport.add_response(json!([log]));
+
+        let addresses = AddressSet::new();
+        let builder = || {
+            EventBuilder::<_, (Address, Address, U256)>::new(
+                web3.clone(),
+                event.clone(),
+                Address::zero(),
+            )
+            .address_set(addresses.clone())
+        };
+
+        // no logs can match an empty address set
+        let events = builder().query().immediate().unwrap();
+        assert!(events.is_empty());
+
+        addresses.insert(Address::repeat_byte(0x01));
+        addresses.insert(pool);
+        let events = builder().query().immediate().unwrap();
+
+        assert_eq!(events[0].meta.as_ref().unwrap().address, pool);
+        transport.assert_request(
+            "eth_getLogs",
+            &[json!({
+                "address": [Address::repeat_byte(0x01), pool],
+                "topics": [event.signature()],
+            })],
+        );
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn event_query_paginated() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+        let (event, log) = test_abi_event();
+
+        // get logs pages
+        transport.add_response(json!([log]));
+        transport.add_response(json!([]));
+        transport.add_response(json!([log, log]));
+
+        let address = Address::repeat_byte(0x01);
+        let events = EventBuilder::<_, (Address, Address, U256)>::new(web3, event, address)
+            .from_block(0.into())
+            .to_block(14.into())
+            .block_page_size(5)
+            .page_concurrency(2)
+            .query_paginated()
+            .try_collect::<Vec<_>>()
+            .immediate()
+            .expect("failed to get logs");
+
+        assert_eq!(events.len(), 3);
+        assert!(events.iter().all(|event| event.data.2 == U256::from(42)));
+        for (from, to) in [(0, 4), (5, 9), (10, 14)] {
+            transport.assert_request_matches("eth_getLogs", |params| {
+                params[0]["fromBlock"] == json!(U64::from(from))
+                    && params[0]["toBlock"] == json!(U64::from(to))
+            });
+        }
+        transport.assert_no_more_requests();
+    }
+
     #[test]
     fn event_stream_next_event() {
         let mut transport = TestTransport::new();
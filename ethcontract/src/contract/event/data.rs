@@ -125,7 +125,7 @@ impl<T> Event<EventStatus<T>> {
 /// Additional metadata from the log for the event.
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub struct EventMetadata {
-    /// From which this event originated from
+    /// The address of the contract that emitted the event.
     pub address: H160,
     /// The hash of the block where the log was produced.
     pub block_hash: H256,
@@ -2,8 +2,17 @@
 //! intended to be used directly but to be used by a contract `Instance` with
 //! [Instance::method](ethcontract::contract::Instance::method).
 
-use crate::transaction::{Account, GasPrice, TransactionBuilder, TransactionResult};
-use crate::{batch::CallBatch, errors::MethodError, tokens::Tokenize};
+use crate::contract::PinnedBlock;
+use crate::multicall::MulticallBatch;
+use crate::state_override::{self, StateOverrides};
+use crate::transaction::{Account, GasPolicy, GasPrice, TransactionBuilder, TransactionResult};
+use crate::transport::DynTransport;
+use crate::{
+    batch::{CallBatch, EthCall},
+    errors::MethodError,
+    tokens::Tokenize,
+    trace,
+};
 use ethcontract_common::abi::{Function, Token};
 use std::marker::PhantomData;
 use web3::types::{AccessList, Address, BlockId, Bytes, CallRequest, U256};
@@ -19,6 +28,35 @@ pub struct MethodDefaults {
     pub gas: Option<U256>,
     /// Default gas price to use for transaction.
     pub gas_price: Option<GasPrice>,
+    /// Default gas policy to use for transaction.
+    pub gas_policy: Option<GasPolicy>,
+}
+
+impl MethodDefaults {
+    /// Specify the default sender of the transaction with the signing strategy
+    /// to use.
+    pub fn from(mut self, value: Account) -> Self {
+        self.from = Some(value);
+        self
+    }
+
+    /// Specify the default gas amount to use for transaction.
+    pub fn gas(mut self, value: U256) -> Self {
+        self.gas = Some(value);
+        self
+    }
+
+    /// Specify the default gas price to use for transaction.
+    pub fn gas_price(mut self, value: GasPrice) -> Self {
+        self.gas_price = Some(value);
+        self
+    }
+
+    /// Specify the default gas policy to use for transaction.
+    pub fn gas_policy(mut self, value: GasPolicy) -> Self {
+        self.gas_policy = Some(value);
+        self
+    }
 }
 
 /// Data used for building a contract method call or transaction. The method
@@ -31,6 +69,7 @@ pub struct MethodBuilder<T: Transport, R: Tokenize> {
     function: Function,
     /// transaction parameters
     pub tx: TransactionBuilder<T>,
+    pinned_block: Option<PinnedBlock>,
     _result: PhantomData<R>,
 }
 
@@ -59,6 +98,7 @@ impl<T: Transport, R: Tokenize> MethodBuilder<T, R> {
             web3: web3.clone(),
             function,
             tx: TransactionBuilder::new(web3).to(address).data(data),
+            pinned_block: None,
             _result: PhantomData,
         }
     }
@@ -68,6 +108,7 @@ impl<T: Transport, R: Tokenize> MethodBuilder<T, R> {
         self.tx.from = self.tx.from.or_else(|| defaults.from.clone());
         self.tx.gas = self.tx.gas.or(defaults.gas);
         self.tx.gas_price = self.tx.gas_price.or(defaults.gas_price);
+        self.tx.gas_policy = self.tx.gas_policy.or(defaults.gas_policy);
         self
     }
 
@@ -97,6 +138,13 @@ impl<T: Transport, R: Tokenize> MethodBuilder<T, R> {
         self
     }
 
+    /// Specify the gas policy to use for padding the gas estimate and limiting
+    /// the transaction fee.
+    pub fn gas_policy(mut self, value: GasPolicy) -> Self {
+        self.tx = self.tx.gas_policy(value);
+        self
+    }
+
     /// Specify what how much ETH to transfer with the transaction, if not
     /// specified then no ETH will be sent.
     pub fn value(mut self, value: U256) -> Self {
@@ -125,6 +173,21 @@ impl<T: Transport, R: Tokenize> MethodBuilder<T, R> {
         self
     }
 
+    /// Specify the transport used for waiting for confirmations with a
+    /// `newHeads` subscription instead of polling.
+    pub fn subscription_transport(mut self, transport: DynTransport) -> Self {
+        self.tx = self.tx.subscription_transport(transport);
+        self
+    }
+
+    /// Pins calls to the specified block, so that they are executed on its hash
+    /// and fail if the block was reorged out of the chain. Transactions are not
+    /// affected.
+    pub fn pinned_block(mut self, block: PinnedBlock) -> Self {
+        self.pinned_block = Some(block);
+        self
+    }
+
     /// Extract inner `TransactionBuilder` from this `SendBuilder`. This exposes
     /// `TransactionBuilder` only APIs.
     pub fn into_inner(self) -> TransactionBuilder<T> {
@@ -134,7 +197,13 @@ impl<T: Transport, R: Tokenize> MethodBuilder<T, R> {
     /// Sign (if required) and send the method call transaction.
     pub async fn send(self) -> Result<TransactionResult, MethodError> {
         let Self { function, tx, .. } = self;
-        tx.send()
+        let span = trace::span!(
+            "send",
+            address = ?tx.to,
+            method = %function.name,
+            function = %function.signature(),
+        );
+        trace::instrument(span, tx.send())
             .await
             .map_err(|err| MethodError::new(&function, err))
     }
@@ -163,14 +232,21 @@ pub struct ViewMethodBuilder<T: Transport, R: Tokenize> {
     pub m: MethodBuilder<T, R>,
     /// optional block number
     pub block: Option<BlockId>,
+    /// whether a block specified by hash must be part of the canonical chain
+    pub require_canonical: bool,
+    /// optional state overrides for the call
+    pub state_overrides: Option<StateOverrides>,
 }
 
 impl<T: Transport, R: Tokenize> ViewMethodBuilder<T, R> {
     /// Create a new `ViewMethodBuilder` by demoting a `MethodBuilder`.
     pub fn from_method(method: MethodBuilder<T, R>) -> Self {
+        let pinned_block = method.pinned_block;
         ViewMethodBuilder {
             m: method,
-            block: None,
+            block: pinned_block.map(|block| BlockId::Hash(block.hash)),
+            require_canonical: pinned_block.is_some(),
+            state_overrides: None,
         }
     }
 
@@ -231,16 +307,41 @@ impl<T: Transport, R: Tokenize> ViewMethodBuilder<T, R> {
         self.block = Some(value);
         self
     }
+
+    /// Specify whether a block specified by hash must be part of the canonical
+    /// chain. If set, the call fails instead of reading the state of a block
+    /// that was reorged out of the chain (EIP-1898).
+    pub fn require_canonical(mut self, value: bool) -> Self {
+        self.require_canonical = value;
+        self
+    }
+
+    /// Specify state overrides for the call, allowing to simulate the call as
+    /// if accounts had a different balance, nonce, code or storage. Note that
+    /// state overrides are not supported by all nodes.
+    pub fn state_overrides(mut self, value: StateOverrides) -> Self {
+        self.state_overrides = Some(value);
+        self
+    }
 }
 
 impl<T: Transport, R: Tokenize> ViewMethodBuilder<T, R> {
     /// Call a contract method. Contract calls do not modify the blockchain and
     /// as such do not require gas or signing.
     pub async fn call(self) -> Result<R, MethodError> {
-        let eth = &self.m.web3.eth();
-        let (function, call, block) = self.decompose();
-        let future = eth.call(call, block);
-        convert_response::<_, R>(future, function).await
+        let span = trace::span!(
+            "call",
+            address = ?self.m.tx.to,
+            method = %self.m.function.name,
+            function = %self.m.function.signature(),
+            block = ?self.block,
+        );
+        let web3 = self.m.web3.clone();
+        let require_canonical = self.require_canonical;
+        let (function, call, block, overrides) = self.decompose();
+        let future =
+            state_override::call(&web3, call, block, require_canonical, overrides.as_ref());
+        trace::instrument(span, convert_response::<_, R>(future, function)).await
     }
 
     /// Adds this view method to a batch. Allows execution with other contract calls in one roundtrip
@@ -250,12 +351,65 @@ impl<T: Transport, R: Tokenize> ViewMethodBuilder<T, R> {
         self,
         batch: &mut CallBatch<B>,
     ) -> impl std::future::Future<Output = Result<R, MethodError>> {
-        let (function, call, block) = self.decompose();
-        let future = batch.push(call, block);
+        let require_canonical = self.require_canonical;
+        let (function, call, block, overrides) = self.decompose();
+        let future = batch.push_request(EthCall {
+            call,
+            block,
+            require_canonical,
+            overrides,
+        });
         async move { convert_response::<_, R>(future, function).await }
     }
 
-    fn decompose(self) -> (Function, CallRequest, Option<BlockId>) {
+    /// Adds this view method to a Multicall3 batch, allowing the call to fail
+    /// without failing the other calls of the batch. The returned future only
+    /// resolves once `batch` is executed. The sender, gas, value and state
+    /// overrides of this method are ignored in favour of the batch's
+    /// `aggregate3` call. If a block is specified for this method, the call
+    /// fails unless the batch executes on the same block with the same
+    /// `requireCanonical` flag.
+    pub fn multicall<B: Transport>(
+        self,
+        batch: &mut MulticallBatch<B>,
+    ) -> impl std::future::Future<Output = Result<R, MethodError>>
+    where
+        R: Send + 'static,
+    {
+        self.multicall_with(batch, true)
+    }
+
+    /// Adds this view method to a Multicall3 batch, specifying whether or not
+    /// the call is allowed to fail. If a call that is not allowed to fail
+    /// reverts, all calls that were sent in the same `aggregate3` call fail.
+    pub fn multicall_with<B: Transport>(
+        self,
+        batch: &mut MulticallBatch<B>,
+        allow_failure: bool,
+    ) -> impl std::future::Future<Output = Result<R, MethodError>>
+    where
+        R: Send + 'static,
+    {
+        let target = self.m.tx.to.unwrap_or_default();
+        let data = self.m.tx.data.unwrap_or_default();
+        batch.push(
+            self.m.function,
+            target,
+            data,
+            self.block,
+            self.require_canonical,
+            allow_failure,
+        )
+    }
+
+    fn decompose(
+        self,
+    ) -> (
+        Function,
+        CallRequest,
+        Option<BlockId>,
+        Option<StateOverrides>,
+    ) {
         let resolved_gas_price = self
             .m
             .tx
@@ -277,6 +431,7 @@ impl<T: Transport, R: Tokenize> ViewMethodBuilder<T, R> {
                 max_priority_fee_per_gas: resolved_gas_price.max_priority_fee_per_gas,
             },
             self.block,
+            self.state_overrides,
         )
     }
 }
@@ -291,9 +446,17 @@ async fn convert_response<
     let bytes = future
         .await
         .map_err(|err| MethodError::new(&function, err))?;
+    decode_response(&function, &bytes.0)
+}
+
+/// Decodes the ABI encoded return data of a contract call.
+pub(crate) fn decode_response<R: Tokenize>(
+    function: &Function,
+    bytes: &[u8],
+) -> Result<R, MethodError> {
     let tokens = function
-        .decode_output(&bytes.0)
-        .map_err(|err| MethodError::new(&function, err))?;
+        .decode_output(bytes)
+        .map_err(|err| MethodError::new(function, err))?;
     let token = match tokens.len() {
         0 => Token::Tuple(Vec::new()),
         1 => tokens.into_iter().next().unwrap(),
@@ -302,7 +465,7 @@ async fn convert_response<
         // accept this too.
         _ => Token::Tuple(tokens),
     };
-    let result = R::from_token(token).map_err(|err| MethodError::new(&function, err))?;
+    let result = R::from_token(token).map_err(|err| MethodError::new(function, err))?;
     Ok(result)
 }
 
@@ -311,7 +474,7 @@ mod tests {
     use super::*;
     use crate::test::prelude::*;
     use ethcontract_common::abi::{Param, ParamType};
-    use web3::types::AccessListItem;
+    use web3::types::{AccessListItem, H256};
 
     fn test_abi_function() -> (Function, Bytes) {
         #[allow(deprecated)]
@@ -404,6 +567,49 @@ mod tests {
         transport.assert_no_more_requests();
     }
 
+    #[test]
+    fn view_method_call_with_state_overrides() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let address = addr!("0x0123456789012345678901234567890123456789");
+        let (function, data) = test_abi_function();
+        let tx = MethodBuilder::<_, U256>::new(web3, function, address, data.clone())
+            .view()
+            .state_overrides(
+                StateOverrides::new()
+                    .code(address, Bytes(vec![0x60, 0x00]))
+                    .state_diff(address, H256::zero(), H256::from_low_u64_be(1)),
+            );
+
+        transport.add_response(json!(
+            "0x000000000000000000000000000000000000000000000000000000000000002a"
+        ));
+        let result = tx.call().immediate().expect("call error");
+
+        assert_eq!(result, 42.into());
+        transport.assert_request(
+            "eth_call",
+            &[
+                json!({
+                    "to": address,
+                    "data": data,
+                }),
+                json!("latest"),
+                json!({
+                    "0x0123456789012345678901234567890123456789": {
+                        "code": "0x6000",
+                        "stateDiff": {
+                            "0x0000000000000000000000000000000000000000000000000000000000000000":
+                                "0x0000000000000000000000000000000000000000000000000000000000000001",
+                        },
+                    },
+                }),
+            ],
+        );
+        transport.assert_no_more_requests();
+    }
+
     #[test]
     fn method_to_view_method_preserves_options() {
         let mut transport = TestTransport::new();
@@ -443,16 +649,19 @@ mod tests {
         let address = addr!("0x0123456789012345678901234567890123456789");
         let (function, data) = test_abi_function();
         let tx = MethodBuilder::<_, U256>::new(web3, function, address, data)
-            .with_defaults(&MethodDefaults {
-                from: Some(Account::Local(from, None)),
-                gas: Some(1.into()),
-                gas_price: Some(2.0.into()),
-            })
+            .with_defaults(
+                &MethodDefaults::default()
+                    .from(Account::Local(from, None))
+                    .gas(1.into())
+                    .gas_price(2.0.into())
+                    .gas_policy(GasPolicy::default().multiplier(1.2)),
+            )
             .into_inner();
 
         assert_eq!(tx.from.map(|a| a.address()), Some(from));
         assert_eq!(tx.gas, Some(1.into()));
         assert_eq!(tx.gas_price, Some(2.0.into()));
+        assert_eq!(tx.gas_policy, Some(GasPolicy::default().multiplier(1.2)));
         transport.assert_no_more_requests();
     }
 }
@@ -7,7 +7,7 @@ mod nethermind;
 mod parity;
 pub(crate) mod revert;
 
-use crate::transaction::TransactionResult;
+use crate::transaction::{DecodeError, TransactionResult};
 use ethcontract_common::abi::{Error as AbiError, Event, Function};
 use ethcontract_common::abiext::EventExt;
 pub use ethcontract_common::errors::*;
@@ -16,7 +16,7 @@ use std::num::ParseIntError;
 use thiserror::Error;
 use uint::FromDecStrErr;
 use web3::error::Error as Web3Error;
-use web3::types::{Log, TransactionReceipt, H256};
+use web3::types::{BlockId, Log, TransactionReceipt, H256, U256};
 
 /// Error that can occur while locating a deployed contract.
 #[derive(Debug, Error)]
@@ -94,6 +94,24 @@ pub enum ExecutionError {
     #[error("missing transaction {0:?}")]
     MissingTransaction(H256),
 
+    /// Failed to find a block, or the block is still pending.
+    #[error("unknown block {0:?}")]
+    UnknownBlock(BlockId),
+
+    /// A block that was queried at was reorged out of the canonical chain.
+    #[error("block {0:?} is no longer part of the canonical chain")]
+    NonCanonicalBlock(H256),
+
+    /// A call for a specific block was added to a Multicall3 batch that
+    /// executes its calls on a different block.
+    #[error("call at block {call:?} does not match multicall batch block {batch:?}")]
+    MulticallBlockMismatch {
+        /// The block of the call.
+        call: BlockId,
+        /// The block of the batch.
+        batch: Option<BlockId>,
+    },
+
     /// Failed to get a block for a pending transaction that has not yet been
     /// mined.
     #[error("pending transaction {0:?}, not yet part of a block")]
@@ -114,6 +132,41 @@ pub enum ExecutionError {
     /// Unexpected transaction hash
     #[error("transaction hash returned from node when sending raw transaction does not match expected hash")]
     UnexpectedTransactionHash,
+
+    /// An error occured while hashing EIP-712 typed data.
+    #[error("typed data error: {0}")]
+    TypedData(#[from] TypedDataError),
+
+    /// The maximum fee of a transaction exceeds the fee budget of its gas
+    /// policy.
+    #[error("transaction fee {fee} exceeds fee budget {budget}")]
+    FeeBudgetExceeded {
+        /// The maximum fee the transaction can pay, computed as its gas limit
+        /// times its maximum fee per gas.
+        fee: U256,
+        /// The fee budget.
+        budget: U256,
+    },
+
+    /// A signed raw transaction could not be decoded.
+    #[error("raw transaction decode error: {0}")]
+    Decode(#[from] DecodeError),
+
+    /// A transaction parameter was not specified and could not be queried
+    /// from the node, either because the transaction is being built in strict
+    /// offline mode or because the sender is not known.
+    #[error("missing transaction parameter: {0}")]
+    MissingTransactionParameter(&'static str),
+
+    /// An attempt was made to build a transaction in strict offline mode with
+    /// an account that requires the node for signing.
+    #[error("account cannot sign transactions offline")]
+    OfflineSigningUnsupported,
+
+    /// The account's signing strategy does not support the requested kind of
+    /// signature.
+    #[error("signing method not supported by account")]
+    UnsupportedSigningMethod,
 }
 
 impl From<Web3Error> for ExecutionError {
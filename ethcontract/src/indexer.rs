@@ -0,0 +1,740 @@
+//! Module implementing a resumable event indexer. The indexer backfills past
+//! logs from a start block, then follows the chain head with a confirmation
+//! depth, emitting logs from orphaned blocks again marked as removed when a
+//! reorg is detected. Progress is persisted in a [`CheckpointStore`] so that
+//! indexing can resume where it left off.
+//!
+//! Checkpoints are saved once the logs of the blocks they cover were consumed
+//! from the indexer stream, so logs are delivered at least once: after a
+//! restart, logs after the last saved checkpoint are emitted again.
+
+mod checkpoint;
+
+pub use self::checkpoint::{
+    Checkpoint, CheckpointError, CheckpointStore, JsonFileCheckpointStore, MemoryCheckpointStore,
+};
+use crate::contract::{block_number_from_transaction_hash, Event, ParseLog, StreamEvent};
+use crate::errors::ExecutionError;
+use crate::log::{
+    past_logs_range, AdaptivePaging, AddressSet, LogFilterBuilder, DEFAULT_BLOCK_PAGE_SIZE,
+    DEFAULT_POLL_INTERVAL, DEFAULT_REORG_DEPTH,
+};
+use ethcontract_common::DeploymentInformation;
+use futures::future;
+use futures::stream::{self, Stream, TryStreamExt};
+use futures_timer::Delay;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::time::Duration;
+use thiserror::Error;
+use web3::api::Web3;
+use web3::error::Error as Web3Error;
+use web3::types::{BlockId, BlockNumber, FilterBuilder, Log, H256};
+use web3::Transport;
+
+/// The default number of blocks that need to be mined on top of a block
+/// before the indexer processes it.
+pub const DEFAULT_CONFIRMATIONS: u64 = 12;
+
+/// Error that can occur while indexing.
+#[derive(Debug, Error)]
+pub enum IndexerError {
+    /// An error occurred querying the node or decoding events.
+    #[error("indexer execution error: {0}")]
+    Execution(#[from] ExecutionError),
+
+    /// An error occurred loading or saving a checkpoint.
+    #[error("indexer checkpoint error: {0}")]
+    Checkpoint(#[from] CheckpointError),
+}
+
+impl From<Web3Error> for IndexerError {
+    fn from(err: Web3Error) -> Self {
+        IndexerError::Execution(err.into())
+    }
+}
+
+/// A resumable indexer for logs matching a log filter.
+///
+/// Indexing starts at the filter's `from_block`, or at the first block that
+/// gets confirmed after the indexer started if it is not specified, and stops
+/// after the filter's `to_block` if it is a block number. The page size and
+/// polling interval of the filter are used for querying logs, and the
+/// addresses of its address set are read again for every queried page.
+#[derive(Debug)]
+#[must_use = "indexers do nothing unless you stream them"]
+pub struct LogIndexer<T: Transport, S: CheckpointStore> {
+    /// The log filter for the indexed logs.
+    pub filter: LogFilterBuilder<T>,
+    /// The store used for persisting indexing progress.
+    pub store: S,
+    /// The number of blocks that need to be mined on top of a block before
+    /// its logs are indexed.
+    pub confirmations: u64,
+    /// The number of blocks below the last indexed block that are tracked for
+    /// detecting reorgs. Logs from orphaned blocks deeper than this are not
+    /// emitted as removed.
+    pub reorg_depth: u64,
+    /// The contract deployment, used as the start block when indexing
+    /// contract events from the earliest block.
+    pub deployment_information: Option<DeploymentInformation>,
+}
+
+impl<T: Transport, S: CheckpointStore> LogIndexer<T, S> {
+    /// Creates a new indexer for a log filter, persisting its progress in the
+    /// specified checkpoint store.
+    pub fn new(filter: LogFilterBuilder<T>, store: S) -> Self {
+        LogIndexer {
+            filter,
+            store,
+            confirmations: DEFAULT_CONFIRMATIONS,
+            reorg_depth: DEFAULT_REORG_DEPTH,
+            deployment_information: None,
+        }
+    }
+
+    /// Sets the number of blocks that need to be mined on top of a block
+    /// before its logs are indexed.
+    pub fn confirmations(mut self, value: u64) -> Self {
+        self.confirmations = value;
+        self
+    }
+
+    /// Sets the number of blocks below the last indexed block that are
+    /// tracked for detecting reorgs.
+    pub fn reorg_depth(mut self, value: u64) -> Self {
+        self.reorg_depth = value;
+        self
+    }
+
+    /// Sets the contract deployment, used as the start block when indexing
+    /// from the earliest block.
+    pub fn deployment_information(mut self, value: Option<DeploymentInformation>) -> Self {
+        self.deployment_information = value;
+        self
+    }
+
+    /// Creates a stream of indexed logs. Logs from blocks that were reorged
+    /// out of the chain are emitted again, in reverse order, with `removed`
+    /// set to `true`.
+    pub fn stream(self) -> impl Stream<Item = Result<Log, IndexerError>> {
+        let mut filter = self.filter;
+        let web3 = filter.web3.clone();
+        let poll_interval = filter.poll_interval.unwrap_or(DEFAULT_POLL_INTERVAL);
+        let page_size = filter
+            .block_page_size
+            .map_or(DEFAULT_BLOCK_PAGE_SIZE, |size| size.get());
+        let start_block = filter.from_block.take();
+        let end_block = match filter.to_block.take() {
+            Some(BlockNumber::Number(number)) => Some(number.as_u64()),
+            Some(BlockNumber::Earliest) => Some(0),
+            _ => None,
+        };
+        let adaptive_paging = filter.adaptive_paging.clone();
+        let address_set = filter.address_set.clone();
+        filter.block_hash = None;
+        filter.limit = None;
+
+        let state = IndexerState {
+            web3,
+            filter: filter.into_filter(),
+            address_set,
+            adaptive_paging,
+            store: self.store,
+            confirmations: self.confirmations,
+            reorg_depth: self.reorg_depth,
+            page_size,
+            poll_interval,
+            start_block,
+            end_block,
+            deployment_information: self.deployment_information,
+            next_block: None,
+            cursor: None,
+            pending: None,
+            caught_up: false,
+            blocks: BTreeMap::new(),
+            logs: BTreeMap::new(),
+        };
+        stream::try_unfold(state, IndexerState::next)
+            .map_ok(|logs| stream::iter(logs.into_iter().map(Ok)))
+            .try_flatten()
+    }
+}
+
+/// A resumable indexer for typed contract events.
+#[derive(Debug)]
+#[must_use = "indexers do nothing unless you stream them"]
+pub struct EventIndexer<T: Transport, E: ParseLog, S: CheckpointStore> {
+    /// The underlying log indexer.
+    pub inner: LogIndexer<T, S>,
+    _events: PhantomData<E>,
+}
+
+impl<T: Transport, E: ParseLog, S: CheckpointStore> EventIndexer<T, E, S> {
+    /// Creates a new event indexer from a log indexer.
+    pub fn new(inner: LogIndexer<T, S>) -> Self {
+        EventIndexer {
+            inner,
+            _events: PhantomData,
+        }
+    }
+
+    /// Sets the number of blocks that need to be mined on top of a block
+    /// before its events are indexed.
+    pub fn confirmations(mut self, value: u64) -> Self {
+        self.inner = self.inner.confirmations(value);
+        self
+    }
+
+    /// Sets the number of blocks below the last indexed block that are
+    /// tracked for detecting reorgs.
+    pub fn reorg_depth(mut self, value: u64) -> Self {
+        self.inner = self.inner.reorg_depth(value);
+        self
+    }
+
+    /// Creates a stream of indexed events. Events from blocks that were
+    /// reorged out of the chain are emitted again, in reverse order, as
+    /// removed events.
+    pub fn stream(self) -> impl Stream<Item = Result<StreamEvent<E>, IndexerError>> {
+        self.inner.stream().and_then(|log| {
+            future::ready(Event::from_streamed_log(log, E::parse_log).map_err(IndexerError::from))
+        })
+    }
+}
+
+/// Internal unfold context for the indexer stream.
+struct IndexerState<T: Transport, S: CheckpointStore> {
+    web3: Web3<T>,
+    /// The web3 filter used for querying logs, without a block range.
+    filter: FilterBuilder,
+    /// The updatable set of addresses to filter logs for, replacing the
+    /// filter's addresses for each queried page.
+    address_set: Option<AddressSet>,
+    adaptive_paging: AdaptivePaging,
+    store: S,
+    confirmations: u64,
+    reorg_depth: u64,
+    page_size: u64,
+    poll_interval: Duration,
+
+    start_block: Option<BlockNumber>,
+    end_block: Option<u64>,
+    deployment_information: Option<DeploymentInformation>,
+
+    /// The next block to index, or `None` if indexing has not started yet.
+    next_block: Option<u64>,
+    /// The last indexed block.
+    cursor: Option<Checkpoint>,
+    /// A checkpoint to save once the previously emitted logs were consumed.
+    pending: Option<Checkpoint>,
+    /// Whether all confirmed blocks were indexed.
+    caught_up: bool,
+    /// The hashes of recently indexed blocks.
+    blocks: BTreeMap<u64, H256>,
+    /// The logs that were emitted for recently indexed blocks.
+    logs: BTreeMap<u64, Vec<Log>>,
+}
+
+impl<T: Transport, S: CheckpointStore> IndexerState<T, S> {
+    async fn next(mut self) -> Result<Option<(Vec<Log>, Self)>, IndexerError> {
+        loop {
+            if let Some(checkpoint) = self.pending.take() {
+                self.store.save(&checkpoint)?;
+            }
+            if let (Some(next_block), Some(end_block)) = (self.next_block, self.end_block) {
+                if next_block > end_block {
+                    return Ok(None);
+                }
+            }
+            if self.caught_up && !self.poll_interval.is_zero() {
+                Delay::new(self.poll_interval).await;
+            }
+
+            let latest_block = self.web3.eth().block_number().await?.as_u64();
+            let safe_block = latest_block.saturating_sub(self.confirmations);
+            let next_block = match self.next_block {
+                Some(next_block) => next_block,
+                None => self.init(safe_block).await?,
+            };
+
+            if let Some(cursor) = self.cursor {
+                if self.block_hash(cursor.block_number).await? != Some(cursor.block_hash) {
+                    let removed = self.rewind(cursor).await?;
+                    if !removed.is_empty() {
+                        return Ok(Some((removed, self)));
+                    }
+                    continue;
+                }
+            }
+
+            let end_block = self.end_block.map_or(safe_block, |end| end.min(safe_block));
+            if next_block > end_block {
+                self.caught_up = true;
+                continue;
+            }
+
+            let to_block = cmp::min(end_block, next_block + self.page_size - 1);
+            let to_hash = self
+                .block_hash(to_block)
+                .await?
+                .ok_or_else(|| ExecutionError::UnknownBlock(BlockId::Number(to_block.into())))?;
+            let logs = past_logs_range(
+                self.web3.clone(),
+                self.filter.clone(),
+                self.address_set.clone(),
+                self.page_size,
+                self.adaptive_paging.clone(),
+                next_block,
+                to_block,
+            )
+            .try_concat()
+            .await?;
+
+            // NOTE: If the block was reorged while querying the page, then
+            //   some of the logs may be from the orphaned chain, so query the
+            //   page again instead of checkpointing them under the new hash.
+            if self.block_hash(to_block).await? != Some(to_hash) {
+                continue;
+            }
+
+            for log in &logs {
+                if let (Some(number), Some(hash)) = (log.block_number, log.block_hash) {
+                    self.blocks.insert(number.as_u64(), hash);
+                    self.logs
+                        .entry(number.as_u64())
+                        .or_default()
+                        .push(log.clone());
+                }
+            }
+            self.blocks.insert(to_block, to_hash);
+
+            let checkpoint = Checkpoint {
+                block_number: to_block,
+                block_hash: to_hash,
+            };
+            self.cursor = Some(checkpoint);
+            self.pending = Some(checkpoint);
+            self.next_block = Some(to_block + 1);
+            self.caught_up = to_block >= safe_block;
+
+            let oldest_block = to_block.saturating_sub(self.reorg_depth);
+            self.blocks = self.blocks.split_off(&oldest_block);
+            self.logs = self.logs.split_off(&oldest_block);
+
+            if !logs.is_empty() {
+                return Ok(Some((logs, self)));
+            }
+        }
+    }
+
+    /// Loads the checkpoint to resume from, and returns the first block to
+    /// index.
+    async fn init(&mut self, safe_block: u64) -> Result<u64, IndexerError> {
+        let next_block = match self.store.load()? {
+            Some(checkpoint) => {
+                self.cursor = Some(checkpoint);
+                self.blocks
+                    .insert(checkpoint.block_number, checkpoint.block_hash);
+                checkpoint.block_number + 1
+            }
+            None => {
+                let deployment_block = match self.deployment_information {
+                    Some(DeploymentInformation::BlockNumber(block)) => Some(block),
+                    Some(DeploymentInformation::TransactionHash(hash)) => {
+                        Some(block_number_from_transaction_hash(self.web3.clone(), hash).await?)
+                    }
+                    None => None,
+                };
+                match (self.start_block, deployment_block) {
+                    (Some(BlockNumber::Earliest), deployment_block) => {
+                        deployment_block.unwrap_or(0)
+                    }
+                    (Some(BlockNumber::Number(number)), deployment_block) => {
+                        cmp::max(number.as_u64(), deployment_block.unwrap_or(0))
+                    }
+                    _ => safe_block + 1,
+                }
+            }
+        };
+        self.next_block = Some(next_block);
+        Ok(next_block)
+    }
+
+    /// Rewinds the indexer to the most recent indexed block that is still
+    /// part of the chain, and returns the logs that were emitted for orphaned
+    /// blocks, marked as removed and in reverse order.
+    async fn rewind(&mut self, cursor: Checkpoint) -> Result<Vec<Log>, IndexerError> {
+        let mut ancestor = None;
+        let tracked = self
+            .blocks
+            .range(..cursor.block_number)
+            .rev()
+            .map(|(&number, &hash)| (number, hash))
+            .collect::<Vec<_>>();
+        for (number, hash) in tracked {
+            if self.block_hash(number).await? == Some(hash) {
+                ancestor = Some(Checkpoint {
+                    block_number: number,
+                    block_hash: hash,
+                });
+                break;
+            }
+        }
+
+        // NOTE: If none of the tracked blocks are part of the chain anymore,
+        //   then the reorg is deeper than the reorg depth, and indexing
+        //   resumes from the oldest tracked block.
+        let first_orphaned = match ancestor {
+            Some(ancestor) => ancestor.block_number + 1,
+            None => *self.blocks.keys().next().unwrap_or(&cursor.block_number),
+        };
+
+        self.blocks.split_off(&first_orphaned);
+        let removed = self
+            .logs
+            .split_off(&first_orphaned)
+            .into_values()
+            .rev()
+            .flat_map(|logs| logs.into_iter().rev())
+            .map(|log| Log {
+                removed: Some(true),
+                ..log
+            })
+            .collect();
+
+        self.cursor = ancestor;
+        self.pending = ancestor;
+        self.next_block = Some(first_orphaned);
+        self.caught_up = false;
+
+        Ok(removed)
+    }
+
+    /// Retrieves the hash of a block by number.
+    async fn block_hash(&self, number: u64) -> Result<Option<H256>, Web3Error> {
+        let block = self
+            .web3
+            .eth()
+            .block(BlockId::Number(number.into()))
+            .await?;
+        Ok(block.and_then(|block| block.hash))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contract::{AllEventsBuilder, EventStatus, RawLog};
+    use crate::test::fixtures::*;
+    use crate::test::prelude::*;
+    use futures::stream::StreamExt;
+    use web3::types::Address;
+
+    #[test]
+    fn backfills_confirmed_blocks_in_pages() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+        let store = MemoryCheckpointStore::new();
+
+        // blocks 1 and 2
+        transport.add_response(json!("0x4"));
+        transport.add_response(block(2, 0x02));
+        transport.add_response(json!([log(1, 0x01)]));
+        transport.add_response(block(2, 0x02));
+        // block 3, checking that block 2 is still part of the chain
+        transport.add_response(json!("0x4"));
+        transport.add_response(block(2, 0x02));
+        transport.add_response(block(3, 0x03));
+        transport.add_response(json!([log(3, 0x03)]));
+        transport.add_response(block(3, 0x03));
+
+        let logs = LogFilterBuilder::new(web3)
+            .from_block(1.into())
+            .to_block(3.into())
+            .block_page_size(2)
+            .indexer(store.clone())
+            .confirmations(1)
+            .stream()
+            .try_collect::<Vec<_>>()
+            .immediate()
+            .unwrap();
+
+        assert_eq!(
+            logs.iter().map(summary).collect::<Vec<_>>(),
+            [
+                (1, H256::repeat_byte(0x01), false),
+                (3, H256::repeat_byte(0x03), false),
+            ]
+        );
+        assert_eq!(store.checkpoint(), Some(checkpoint(3, 0x03)));
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x2"), json!(false)]);
+        transport.assert_request(
+            "eth_getLogs",
+            &[json!({ "fromBlock": "0x1", "toBlock": "0x2" })],
+        );
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x2"), json!(false)]);
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x2"), json!(false)]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x3"), json!(false)]);
+        transport.assert_request(
+            "eth_getLogs",
+            &[json!({ "fromBlock": "0x3", "toBlock": "0x3" })],
+        );
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x3"), json!(false)]);
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn resumes_from_checkpoint_and_handles_reorgs() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+        let store = MemoryCheckpointStore::with_checkpoint(checkpoint(2, 0x02));
+
+        // resume after block 2
+        transport.add_response(json!("0x5"));
+        transport.add_response(block(2, 0x02));
+        transport.add_response(block(4, 0x04));
+        transport.add_response(json!([log(3, 0x03), log(4, 0x04)]));
+        transport.add_response(block(4, 0x04));
+        // blocks 3 and 4 were reorged out of the chain
+        transport.add_response(json!("0x6"));
+        transport.add_response(block(4, 0xb4));
+        transport.add_response(block(3, 0xb3));
+        transport.add_response(block(2, 0x02));
+        // re-index blocks from the common ancestor
+        transport.add_response(json!("0x6"));
+        transport.add_response(block(2, 0x02));
+        transport.add_response(block(5, 0xb5));
+        transport.add_response(json!([log(3, 0xb3)]));
+        transport.add_response(block(5, 0xb5));
+
+        let logs = LogFilterBuilder::new(web3)
+            .indexer(store.clone())
+            .confirmations(1)
+            .stream()
+            .take(5)
+            .try_collect::<Vec<_>>()
+            .immediate()
+            .unwrap();
+
+        assert_eq!(
+            logs.iter().map(summary).collect::<Vec<_>>(),
+            [
+                (3, H256::repeat_byte(0x03), false),
+                (4, H256::repeat_byte(0x04), false),
+                (4, H256::repeat_byte(0x04), true),
+                (3, H256::repeat_byte(0x03), true),
+                (3, H256::repeat_byte(0xb3), false),
+            ]
+        );
+        // NOTE: The checkpoint for block 5 is only saved once the next log is
+        //   requested from the stream.
+        assert_eq!(store.checkpoint(), Some(checkpoint(2, 0x02)));
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x2"), json!(false)]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x4"), json!(false)]);
+        transport.assert_request(
+            "eth_getLogs",
+            &[json!({ "fromBlock": "0x3", "toBlock": "0x4" })],
+        );
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x4"), json!(false)]);
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x4"), json!(false)]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x3"), json!(false)]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x2"), json!(false)]);
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x2"), json!(false)]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x5"), json!(false)]);
+        transport.assert_request(
+            "eth_getLogs",
+            &[json!({ "fromBlock": "0x3", "toBlock": "0x5" })],
+        );
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x5"), json!(false)]);
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn queries_page_again_when_reorged_while_querying_logs() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+        let store = MemoryCheckpointStore::new();
+
+        // block 2 is reorged while querying the logs of blocks 1 and 2
+        transport.add_response(json!("0x3"));
+        transport.add_response(block(2, 0x02));
+        transport.add_response(json!([log(2, 0x02)]));
+        transport.add_response(block(2, 0xb2));
+        // the page is queried again on the new chain
+        transport.add_response(json!("0x3"));
+        transport.add_response(block(2, 0xb2));
+        transport.add_response(json!([log(2, 0xb2)]));
+        transport.add_response(block(2, 0xb2));
+
+        let logs = LogFilterBuilder::new(web3)
+            .from_block(1.into())
+            .to_block(2.into())
+            .indexer(store.clone())
+            .confirmations(1)
+            .stream()
+            .try_collect::<Vec<_>>()
+            .immediate()
+            .unwrap();
+
+        assert_eq!(
+            logs.iter().map(summary).collect::<Vec<_>>(),
+            [(2, H256::repeat_byte(0xb2), false)]
+        );
+        assert_eq!(store.checkpoint(), Some(checkpoint(2, 0xb2)));
+        for _ in 0..2 {
+            transport.assert_request("eth_blockNumber", &[]);
+            transport.assert_request("eth_getBlockByNumber", &[json!("0x2"), json!(false)]);
+            transport.assert_request(
+                "eth_getLogs",
+                &[json!({ "fromBlock": "0x1", "toBlock": "0x2" })],
+            );
+            transport.assert_request("eth_getBlockByNumber", &[json!("0x2"), json!(false)]);
+        }
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn indexes_events_from_deployment_block() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+        let address = Address::repeat_byte(0x42);
+
+        transport.add_response(json!("0x10"));
+        transport.add_response(block(4, 0x04));
+        transport.add_response(json!([log(3, 0x03)]));
+        transport.add_response(block(4, 0x04));
+
+        let event = AllEventsBuilder::<_, RawLog>::new(
+            web3,
+            address,
+            Some(DeploymentInformation::BlockNumber(3)),
+        )
+        .from_block(BlockNumber::Earliest)
+        .to_block(4.into())
+        .indexer(MemoryCheckpointStore::new())
+        .confirmations(0)
+        .stream()
+        .boxed()
+        .next()
+        .immediate()
+        .unwrap()
+        .unwrap();
+
+        assert!(matches!(event.data, EventStatus::Added(_)));
+        assert_eq!(event.meta.unwrap().block_number, 3);
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x4"), json!(false)]);
+        transport.assert_request(
+            "eth_getLogs",
+            &[json!({ "address": address, "fromBlock": "0x3", "toBlock": "0x4" })],
+        );
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x4"), json!(false)]);
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn does_not_query_logs_for_empty_address_set() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+        let store = MemoryCheckpointStore::new();
+
+        transport.add_response(json!("0x4"));
+        transport.add_response(block(2, 0x02));
+        transport.add_response(block(2, 0x02));
+        transport.add_response(json!("0x4"));
+        transport.add_response(block(2, 0x02));
+        transport.add_response(block(3, 0x03));
+        transport.add_response(block(3, 0x03));
+
+        let logs = LogFilterBuilder::new(web3)
+            .from_block(1.into())
+            .to_block(3.into())
+            .address_set(AddressSet::new())
+            .block_page_size(2)
+            .indexer(store.clone())
+            .confirmations(1)
+            .stream()
+            .try_collect::<Vec<_>>()
+            .immediate()
+            .unwrap();
+
+        assert!(logs.is_empty());
+        assert_eq!(store.checkpoint(), Some(checkpoint(3, 0x03)));
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x2"), json!(false)]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x2"), json!(false)]);
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x2"), json!(false)]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x3"), json!(false)]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x3"), json!(false)]);
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn uses_current_address_set_for_each_page() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+        let addresses = AddressSet::from_iter([Address::repeat_byte(1)]);
+
+        transport.add_response(json!("0x4"));
+        transport.add_response(block(2, 0x02));
+        transport.add_response(json!([log(1, 0x01)]));
+        transport.add_response(block(2, 0x02));
+        transport.add_response(json!("0x4"));
+        transport.add_response(block(2, 0x02));
+        transport.add_response(block(3, 0x03));
+        transport.add_response(json!([log(3, 0x03)]));
+        transport.add_response(block(3, 0x03));
+
+        let mut stream = LogFilterBuilder::new(web3)
+            .from_block(1.into())
+            .to_block(3.into())
+            .address_set(addresses.clone())
+            .block_page_size(2)
+            .indexer(MemoryCheckpointStore::new())
+            .confirmations(1)
+            .stream()
+            .boxed();
+
+        let first = stream.next().immediate().unwrap().unwrap();
+        addresses.insert(Address::repeat_byte(2));
+        let rest = stream.try_collect::<Vec<_>>().immediate().unwrap();
+
+        assert_eq!(summary(&first), (1, H256::repeat_byte(0x01), false));
+        assert_eq!(
+            rest.iter().map(summary).collect::<Vec<_>>(),
+            [(3, H256::repeat_byte(0x03), false)]
+        );
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x2"), json!(false)]);
+        transport.assert_request(
+            "eth_getLogs",
+            &[json!({
+                "address": Address::repeat_byte(1),
+                "fromBlock": "0x1",
+                "toBlock": "0x2",
+            })],
+        );
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x2"), json!(false)]);
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x2"), json!(false)]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x3"), json!(false)]);
+        transport.assert_request(
+            "eth_getLogs",
+            &[json!({
+                "address": [Address::repeat_byte(1), Address::repeat_byte(2)],
+                "fromBlock": "0x3",
+                "toBlock": "0x3",
+            })],
+        );
+        transport.assert_request("eth_getBlockByNumber", &[json!("0x3"), json!(false)]);
+        transport.assert_no_more_requests();
+    }
+}
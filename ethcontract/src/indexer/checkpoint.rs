@@ -0,0 +1,165 @@
+//! Module containing the checkpoint stores used by indexers for persisting
+//! their progress.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+use web3::types::H256;
+
+/// The position of an indexer in the chain. All events up to and including
+/// the checkpoint block were processed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Checkpoint {
+    /// The number of the last processed block.
+    pub block_number: u64,
+    /// The hash of the last processed block, used for detecting whether the
+    /// block was reorged out of the chain when resuming.
+    pub block_hash: H256,
+}
+
+/// Error loading or saving a checkpoint.
+#[derive(Debug, Error)]
+pub enum CheckpointError {
+    /// An IO error occurred reading or writing the checkpoint file.
+    #[error("failed to access checkpoint file: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// The checkpoint could not be serialized or deserialized.
+    #[error("invalid checkpoint JSON: {0}")]
+    Json(#[from] serde_json::Error),
+
+    /// An error from a custom checkpoint store.
+    #[error("checkpoint store error: {0}")]
+    Other(Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// Persistent storage for indexer checkpoints.
+pub trait CheckpointStore {
+    /// Loads the last saved checkpoint, or `None` if indexing has not started
+    /// yet.
+    fn load(&mut self) -> Result<Option<Checkpoint>, CheckpointError>;
+
+    /// Saves a checkpoint, replacing the previously saved one.
+    fn save(&mut self, checkpoint: &Checkpoint) -> Result<(), CheckpointError>;
+}
+
+/// A checkpoint store that keeps the checkpoint in memory. Clones of the store
+/// share the same checkpoint, so it can be inspected while indexing.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryCheckpointStore(Arc<Mutex<Option<Checkpoint>>>);
+
+impl MemoryCheckpointStore {
+    /// Creates an empty in-memory checkpoint store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an in-memory checkpoint store that resumes from a checkpoint.
+    pub fn with_checkpoint(checkpoint: Checkpoint) -> Self {
+        MemoryCheckpointStore(Arc::new(Mutex::new(Some(checkpoint))))
+    }
+
+    /// Returns the currently stored checkpoint.
+    pub fn checkpoint(&self) -> Option<Checkpoint> {
+        *self.0.lock().unwrap()
+    }
+}
+
+impl CheckpointStore for MemoryCheckpointStore {
+    fn load(&mut self) -> Result<Option<Checkpoint>, CheckpointError> {
+        Ok(self.checkpoint())
+    }
+
+    fn save(&mut self, checkpoint: &Checkpoint) -> Result<(), CheckpointError> {
+        *self.0.lock().unwrap() = Some(*checkpoint);
+        Ok(())
+    }
+}
+
+/// A checkpoint store that persists the checkpoint as a JSON file.
+///
+/// Checkpoints are written and synced to a temporary file next to the
+/// checkpoint file that is then renamed, so an interrupted save never leaves a
+/// corrupt checkpoint behind.
+#[derive(Clone, Debug)]
+pub struct JsonFileCheckpointStore {
+    path: PathBuf,
+}
+
+impl JsonFileCheckpointStore {
+    /// Creates a checkpoint store for the specified file. The file does not
+    /// need to exist yet.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        JsonFileCheckpointStore { path: path.into() }
+    }
+
+    /// Returns the path of the checkpoint file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl CheckpointStore for JsonFileCheckpointStore {
+    fn load(&mut self) -> Result<Option<Checkpoint>, CheckpointError> {
+        let json = match fs::read_to_string(&self.path) {
+            Ok(json) => json,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Some(serde_json::from_str(&json)?))
+    }
+
+    fn save(&mut self, checkpoint: &Checkpoint) -> Result<(), CheckpointError> {
+        let json = serde_json::to_string_pretty(checkpoint)?;
+        let mut temp_path = self.path.clone().into_os_string();
+        temp_path.push(".tmp");
+        let mut file = File::create(&temp_path)?;
+        file.write_all(json.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::fixtures::checkpoint;
+
+    #[test]
+    fn memory_store_shares_checkpoint() {
+        let store = MemoryCheckpointStore::new();
+        assert_eq!(store.clone().load().unwrap(), None);
+
+        store.clone().save(&checkpoint(1, 1)).unwrap();
+        assert_eq!(store.checkpoint(), Some(checkpoint(1, 1)));
+        assert_eq!(
+            MemoryCheckpointStore::with_checkpoint(checkpoint(2, 2)).checkpoint(),
+            Some(checkpoint(2, 2))
+        );
+    }
+
+    #[test]
+    fn json_file_store_save_and_load() {
+        let path = std::env::temp_dir().join(format!(
+            "ethcontract-checkpoint-{}.json",
+            std::process::id()
+        ));
+        let mut store = JsonFileCheckpointStore::new(&path);
+        assert_eq!(store.load().unwrap(), None);
+
+        store.save(&checkpoint(1, 1)).unwrap();
+        store.save(&checkpoint(2, 2)).unwrap();
+        let loaded = JsonFileCheckpointStore::new(&path).load().unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, Some(checkpoint(2, 2)));
+        assert_eq!(json["blockNumber"], 2);
+    }
+}
@@ -96,12 +96,19 @@ mod test_macros;
 pub mod batch;
 pub mod contract;
 pub mod errors;
+pub mod indexer;
 mod int;
 pub mod log;
+pub mod multicall;
 pub mod secret;
+pub mod state_override;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 pub mod tokens;
+mod trace;
 pub mod transaction;
 pub mod transport;
+pub mod verification;
 
 pub use crate::contract::Instance;
 pub use crate::prelude::*;
@@ -177,6 +184,6 @@ pub mod private {
 #[cfg(test)]
 #[allow(missing_docs)]
 mod test {
+    pub mod fixtures;
     pub mod prelude;
-    pub mod transport;
 }
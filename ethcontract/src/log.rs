@@ -1,17 +1,27 @@
 //! This module implements event builders and streams for retrieving events
 //! emitted by a contract.
 
+use crate::contract::PinnedBlock;
 use crate::errors::ExecutionError;
+use crate::indexer::{CheckpointStore, LogIndexer};
+use crate::trace;
+use crate::transport::DynTransport;
 use ethcontract_common::abi::{Topic, TopicFilter};
-use futures::future::{self, TryFutureExt};
-use futures::stream::{self, Stream, TryStreamExt};
-use std::num::NonZeroU64;
+use futures::future::{self, Either, TryFutureExt};
+use futures::stream::{self, Stream, StreamExt as _, TryStreamExt};
+use std::collections::BTreeSet;
+use std::iter;
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use web3::api::Web3;
 use web3::error::Error as Web3Error;
 use web3::types::{Address, BlockId, BlockNumber, Filter, FilterBuilder, Log, H256};
 use web3::Transport;
 
+mod poll;
+mod subscribe;
+
 /// The default poll interval to use for polling logs from the block chain.
 #[cfg(not(test))]
 pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
@@ -22,13 +32,192 @@ pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(0);
 /// The default block page size used for querying past events.
 pub const DEFAULT_BLOCK_PAGE_SIZE: u64 = 10_000;
 
+/// The default number of blocks below the latest block that are tracked for
+/// detecting reorgs when polling logs with `eth_getLogs`.
+pub const DEFAULT_REORG_DEPTH: u64 = 64;
+
+/// Error messages returned by nodes when an `eth_getLogs` query covers too
+/// many blocks or returns too many results. Messages are matched case
+/// insensitively.
+pub const RANGE_TOO_LARGE_ERRORS: &[&str] = &[
+    "query returned more than",
+    "block range is too wide",
+    "exceed maximum block range",
+    "range too large",
+    "too many results",
+    "response size exceeded",
+];
+
+/// Configuration for adapting the block range of past log pages to node
+/// limits.
+///
+/// When a page query fails because the block range is too large or returns too
+/// many results, the range is halved and the query retried. After enough
+/// successful pages with a reduced range, the range is doubled again up to the
+/// configured block page size.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdaptivePaging {
+    /// The minimum page size in blocks. Pages of this size are not split any
+    /// further, and their errors are returned instead.
+    pub min_page_size: u64,
+    /// The maximum number of consecutive times a page is split before giving
+    /// up and returning the error. A value of 0 disables adaptive paging.
+    pub max_splits: u32,
+    /// The number of consecutive successful pages after which the page size is
+    /// doubled again.
+    pub grow_after: u32,
+}
+
+impl AdaptivePaging {
+    /// Returns a configuration that never adapts the page size, so errors
+    /// are always returned.
+    pub fn disabled() -> Self {
+        AdaptivePaging {
+            max_splits: 0,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for AdaptivePaging {
+    fn default() -> Self {
+        AdaptivePaging {
+            min_page_size: 1,
+            max_splits: 16,
+            grow_after: 2,
+        }
+    }
+}
+
+/// Returns true if the error indicates that an `eth_getLogs` query covered
+/// too many blocks or returned too many results.
+pub fn is_range_too_large(err: &Web3Error) -> bool {
+    let message = match err {
+        Web3Error::Rpc(err) => &err.message,
+        Web3Error::InvalidResponse(message) => message,
+        _ => return false,
+    };
+    let message = message.to_lowercase();
+    RANGE_TOO_LARGE_ERRORS
+        .iter()
+        .any(|pattern| message.contains(pattern))
+}
+
+/// The strategy used by log streams for retrieving new logs.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum LogStreamMode {
+    /// Subscribe to new logs with `eth_subscribe` when a transport supporting
+    /// subscriptions was specified with
+    /// [`LogFilterBuilder::subscription_transport`], and fall back to
+    /// [`LogStreamMode::Filter`] otherwise.
+    /// Logs from orphaned blocks are emitted again marked as removed.
+    ///
+    /// Subscriptions only emit logs for new blocks, so filters starting from
+    /// or ending at a specific block are always streamed with a node side
+    /// filter.
+    #[default]
+    Subscription,
+    /// Install a node side filter with `eth_newFilter` and poll it with
+    /// `eth_getFilterChanges`. Reorgs are handled by the node.
+    Filter,
+    /// Poll `eth_getLogs` over new block ranges, without requiring filter
+    /// support from the node. Reorgs are detected by comparing block hashes
+    /// of recently processed blocks, and logs from orphaned blocks are emitted
+    /// again marked as removed. The stream ends once the filter's `to_block`
+    /// was processed, if it is a block number.
+    Polling {
+        /// The number of blocks below the latest block that are tracked for
+        /// detecting reorgs. Reorgs deeper than this are not detected.
+        reorg_depth: u64,
+    },
+}
+
+impl LogStreamMode {
+    /// Polling `eth_getLogs` with the default reorg depth.
+    pub fn polling() -> Self {
+        LogStreamMode::Polling {
+            reorg_depth: DEFAULT_REORG_DEPTH,
+        }
+    }
+}
+
+/// A set of contract addresses to filter logs for that can be updated while
+/// logs are being streamed. Clones of the set share the same addresses.
+///
+/// This allows, for example, following the events of all pools created by a
+/// factory contract, adding new pools as they are deployed.
+#[derive(Clone, Debug, Default)]
+pub struct AddressSet(Arc<Mutex<BTreeSet<Address>>>);
+
+impl AddressSet {
+    /// Creates an empty address set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an address to the set, returning `false` if it was already
+    /// present.
+    pub fn insert(&self, address: Address) -> bool {
+        self.0.lock().unwrap().insert(address)
+    }
+
+    /// Removes an address from the set, returning `false` if it was not
+    /// present.
+    pub fn remove(&self, address: &Address) -> bool {
+        self.0.lock().unwrap().remove(address)
+    }
+
+    /// Returns true if the set contains the specified address.
+    pub fn contains(&self, address: &Address) -> bool {
+        self.0.lock().unwrap().contains(address)
+    }
+
+    /// Returns a snapshot of the addresses currently in the set.
+    pub fn addresses(&self) -> Vec<Address> {
+        self.0.lock().unwrap().iter().copied().collect()
+    }
+
+    /// Returns the number of addresses in the set.
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    /// Returns true if the set contains no addresses.
+    pub fn is_empty(&self) -> bool {
+        self.0.lock().unwrap().is_empty()
+    }
+}
+
+impl FromIterator<Address> for AddressSet {
+    fn from_iter<I: IntoIterator<Item = Address>>(iter: I) -> Self {
+        AddressSet(Arc::new(Mutex::new(iter.into_iter().collect())))
+    }
+}
+
+/// Returns the web3 filter restricted to the addresses currently in the
+/// address set, if any, or `None` if the set is empty and so the filter can
+/// not match any logs.
+pub(crate) fn address_set_filter(
+    filter: &FilterBuilder,
+    address_set: Option<&AddressSet>,
+) -> Option<FilterBuilder> {
+    let Some(address_set) = address_set else {
+        return Some(filter.clone());
+    };
+
+    // NOTE: An empty address filter matches all addresses, so the query needs
+    //   to be skipped instead.
+    let addresses = address_set.addresses();
+    (!addresses.is_empty()).then(|| filter.clone().address(addresses))
+}
+
 /// A log filter builder for configuring either a query for past logs or a
 /// stream that constantly queries new logs and deals with re-orgs.
 #[derive(Debug)]
 #[must_use = "log filter builders do nothing unless you query or stream them"]
 pub struct LogFilterBuilder<T: Transport> {
     /// The underlying web3 provider used for retrieving logs.
-    web3: Web3<T>,
+    pub(crate) web3: Web3<T>,
     /// The block to start streaming logs from.
     ///
     /// See [`web3::types::BlockNumber`] for more details on possible values.
@@ -41,6 +230,12 @@ pub struct LogFilterBuilder<T: Transport> {
     pub block_hash: Option<H256>,
     /// The contract addresses to filter logs for.
     pub address: Vec<Address>,
+    /// An updatable set of contract addresses to filter logs for. When
+    /// specified, it replaces `address`.
+    pub address_set: Option<AddressSet>,
+    /// The block that queries are pinned to. Queries only return logs up to
+    /// this block, and fail if it is no longer part of the canonical chain.
+    pub pinned_block: Option<PinnedBlock>,
     /// Topic filters used for filtering logs based on indexed topics.
     pub topics: TopicFilter,
     /// Limit the number of events that can be retrieved by this filter.
@@ -52,8 +247,19 @@ pub struct LogFilterBuilder<T: Transport> {
     /// logs. This provides no guarantee in how many logs will be returned per
     /// page, but used to limit the block range for the query.
     pub block_page_size: Option<NonZeroU64>,
+    /// The configuration for adapting the block page size when nodes reject
+    /// page queries for covering too many blocks or logs.
+    pub adaptive_paging: AdaptivePaging,
+    /// The number of pages to query concurrently when doing a paginated query
+    /// on past logs. This also bounds the number of pages that are buffered
+    /// in memory, as pages are always returned in block order.
+    pub page_concurrency: Option<NonZeroUsize>,
     /// The polling interval for querying the node for more logs.
     pub poll_interval: Option<Duration>,
+    /// The strategy used for streaming new logs.
+    pub stream_mode: LogStreamMode,
+    /// The transport used for subscribing to new logs.
+    pub(crate) subscription_transport: Option<DynTransport>,
 }
 
 impl<T: Transport> LogFilterBuilder<T> {
@@ -64,10 +270,16 @@ impl<T: Transport> LogFilterBuilder<T> {
             from_block: None,
             to_block: None,
             address: Vec::new(),
+            address_set: None,
+            pinned_block: None,
             topics: TopicFilter::default(),
             limit: None,
             block_page_size: None,
+            adaptive_paging: Default::default(),
+            page_concurrency: None,
             poll_interval: None,
+            stream_mode: LogStreamMode::Subscription,
+            subscription_transport: None,
             block_hash: None,
         }
     }
@@ -104,6 +316,30 @@ impl<T: Transport> LogFilterBuilder<T> {
         self
     }
 
+    /// Adds a filter to only retrieve logs that were emitted by a contract in
+    /// the provided address set, replacing any `address` filter.
+    ///
+    /// Queries use the addresses in the set at the time they are made, and
+    /// streams pick up changes to the set starting with the next polled block
+    /// range. Because of this, streams with an address set always poll
+    /// `eth_getLogs` as if [`LogStreamMode::Polling`] was specified. Queries
+    /// and streams with an empty address set do not return any logs.
+    pub fn address_set(mut self, addresses: AddressSet) -> Self {
+        self.address_set = Some(addresses);
+        self
+    }
+
+    /// Pins queries to the specified block, so that they only return logs up
+    /// to the block and fail with [`ExecutionError::NonCanonicalBlock`] if the
+    /// block was reorged out of the chain by the time the logs are retrieved.
+    ///
+    /// Note that log streams are only limited by the block number.
+    pub fn pinned_block(mut self, block: PinnedBlock) -> Self {
+        self.to_block = Some(BlockNumber::Number(block.number));
+        self.pinned_block = Some(block);
+        self
+    }
+
     /// Adds a filter for the first indexed topic.
     ///
     /// For regular events, this corresponds to the event signature. For
@@ -150,6 +386,29 @@ impl<T: Transport> LogFilterBuilder<T> {
         self
     }
 
+    /// The configuration for adapting the block page size of paginated queries
+    /// to node limits. Use [`AdaptivePaging::disabled`] to always use the
+    /// block page size.
+    pub fn adaptive_paging(mut self, value: AdaptivePaging) -> Self {
+        self.adaptive_paging = value;
+        self
+    }
+
+    /// The number of pages to query concurrently when doing a paginated query
+    /// on past events. Pages are still returned in block order.
+    ///
+    /// Note that with concurrent queries, page ranges that are too large are
+    /// split independently of each other and the page size does not grow back.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a page concurrency of 0 is specified.
+    pub fn page_concurrency(mut self, value: usize) -> Self {
+        self.page_concurrency =
+            Some(NonZeroUsize::new(value).expect("page concurrency cannot be 0"));
+        self
+    }
+
     /// The polling interval. This is used as the interval between consecutive
     /// `eth_getLogs` calls to get log updates.
     pub fn poll_interval(mut self, value: Duration) -> Self {
@@ -157,6 +416,28 @@ impl<T: Transport> LogFilterBuilder<T> {
         self
     }
 
+    /// Sets the strategy used for streaming new logs. By default, logs are
+    /// streamed with a subscription if a subscription transport was specified,
+    /// and with a node side filter otherwise.
+    pub fn stream_mode(mut self, value: LogStreamMode) -> Self {
+        self.stream_mode = value;
+        self
+    }
+
+    /// Sets the transport used for subscribing to new logs with
+    /// `eth_subscribe`, usually the [`DynTransport`] of the web3 provider.
+    /// It is only used if it supports subscriptions.
+    pub fn subscription_transport(mut self, transport: DynTransport) -> Self {
+        self.subscription_transport = Some(transport);
+        self
+    }
+
+    /// Creates a resumable indexer for the logs matching the filter,
+    /// persisting its progress in the specified checkpoint store.
+    pub fn indexer<S: CheckpointStore>(self, store: S) -> LogIndexer<T, S> {
+        LogIndexer::new(self, store)
+    }
+
     /// Returns a web3 filter builder needed for querying and streaming logs.
     pub fn into_filter(self) -> FilterBuilder {
         let mut filter = FilterBuilder::default();
@@ -169,8 +450,22 @@ impl<T: Transport> LogFilterBuilder<T> {
         if let Some(hash) = self.block_hash {
             filter = filter.block_hash(hash);
         }
-        if !self.address.is_empty() {
-            filter = filter.address(self.address);
+        let address = match &self.address_set {
+            Some(addresses) => {
+                // NOTE: An empty address filter matches all addresses, so
+                //   filter for the zero address instead, which can not emit
+                //   logs as no contract can be deployed to it.
+                let addresses = addresses.addresses();
+                if addresses.is_empty() {
+                    vec![Address::zero()]
+                } else {
+                    addresses
+                }
+            }
+            None => self.address,
+        };
+        if !address.is_empty() {
+            filter = filter.address(address);
         }
         if self.topics != TopicFilter::default() {
             filter = filter.topics(
@@ -191,9 +486,17 @@ impl<T: Transport> LogFilterBuilder<T> {
     /// such as retrieving all contract logs since genesis, it is recommended to
     /// use the `past_logs_pages` method instead.
     pub async fn past_logs(self) -> Result<Vec<Log>, ExecutionError> {
+        if self.matches_no_address() {
+            return Ok(Vec::new());
+        }
+
         let web3 = self.web3.clone();
+        let pinned_block = self.pinned_block;
         let filter = self.into_filter();
         let logs = web3.eth().logs(filter.build()).await?;
+        if let Some(block) = pinned_block {
+            check_canonical(&web3, block).await?;
+        }
 
         Ok(logs)
     }
@@ -204,33 +507,112 @@ impl<T: Transport> LogFilterBuilder<T> {
         // NOTE: Ignore the `limit` option when doing paginated queries as it
         //   can interfere.
         self.limit = None;
+        if self.matches_no_address() {
+            return Either::Left(stream::empty());
+        }
+
+        let web3 = self.web3.clone();
+        let pinned_block = self.pinned_block;
+        let concurrency = self.page_concurrency.map_or(1, NonZeroUsize::get);
+        let pages = if concurrency == 1 {
+            Either::Left(stream::try_unfold(
+                PastLogsStream::Init(Box::new(self)),
+                PastLogsStream::next,
+            ))
+        } else {
+            Either::Right(
+                PastLogsStream::init(self)
+                    .map_ok(move |state| match state {
+                        PastLogsStream::Paging(pager) => {
+                            Either::Left(pager.into_concurrent_pages(concurrency))
+                        }
+                        state => Either::Right(stream::try_unfold(state, PastLogsStream::next)),
+                    })
+                    .try_flatten_stream(),
+            )
+        };
 
-        stream::try_unfold(PastLogsStream::Init(self), PastLogsStream::next)
-            .try_filter(|logs| future::ready(!logs.is_empty()))
+        // NOTE: Check that the pinned block is still canonical once all pages
+        //   were retrieved, so that none of the pages are from a reorged
+        //   chain.
+        let check = stream::iter(pinned_block).then(move |block| {
+            let web3 = web3.clone();
+            async move { check_canonical(&web3, block).await.map(|()| Vec::new()) }
+        });
+        Either::Right(
+            pages
+                .chain(check)
+                .try_filter(|logs| future::ready(!logs.is_empty())),
+        )
     }
 
-    /// Creates a filter-based log stream that emits logs for each filter change.
-    pub fn stream(self) -> impl Stream<Item = Result<Log, ExecutionError>> {
-        let web3 = self.web3.clone();
-        let poll_interval = self.poll_interval.unwrap_or(DEFAULT_POLL_INTERVAL);
-        let filter = self.into_filter();
+    /// Returns true if the filter has an empty address set, and so can not
+    /// match any logs.
+    fn matches_no_address(&self) -> bool {
+        self.address_set
+            .as_ref()
+            .is_some_and(|addresses| addresses.is_empty())
+    }
 
-        async move {
-            let eth_filter = web3
-                .eth_filter()
-                .create_logs_filter(filter.build())
-                .await
-                .map_err(ExecutionError::from)?;
-            let stream = eth_filter
-                .stream(poll_interval)
-                .map_err(ExecutionError::from);
-
-            Ok(stream)
+    /// Creates a log stream that emits new logs, either as they are notified
+    /// by a subscription, for each filter change or for each new block range,
+    /// depending on the stream mode.
+    pub fn stream(self) -> impl Stream<Item = Result<Log, ExecutionError>> {
+        match self.stream_mode {
+            LogStreamMode::Subscription | LogStreamMode::Filter if self.address_set.is_some() => {
+                Either::Right(Either::Right(poll::stream(self, DEFAULT_REORG_DEPTH)))
+            }
+            LogStreamMode::Subscription => Either::Left(subscribe::stream(self)),
+            LogStreamMode::Filter => {
+                let web3 = self.web3.clone();
+                let poll_interval = self.poll_interval.unwrap_or(DEFAULT_POLL_INTERVAL);
+                let filter = self.into_filter().build();
+                Either::Right(Either::Left(filter_stream(web3, filter, poll_interval)))
+            }
+            LogStreamMode::Polling { reorg_depth } => {
+                Either::Right(Either::Right(poll::stream(self, reorg_depth)))
+            }
         }
-        .try_flatten_stream()
     }
 }
 
+/// Creates a filter-based log stream that emits logs for each filter change.
+fn filter_stream<T: Transport>(
+    web3: Web3<T>,
+    filter: Filter,
+    poll_interval: Duration,
+) -> impl Stream<Item = Result<Log, ExecutionError>> {
+    async move {
+        let eth_filter = web3
+            .eth_filter()
+            .create_logs_filter(filter)
+            .await
+            .map_err(ExecutionError::from)?;
+        let stream = eth_filter
+            .stream(poll_interval)
+            .map_err(ExecutionError::from);
+
+        Ok(stream)
+    }
+    .try_flatten_stream()
+}
+
+/// Returns an error if the pinned block is no longer part of the canonical
+/// chain.
+async fn check_canonical<T: Transport>(
+    web3: &Web3<T>,
+    block: PinnedBlock,
+) -> Result<(), ExecutionError> {
+    let canonical = web3
+        .eth()
+        .block(BlockId::Number(BlockNumber::Number(block.number)))
+        .await?;
+    if canonical.and_then(|canonical| canonical.hash) != Some(block.hash) {
+        return Err(ExecutionError::NonCanonicalBlock(block.hash));
+    }
+    Ok(())
+}
+
 /// Converts a `Topic` to an equivalent `Option<Vec<T>>`, suitable for `FilterBuilder::topics`
 fn topic_to_option(topic: Topic<H256>) -> Option<Vec<H256>> {
     match topic {
@@ -240,9 +622,37 @@ fn topic_to_option(topic: Topic<H256>) -> Option<Vec<H256>> {
     }
 }
 
+/// Returns a stream that resolves into pages of logs matching a web3 filter
+/// within a block range, adapting the page size like `past_logs_pages`.
+pub(crate) fn past_logs_range<T: Transport>(
+    web3: Web3<T>,
+    filter: FilterBuilder,
+    address_set: Option<AddressSet>,
+    block_page_size: u64,
+    adaptive_paging: AdaptivePaging,
+    from_block: u64,
+    to_block: u64,
+) -> impl Stream<Item = Result<Vec<Log>, ExecutionError>> {
+    let pager = PastLogsPager {
+        web3,
+        span: trace::span!("past_logs_range"),
+        to_block: to_block.into(),
+        block_page_size,
+        max_block_page_size: block_page_size,
+        adaptive_paging,
+        splits: 0,
+        successes: 0,
+        filter,
+        address_set,
+        page_block: from_block,
+        end_block: to_block,
+    };
+    stream::try_unfold(PastLogsStream::Paging(pager), PastLogsStream::next)
+}
+
 /// Internal unfold context for creating a `past_logs` `Stream`.
 enum PastLogsStream<T: Transport> {
-    Init(LogFilterBuilder<T>),
+    Init(Box<LogFilterBuilder<T>>),
     Done,
     Paging(PastLogsPager<T>),
     Querying(Web3<T>, Filter),
@@ -269,7 +679,7 @@ impl<T: Transport> PastLogsStream<T> {
         loop {
             let (logs, next) = match self {
                 PastLogsStream::Init(builder) => {
-                    self = PastLogsStream::init(builder).await?;
+                    self = PastLogsStream::init(*builder).await?;
                     continue;
                 }
                 PastLogsStream::Done => return Ok(None),
@@ -294,10 +704,13 @@ impl<T: Transport> PastLogsStream<T> {
         let to_block = builder.to_block.unwrap_or(BlockNumber::Latest);
 
         let web3 = builder.web3.clone();
+        let span = trace::span!("past_logs_pages", address = ?builder.address);
         let block_page_size = builder
             .block_page_size
             .map(|size| size.get())
             .unwrap_or(DEFAULT_BLOCK_PAGE_SIZE);
+        let adaptive_paging = builder.adaptive_paging.clone();
+        let address_set = builder.address_set.clone();
         let filter = builder.into_filter();
 
         let start_block = match from_block {
@@ -319,9 +732,15 @@ impl<T: Transport> PastLogsStream<T> {
         let next = match (start_block, end_block) {
             (Some(page_block), Some(end_block)) => PastLogsStream::Paging(PastLogsPager {
                 web3,
+                span,
                 to_block,
                 block_page_size,
+                max_block_page_size: block_page_size,
+                adaptive_paging,
+                splits: 0,
+                successes: 0,
                 filter,
+                address_set,
                 page_block,
                 end_block,
             }),
@@ -335,13 +754,28 @@ impl<T: Transport> PastLogsStream<T> {
 /// Internal state for paging though past logs.
 struct PastLogsPager<T: Transport> {
     web3: Web3<T>,
+    /// The span that page queries are instrumented in.
+    span: trace::Span,
 
     /// The `to_block` specified by the log filter.
     to_block: BlockNumber,
     /// The block page size being used for queries.
     block_page_size: u64,
+    /// The configured block page size, which the page size grows back to
+    /// after being reduced.
+    max_block_page_size: u64,
+    /// The configuration for adapting the block page size.
+    adaptive_paging: AdaptivePaging,
+    /// The number of consecutive times the current page was split.
+    splits: u32,
+    /// The number of consecutive successful pages since the page size was
+    /// last changed.
+    successes: u32,
     /// The web3 filter used for retrieving the logs.
     filter: FilterBuilder,
+    /// The updatable set of addresses to filter logs for, replacing the
+    /// filter's addresses for each page.
+    address_set: Option<AddressSet>,
 
     /// The block number for the next page.
     page_block: u64,
@@ -373,19 +807,38 @@ impl<T: Transport> PastLogsPager<T> {
                 self.to_block
             };
 
-            let page = self
-                .web3
-                .eth()
-                .logs(
-                    self.filter
-                        .clone()
+            let Some(filter) = address_set_filter(&self.filter, self.address_set.as_ref()) else {
+                self.page_block = page_end + 1;
+                continue;
+            };
+
+            let span = trace::span!(
+                parent: self.span,
+                "past_logs_page",
+                from_block = self.page_block,
+                to_block = ?page_to_block,
+            );
+            let result = trace::instrument(
+                span,
+                self.web3.eth().logs(
+                    filter
                         .from_block(self.page_block.into())
                         .to_block(page_to_block)
                         .build(),
-                )
-                .await?;
+                ),
+            )
+            .await;
+            let page = match result {
+                Ok(page) => page,
+                Err(err) if self.can_split(&err) => {
+                    self.split();
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
 
             self.page_block = page_end + 1;
+            self.grow();
             if page.is_empty() {
                 continue;
             }
@@ -395,14 +848,161 @@ impl<T: Transport> PastLogsPager<T> {
 
         Ok(None)
     }
+
+    /// Converts the pager into a stream of pages that queries up to
+    /// `concurrency` pages at the same time while returning them in order.
+    fn into_concurrent_pages(
+        self,
+        concurrency: usize,
+    ) -> impl Stream<Item = Result<Vec<Log>, ExecutionError>> {
+        let PastLogsPager {
+            web3,
+            span,
+            to_block,
+            block_page_size,
+            adaptive_paging,
+            filter,
+            address_set,
+            page_block,
+            end_block,
+            ..
+        } = self;
+
+        let first_page = Some(page_block).filter(|block| *block <= end_block);
+        let pages = iter::successors(first_page, move |block| {
+            block
+                .checked_add(block_page_size)
+                .filter(|next| *next <= end_block)
+        })
+        .map(move |page_block| {
+            // NOTE: The last page uses the original `to_block`, see
+            //   `next_page` for more details.
+            let page_end = page_block.saturating_add(block_page_size - 1);
+            if page_end < end_block {
+                (page_block, page_end, BlockNumber::Number(page_end.into()))
+            } else {
+                (page_block, end_block, to_block)
+            }
+        });
+
+        stream::iter(pages)
+            .map(move |(page_block, page_end, page_to_block)| {
+                query_page_range(
+                    web3.clone(),
+                    span.clone(),
+                    address_set_filter(&filter, address_set.as_ref()),
+                    adaptive_paging.clone(),
+                    (page_block, page_end, page_to_block),
+                )
+            })
+            .buffered(concurrency)
+    }
+
+    /// Returns true if the page query error can be handled by splitting the
+    /// page into smaller block ranges.
+    fn can_split(&self, err: &Web3Error) -> bool {
+        self.splits < self.adaptive_paging.max_splits
+            && self.block_page_size > self.adaptive_paging.min_page_size.max(1)
+            && is_range_too_large(err)
+    }
+
+    /// Halves the block page size.
+    fn split(&mut self) {
+        self.block_page_size =
+            (self.block_page_size / 2).max(self.adaptive_paging.min_page_size.max(1));
+        self.splits += 1;
+        self.successes = 0;
+    }
+
+    /// Records a successful page and grows the block page size back after
+    /// enough consecutive successful pages.
+    fn grow(&mut self) {
+        self.splits = 0;
+        if self.block_page_size >= self.max_block_page_size {
+            return;
+        }
+
+        self.successes += 1;
+        if self.successes >= self.adaptive_paging.grow_after {
+            self.block_page_size = self
+                .block_page_size
+                .saturating_mul(2)
+                .min(self.max_block_page_size);
+            self.successes = 0;
+        }
+    }
+}
+
+/// Queries all logs in a page range, splitting the range into smaller ranges
+/// that are queried one after the other if the node rejects it. No logs are
+/// queried without a filter, which is the case for empty address sets.
+async fn query_page_range<T: Transport>(
+    web3: Web3<T>,
+    span: trace::Span,
+    filter: Option<FilterBuilder>,
+    adaptive_paging: AdaptivePaging,
+    range: (u64, u64, BlockNumber),
+) -> Result<Vec<Log>, ExecutionError> {
+    let Some(filter) = filter else {
+        return Ok(Vec::new());
+    };
+    let min_page_size = adaptive_paging.min_page_size.max(1);
+
+    // NOTE: Ranges are processed as a stack, so the second half of a split
+    //   range is pushed first in order to keep the logs in block order.
+    let mut logs = Vec::new();
+    let mut ranges = vec![(range, 0)];
+    while let Some(((from_block, end_block, to_block), splits)) = ranges.pop() {
+        let span = trace::span!(
+            parent: span,
+            "past_logs_page",
+            from_block = from_block,
+            to_block = ?to_block,
+        );
+        let result = trace::instrument(
+            span,
+            web3.eth().logs(
+                filter
+                    .clone()
+                    .from_block(from_block.into())
+                    .to_block(to_block)
+                    .build(),
+            ),
+        )
+        .await;
+
+        match result {
+            Ok(page) => logs.extend(page),
+            Err(err)
+                if splits < adaptive_paging.max_splits
+                    && end_block - from_block + 1 > min_page_size
+                    && is_range_too_large(&err) =>
+            {
+                let range_size = end_block - from_block + 1;
+                let page_size = (range_size / 2).max(min_page_size);
+                let middle = from_block + page_size - 1;
+                ranges.push(((middle + 1, end_block, to_block), splits + 1));
+                ranges.push((
+                    (from_block, middle, BlockNumber::Number(middle.into())),
+                    splits + 1,
+                ));
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    Ok(logs)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::test::prelude::*;
+    use futures::channel::oneshot;
     use futures::stream::StreamExt;
     use serde_json::Value;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll};
     use web3::types::U64;
 
     fn generate_log(kind: &str) -> Value {
@@ -466,6 +1066,67 @@ mod tests {
         transport.assert_no_more_requests();
     }
 
+    #[test]
+    fn empty_address_set_filter_matches_nothing() {
+        let web3 = Web3::new(TestTransport::new());
+
+        let filter = LogFilterBuilder::new(web3)
+            .address(vec![Address::repeat_byte(0x42)])
+            .address_set(AddressSet::new())
+            .into_filter()
+            .build();
+
+        assert_eq!(
+            serde_json::to_value(filter).unwrap(),
+            json!({ "address": Address::zero() }),
+        );
+    }
+
+    #[test]
+    fn past_log_stream_uses_current_address_set_for_each_page() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+        let addresses = AddressSet::from_iter([Address::repeat_byte(1)]);
+
+        transport.add_response(json!([generate_log("first")]));
+        transport.add_response(json!([generate_log("second")]));
+
+        let mut pages = LogFilterBuilder::new(web3)
+            .from_block(0.into())
+            .to_block(5.into())
+            .address_set(addresses.clone())
+            .block_page_size(2)
+            .past_logs_pages()
+            .boxed();
+
+        let first = pages.next().immediate().unwrap().unwrap();
+        addresses.insert(Address::repeat_byte(2));
+        let second = pages.next().immediate().unwrap().unwrap();
+        addresses.remove(&Address::repeat_byte(1));
+        addresses.remove(&Address::repeat_byte(2));
+        assert!(pages.next().immediate().is_none());
+
+        assert_eq!(first[0].log_type.as_deref(), Some("first"));
+        assert_eq!(second[0].log_type.as_deref(), Some("second"));
+        transport.assert_request(
+            "eth_getLogs",
+            &[json!({
+                "address": Address::repeat_byte(1),
+                "fromBlock": U64::from(0),
+                "toBlock": U64::from(1),
+            })],
+        );
+        transport.assert_request(
+            "eth_getLogs",
+            &[json!({
+                "address": [Address::repeat_byte(1), Address::repeat_byte(2)],
+                "fromBlock": U64::from(2),
+                "toBlock": U64::from(3),
+            })],
+        );
+        transport.assert_no_more_requests();
+    }
+
     #[test]
     fn past_log_stream_logs() {
         let mut transport = TestTransport::new();
@@ -544,6 +1205,263 @@ mod tests {
         transport.assert_no_more_requests();
     }
 
+    fn range_error() -> Web3Error {
+        Web3Error::Rpc(jsonrpc_core::Error {
+            code: jsonrpc_core::ErrorCode::ServerError(-32005),
+            message: "query returned more than 10000 results".to_owned(),
+            data: None,
+        })
+    }
+
+    fn page_range(params: &[Value]) -> (Value, Value) {
+        (params[0]["fromBlock"].clone(), params[0]["toBlock"].clone())
+    }
+
+    #[test]
+    fn detects_range_too_large_errors() {
+        assert!(is_range_too_large(&range_error()));
+        assert!(is_range_too_large(&Web3Error::Rpc(
+            jsonrpc_core::Error::invalid_params("Block range is too wide")
+        )));
+        assert!(is_range_too_large(&Web3Error::Rpc(
+            jsonrpc_core::Error::invalid_params("exceed maximum block range: 5000")
+        )));
+        assert!(!is_range_too_large(&Web3Error::Rpc(
+            jsonrpc_core::Error::invalid_params("invalid topic")
+        )));
+        assert!(!is_range_too_large(&Web3Error::Rpc(
+            jsonrpc_core::Error::invalid_params("invalid block range params")
+        )));
+        assert!(!is_range_too_large(&Web3Error::Unreachable));
+    }
+
+    #[test]
+    fn past_log_stream_splits_and_grows_pages() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+        let log = generate_log("awesome");
+
+        // first page of 8 blocks is too large, and so is the first split
+        transport.add_error(range_error());
+        transport.add_error(range_error());
+        // two successful pages of 2 blocks grow the page size back to 4
+        transport.add_response(json!([log]));
+        transport.add_response(json!([log]));
+        // and two successful pages of 4 blocks grow it back to 8
+        transport.add_response(json!([log]));
+        transport.add_response(json!([log]));
+        transport.add_response(json!([log]));
+
+        let pages = LogFilterBuilder::new(web3)
+            .from_block(0.into())
+            .to_block(19.into())
+            .block_page_size(8)
+            .adaptive_paging(AdaptivePaging {
+                grow_after: 2,
+                ..Default::default()
+            })
+            .past_logs_pages()
+            .collect::<Vec<_>>()
+            .immediate();
+
+        assert_eq!(pages.len(), 5);
+        assert!(pages.iter().all(|page| page.is_ok()));
+
+        let ranges = [(0, 7), (0, 3), (0, 1), (2, 3), (4, 7), (8, 11), (12, 19)];
+        for (from, to) in ranges {
+            transport.assert_request_matches("eth_getLogs", |params| {
+                page_range(params) == (json!(U64::from(from)), json!(U64::from(to)))
+            });
+        }
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn past_log_stream_stops_splitting_at_min_page_size() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        transport.add_error(range_error());
+        transport.add_error(range_error());
+
+        let page = LogFilterBuilder::new(web3)
+            .from_block(0.into())
+            .to_block(19.into())
+            .block_page_size(8)
+            .adaptive_paging(AdaptivePaging {
+                min_page_size: 4,
+                ..Default::default()
+            })
+            .past_logs_pages()
+            .boxed()
+            .next()
+            .immediate();
+
+        assert!(matches!(
+            page,
+            Some(Err(ExecutionError::Web3(Web3Error::Rpc(_))))
+        ));
+        transport.assert_request_matches("eth_getLogs", |params| {
+            page_range(params) == (json!(U64::from(0)), json!(U64::from(7)))
+        });
+        transport.assert_request_matches("eth_getLogs", |params| {
+            page_range(params) == (json!(U64::from(0)), json!(U64::from(3)))
+        });
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn past_log_stream_without_adaptive_paging() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        transport.add_error(range_error());
+
+        let page = LogFilterBuilder::new(web3)
+            .from_block(0.into())
+            .to_block(19.into())
+            .adaptive_paging(AdaptivePaging::disabled())
+            .past_logs_pages()
+            .boxed()
+            .next()
+            .immediate();
+
+        assert!(matches!(page, Some(Err(_))));
+        transport.assert_request(
+            "eth_getLogs",
+            &[json!({ "fromBlock": "0x0", "toBlock": "0x13" })],
+        );
+        transport.assert_no_more_requests();
+    }
+
+    /// A transport that answers `eth_getLogs` requests with a single log in
+    /// the `fromBlock` of the query once the test releases the request.
+    #[derive(Clone, Debug, Default)]
+    struct DelayedLogsTransport {
+        pending: Arc<Mutex<Vec<oneshot::Sender<()>>>>,
+    }
+
+    impl DelayedLogsTransport {
+        /// Completes all pending requests in reverse order, returning how many
+        /// requests were in flight.
+        fn release_in_reverse(&self) -> usize {
+            let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+            let in_flight = pending.len();
+            for sender in pending.into_iter().rev() {
+                sender.send(()).unwrap();
+            }
+            in_flight
+        }
+    }
+
+    impl Transport for DelayedLogsTransport {
+        type Out = futures::future::BoxFuture<'static, Result<Value, Web3Error>>;
+
+        fn prepare(
+            &self,
+            method: &str,
+            params: Vec<Value>,
+        ) -> (web3::RequestId, jsonrpc_core::Call) {
+            (0, web3::helpers::build_request(0, method, params))
+        }
+
+        fn send(&self, _: web3::RequestId, request: jsonrpc_core::Call) -> Self::Out {
+            let from_block = match request {
+                jsonrpc_core::Call::MethodCall(call) => match call.params {
+                    jsonrpc_core::Params::Array(params) => {
+                        serde_json::from_value::<U64>(params[0]["fromBlock"].clone())
+                            .unwrap()
+                            .as_u64()
+                    }
+                    _ => unreachable!(),
+                },
+                _ => unreachable!(),
+            };
+            let (sender, receiver) = oneshot::channel();
+            self.pending.lock().unwrap().push(sender);
+            Box::pin(async move {
+                receiver.await.unwrap();
+
+                let mut log = generate_log("awesome");
+                log["blockNumber"] = json!(U64::from(from_block));
+                Ok(json!([log]))
+            })
+        }
+    }
+
+    #[test]
+    fn past_log_stream_concurrent_pages_are_ordered() {
+        let transport = DelayedLogsTransport::default();
+        let web3 = Web3::new(transport.clone());
+
+        let stream = LogFilterBuilder::new(web3)
+            .from_block(0.into())
+            .to_block(9.into())
+            .block_page_size(2)
+            .page_concurrency(3)
+            .past_logs_pages();
+        futures::pin_mut!(stream);
+
+        // NOTE: Poll the stream until it is waiting on all of its in flight
+        //   requests and then complete them with the latest page first.
+        let mut context = Context::from_waker(futures::task::noop_waker_ref());
+        let mut pages = Vec::new();
+        let mut max_in_flight = 0;
+        loop {
+            match stream.as_mut().poll_next(&mut context) {
+                Poll::Ready(Some(page)) => pages.push(page.expect("failed to get logs")),
+                Poll::Ready(None) => break,
+                Poll::Pending => {
+                    let in_flight = transport.release_in_reverse();
+                    assert!(in_flight > 0, "stream is pending without requests");
+                    max_in_flight = max_in_flight.max(in_flight);
+                }
+            }
+        }
+
+        let blocks = pages
+            .iter()
+            .flatten()
+            .map(|log| log.block_number.unwrap().as_u64())
+            .collect::<Vec<_>>();
+        assert_eq!(blocks, [0, 2, 4, 6, 8]);
+        assert_eq!(max_in_flight, 3);
+    }
+
+    #[test]
+    fn past_log_stream_concurrent_pages_split_ranges() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+        let log = generate_log("awesome");
+
+        transport.add_error(range_error());
+        transport.add_response(json!([log]));
+        transport.add_response(json!([log]));
+        transport.add_response(json!([log]));
+
+        let pages = LogFilterBuilder::new(web3)
+            .from_block(0.into())
+            .to_block(7.into())
+            .block_page_size(4)
+            .page_concurrency(2)
+            .past_logs_pages()
+            .try_collect::<Vec<_>>()
+            .immediate()
+            .expect("failed to get logs");
+
+        assert_eq!(
+            pages.iter().map(Vec::len).collect::<Vec<_>>(),
+            [2, 1],
+            "split page should be returned as a single page",
+        );
+        for (from, to) in [(0, 3), (0, 1), (2, 3), (4, 7)] {
+            transport.assert_request_matches("eth_getLogs", |params| {
+                page_range(params) == (json!(U64::from(from)), json!(U64::from(to)))
+            });
+        }
+        transport.assert_no_more_requests();
+    }
+
     #[test]
     fn log_stream_next_log() {
         let mut transport = TestTransport::new();
@@ -0,0 +1,559 @@
+//! Implementation of a log stream that polls `eth_getLogs` over new block
+//! ranges instead of relying on node side filters.
+
+use super::{address_set_filter, AddressSet, LogFilterBuilder, DEFAULT_POLL_INTERVAL};
+use crate::errors::ExecutionError;
+use futures::stream::{self, Stream, TryStreamExt};
+use futures_timer::Delay;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use web3::api::Web3;
+use web3::error::Error as Web3Error;
+use web3::types::{BlockId, BlockNumber, FilterBuilder, Log, H256};
+use web3::Transport;
+
+/// Creates a log stream that polls `eth_getLogs` for new blocks and emits
+/// removed logs for blocks that were reorged out of the chain.
+pub(super) fn stream<T: Transport>(
+    mut builder: LogFilterBuilder<T>,
+    reorg_depth: u64,
+) -> impl Stream<Item = Result<Log, ExecutionError>> {
+    let web3 = builder.web3.clone();
+    let poll_interval = builder.poll_interval.unwrap_or(DEFAULT_POLL_INTERVAL);
+    let from_block = builder.from_block.take();
+    let to_block = match builder.to_block.take() {
+        Some(BlockNumber::Number(number)) => Some(number.as_u64()),
+        Some(BlockNumber::Earliest) => Some(0),
+        _ => None,
+    };
+
+    // NOTE: Block ranges are set for each query, and the `limit` option is
+    //   ignored as it would cause logs to be skipped.
+    builder.block_hash = None;
+    builder.limit = None;
+    let address_set = builder.address_set.take();
+    let filter = builder.into_filter();
+
+    let poller = LogPoller {
+        web3,
+        filter,
+        address_set,
+        poll_interval,
+        reorg_depth,
+        from_block,
+        to_block,
+        next_block: None,
+        blocks: BTreeMap::new(),
+        logs: BTreeMap::new(),
+        polled: false,
+    };
+    stream::try_unfold(poller, LogPoller::next)
+        .map_ok(|logs| stream::iter(logs.into_iter().map(Ok)))
+        .try_flatten()
+}
+
+/// Internal state for polling logs.
+struct LogPoller<T: Transport> {
+    web3: Web3<T>,
+    /// The web3 filter used for retrieving the logs, without a block range.
+    filter: FilterBuilder,
+    /// The updatable set of addresses to filter logs for, replacing the
+    /// filter's addresses for each query.
+    address_set: Option<AddressSet>,
+    poll_interval: Duration,
+    /// The number of blocks below the latest block that are tracked for
+    /// detecting reorgs.
+    reorg_depth: u64,
+
+    /// The `from_block` specified by the log filter.
+    from_block: Option<BlockNumber>,
+    /// The last block to query logs for, if specified by the log filter.
+    to_block: Option<u64>,
+
+    /// The next block to query logs for, or `None` if polling has not started
+    /// yet.
+    next_block: Option<u64>,
+    /// The hashes of recently processed blocks.
+    blocks: BTreeMap<u64, H256>,
+    /// The logs that were emitted for recently processed blocks.
+    logs: BTreeMap<u64, Vec<Log>>,
+    /// Whether or not the node was polled already.
+    polled: bool,
+}
+
+impl<T: Transport> LogPoller<T> {
+    async fn next(mut self) -> Result<Option<(Vec<Log>, Self)>, ExecutionError> {
+        loop {
+            if let (Some(next_block), Some(to_block)) = (self.next_block, self.to_block) {
+                if next_block > to_block {
+                    return Ok(None);
+                }
+            }
+            if self.polled {
+                delay(self.poll_interval).await;
+            }
+            self.polled = true;
+
+            let logs = self.poll().await?;
+            if !logs.is_empty() {
+                return Ok(Some((logs, self)));
+            }
+        }
+    }
+
+    /// Polls the node once, returning logs that were removed by a reorg
+    /// followed by new logs.
+    async fn poll(&mut self) -> Result<Vec<Log>, ExecutionError> {
+        let (head, head_hash) = self
+            .block(BlockNumber::Latest)
+            .await?
+            .ok_or_else(|| Web3Error::InvalidResponse("latest block does not exist".to_owned()))?;
+
+        // NOTE: A latest block below the blocks that were already processed
+        //   means that the node is lagging behind, for example a different
+        //   node behind a load balancer, so skip polling until it caught up.
+        if matches!(self.blocks.last_key_value(), Some((&highest, _)) if head < highest) {
+            return Ok(Vec::new());
+        }
+
+        let next_block = match self.next_block {
+            Some(next_block) => next_block,
+            None => match self.from_block {
+                Some(BlockNumber::Number(number)) => number.as_u64(),
+                Some(BlockNumber::Earliest) => 0,
+                _ => {
+                    self.blocks.insert(head, head_hash);
+                    head + 1
+                }
+            },
+        };
+        self.next_block = Some(next_block);
+
+        let mut logs = if self.blocks.get(&head) == Some(&head_hash) {
+            // NOTE: The latest block is known, so all tracked blocks, which
+            //   are its ancestors, are still part of the chain.
+            Vec::new()
+        } else {
+            match self.remove_orphaned_blocks().await? {
+                Some(removed) => removed,
+                None => return Ok(Vec::new()),
+            }
+        };
+
+        let next_block = self.next_block.unwrap_or(next_block);
+        let end_block = self.to_block.map_or(head, |to_block| to_block.min(head));
+        if next_block <= end_block {
+            let new_logs = match address_set_filter(&self.filter, self.address_set.as_ref()) {
+                Some(filter) => {
+                    let filter = filter
+                        .from_block(next_block.into())
+                        .to_block(end_block.into())
+                        .build();
+                    self.web3.eth().logs(filter).await?
+                }
+                None => Vec::new(),
+            };
+
+            if end_block == head {
+                self.blocks.insert(head, head_hash);
+            }
+            for log in &new_logs {
+                if let (Some(number), Some(hash)) = (log.block_number, log.block_hash) {
+                    self.blocks.insert(number.as_u64(), hash);
+                    self.logs
+                        .entry(number.as_u64())
+                        .or_default()
+                        .push(log.clone());
+                }
+            }
+            self.next_block = Some(end_block + 1);
+            logs.extend(new_logs);
+        }
+
+        let oldest_block = head.saturating_sub(self.reorg_depth);
+        self.blocks = self.blocks.split_off(&oldest_block);
+        self.logs = self.logs.split_off(&oldest_block);
+
+        Ok(logs)
+    }
+
+    /// Finds tracked blocks that are no longer part of the chain and returns
+    /// the logs that were emitted for them, marked as removed and in reverse
+    /// order. Polling resumes after the most recent block that is still part
+    /// of the chain.
+    ///
+    /// Only blocks for which the node returns a different hash are considered
+    /// orphaned. If the node does not know a tracked block, it is lagging
+    /// behind and `None` is returned without changing any state.
+    async fn remove_orphaned_blocks(&mut self) -> Result<Option<Vec<Log>>, ExecutionError> {
+        let mut ancestor = None;
+        for (&number, &hash) in self.blocks.iter().rev() {
+            match self.block(BlockNumber::Number(number.into())).await? {
+                Some((_, current)) if current == hash => {
+                    ancestor = Some(number);
+                    break;
+                }
+                Some(_) => {}
+                None => return Ok(None),
+            }
+        }
+
+        let first_orphaned = match ancestor {
+            Some(ancestor) => ancestor + 1,
+            None => match self.blocks.keys().next() {
+                Some(oldest) => *oldest,
+                None => return Ok(Some(Vec::new())),
+            },
+        };
+        if self.blocks.range(first_orphaned..).next().is_none() {
+            return Ok(Some(Vec::new()));
+        }
+
+        self.blocks.split_off(&first_orphaned);
+        let removed = self
+            .logs
+            .split_off(&first_orphaned)
+            .into_values()
+            .rev()
+            .flat_map(|logs| logs.into_iter().rev())
+            .map(|log| Log {
+                removed: Some(true),
+                ..log
+            })
+            .collect();
+        self.next_block = Some(first_orphaned);
+
+        Ok(Some(removed))
+    }
+
+    /// Retrieves the number and hash of a block.
+    async fn block(&self, block: BlockNumber) -> Result<Option<(u64, H256)>, Web3Error> {
+        let block = self.web3.eth().block(BlockId::Number(block)).await?;
+        Ok(block.and_then(|block| Some((block.number?.as_u64(), block.hash?))))
+    }
+}
+
+/// Create a new delay that may resolve immediately when delayed for a zero
+/// duration.
+async fn delay(duration: Duration) {
+    if !duration.is_zero() {
+        Delay::new(duration).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::{AddressSet, LogStreamMode};
+    use crate::test::fixtures::*;
+    use crate::test::prelude::*;
+    use futures::stream::StreamExt;
+    use web3::types::Address;
+
+    #[test]
+    fn polls_new_block_ranges() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        // first poll from block 10 to the latest block 12
+        transport.add_response(block(12, 0x12));
+        transport.add_response(json!([log(10, 0x10), log(12, 0x12)]));
+        // latest block is unchanged
+        transport.add_response(block(12, 0x12));
+        // latest block 14, verify block 12 is still part of the chain
+        transport.add_response(block(14, 0x14));
+        transport.add_response(block(12, 0x12));
+        transport.add_response(json!([log(13, 0x13)]));
+
+        let logs = LogFilterBuilder::new(web3)
+            .from_block(10.into())
+            .stream_mode(LogStreamMode::polling())
+            .stream()
+            .take(3)
+            .try_collect::<Vec<_>>()
+            .immediate()
+            .unwrap();
+
+        assert_eq!(
+            logs.iter().map(summary).collect::<Vec<_>>(),
+            [
+                (10, H256::repeat_byte(0x10), false),
+                (12, H256::repeat_byte(0x12), false),
+                (13, H256::repeat_byte(0x13), false),
+            ]
+        );
+        transport.assert_request("eth_getBlockByNumber", &[json!("latest"), json!(false)]);
+        transport.assert_request(
+            "eth_getLogs",
+            &[json!({ "fromBlock": "0xa", "toBlock": "0xc" })],
+        );
+        transport.assert_request("eth_getBlockByNumber", &[json!("latest"), json!(false)]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("latest"), json!(false)]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0xc"), json!(false)]);
+        transport.assert_request(
+            "eth_getLogs",
+            &[json!({ "fromBlock": "0xd", "toBlock": "0xe" })],
+        );
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn ends_after_to_block() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        transport.add_response(block(12, 0x12));
+        transport.add_response(json!([log(10, 0x10), log(11, 0x11)]));
+
+        let logs = LogFilterBuilder::new(web3)
+            .from_block(10.into())
+            .to_block(11.into())
+            .stream_mode(LogStreamMode::polling())
+            .stream()
+            .try_collect::<Vec<_>>()
+            .immediate()
+            .unwrap();
+
+        assert_eq!(
+            logs.iter().map(summary).collect::<Vec<_>>(),
+            [
+                (10, H256::repeat_byte(0x10), false),
+                (11, H256::repeat_byte(0x11), false),
+            ]
+        );
+        transport.assert_request("eth_getBlockByNumber", &[json!("latest"), json!(false)]);
+        transport.assert_request(
+            "eth_getLogs",
+            &[json!({ "fromBlock": "0xa", "toBlock": "0xb" })],
+        );
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn starts_after_latest_block() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        transport.add_response(block(12, 0x12));
+        transport.add_response(block(13, 0x13));
+        transport.add_response(block(12, 0x12));
+        transport.add_response(json!([log(13, 0x13)]));
+
+        let log = LogFilterBuilder::new(web3)
+            .stream_mode(LogStreamMode::polling())
+            .stream()
+            .boxed()
+            .next()
+            .immediate()
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(summary(&log), (13, H256::repeat_byte(0x13), false));
+        transport.assert_request("eth_getBlockByNumber", &[json!("latest"), json!(false)]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("latest"), json!(false)]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0xc"), json!(false)]);
+        transport.assert_request(
+            "eth_getLogs",
+            &[json!({ "fromBlock": "0xd", "toBlock": "0xd" })],
+        );
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn emits_removed_logs_for_orphaned_blocks() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        transport.add_response(block(12, 0x12));
+        transport.add_response(json!([log(11, 0x11), log(12, 0x12)]));
+        // block 12 was replaced, block 11 is still part of the chain
+        transport.add_response(block(13, 0xb3));
+        transport.add_response(block(12, 0xb2));
+        transport.add_response(block(11, 0x11));
+        transport.add_response(json!([log(12, 0xb2), log(13, 0xb3)]));
+
+        let logs = LogFilterBuilder::new(web3)
+            .from_block(11.into())
+            .stream_mode(LogStreamMode::polling())
+            .stream()
+            .take(5)
+            .try_collect::<Vec<_>>()
+            .immediate()
+            .unwrap();
+
+        assert_eq!(
+            logs.iter().map(summary).collect::<Vec<_>>(),
+            [
+                (11, H256::repeat_byte(0x11), false),
+                (12, H256::repeat_byte(0x12), false),
+                (12, H256::repeat_byte(0x12), true),
+                (12, H256::repeat_byte(0xb2), false),
+                (13, H256::repeat_byte(0xb3), false),
+            ]
+        );
+        transport.assert_request("eth_getBlockByNumber", &[json!("latest"), json!(false)]);
+        transport.assert_request(
+            "eth_getLogs",
+            &[json!({ "fromBlock": "0xb", "toBlock": "0xc" })],
+        );
+        transport.assert_request("eth_getBlockByNumber", &[json!("latest"), json!(false)]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0xc"), json!(false)]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0xb"), json!(false)]);
+        transport.assert_request(
+            "eth_getLogs",
+            &[json!({ "fromBlock": "0xc", "toBlock": "0xd" })],
+        );
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn skips_polls_while_node_is_lagging() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        transport.add_response(block(12, 0x12));
+        transport.add_response(json!([log(12, 0x12)]));
+        // the latest block goes backwards
+        transport.add_response(block(11, 0x11));
+        // the latest block is new, but the node does not know block 12 yet
+        transport.add_response(block(13, 0x13));
+        transport.add_response(json!(null));
+        // the node caught up
+        transport.add_response(block(13, 0x13));
+        transport.add_response(block(12, 0x12));
+        transport.add_response(json!([log(13, 0x13)]));
+
+        let logs = LogFilterBuilder::new(web3)
+            .from_block(12.into())
+            .stream_mode(LogStreamMode::polling())
+            .stream()
+            .take(2)
+            .try_collect::<Vec<_>>()
+            .immediate()
+            .unwrap();
+
+        assert_eq!(
+            logs.iter().map(summary).collect::<Vec<_>>(),
+            [
+                (12, H256::repeat_byte(0x12), false),
+                (13, H256::repeat_byte(0x13), false),
+            ]
+        );
+        transport.assert_request("eth_getBlockByNumber", &[json!("latest"), json!(false)]);
+        transport.assert_request(
+            "eth_getLogs",
+            &[json!({ "fromBlock": "0xc", "toBlock": "0xc" })],
+        );
+        transport.assert_request("eth_getBlockByNumber", &[json!("latest"), json!(false)]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("latest"), json!(false)]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0xc"), json!(false)]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("latest"), json!(false)]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0xc"), json!(false)]);
+        transport.assert_request(
+            "eth_getLogs",
+            &[json!({ "fromBlock": "0xd", "toBlock": "0xd" })],
+        );
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn forgets_blocks_below_reorg_depth() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        transport.add_response(block(12, 0x12));
+        transport.add_response(json!([log(10, 0x10)]));
+        // the whole tracked history was replaced
+        transport.add_response(block(13, 0xb3));
+        transport.add_response(block(12, 0xb2));
+        transport.add_response(json!([log(12, 0xb2)]));
+
+        let logs = LogFilterBuilder::new(web3)
+            .from_block(10.into())
+            .stream_mode(LogStreamMode::Polling { reorg_depth: 1 })
+            .stream()
+            .take(2)
+            .try_collect::<Vec<_>>()
+            .immediate()
+            .unwrap();
+
+        // NOTE: Block 10 is deeper than the reorg depth, so its log is not
+        //   removed and polling resumes from the oldest tracked block.
+        assert_eq!(
+            logs.iter().map(summary).collect::<Vec<_>>(),
+            [
+                (10, H256::repeat_byte(0x10), false),
+                (12, H256::repeat_byte(0xb2), false),
+            ]
+        );
+        transport.assert_request("eth_getBlockByNumber", &[json!("latest"), json!(false)]);
+        transport.assert_request(
+            "eth_getLogs",
+            &[json!({ "fromBlock": "0xa", "toBlock": "0xc" })],
+        );
+        transport.assert_request("eth_getBlockByNumber", &[json!("latest"), json!(false)]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0xc"), json!(false)]);
+        transport.assert_request(
+            "eth_getLogs",
+            &[json!({ "fromBlock": "0xc", "toBlock": "0xd" })],
+        );
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn uses_current_address_set_for_each_poll() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+        let addresses = AddressSet::from_iter([Address::repeat_byte(1)]);
+
+        transport.add_response(block(12, 0x12));
+        transport.add_response(json!([log(10, 0x10)]));
+        transport.add_response(block(13, 0x13));
+        transport.add_response(block(12, 0x12));
+        transport.add_response(json!([log(13, 0x13)]));
+        // the address set is empty, so no logs are queried
+        transport.add_response(block(14, 0x14));
+        transport.add_response(block(13, 0x13));
+
+        let mut logs = LogFilterBuilder::new(web3)
+            .from_block(10.into())
+            .address_set(addresses.clone())
+            .stream()
+            .boxed();
+
+        let log = logs.next().immediate().unwrap().unwrap();
+        assert_eq!(summary(&log), (10, H256::repeat_byte(0x10), false));
+
+        addresses.insert(Address::repeat_byte(2));
+        let log = logs.next().immediate().unwrap().unwrap();
+        assert_eq!(summary(&log), (13, H256::repeat_byte(0x13), false));
+
+        addresses.remove(&Address::repeat_byte(1));
+        addresses.remove(&Address::repeat_byte(2));
+        assert!(logs.next().immediate().unwrap().is_err());
+
+        transport.assert_request("eth_getBlockByNumber", &[json!("latest"), json!(false)]);
+        transport.assert_request(
+            "eth_getLogs",
+            &[json!({
+                "address": Address::repeat_byte(1),
+                "fromBlock": "0xa",
+                "toBlock": "0xc",
+            })],
+        );
+        transport.assert_request("eth_getBlockByNumber", &[json!("latest"), json!(false)]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0xc"), json!(false)]);
+        transport.assert_request(
+            "eth_getLogs",
+            &[json!({
+                "address": [Address::repeat_byte(1), Address::repeat_byte(2)],
+                "fromBlock": "0xd",
+                "toBlock": "0xd",
+            })],
+        );
+        transport.assert_request("eth_getBlockByNumber", &[json!("latest"), json!(false)]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("0xd"), json!(false)]);
+        transport.assert_request("eth_getBlockByNumber", &[json!("latest"), json!(false)]);
+        transport.assert_no_more_requests();
+    }
+}
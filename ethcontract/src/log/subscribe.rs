@@ -0,0 +1,179 @@
+//! Implementation of a log stream that subscribes to new logs with
+//! `eth_subscribe` for transports that support subscriptions.
+
+use super::{filter_stream, LogFilterBuilder, DEFAULT_POLL_INTERVAL};
+use crate::errors::ExecutionError;
+use futures::future::{Either, TryFutureExt};
+use futures::stream::{Stream, TryStreamExt};
+use web3::api::Web3;
+use web3::error::Error as Web3Error;
+use web3::types::{BlockNumber, Log};
+use web3::Transport;
+
+/// Creates a log stream that subscribes to new logs with the builder's
+/// subscription transport, falling back to a filter based stream if the
+/// transport or node does not support subscriptions.
+pub(super) fn stream<T: Transport>(
+    mut builder: LogFilterBuilder<T>,
+) -> impl Stream<Item = Result<Log, ExecutionError>> {
+    let web3 = builder.web3.clone();
+    let poll_interval = builder.poll_interval.unwrap_or(DEFAULT_POLL_INTERVAL);
+    let transport = builder
+        .subscription_transport
+        .take()
+        .filter(|transport| transport.supports_subscriptions() && streams_new_blocks(&builder));
+
+    let Some(transport) = transport else {
+        let filter = builder.into_filter().build();
+        return Either::Left(filter_stream(web3, filter, poll_interval));
+    };
+
+    // NOTE: Subscriptions always start at the latest block and do not support
+    //   the non-standard `limit` option.
+    builder.from_block = None;
+    builder.to_block = None;
+    builder.limit = None;
+    let filter = builder.into_filter().build();
+
+    let stream = async move {
+        let subscription = Web3::new(transport)
+            .eth_subscribe()
+            .subscribe_logs(filter.clone())
+            .await;
+        match subscription {
+            Ok(subscription) => Ok(Either::Left(subscription.map_err(ExecutionError::from))),
+            // NOTE: Nodes that do not support subscriptions reject
+            //   `eth_subscribe` with an RPC error, fall back to polling a
+            //   filter in that case.
+            Err(Web3Error::Rpc(_)) => Ok(Either::Right(filter_stream(web3, filter, poll_interval))),
+            Err(err) => Err(ExecutionError::from(err)),
+        }
+    }
+    .try_flatten_stream();
+    Either::Right(stream)
+}
+
+/// Returns true if the log filter only matches logs from new blocks, which is
+/// required for using a subscription.
+fn streams_new_blocks<T: Transport>(builder: &LogFilterBuilder<T>) -> bool {
+    matches!(builder.from_block, None | Some(BlockNumber::Latest))
+        && matches!(
+            builder.to_block,
+            None | Some(BlockNumber::Latest) | Some(BlockNumber::Pending)
+        )
+        && builder.block_hash.is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::fixtures::{log, removed_log};
+    use crate::test::prelude::*;
+    use crate::transport::DynTransport;
+    use futures::stream::StreamExt;
+    use web3::types::Address;
+
+    #[test]
+    fn subscribes_to_logs() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        transport.add_response(json!("0x1"));
+        transport.add_notification("0x1", log(1, 1));
+        transport.add_notification("0x1", removed_log(1, 1));
+        transport.add_notification("0x1", log(2, 2));
+
+        let logs = LogFilterBuilder::new(web3)
+            .subscription_transport(DynTransport::new_duplex(transport.clone()))
+            .address(vec![Address::repeat_byte(1)])
+            .limit(10)
+            .stream()
+            .try_collect::<Vec<_>>()
+            .immediate()
+            .unwrap();
+
+        assert_eq!(
+            logs.iter()
+                .map(|log| (log.block_number.unwrap().as_u64(), log.removed))
+                .collect::<Vec<_>>(),
+            [(1, Some(false)), (1, Some(true)), (2, Some(false))]
+        );
+        transport.assert_request(
+            "eth_subscribe",
+            &[json!("logs"), json!({ "address": Address::repeat_byte(1) })],
+        );
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn falls_back_to_filter_when_subscriptions_are_not_supported() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        transport.add_rpc_error(-32601, "the method eth_subscribe does not exist");
+        transport.add_response(json!("0xf0"));
+        transport.add_response(json!([log(1, 1)]));
+
+        let log = LogFilterBuilder::new(web3)
+            .subscription_transport(DynTransport::new_duplex(transport.clone()))
+            .stream()
+            .boxed()
+            .next()
+            .wait()
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(log.block_number, Some(1.into()));
+        transport.assert_request("eth_subscribe", &[json!("logs"), json!({})]);
+        transport.assert_request("eth_newFilter", &[json!({})]);
+        transport.assert_request("eth_getFilterChanges", &[json!("0xf0")]);
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn uses_filter_for_past_blocks() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        transport.add_response(json!("0xf0"));
+        transport.add_response(json!([log(1, 1)]));
+
+        let log = LogFilterBuilder::new(web3)
+            .subscription_transport(DynTransport::new_duplex(transport.clone()))
+            .from_block(1.into())
+            .stream()
+            .boxed()
+            .next()
+            .wait()
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(log.block_number, Some(1.into()));
+        transport.assert_request("eth_newFilter", &[json!({ "fromBlock": "0x1" })]);
+        transport.assert_request("eth_getFilterChanges", &[json!("0xf0")]);
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn uses_filter_without_subscription_support() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        transport.add_response(json!("0xf0"));
+        transport.add_response(json!([log(1, 1)]));
+
+        let log = LogFilterBuilder::new(web3)
+            .subscription_transport(DynTransport::new(transport.clone()))
+            .stream()
+            .boxed()
+            .next()
+            .wait()
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(log.block_number, Some(1.into()));
+        transport.assert_request("eth_newFilter", &[json!({})]);
+        transport.assert_request("eth_getFilterChanges", &[json!("0xf0")]);
+        transport.assert_no_more_requests();
+    }
+}
@@ -0,0 +1,568 @@
+//! Module containing components to aggregate multiple contract view calls
+//! into a single `eth_call` to the canonical Multicall3 contract.
+//!
+//! This is an alternative to [`CallBatch`](crate::batch::CallBatch) for nodes
+//! that do not support JSON RPC batches or that count every request of a
+//! batch individually.
+
+use crate::errors::{revert, ExecutionError, MethodError};
+use crate::state_override::{self, StateOverrides};
+use crate::tokens::Tokenize;
+use ethcontract_common::abi::{Function, Param, ParamType, StateMutability, Token};
+use futures::channel::oneshot::channel;
+use lazy_static::lazy_static;
+use web3::api::Web3;
+use web3::error::{Error as Web3Error, TransportError};
+use web3::types::{Address, BlockId, Bytes, CallRequest, H160};
+use web3::Transport;
+
+/// The address of the Multicall3 contract, which is deployed at the same
+/// address on most chains.
+pub const MULTICALL3_ADDRESS: Address = H160([
+    0xca, 0x11, 0xbd, 0xe0, 0x59, 0x77, 0xb3, 0x63, 0x11, 0x67, 0x02, 0x88, 0x62, 0xbe, 0x2a, 0x17,
+    0x39, 0x76, 0xca, 0x11,
+]);
+
+/// The default maximum size in bytes of the calldata of a single `aggregate3`
+/// call.
+pub const DEFAULT_MAX_CALLDATA_SIZE: usize = 100_000;
+
+lazy_static! {
+    /// The Multicall3 `aggregate3` function.
+    static ref AGGREGATE3: Function = {
+        #[allow(deprecated)]
+        let function = Function {
+            name: "aggregate3".into(),
+            inputs: vec![Param {
+                name: "calls".into(),
+                kind: ParamType::Array(Box::new(ParamType::Tuple(vec![
+                    ParamType::Address,
+                    ParamType::Bool,
+                    ParamType::Bytes,
+                ]))),
+                internal_type: None,
+            }],
+            outputs: vec![Param {
+                name: "returnData".into(),
+                kind: ParamType::Array(Box::new(ParamType::Tuple(vec![
+                    ParamType::Bool,
+                    ParamType::Bytes,
+                ]))),
+                internal_type: None,
+            }],
+            constant: None,
+            state_mutability: StateMutability::Payable,
+        };
+        function
+    };
+}
+
+/// Struct allowing to aggregate multiple contract view calls into `aggregate3`
+/// calls on a Multicall3 contract.
+///
+/// Calls are added with [`ViewMethodBuilder::multicall`] and resolve
+/// individually once the batch is executed with
+/// [`execute_all`](Self::execute_all).
+///
+/// [`ViewMethodBuilder::multicall`]: crate::contract::ViewMethodBuilder::multicall
+pub struct MulticallBatch<T: Transport> {
+    web3: Web3<T>,
+    address: Address,
+    block: Option<BlockId>,
+    require_canonical: bool,
+    overrides: Option<StateOverrides>,
+    max_calldata_size: usize,
+    calls: Vec<PendingCall>,
+}
+
+struct PendingCall {
+    target: Address,
+    allow_failure: bool,
+    data: Bytes,
+    handler: CompletionHandler,
+}
+
+type CompletionHandler = Box<dyn FnOnce(Result<(bool, Vec<u8>), Web3Error>) + Send>;
+
+impl<T: Transport> MulticallBatch<T> {
+    /// Creates a new batch using the Multicall3 contract at its canonical
+    /// address.
+    pub fn new(web3: Web3<T>) -> Self {
+        MulticallBatch {
+            web3,
+            address: MULTICALL3_ADDRESS,
+            block: None,
+            require_canonical: false,
+            overrides: None,
+            max_calldata_size: DEFAULT_MAX_CALLDATA_SIZE,
+            calls: Vec::new(),
+        }
+    }
+
+    /// Specify the address of the Multicall3 contract, for chains where it is
+    /// not deployed at its canonical address.
+    pub fn address(mut self, value: Address) -> Self {
+        self.address = value;
+        self
+    }
+
+    /// Specify the block height for the calls, if not specified then latest
+    /// mined block will be used.
+    pub fn block(mut self, value: BlockId) -> Self {
+        self.block = Some(value);
+        self
+    }
+
+    /// Specify whether a block specified by hash must be part of the canonical
+    /// chain. If set, the calls fail instead of reading the state of a block
+    /// that was reorged out of the chain (EIP-1898).
+    pub fn require_canonical(mut self, value: bool) -> Self {
+        self.require_canonical = value;
+        self
+    }
+
+    /// Specify state overrides for the `aggregate3` calls. Note that state
+    /// overrides are not supported by all nodes.
+    pub fn state_overrides(mut self, value: StateOverrides) -> Self {
+        self.overrides = Some(value);
+        self
+    }
+
+    /// Specify the maximum size in bytes of the calldata of a single
+    /// `aggregate3` call. Calls are split into multiple `aggregate3` calls
+    /// when they exceed this size.
+    pub fn max_calldata_size(mut self, value: usize) -> Self {
+        self.max_calldata_size = value;
+        self
+    }
+
+    /// Returns the number of calls in the batch.
+    pub fn len(&self) -> usize {
+        self.calls.len()
+    }
+
+    /// Returns true if the batch does not contain any calls.
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    /// Adds a call to the batch. The returned future only resolves once the
+    /// batch is executed, and resolves to an error if the batch is dropped
+    /// without executing.
+    ///
+    /// Calls for a specific block resolve to an error right away unless the
+    /// batch executes on the same block with the same `requireCanonical` flag.
+    pub(crate) fn push<R>(
+        &mut self,
+        function: Function,
+        target: Address,
+        data: Bytes,
+        block: Option<BlockId>,
+        require_canonical: bool,
+        allow_failure: bool,
+    ) -> impl std::future::Future<Output = Result<R, MethodError>>
+    where
+        R: Tokenize + Send + 'static,
+    {
+        let (tx, rx) = channel();
+        let signature = function.signature();
+        let mismatch = block.filter(|block| {
+            (Some(*block), require_canonical) != (self.block, self.require_canonical)
+        });
+        if let Some(block) = mismatch {
+            let err = ExecutionError::MulticallBlockMismatch {
+                call: block,
+                batch: self.block,
+            };
+            let _ = tx.send(Err(MethodError::new(&function, err)));
+        } else {
+            self.calls.push(PendingCall {
+                target,
+                allow_failure,
+                data,
+                handler: Box::new(move |result| {
+                    let _ = tx.send(decode_result::<R>(&function, result));
+                }),
+            });
+        }
+        async move {
+            rx.await.unwrap_or_else(|_| {
+                Err(MethodError::from_parts(
+                    signature,
+                    ExecutionError::Web3(Web3Error::Transport(TransportError::Message(
+                        "Batch has been dropped without executing".to_owned(),
+                    ))),
+                ))
+            })
+        }
+    }
+
+    /// Execute and resolve all enqueued calls with as few `aggregate3` calls
+    /// as the maximum calldata size allows. Failures of an `aggregate3` call
+    /// are forwarded to all of its calls.
+    pub async fn execute_all(self) {
+        let Self {
+            web3,
+            address,
+            block,
+            require_canonical,
+            overrides,
+            max_calldata_size,
+            calls,
+        } = self;
+
+        for chunk in chunks(calls, max_calldata_size) {
+            let (tokens, handlers): (Vec<_>, Vec<_>) = chunk
+                .into_iter()
+                .map(|call| {
+                    let token = Token::Tuple(vec![
+                        Token::Address(call.target),
+                        Token::Bool(call.allow_failure),
+                        Token::Bytes(call.data.0),
+                    ]);
+                    (token, call.handler)
+                })
+                .unzip();
+
+            let data = AGGREGATE3
+                .encode_input(&[Token::Array(tokens)])
+                .expect("aggregate3 input is always valid");
+            let call = CallRequest {
+                to: Some(address),
+                data: Some(Bytes(data)),
+                ..Default::default()
+            };
+            let result =
+                state_override::call(&web3, call, block, require_canonical, overrides.as_ref())
+                    .await
+                    .and_then(|bytes| decode_aggregate3(&bytes.0));
+
+            match result {
+                Ok(results) => {
+                    let mut results = results.into_iter();
+                    for handler in handlers {
+                        handler(results.next().ok_or_else(|| {
+                            Web3Error::Decoder(
+                                "aggregate3 result did not contain enough results".to_owned(),
+                            )
+                        }));
+                    }
+                }
+                Err(err) => {
+                    for handler in handlers {
+                        handler(Err(err.clone()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Splits calls into chunks whose `aggregate3` calldata does not exceed the
+/// specified size. A single call exceeding the size is sent on its own.
+fn chunks(calls: Vec<PendingCall>, max_calldata_size: usize) -> Vec<Vec<PendingCall>> {
+    // NOTE: The calldata consists of the selector, the offset and length of
+    //   the calls array, and for each call its offset and the address, flag,
+    //   offset, length and padded contents of its data.
+    const HEADER_SIZE: usize = 4 + 32 + 32;
+    const CALL_SIZE: usize = 32 * 5;
+
+    let mut chunks = Vec::new();
+    let mut chunk = Vec::new();
+    let mut size = HEADER_SIZE;
+    for call in calls {
+        let call_size = CALL_SIZE + call.data.0.len().div_ceil(32) * 32;
+        if !chunk.is_empty() && size + call_size > max_calldata_size {
+            chunks.push(std::mem::take(&mut chunk));
+            size = HEADER_SIZE;
+        }
+        size += call_size;
+        chunk.push(call);
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+/// Decodes the results of an `aggregate3` call.
+fn decode_aggregate3(bytes: &[u8]) -> Result<Vec<(bool, Vec<u8>)>, Web3Error> {
+    let invalid = || Web3Error::Decoder("invalid aggregate3 result".to_owned());
+    let mut tokens = AGGREGATE3
+        .decode_output(bytes)
+        .map_err(|err| Web3Error::Decoder(err.to_string()))?;
+    let results = match tokens.pop() {
+        Some(Token::Array(results)) => results,
+        _ => return Err(invalid()),
+    };
+    results
+        .into_iter()
+        .map(|result| match result {
+            Token::Tuple(fields) => match &fields[..] {
+                [Token::Bool(success), Token::Bytes(data)] => Ok((*success, data.clone())),
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        })
+        .collect()
+}
+
+/// Decodes the result of a single call, converting reverts into errors.
+fn decode_result<R: Tokenize>(
+    function: &Function,
+    result: Result<(bool, Vec<u8>), Web3Error>,
+) -> Result<R, MethodError> {
+    match result {
+        Ok((true, data)) => crate::contract::decode_response(function, &data),
+        Ok((false, data)) => Err(MethodError::new(
+            function,
+            ExecutionError::Revert(revert::decode_reason(&data)),
+        )),
+        Err(err) => Err(MethodError::new(function, err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contract::{MethodBuilder, ViewMethodBuilder};
+    use crate::test::prelude::*;
+    use ethcontract_common::abi::Param;
+    use futures::future::join_all;
+    use web3::types::{BlockNumber, H256, U256};
+
+    fn function(name: &str) -> Function {
+        #[allow(deprecated)]
+        Function {
+            name: name.to_owned(),
+            inputs: Vec::new(),
+            outputs: vec![Param {
+                name: "".to_owned(),
+                kind: ParamType::Uint(256),
+                internal_type: None,
+            }],
+            constant: None,
+            state_mutability: Default::default(),
+        }
+    }
+
+    fn view(
+        web3: &Web3<TestTransport>,
+        address: Address,
+        name: &str,
+    ) -> ViewMethodBuilder<TestTransport, U256> {
+        let function = function(name);
+        let data = function.encode_input(&[]).unwrap();
+        MethodBuilder::new(web3.clone(), function, address, Bytes(data)).view()
+    }
+
+    fn encode_results(results: &[(bool, Vec<u8>)]) -> serde_json::Value {
+        let tokens = results
+            .iter()
+            .map(|(success, data)| {
+                Token::Tuple(vec![Token::Bool(*success), Token::Bytes(data.clone())])
+            })
+            .collect();
+        let bytes = ethcontract_common::abi::encode(&[Token::Array(tokens)]);
+        json!(format!("0x{}", hex::encode(bytes)))
+    }
+
+    fn uint(value: u64) -> Vec<u8> {
+        ethcontract_common::abi::encode(&[Token::Uint(value.into())])
+    }
+
+    #[test]
+    fn aggregates_calls() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+        let address = addr!("0x0101010101010101010101010101010101010101");
+
+        let mut batch =
+            MulticallBatch::new(web3.clone()).block(BlockNumber::Number(42.into()).into());
+        let first = view(&web3, address, "first").multicall(&mut batch);
+        let second = view(&web3, address, "second").multicall_with(&mut batch, false);
+        assert_eq!(batch.len(), 2);
+
+        transport.add_response(encode_results(&[(true, uint(1)), (true, uint(2))]));
+        batch.execute_all().immediate();
+
+        assert_eq!(first.immediate().unwrap(), U256::from(1));
+        assert_eq!(second.immediate().unwrap(), U256::from(2));
+
+        let data = AGGREGATE3
+            .encode_input(&[Token::Array(
+                [("first", true), ("second", false)]
+                    .iter()
+                    .map(|(name, allow_failure)| {
+                        Token::Tuple(vec![
+                            Token::Address(address),
+                            Token::Bool(*allow_failure),
+                            Token::Bytes(function(name).encode_input(&[]).unwrap()),
+                        ])
+                    })
+                    .collect(),
+            )])
+            .unwrap();
+        transport.assert_request(
+            "eth_call",
+            &[
+                json!({
+                    "to": MULTICALL3_ADDRESS,
+                    "data": Bytes(data),
+                }),
+                json!("0x2a"),
+            ],
+        );
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn rejects_calls_for_other_blocks() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+        let address = addr!("0x0101010101010101010101010101010101010101");
+        let block = BlockId::Hash(H256::repeat_byte(0x42));
+
+        let mut batch = MulticallBatch::new(web3.clone())
+            .block(block)
+            .require_canonical(true);
+        let pinned = view(&web3, address, "pinned")
+            .block(block)
+            .require_canonical(true)
+            .multicall(&mut batch);
+        let unpinned = view(&web3, address, "unpinned").multicall(&mut batch);
+        let not_canonical = view(&web3, address, "not_canonical")
+            .block(block)
+            .multicall(&mut batch);
+        let other = view(&web3, address, "other")
+            .block(BlockNumber::Number(42.into()).into())
+            .multicall(&mut batch);
+        assert_eq!(batch.len(), 2);
+
+        transport.add_response(encode_results(&[(true, uint(1)), (true, uint(2))]));
+        batch.execute_all().immediate();
+
+        assert_eq!(pinned.immediate().unwrap(), U256::from(1));
+        assert_eq!(unpinned.immediate().unwrap(), U256::from(2));
+        assert!(matches!(
+            not_canonical.immediate().unwrap_err().inner,
+            ExecutionError::MulticallBlockMismatch { call, batch: Some(batch) }
+                if call == block && batch == block
+        ));
+        assert!(matches!(
+            other.immediate().unwrap_err().inner,
+            ExecutionError::MulticallBlockMismatch { batch: Some(batch), .. } if batch == block
+        ));
+
+        transport.assert_request_matches("eth_call", |params| {
+            params[1]
+                == json!({
+                    "blockHash": H256::repeat_byte(0x42),
+                    "requireCanonical": true,
+                })
+        });
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn decodes_reverts_per_call() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+        let address = addr!("0x0101010101010101010101010101010101010101");
+
+        let mut batch = MulticallBatch::new(web3.clone());
+        let calls = vec![
+            view(&web3, address, "a").multicall(&mut batch),
+            view(&web3, address, "b").multicall(&mut batch),
+            view(&web3, address, "c").multicall(&mut batch),
+        ];
+
+        transport.add_response(encode_results(&[
+            (false, revert::encode_reason("no")),
+            (false, Vec::new()),
+            (true, Vec::new()),
+        ]));
+        batch.execute_all().immediate();
+
+        let results = join_all(calls).immediate();
+        assert!(matches!(
+            &results[0].as_ref().unwrap_err().inner,
+            ExecutionError::Revert(Some(reason)) if reason == "no"
+        ));
+        assert!(matches!(
+            &results[1].as_ref().unwrap_err().inner,
+            ExecutionError::Revert(None)
+        ));
+        assert!(matches!(
+            &results[2].as_ref().unwrap_err().inner,
+            ExecutionError::AbiDecode(_)
+        ));
+    }
+
+    #[test]
+    fn chunks_by_calldata_size() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+        let address = addr!("0x0101010101010101010101010101010101010101");
+
+        // NOTE: Each call takes 192 bytes of calldata, so two calls fit into
+        //   a single `aggregate3` call.
+        let mut batch = MulticallBatch::new(web3.clone()).max_calldata_size(4 + 64 + 2 * 192);
+        let calls = (0..3)
+            .map(|_| view(&web3, address, "value").multicall(&mut batch))
+            .collect::<Vec<_>>();
+
+        transport.add_response(encode_results(&[(true, uint(1)), (true, uint(2))]));
+        transport.add_response(encode_results(&[(true, uint(3))]));
+        batch.execute_all().immediate();
+
+        let results = join_all(calls).immediate();
+        assert_eq!(
+            results.into_iter().map(Result::unwrap).collect::<Vec<_>>(),
+            vec![U256::from(1), U256::from(2), U256::from(3)]
+        );
+        transport.assert_request_matches("eth_call", |_| true);
+        transport.assert_request_matches("eth_call", |_| true);
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn fails_all_calls_if_aggregate_fails() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+        let address = addr!("0x0101010101010101010101010101010101010101");
+
+        let mut batch = MulticallBatch::new(web3.clone());
+        let calls = vec![
+            view(&web3, address, "a").multicall_with(&mut batch, false),
+            view(&web3, address, "b").multicall_with(&mut batch, true),
+        ];
+
+        transport.add_error(Web3Error::Unreachable);
+        batch.execute_all().immediate();
+
+        for result in join_all(calls).immediate() {
+            assert!(matches!(
+                result.unwrap_err().inner,
+                ExecutionError::Web3(Web3Error::Unreachable)
+            ));
+        }
+    }
+
+    #[test]
+    fn resolves_calls_to_error_if_dropped() {
+        let transport = TestTransport::new();
+        let web3 = Web3::new(transport);
+        let address = addr!("0x0101010101010101010101010101010101010101");
+
+        let call = {
+            let mut batch = MulticallBatch::new(web3.clone());
+            view(&web3, address, "a").multicall(&mut batch)
+        };
+
+        assert!(matches!(
+            call.immediate().unwrap_err().inner,
+            ExecutionError::Web3(Web3Error::Transport(_))
+        ));
+    }
+}
@@ -0,0 +1,234 @@
+//! Module containing types for overriding account state in `eth_call`s and
+//! `eth_estimateGas`.
+//!
+//! State overrides are passed as the third `eth_call` or `eth_estimateGas`
+//! parameter and are supported by Geth and most Geth compatible nodes. They
+//! allow simulating calls as if accounts had a different balance, nonce, code
+//! or storage.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use web3::api::Web3;
+use web3::error::Error as Web3Error;
+use web3::helpers;
+use web3::types::{Address, BlockId, BlockNumber, Bytes, CallRequest, H256, U256, U64};
+use web3::Transport;
+
+/// A set of account state overrides for an `eth_call`, keyed by account
+/// address.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct StateOverrides(pub BTreeMap<Address, AccountOverride>);
+
+/// State overrides for a single account.
+///
+/// Note that `state` and `state_diff` are mutually exclusive, nodes reject
+/// overrides that specify both for the same account.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountOverride {
+    /// Fake balance to set for the account.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub balance: Option<U256>,
+    /// Fake nonce to set for the account.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<U64>,
+    /// Fake code to set for the account.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<Bytes>,
+    /// Fake storage to replace the entire account storage with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<BTreeMap<H256, H256>>,
+    /// Fake storage slots to set, leaving the other slots of the account
+    /// storage untouched.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_diff: Option<BTreeMap<H256, H256>>,
+}
+
+impl StateOverrides {
+    /// Creates an empty set of state overrides.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns true if no account is overridden.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns a mutable reference to the overrides of an account, inserting
+    /// empty overrides if the account is not overridden yet.
+    pub fn account(&mut self, address: Address) -> &mut AccountOverride {
+        self.0.entry(address).or_default()
+    }
+
+    /// Override the balance of an account.
+    pub fn balance(mut self, address: Address, value: U256) -> Self {
+        self.account(address).balance = Some(value);
+        self
+    }
+
+    /// Override the nonce of an account.
+    pub fn nonce(mut self, address: Address, value: U64) -> Self {
+        self.account(address).nonce = Some(value);
+        self
+    }
+
+    /// Override the code of an account.
+    pub fn code(mut self, address: Address, value: Bytes) -> Self {
+        self.account(address).code = Some(value);
+        self
+    }
+
+    /// Replace the entire storage of an account with the specified slots.
+    /// Subsequent calls for the same account add to the replaced storage.
+    pub fn state(mut self, address: Address, slot: H256, value: H256) -> Self {
+        self.account(address)
+            .state
+            .get_or_insert_with(Default::default)
+            .insert(slot, value);
+        self
+    }
+
+    /// Override a single storage slot of an account.
+    pub fn state_diff(mut self, address: Address, slot: H256, value: H256) -> Self {
+        self.account(address)
+            .state_diff
+            .get_or_insert_with(Default::default)
+            .insert(slot, value);
+        self
+    }
+}
+
+/// Returns the parameters of an `eth_call`, including the state overrides if
+/// there are any. Blocks specified by hash are required to be canonical if
+/// `require_canonical` is set.
+pub(crate) fn call_params(
+    call: &CallRequest,
+    block: Option<BlockId>,
+    require_canonical: bool,
+    overrides: Option<&StateOverrides>,
+) -> Vec<serde_json::Value> {
+    let block = match block {
+        Some(BlockId::Hash(hash)) if require_canonical => serde_json::json!({
+            "blockHash": hash,
+            "requireCanonical": true,
+        }),
+        block => helpers::serialize(&block.unwrap_or_else(|| BlockNumber::Latest.into())),
+    };
+    let mut params = vec![helpers::serialize(call), block];
+    if let Some(overrides) = overrides {
+        params.push(helpers::serialize(overrides));
+    }
+    params
+}
+
+/// Returns the parameters of an `eth_estimateGas`, including the state
+/// overrides if there are any. The block is omitted if it is not specified
+/// and there are no overrides.
+pub(crate) fn estimate_gas_params(
+    call: &CallRequest,
+    block: Option<BlockNumber>,
+    overrides: Option<&StateOverrides>,
+) -> Vec<serde_json::Value> {
+    let mut params = vec![helpers::serialize(call)];
+    match (block, overrides) {
+        (block, Some(overrides)) => {
+            params.push(helpers::serialize(&block.unwrap_or(BlockNumber::Latest)));
+            params.push(helpers::serialize(overrides));
+        }
+        (Some(block), None) => params.push(helpers::serialize(&block)),
+        (None, None) => {}
+    }
+    params
+}
+
+/// Performs an `eth_estimateGas` with optional state overrides.
+pub(crate) async fn estimate_gas<T: Transport>(
+    web3: &Web3<T>,
+    call: CallRequest,
+    block: Option<BlockNumber>,
+    overrides: Option<&StateOverrides>,
+) -> Result<U256, Web3Error> {
+    let params = estimate_gas_params(&call, block, overrides);
+    let value = web3.transport().execute("eth_estimateGas", params).await?;
+    helpers::decode(value)
+}
+
+/// Performs an `eth_call` with optional state overrides.
+pub(crate) async fn call<T: Transport>(
+    web3: &Web3<T>,
+    call: CallRequest,
+    block: Option<BlockId>,
+    require_canonical: bool,
+    overrides: Option<&StateOverrides>,
+) -> Result<Bytes, Web3Error> {
+    let params = call_params(&call, block, require_canonical, overrides);
+    let value = web3.transport().execute("eth_call", params).await?;
+    helpers::decode(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::prelude::*;
+
+    #[test]
+    fn serializes_overrides() {
+        let account = addr!("0x0101010101010101010101010101010101010101");
+        let contract = addr!("0x0202020202020202020202020202020202020202");
+        let overrides = StateOverrides::new()
+            .balance(account, 1_000.into())
+            .nonce(account, 7.into())
+            .code(contract, Bytes(vec![0x60, 0x00]))
+            .state_diff(contract, H256::from_low_u64_be(1), H256::from_low_u64_be(2))
+            .state(account, H256::zero(), H256::from_low_u64_be(3));
+
+        assert_eq!(
+            serde_json::to_value(&overrides).unwrap(),
+            json!({
+                "0x0101010101010101010101010101010101010101": {
+                    "balance": "0x3e8",
+                    "nonce": "0x7",
+                    "state": {
+                        "0x0000000000000000000000000000000000000000000000000000000000000000":
+                            "0x0000000000000000000000000000000000000000000000000000000000000003",
+                    },
+                },
+                "0x0202020202020202020202020202020202020202": {
+                    "code": "0x6000",
+                    "stateDiff": {
+                        "0x0000000000000000000000000000000000000000000000000000000000000001":
+                            "0x0000000000000000000000000000000000000000000000000000000000000002",
+                    },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn call_includes_overrides() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+        let account = addr!("0x0101010101010101010101010101010101010101");
+        let overrides = StateOverrides::new().balance(account, 1.into());
+
+        transport.add_response(json!("0x01"));
+        transport.add_response(json!("0x02"));
+        let with = call(&web3, CallRequest::default(), None, false, Some(&overrides)).immediate();
+        let without = call(&web3, CallRequest::default(), None, false, None).immediate();
+
+        assert_eq!(with.unwrap().0, vec![1]);
+        assert_eq!(without.unwrap().0, vec![2]);
+        transport.assert_request(
+            "eth_call",
+            &[
+                json!({}),
+                json!("latest"),
+                json!({ "0x0101010101010101010101010101010101010101": { "balance": "0x1" } }),
+            ],
+        );
+        transport.assert_request("eth_call", &[json!({}), json!("latest")]);
+        transport.assert_no_more_requests();
+    }
+}
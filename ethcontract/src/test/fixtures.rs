@@ -0,0 +1,78 @@
+//! Fixtures shared by the unit tests of different modules.
+
+use crate::indexer::Checkpoint;
+use crate::secret::PrivateKey;
+use ethcontract_common::hash::TypedData;
+use serde_json::{json, Value};
+use web3::types::{Address, Block, Log, H256, U64};
+
+/// Returns a JSON block with the specified number and a hash made of the
+/// repeated `hash` byte.
+pub fn block(number: u64, hash: u8) -> Value {
+    serde_json::to_value(Block::<H256> {
+        number: Some(number.into()),
+        hash: Some(H256::repeat_byte(hash)),
+        ..Default::default()
+    })
+    .unwrap()
+}
+
+/// Returns a JSON log emitted in the specified block.
+pub fn log(number: u64, hash: u8) -> Value {
+    json!({
+        "address": Address::zero(),
+        "topics": [],
+        "data": "0x",
+        "blockHash": H256::repeat_byte(hash),
+        "blockNumber": U64::from(number),
+        "transactionHash": H256::zero(),
+        "transactionIndex": "0x0",
+        "logIndex": "0x0",
+        "removed": false,
+    })
+}
+
+/// Returns a JSON log emitted in the specified block that was removed because
+/// of a reorg.
+pub fn removed_log(number: u64, hash: u8) -> Value {
+    let mut log = log(number, hash);
+    log["removed"] = json!(true);
+    log
+}
+
+/// Returns a checkpoint for the specified block.
+pub fn checkpoint(block_number: u64, hash: u8) -> Checkpoint {
+    Checkpoint {
+        block_number,
+        block_hash: H256::repeat_byte(hash),
+    }
+}
+
+/// Summarizes a log as its block number, block hash and removed flag.
+pub fn summary(log: &Log) -> (u64, H256, bool) {
+    (
+        log.block_number.unwrap().as_u64(),
+        log.block_hash.unwrap(),
+        log.removed == Some(true),
+    )
+}
+
+/// Returns the typed data of the EIP-712 specification example, which is
+/// shared with the typed data hashing tests of `ethcontract-common`.
+pub fn mail() -> TypedData {
+    serde_json::from_str(include_str!(
+        "../../../ethcontract-common/src/hash/eip712_example.json"
+    ))
+    .unwrap()
+}
+
+/// Returns the private key `keccak256("cow")`, the key used in the EIP-712
+/// examples.
+pub fn cow() -> PrivateKey {
+    key!("0xc85ef7d79691fe79573b1a7064c19c1a9819ebdbd1faaab1a8ec92344438aaf4")
+}
+
+/// Returns the private key used for signing transactions in tests.
+pub fn key() -> PrivateKey {
+    key!("0x0102030405060708091011121314151617181920212223242526272829303132")
+}
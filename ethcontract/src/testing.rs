@@ -0,0 +1,31 @@
+//! Utilities for unit testing code built on top of `ethcontract` without a
+//! node. This module is only available with the `testing` feature.
+//!
+//! The [`TestTransport`] is a scripted transport that returns queued responses
+//! in order and records the requests that were prepared for asserting on them
+//! later. The [`FutureTestExt`] trait provides helpers for resolving futures
+//! in synchronous tests.
+
+mod transport;
+
+pub use self::transport::TestTransport;
+use futures::future::FutureExt;
+use std::future::Future;
+
+/// An extension future to wait for a future.
+pub trait FutureTestExt: Future {
+    /// Block thread on a future completing.
+    fn wait(self) -> Self::Output;
+    /// Assert that future is ready immediately and return the output.
+    fn immediate(self) -> Self::Output;
+}
+
+impl<F: Future + Sized> FutureTestExt for F {
+    fn wait(self) -> Self::Output {
+        futures::executor::block_on(self)
+    }
+    fn immediate(self) -> Self::Output {
+        self.now_or_never()
+            .expect("future did not resolve immediately")
+    }
+}
@@ -0,0 +1,308 @@
+//! Implementation of a transport for testing purposes. This is largely based on
+//! the `rust-web3` `TestTransport` type with some modifications.
+
+use jsonrpc_core::{Call, ErrorCode, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use web3::api::SubscriptionId;
+use web3::futures::future::{self, Ready};
+use web3::futures::stream::{self, Iter};
+use web3::helpers;
+use web3::{error::Error, BatchTransport, DuplexTransport};
+use web3::{RequestId, Transport};
+
+/// Type alias for request method and value pairs
+type Requests = Vec<(String, Vec<Value>)>;
+
+#[derive(Debug, Default)]
+struct Inner {
+    asserted: usize,
+    requests: Requests,
+    responses: VecDeque<Response>,
+    notifications: BTreeMap<SubscriptionId, Vec<Value>>,
+}
+
+/// A queued response.
+#[derive(Debug)]
+enum Response {
+    Single(Result<Value, Error>),
+    Batch(Vec<Result<Value, Error>>),
+}
+
+/// Scripted transport for tests that returns queued responses in order and
+/// records prepared requests so they can be asserted on.
+///
+/// Batch requests consume a single queued response. This can either be a
+/// batch response added with [`add_batch_response`](Self::add_batch_response),
+/// an array value whose elements are returned as the successful results of the
+/// batch, or an error which fails the whole batch.
+///
+/// Subscriptions yield the notifications queued for their subscription ID
+/// with [`add_notification`](Self::add_notification) and then end.
+#[derive(Debug, Default, Clone)]
+pub struct TestTransport {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Transport for TestTransport {
+    type Out = Ready<Result<Value, Error>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        let request = helpers::build_request(1, method, params.clone());
+        let mut inner = self.inner.lock().unwrap();
+        inner.requests.push((method.into(), params));
+        (inner.requests.len(), request)
+    }
+
+    fn send(&self, id: RequestId, request: Call) -> Self::Out {
+        let response = self.inner.lock().unwrap().responses.pop_front();
+        match response {
+            Some(Response::Single(response)) => future::ready(response),
+            Some(Response::Batch(_)) => future::err(Error::InvalidResponse(format!(
+                "batch response queued for single request (id: {:?}): {:?}",
+                id, request
+            ))),
+            None => future::err(Error::InvalidResponse(format!(
+                "unexpected request (id: {:?}): {:?}",
+                id, request
+            ))),
+        }
+    }
+}
+
+impl BatchTransport for TestTransport {
+    type Batch = Ready<Result<Vec<Result<Value, Error>>, Error>>;
+
+    fn send_batch<T>(&self, requests: T) -> Self::Batch
+    where
+        T: IntoIterator<Item = (RequestId, Call)>,
+    {
+        let requests: Vec<_> = requests.into_iter().collect();
+        if requests.is_empty() {
+            return future::err(Error::Unreachable);
+        }
+
+        let response = self.inner.lock().unwrap().responses.pop_front();
+        match response {
+            Some(Response::Batch(responses)) => future::ok(responses),
+            Some(Response::Single(Ok(Value::Array(values)))) => {
+                future::ok(values.into_iter().map(Ok).collect())
+            }
+            Some(Response::Single(Ok(value))) => future::err(Error::InvalidResponse(format!(
+                "batch response should be a list of values: {}",
+                value
+            ))),
+            Some(Response::Single(Err(err))) => future::err(err),
+            None => future::err(Error::InvalidResponse(format!(
+                "unexpected batch request: {:?}",
+                requests
+            ))),
+        }
+    }
+}
+
+impl DuplexTransport for TestTransport {
+    type NotificationStream = Iter<std::vec::IntoIter<Value>>;
+
+    fn subscribe(&self, id: SubscriptionId) -> Result<Self::NotificationStream, Error> {
+        let notifications = self
+            .inner
+            .lock()
+            .unwrap()
+            .notifications
+            .remove(&id)
+            .unwrap_or_default();
+        Ok(stream::iter(notifications))
+    }
+
+    fn unsubscribe(&self, _id: SubscriptionId) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl TestTransport {
+    /// Create a new test transport instance.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add a response to an eventual request.
+    pub fn add_response(&mut self, value: Value) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.responses.push_back(Response::Single(Ok(value)));
+    }
+
+    /// Add an error to an eventual request, or to fail an eventual batch.
+    pub fn add_error(&mut self, error: Error) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.responses.push_back(Response::Single(Err(error)));
+    }
+
+    /// Add a JSON RPC error response to an eventual request.
+    pub fn add_rpc_error(&mut self, code: i64, message: &str) {
+        self.add_error(Error::Rpc(jsonrpc_core::Error {
+            code: ErrorCode::from(code),
+            message: message.into(),
+            data: None,
+        }));
+    }
+
+    /// Add responses for the requests of an eventual batch.
+    pub fn add_batch_response(&mut self, responses: Vec<Result<Value, Error>>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.responses.push_back(Response::Batch(responses));
+    }
+
+    /// Add a notification for an eventual subscription with the specified ID.
+    pub fn add_notification(&mut self, id: &str, value: Value) {
+        let mut inner = self.inner.lock().unwrap();
+        inner
+            .notifications
+            .entry(SubscriptionId::from(id.to_owned()))
+            .or_default()
+            .push(value);
+    }
+
+    /// Returns all requests that were prepared so far as method and
+    /// parameter pairs.
+    pub fn requests(&self) -> Vec<(String, Vec<Value>)> {
+        self.inner.lock().unwrap().requests.clone()
+    }
+
+    /// Assert that a request was made.
+    pub fn assert_request(&mut self, method: &str, params: &[Value]) {
+        let mut inner = self.inner.lock().unwrap();
+        let idx = inner.asserted;
+        inner.asserted += 1;
+
+        let (m, p) = inner.requests.get(idx).expect("Expected result.").clone();
+        assert_eq!(&m, method);
+        assert_eq!(&p[..], params);
+    }
+
+    /// Assert that a request was made with parameters matching a predicate.
+    pub fn assert_request_matches<F>(&mut self, method: &str, predicate: F)
+    where
+        F: FnOnce(&[Value]) -> bool,
+    {
+        let mut inner = self.inner.lock().unwrap();
+        let idx = inner.asserted;
+        inner.asserted += 1;
+
+        let (m, p) = inner.requests.get(idx).expect("Expected result.").clone();
+        assert_eq!(&m, method);
+        assert!(
+            predicate(&p),
+            "Request parameters do not match predicate: {:?}",
+            p
+        );
+    }
+
+    /// Assert that there are no more pending requests.
+    pub fn assert_no_more_requests(&self) {
+        let inner = self.inner.lock().unwrap();
+        assert_eq!(
+            inner.asserted,
+            inner.requests.len(),
+            "Expected no more requests, got: {:?}",
+            &inner.requests[inner.asserted..]
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FutureTestExt;
+    use futures::stream::StreamExt;
+    use serde_json::json;
+
+    #[test]
+    fn batch_responses() {
+        let mut transport = TestTransport::new();
+        let requests = {
+            let transport = transport.clone();
+            move || {
+                vec![
+                    transport.prepare("eth_blockNumber", vec![]),
+                    transport.prepare("eth_chainId", vec![]),
+                ]
+            }
+        };
+
+        transport.add_response(json!(["0x1", "0x2"]));
+        let results = transport.send_batch(requests()).immediate().unwrap();
+        assert_eq!(results.len(), 2);
+
+        transport.add_batch_response(vec![Ok(json!("0x1")), Err(Error::Unreachable)]);
+        let results = transport.send_batch(requests()).immediate().unwrap();
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+
+        transport.add_error(Error::Unreachable);
+        assert!(transport.send_batch(requests()).immediate().is_err());
+
+        transport.add_batch_response(vec![]);
+        assert!(transport
+            .execute("eth_blockNumber", vec![])
+            .immediate()
+            .is_err());
+    }
+
+    #[test]
+    fn rpc_errors() {
+        let mut transport = TestTransport::new();
+        transport.add_rpc_error(3, "execution reverted");
+
+        let err = transport
+            .execute("eth_call", vec![])
+            .immediate()
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Rpc(err) if err.code == ErrorCode::ServerError(3)
+                && err.message == "execution reverted"
+        ));
+    }
+
+    #[test]
+    fn request_predicates() {
+        let mut transport = TestTransport::new();
+        transport.prepare("eth_getBalance", vec![json!("0x01"), json!("latest")]);
+
+        transport.assert_request_matches("eth_getBalance", |params| {
+            params.len() == 2 && params[1] == "latest"
+        });
+        transport.assert_no_more_requests();
+        assert_eq!(transport.requests().len(), 1);
+    }
+
+    #[test]
+    fn subscription_notifications() {
+        let mut transport = TestTransport::new();
+        transport.add_notification("0x1", json!(1));
+        transport.add_notification("0x1", json!(2));
+
+        let notifications = transport
+            .subscribe(SubscriptionId::from("0x1".to_owned()))
+            .unwrap()
+            .collect::<Vec<_>>()
+            .immediate();
+        assert_eq!(notifications, [json!(1), json!(2)]);
+
+        let notifications = transport
+            .subscribe(SubscriptionId::from("0x1".to_owned()))
+            .unwrap()
+            .collect::<Vec<_>>()
+            .immediate();
+        assert!(notifications.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Request parameters do not match predicate")]
+    fn request_predicate_mismatch() {
+        let mut transport = TestTransport::new();
+        transport.prepare("eth_getBalance", vec![json!("0x01")]);
+        transport.assert_request_matches("eth_getBalance", |params| params.is_empty());
+    }
+}
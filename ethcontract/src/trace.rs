@@ -0,0 +1,85 @@
+//! Internal helpers for instrumenting contract interactions with `tracing`
+//! spans when the `tracing` feature is enabled. Without the feature, spans are
+//! zero-sized placeholders and instrumenting a future is a no-op.
+
+use std::future::Future;
+
+/// A span for instrumenting contract interactions.
+#[cfg(feature = "tracing")]
+pub(crate) type Span = tracing::Span;
+
+/// A placeholder span when the `tracing` feature is disabled.
+#[cfg(not(feature = "tracing"))]
+#[derive(Clone, Debug)]
+pub(crate) struct Span;
+
+/// Creates a new debug span with the specified name and fields. The span has
+/// an additional `duration_ms` field that is recorded by [`instrument`].
+macro_rules! span {
+    (parent: $parent:expr, $name:literal $(, $($fields:tt)*)?) => {{
+        #[cfg(feature = "tracing")]
+        let span = ::tracing::debug_span!(
+            parent: &$parent,
+            $name,
+            duration_ms = ::tracing::field::Empty
+            $(, $($fields)*)?
+        );
+        #[cfg(not(feature = "tracing"))]
+        let span = {
+            let _ = &$parent;
+            $crate::trace::Span
+        };
+        span
+    }};
+    ($name:literal $(, $($fields:tt)*)?) => {{
+        #[cfg(feature = "tracing")]
+        let span = ::tracing::debug_span!(
+            $name,
+            duration_ms = ::tracing::field::Empty
+            $(, $($fields)*)?
+        );
+        #[cfg(not(feature = "tracing"))]
+        let span = $crate::trace::Span;
+        span
+    }};
+}
+
+/// Records a value for a field of a span that was declared as
+/// `tracing::field::Empty`.
+macro_rules! record {
+    ($span:expr, $field:literal, ?$value:expr) => {{
+        #[cfg(feature = "tracing")]
+        $span.record($field, ::tracing::field::debug(&$value));
+        #[cfg(not(feature = "tracing"))]
+        let _ = (&$span, &$value);
+    }};
+    ($span:expr, $field:literal, %$value:expr) => {{
+        #[cfg(feature = "tracing")]
+        $span.record($field, ::tracing::field::display(&$value));
+        #[cfg(not(feature = "tracing"))]
+        let _ = (&$span, &$value);
+    }};
+}
+
+pub(crate) use {record, span};
+
+/// Runs a future within a span, recording its duration once it completes.
+pub(crate) async fn instrument<F>(span: Span, future: F) -> F::Output
+where
+    F: Future,
+{
+    #[cfg(feature = "tracing")]
+    {
+        use tracing::Instrument as _;
+
+        let start = std::time::Instant::now();
+        let output = future.instrument(span.clone()).await;
+        span.record("duration_ms", start.elapsed().as_millis() as u64);
+        output
+    }
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = span;
+        future.await
+    }
+}
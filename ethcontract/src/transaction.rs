@@ -3,17 +3,26 @@
 
 mod build;
 pub mod confirm;
+mod decode;
+pub mod gas_policy;
 pub mod gas_price;
 #[cfg(feature = "aws-kms")]
 pub mod kms;
 mod send;
+mod sign;
+mod unsigned;
 
 pub use self::build::Transaction;
 use self::confirm::ConfirmParams;
+pub use self::decode::{DecodeError, DecodedTransaction};
+pub use self::gas_policy::GasPolicy;
 pub use self::gas_price::GasPrice;
 pub use self::send::TransactionResult;
+pub use self::unsigned::{TransactionKind, UnsignedTransaction};
 use crate::errors::ExecutionError;
 use crate::secret::{Password, PrivateKey};
+use crate::state_override::{self, StateOverrides};
+use crate::transport::DynTransport;
 use web3::api::Web3;
 use web3::types::{AccessList, Address, Bytes, CallRequest, TransactionCondition, U256};
 use web3::Transport;
@@ -44,6 +53,17 @@ impl Account {
             Account::Kms(kms, _) => kms.public_address(),
         }
     }
+
+    /// Returns the chain ID of an account that signs transactions offline, if
+    /// it was specified.
+    pub fn chain_id(&self) -> Option<u64> {
+        match self {
+            Account::Offline(_, chain_id) => *chain_id,
+            #[cfg(feature = "aws-kms")]
+            Account::Kms(_, chain_id) => *chain_id,
+            _ => None,
+        }
+    }
 }
 
 /// The condition on which a transaction's `SendFuture` gets resolved.
@@ -85,6 +105,9 @@ pub struct TransactionBuilder<T: Transport> {
     pub gas: Option<U256>,
     /// Optional gas price to use for transaction. Defaults to None.
     pub gas_price: Option<GasPrice>,
+    /// Optional policy for padding gas estimates and limiting the transaction
+    /// fee. Defaults to None.
+    pub gas_policy: Option<GasPolicy>,
     /// The ETH value to send with the transaction. Defaults to 0.
     pub value: Option<U256>,
     /// The data for the transaction. Defaults to empty data.
@@ -97,6 +120,16 @@ pub struct TransactionBuilder<T: Transport> {
     pub resolve: Option<ResolveCondition>,
     /// Access list
     pub access_list: Option<AccessList>,
+    /// Build the transaction without querying the node for missing
+    /// parameters, failing instead. Defaults to `false`.
+    pub strict_offline: bool,
+    /// Optional transport used for subscribing to new blocks when waiting for
+    /// confirmations. Defaults to polling for new blocks.
+    pub subscription_transport: Option<DynTransport>,
+    /// Optional state overrides used when estimating gas with
+    /// [`TransactionBuilder::estimate_gas`]. Sent transactions are not
+    /// affected.
+    pub state_overrides: Option<StateOverrides>,
 }
 
 impl<T: Transport> TransactionBuilder<T> {
@@ -108,11 +141,15 @@ impl<T: Transport> TransactionBuilder<T> {
             to: None,
             gas: None,
             gas_price: None,
+            gas_policy: None,
             value: None,
             data: None,
             nonce: None,
             resolve: None,
             access_list: None,
+            strict_offline: false,
+            subscription_transport: None,
+            state_overrides: None,
         }
     }
 
@@ -144,6 +181,15 @@ impl<T: Transport> TransactionBuilder<T> {
         self
     }
 
+    /// Specify the gas policy to use. When a fee budget is specified, the
+    /// total fee is checked before sending. Note that transactions signed by
+    /// the node with a `Local` account need an explicit gas price for the fee
+    /// to be known, and fail to build without one.
+    pub fn gas_policy(mut self, value: GasPolicy) -> Self {
+        self.gas_policy = Some(value);
+        self
+    }
+
     /// Specify what how much ETH to transfer with the transaction, if not
     /// specified then no ETH will be sent.
     pub fn value(mut self, value: U256) -> Self {
@@ -178,6 +224,15 @@ impl<T: Transport> TransactionBuilder<T> {
         self
     }
 
+    /// Specify whether or not the transaction should be built in strict
+    /// offline mode. In this mode, building the transaction never queries the
+    /// node and instead fails if the chain ID, nonce, gas or gas price are not
+    /// specified, or if the account requires the node for signing.
+    pub fn strict_offline(mut self, value: bool) -> Self {
+        self.strict_offline = value;
+        self
+    }
+
     /// Specify the number of confirmations to use for the confirmation options.
     /// This is a utility method for specifying the resolve condition.
     pub fn confirmations(mut self, value: usize) -> Self {
@@ -195,6 +250,25 @@ impl<T: Transport> TransactionBuilder<T> {
         self
     }
 
+    /// Specify the transport used for waiting for confirmations with a
+    /// `newHeads` subscription instead of polling, usually the
+    /// [`DynTransport`] of the web3 provider. It is only used if it supports
+    /// subscriptions.
+    pub fn subscription_transport(mut self, transport: DynTransport) -> Self {
+        self.subscription_transport = Some(transport);
+        self
+    }
+
+    /// Specify state overrides for estimating gas, allowing to simulate the
+    /// transaction as if accounts had a different balance, nonce, code or
+    /// storage. The overrides are used both by [`Self::estimate_gas`] and when
+    /// estimating a missing gas limit while building the transaction. Note
+    /// that state overrides are not supported by all nodes.
+    pub fn state_overrides(mut self, value: StateOverrides) -> Self {
+        self.state_overrides = Some(value);
+        self
+    }
+
     /// Estimate the gas required for this transaction.
     pub async fn estimate_gas(self) -> Result<U256, ExecutionError> {
         let from = self.from.map(|account| account.address());
@@ -202,31 +276,32 @@ impl<T: Transport> TransactionBuilder<T> {
             .gas_price
             .map(|gas_price| gas_price.resolve_for_transaction())
             .unwrap_or_default();
-        self.web3
-            .eth()
-            .estimate_gas(
-                CallRequest {
-                    from,
-                    to: self.to,
-                    gas: None,
-                    gas_price: resolved_gas_price.gas_price,
-                    value: self.value,
-                    data: self.data.clone(),
-                    transaction_type: resolved_gas_price.transaction_type,
-                    access_list: self.access_list,
-                    max_fee_per_gas: resolved_gas_price.max_fee_per_gas,
-                    max_priority_fee_per_gas: resolved_gas_price.max_priority_fee_per_gas,
-                },
-                None,
-            )
-            .await
-            .map_err(From::from)
+        state_override::estimate_gas(
+            &self.web3,
+            CallRequest {
+                from,
+                to: self.to,
+                gas: None,
+                gas_price: resolved_gas_price.gas_price,
+                value: self.value,
+                data: self.data.clone(),
+                transaction_type: resolved_gas_price.transaction_type,
+                access_list: self.access_list,
+                max_fee_per_gas: resolved_gas_price.max_fee_per_gas,
+                max_priority_fee_per_gas: resolved_gas_price.max_priority_fee_per_gas,
+            },
+            None,
+            self.state_overrides.as_ref(),
+        )
+        .await
+        .map_err(From::from)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test::fixtures::key;
     use crate::test::prelude::*;
     use hex_literal::hex;
     use web3::types::{AccessListItem, H2048, H256};
@@ -257,6 +332,74 @@ mod tests {
         transport.assert_no_more_requests();
     }
 
+    #[test]
+    fn tx_builder_estimate_gas_with_state_overrides() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let from = addr!("0x9876543210987654321098765432109876543210");
+        let to = addr!("0x0123456789012345678901234567890123456789");
+
+        transport.add_response(json!("0x42")); // estimate gas response
+        let estimate_gas = TransactionBuilder::new(web3)
+            .from(Account::Local(from, None))
+            .to(to)
+            .state_overrides(StateOverrides::new().balance(from, 1_000.into()))
+            .estimate_gas()
+            .immediate()
+            .expect("success");
+
+        assert_eq!(estimate_gas, 0x42.into());
+        transport.assert_request(
+            "eth_estimateGas",
+            &[
+                json!({
+                    "from": from,
+                    "to": to,
+                }),
+                json!("latest"),
+                json!({ "0x9876543210987654321098765432109876543210": { "balance": "0x3e8" } }),
+            ],
+        );
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn tx_build_estimates_gas_with_state_overrides() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let from = addr!("0x9876543210987654321098765432109876543210");
+        let to = addr!("0x0123456789012345678901234567890123456789");
+
+        transport.add_response(json!("0x42")); // estimate gas response
+        let tx = TransactionBuilder::new(web3)
+            .from(Account::Local(from, None))
+            .to(to)
+            .gas_price(1.0.into())
+            .state_overrides(StateOverrides::new().balance(from, 1_000.into()))
+            .build()
+            .immediate()
+            .expect("failed to build transaction")
+            .request()
+            .unwrap();
+
+        assert_eq!(tx.gas, Some(0x42.into()));
+        transport.assert_request(
+            "eth_estimateGas",
+            &[
+                json!({
+                    "from": from,
+                    "to": to,
+                    "gasPrice": "0x1",
+                }),
+                json!("latest"),
+                json!({ "0x9876543210987654321098765432109876543210": { "balance": "0x3e8" } }),
+            ],
+        );
+        transport.assert_no_more_requests();
+    }
+
     #[test]
     fn tx_send_local() {
         let mut transport = TestTransport::new();
@@ -311,7 +454,7 @@ mod tests {
         let mut transport = TestTransport::new();
         let web3 = Web3::new(transport.clone());
 
-        let key = key!("0x0102030405060708091011121314151617181920212223242526272829303132");
+        let key = key();
         let chain_id = 77777;
         let tx_hash = H256(hex!(
             "248988e44deaff5162c3f998a8b1f510862366a68ef4339dff6ec89e120a6c19"
@@ -369,7 +512,7 @@ mod tests {
         let mut transport = TestTransport::new();
         let web3 = Web3::new(transport.clone());
 
-        let key = key!("0x0102030405060708091011121314151617181920212223242526272829303132");
+        let key = key();
         let chain_id = 77777;
         let tx_hash = H256(hex!(
             "248988e44deaff5162c3f998a8b1f510862366a68ef4339dff6ec89e120a6c19"
@@ -418,4 +561,384 @@ mod tests {
         transport.assert_request("eth_getTransactionReceipt", &[json!(tx_hash)]);
         transport.assert_no_more_requests();
     }
+
+    #[test]
+    fn tx_build_strict_offline() {
+        let transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let builder = TransactionBuilder::new(web3)
+            .from(Account::Offline(key(), Some(77777)))
+            .to(Address::zero())
+            .gas(0x1337.into())
+            .gas_price(f64::from(0x00ba_b10c).into())
+            .nonce(0x42.into())
+            .strict_offline(true);
+
+        let tx = builder
+            .clone()
+            .build()
+            .immediate()
+            .expect("failed to build offline transaction");
+        assert_eq!(
+            tx,
+            builder
+                .build_unsigned()
+                .immediate()
+                .expect("failed to build unsigned transaction")
+                .sign(&key())
+        );
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn tx_build_strict_offline_missing_parameters() {
+        let transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let key = key();
+        let builder = TransactionBuilder::new(web3)
+            .to(Address::zero())
+            .gas(0x1337.into())
+            .strict_offline(true);
+
+        let result = builder
+            .clone()
+            .from(Account::Offline(key.clone(), None))
+            .build()
+            .immediate();
+        assert!(matches!(
+            result,
+            Err(ExecutionError::MissingTransactionParameter("chain ID"))
+        ));
+
+        let result = builder
+            .clone()
+            .from(Account::Offline(key.clone(), Some(1)))
+            .nonce(0.into())
+            .build()
+            .immediate();
+        assert!(matches!(
+            result,
+            Err(ExecutionError::MissingTransactionParameter("gas price"))
+        ));
+
+        let result = builder
+            .clone()
+            .from(Account::Offline(key, Some(1)))
+            .gas_price(1.0.into())
+            .build_unsigned()
+            .immediate();
+        assert!(matches!(
+            result,
+            Err(ExecutionError::MissingTransactionParameter("nonce"))
+        ));
+
+        let result = builder
+            .from(Account::Local(Address::zero(), None))
+            .build()
+            .immediate();
+        assert!(matches!(
+            result,
+            Err(ExecutionError::OfflineSigningUnsupported)
+        ));
+
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn tx_build_unsigned_queries_missing_parameters() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let from = addr!("0x9876543210987654321098765432109876543210");
+        let to = addr!("0x0123456789012345678901234567890123456789");
+
+        transport.add_response(json!("0x1337")); // gas limit
+        transport.add_response(json!("0x42")); // nonce
+        transport.add_response(json!("0xbab10c")); // gas price
+        transport.add_response(json!("0x1")); // chain ID
+        let tx = TransactionBuilder::new(web3)
+            .from(Account::Local(from, None))
+            .to(to)
+            .access_list(vec![AccessListItem::default()])
+            .build_unsigned()
+            .immediate()
+            .expect("failed to build unsigned transaction");
+
+        transport.assert_request(
+            "eth_estimateGas",
+            &[json!({
+                "from": from,
+                "to": to,
+                "accessList": [{
+                    "address": "0x0000000000000000000000000000000000000000",
+                    "storageKeys": [],
+                }],
+            })],
+        );
+        transport.assert_request("eth_getTransactionCount", &[json!(from), json!("latest")]);
+        transport.assert_request("eth_gasPrice", &[]);
+        transport.assert_request("eth_chainId", &[]);
+        transport.assert_no_more_requests();
+
+        assert_eq!(
+            tx,
+            UnsignedTransaction {
+                chain_id: 1,
+                nonce: 0x42.into(),
+                gas: 0x1337.into(),
+                to: Some(to),
+                value: 0.into(),
+                data: Bytes::default(),
+                kind: TransactionKind::Eip2930 {
+                    gas_price: 0xbab10c.into(),
+                    access_list: vec![AccessListItem::default()],
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn tx_send_imported_raw_transaction() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let key = key();
+        let unsigned = UnsignedTransaction {
+            chain_id: 77777,
+            nonce: 0x42.into(),
+            gas: 0x1337.into(),
+            to: Some(Address::zero()),
+            value: 0.into(),
+            data: Bytes::default(),
+            kind: TransactionKind::Legacy {
+                gas_price: 0xbab10c.into(),
+            },
+        };
+        let raw = unsigned.sign(&key).raw().unwrap();
+
+        let tx = Transaction::from_raw(raw.clone());
+        let signed = tx.decoded().cloned().expect("invalid raw transaction");
+        assert_eq!(signed.hash, H256(web3::signing::keccak256(&raw.0)));
+        assert_eq!(signed.from, key.public_address());
+        assert_eq!(signed.transaction, unsigned);
+
+        transport.add_response(json!(signed.hash));
+        let result = tx
+            .send(&web3, ResolveCondition::Pending)
+            .immediate()
+            .expect("failed to send transaction");
+
+        transport.assert_request("eth_sendRawTransaction", &[json!(raw)]);
+        transport.assert_no_more_requests();
+        assert_eq!(result.hash(), signed.hash);
+
+        assert_eq!(
+            Transaction::from_raw(Bytes(vec![0x13, 0x37])).decoded(),
+            None
+        );
+    }
+
+    #[test]
+    fn tx_build_raw_decodes_signed_transaction() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let key = key();
+        let tx = TransactionBuilder::new(web3.clone())
+            .from(Account::Offline(key.clone(), Some(77777)))
+            .to(Address::zero())
+            .gas(0x1337.into())
+            .gas_price((100.0, 2.0).into())
+            .nonce(0x42.into())
+            .build()
+            .immediate()
+            .expect("failed to build transaction");
+
+        let signed = tx.decoded().expect("offline transactions are decoded");
+        assert_eq!(signed.from, key.public_address());
+        assert_eq!(signed.transaction.chain_id, 77777);
+        assert_eq!(signed.transaction.nonce, 0x42.into());
+        assert_eq!(
+            signed.transaction.kind,
+            TransactionKind::Eip1559 {
+                max_fee_per_gas: 100.into(),
+                max_priority_fee_per_gas: 2.into(),
+                access_list: vec![],
+            }
+        );
+
+        assert_eq!(
+            signed,
+            &DecodedTransaction::decode(&tx.clone().raw().unwrap().0).unwrap()
+        );
+
+        // Transactions signed by the node with a locked account are decoded
+        // from the node's raw transaction.
+        let from = addr!("0x9876543210987654321098765432109876543210");
+        transport.add_response(json!({
+            "raw": tx.clone().raw().unwrap(),
+            "tx": {
+                "hash": signed.hash,
+                "nonce": "0x42",
+                "from": from,
+                "value": "0x0",
+                "gas": "0x1337",
+                "input": "0x",
+            }
+        }));
+        let result = TransactionBuilder::new(web3.clone())
+            .from(Account::Locked(from, "foobar".into(), None))
+            .gas(0x1337.into())
+            .build()
+            .immediate()
+            .expect("failed to build transaction");
+        let locked = result.decoded().expect("locked transactions are decoded");
+        assert_eq!(locked.from, from);
+        assert_eq!(locked.transaction, signed.transaction);
+
+        // Transactions signed by the node with a locked account are built
+        // even if they can not be decoded.
+        transport.add_response(json!({
+            "raw": "0x0123456789",
+            "tx": {
+                "hash": H256::repeat_byte(0x42),
+                "nonce": "0x0",
+                "from": from,
+                "value": "0x0",
+                "gas": "0x1337",
+                "gasPrice": "0x0",
+                "input": "0x",
+            }
+        }));
+        let result = TransactionBuilder::new(web3)
+            .from(Account::Locked(from, "foobar".into(), None))
+            .gas(0x1337.into())
+            .build()
+            .immediate()
+            .expect("failed to build transaction");
+        assert_eq!(
+            result,
+            Transaction::Raw {
+                bytes: Bytes(vec![0x01, 0x23, 0x45, 0x67, 0x89]),
+                hash: H256::repeat_byte(0x42),
+                decoded: None,
+            }
+        );
+    }
+
+    #[test]
+    fn tx_build_applies_gas_policy_to_estimate() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let from = addr!("0x9876543210987654321098765432109876543210");
+
+        transport.add_response(json!("0x5208")); // gas limit
+        let tx = TransactionBuilder::new(web3)
+            .from(Account::Local(from, None))
+            .gas_price(1.0.into())
+            .gas_policy(GasPolicy::default().multiplier(1.5).ceiling(30_000.into()))
+            .build()
+            .immediate()
+            .expect("failed to build transaction")
+            .request()
+            .unwrap();
+
+        transport.assert_request(
+            "eth_estimateGas",
+            &[json!({ "from": from, "gasPrice": "0x1" })],
+        );
+        transport.assert_no_more_requests();
+        assert_eq!(tx.gas, Some(30_000.into()));
+    }
+
+    #[test]
+    fn tx_build_exceeding_fee_budget() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let builder = TransactionBuilder::new(web3)
+            .from(Account::Offline(key(), Some(77777)))
+            .to(Address::zero())
+            .nonce(0.into())
+            .gas_policy(
+                GasPolicy::default()
+                    .padding(1_000.into())
+                    .fee_budget(1_000_000.into()),
+            );
+
+        // explicit gas and gas price within budget
+        builder
+            .clone()
+            .gas(1_000.into())
+            .gas_price((1_000.0, 1.0).into())
+            .build()
+            .immediate()
+            .expect("transaction within fee budget");
+
+        // estimated gas with padding and queried gas price exceeding budget
+        transport.add_response(json!("0x1")); // gas limit
+        transport.add_response(json!("0x3e8")); // gas price
+        let result = builder.build().immediate();
+
+        transport.assert_request(
+            "eth_estimateGas",
+            &[json!({
+                "from": key().public_address(),
+                "to": Address::zero(),
+            })],
+        );
+        transport.assert_request("eth_gasPrice", &[]);
+        transport.assert_no_more_requests();
+        assert!(matches!(
+            result,
+            Err(ExecutionError::FeeBudgetExceeded { fee, budget })
+                if fee == 1_001_000.into() && budget == 1_000_000.into()
+        ));
+    }
+
+    #[test]
+    fn tx_build_fee_budget_requires_known_fee() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let from = addr!("0x9876543210987654321098765432109876543210");
+
+        // the node chooses the gas price when signing, so the fee is unknown
+        // and the transaction is rejected before querying the node
+        for account in [None, Some(Account::Local(from, None))] {
+            let mut builder = TransactionBuilder::new(web3.clone())
+                .gas_policy(GasPolicy::default().fee_budget(1_000_000.into()));
+            builder.from = account;
+            let result = builder.build().immediate();
+
+            transport.assert_no_more_requests();
+            assert!(matches!(
+                result,
+                Err(ExecutionError::MissingTransactionParameter("gas price"))
+            ));
+        }
+
+        // with an explicit gas price, the fee is checked on the estimate
+        transport.add_response(json!("0x5208")); // gas limit
+        let result = TransactionBuilder::new(web3)
+            .from(Account::Local(from, None))
+            .gas_price(100.0.into())
+            .gas_policy(GasPolicy::default().fee_budget(1_000_000.into()))
+            .build()
+            .immediate();
+
+        transport.assert_request(
+            "eth_estimateGas",
+            &[json!({ "from": from, "gasPrice": "0x64" })],
+        );
+        transport.assert_no_more_requests();
+        assert!(matches!(
+            result,
+            Err(ExecutionError::FeeBudgetExceeded { fee, budget })
+                if fee == 2_100_000.into() && budget == 1_000_000.into()
+        ));
+    }
 }
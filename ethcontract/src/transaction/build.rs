@@ -6,10 +6,15 @@
 
 use crate::errors::ExecutionError;
 use crate::secret::{Password, PrivateKey};
+use crate::state_override::{self, StateOverrides};
 use crate::transaction::gas_price::GasPrice;
 #[cfg(feature = "aws-kms")]
 use crate::transaction::kms;
-use crate::transaction::{Account, TransactionBuilder};
+use crate::transaction::GasPolicy;
+use crate::transaction::{
+    Account, DecodedTransaction, TransactionBuilder, TransactionKind, UnsignedTransaction,
+};
+use ethcontract_common::hash;
 use web3::api::Web3;
 use web3::types::{
     AccessList, Address, Bytes, CallRequest, RawTransaction, SignedTransaction,
@@ -24,14 +29,29 @@ impl<T: Transport> TransactionBuilder<T> {
     /// signed transactions or raw signed transaction `Bytes` when sending a raw
     /// transaction.
     pub async fn build(self) -> Result<Transaction, ExecutionError> {
+        if self.strict_offline {
+            self.check_strict_offline()?;
+        }
+
+        let gas_policy = self.gas_policy;
+        // NOTE: Transaction requests are signed by the node, which chooses the
+        //   gas price if it is not specified, so the fee is not known.
+        let signed_by_node = matches!(self.from, None | Some(Account::Local(..)));
+        let fee_budget = gas_policy.and_then(|policy| policy.fee_budget);
+        if signed_by_node && fee_budget.is_some() && self.gas_price.is_none() {
+            return Err(ExecutionError::MissingTransactionParameter("gas price"));
+        }
+
         let options = TransactionOptions {
             to: self.to,
             gas: self.gas,
             gas_price: self.gas_price,
+            gas_policy,
             value: self.value,
             data: self.data,
             nonce: self.nonce,
             access_list: self.access_list,
+            state_overrides: self.state_overrides,
         };
 
         let tx = match self.from {
@@ -59,32 +79,184 @@ impl<T: Transport> TransactionBuilder<T> {
                     TransactionRequestOptions(options, condition),
                 )
                 .await
-                .map(|signed| Transaction::Raw {
-                    bytes: signed.raw,
-                    hash: signed.tx.hash,
-                })?
+                .map(|signed| Transaction::signed_by(signed.raw, signed.tx.hash, from))?
             }
             Some(Account::Offline(key, chain_id)) => {
+                let from = key.public_address();
                 build_offline_signed_transaction(self.web3, key, chain_id, options)
                     .await
-                    .map(|signed| Transaction::Raw {
-                        bytes: signed.raw_transaction,
-                        hash: signed.transaction_hash,
+                    .map(|signed| {
+                        Transaction::signed_by(
+                            signed.raw_transaction,
+                            signed.transaction_hash,
+                            from,
+                        )
                     })?
             }
             #[cfg(feature = "aws-kms")]
             Some(Account::Kms(account, chain_id)) => {
+                let from = account.public_address();
                 build_kms_signed_transaction(self.web3, account, chain_id, options)
                     .await
-                    .map(|signed| Transaction::Raw {
-                        bytes: signed.raw_transaction,
-                        hash: signed.transaction_hash,
+                    .map(|signed| {
+                        Transaction::signed_by(
+                            signed.raw_transaction,
+                            signed.transaction_hash,
+                            from,
+                        )
                     })?
             }
         };
 
+        // NOTE: The fee is checked on the finalized transaction, so that it is
+        //   resolved the same way with or without a fee budget.
+        if let Some(gas_policy) = gas_policy.filter(|policy| policy.fee_budget.is_some()) {
+            let (gas, max_fee_per_gas) = match &tx {
+                Transaction::Request(request) => (
+                    request
+                        .gas
+                        .ok_or(ExecutionError::MissingTransactionParameter("gas"))?,
+                    request
+                        .max_fee_per_gas
+                        .or(request.gas_price)
+                        .ok_or(ExecutionError::MissingTransactionParameter("gas price"))?,
+                ),
+                Transaction::Raw {
+                    decoded: Some(signed),
+                    ..
+                } => (
+                    signed.transaction.gas,
+                    signed.transaction.kind.max_fee_per_gas(),
+                ),
+                // NOTE: The fee of a raw transaction that could not be decoded
+                //   is unknown, so it is decoded again for reporting the error.
+                Transaction::Raw { bytes, .. } => {
+                    let signed = DecodedTransaction::decode(&bytes.0)?;
+                    (
+                        signed.transaction.gas,
+                        signed.transaction.kind.max_fee_per_gas(),
+                    )
+                }
+            };
+            gas_policy.check_fee_budget(gas, max_fee_per_gas)?;
+        }
+
         Ok(tx)
     }
+
+    /// Build an unsigned transaction with all its parameters resolved, so that
+    /// it can be exported and signed elsewhere.
+    ///
+    /// Missing parameters are queried from the node, unless the transaction is
+    /// being built in strict offline mode. Note that the chain ID is taken from
+    /// the `Offline` or `Kms` account if specified.
+    pub async fn build_unsigned(self) -> Result<UnsignedTransaction, ExecutionError> {
+        let strict_offline = self.strict_offline;
+        let query = |name| {
+            if strict_offline {
+                Err(ExecutionError::MissingTransactionParameter(name))
+            } else {
+                Ok(())
+            }
+        };
+        let from = self.from.as_ref().map(Account::address);
+        let options = TransactionOptions {
+            to: self.to,
+            gas: self.gas,
+            gas_price: self.gas_price,
+            gas_policy: self.gas_policy,
+            value: self.value,
+            data: self.data,
+            nonce: self.nonce,
+            access_list: self.access_list,
+            state_overrides: self.state_overrides,
+        };
+
+        let gas = match options.gas {
+            Some(gas) => gas,
+            None => {
+                query("gas")?;
+                let from = from.ok_or(ExecutionError::MissingTransactionParameter("from"))?;
+                resolve_gas_limit(&self.web3, from, &options).await?
+            }
+        };
+        let nonce = match options.nonce {
+            Some(nonce) => nonce,
+            None => {
+                query("nonce")?;
+                let from = from.ok_or(ExecutionError::MissingTransactionParameter("from"))?;
+                self.web3.eth().transaction_count(from, None).await?
+            }
+        };
+        let gas_price = match options.gas_price {
+            Some(gas_price) => gas_price,
+            None => {
+                query("gas price")?;
+                GasPrice::Legacy(self.web3.eth().gas_price().await?)
+            }
+        };
+        let chain_id = match self.from.as_ref().and_then(Account::chain_id) {
+            Some(chain_id) => chain_id,
+            None => {
+                query("chain ID")?;
+                self.web3.eth().chain_id().await?.as_u64()
+            }
+        };
+
+        let kind = match (gas_price, options.access_list) {
+            (GasPrice::Legacy(gas_price), None) => TransactionKind::Legacy { gas_price },
+            (GasPrice::Legacy(gas_price), Some(access_list)) => TransactionKind::Eip2930 {
+                gas_price,
+                access_list,
+            },
+            (
+                GasPrice::Eip1559 {
+                    max_fee_per_gas,
+                    max_priority_fee_per_gas,
+                },
+                access_list,
+            ) => TransactionKind::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+                access_list: access_list.unwrap_or_default(),
+            },
+        };
+
+        if let Some(gas_policy) = options.gas_policy {
+            gas_policy.check_fee_budget(gas, gas_price.max_fee_per_gas())?;
+        }
+
+        Ok(UnsignedTransaction {
+            chain_id,
+            nonce,
+            gas,
+            to: options.to,
+            value: options.value.unwrap_or_default(),
+            data: options.data.unwrap_or_default(),
+            kind,
+        })
+    }
+
+    /// Checks that the transaction can be built without querying the node.
+    fn check_strict_offline(&self) -> Result<(), ExecutionError> {
+        let chain_id = match &self.from {
+            Some(account @ Account::Offline(..)) => account.chain_id(),
+            #[cfg(feature = "aws-kms")]
+            Some(account @ Account::Kms(..)) => account.chain_id(),
+            _ => return Err(ExecutionError::OfflineSigningUnsupported),
+        };
+
+        let missing = [
+            ("chain ID", chain_id.is_none()),
+            ("nonce", self.nonce.is_none()),
+            ("gas", self.gas.is_none()),
+            ("gas price", self.gas_price.is_none()),
+        ];
+        match missing.iter().find(|(_, missing)| *missing) {
+            Some((name, _)) => Err(ExecutionError::MissingTransactionParameter(name)),
+            None => Ok(()),
+        }
+    }
 }
 
 /// Represents a prepared and optionally signed transaction that is ready for
@@ -100,10 +272,46 @@ pub enum Transaction {
         bytes: Bytes,
         /// The transaction hash
         hash: H256,
+        /// The decoded signed transaction, for inspecting the exact transaction
+        /// that will be sent. This is `None` if the raw transaction can not be
+        /// decoded, for example because it uses an unsupported transaction
+        /// type.
+        decoded: Option<DecodedTransaction>,
     },
 }
 
 impl Transaction {
+    /// Creates a raw transaction from signed transaction bytes, for example
+    /// when importing a transaction that was signed on a different machine.
+    pub fn from_raw(bytes: Bytes) -> Self {
+        let hash = H256(hash::keccak256(&bytes.0));
+        let decoded = DecodedTransaction::decode(&bytes.0).ok();
+        Transaction::Raw {
+            bytes,
+            hash,
+            decoded,
+        }
+    }
+
+    /// Creates a raw transaction that was just signed by a known sender.
+    fn signed_by(bytes: Bytes, hash: H256, from: Address) -> Self {
+        let decoded = DecodedTransaction::decode_signed_by(&bytes.0, from).ok();
+        Transaction::Raw {
+            bytes,
+            hash,
+            decoded,
+        }
+    }
+
+    /// Returns the decoded signed raw transaction, or `None` if it is a
+    /// transaction request or a raw transaction that can not be decoded.
+    pub fn decoded(&self) -> Option<&DecodedTransaction> {
+        match self {
+            Transaction::Raw { decoded, .. } => decoded.as_ref(),
+            _ => None,
+        }
+    }
+
     /// Unwraps the transaction into a transaction request, returning None if the
     /// transaction is a raw transaction.
     pub fn request(self) -> Option<TransactionRequest> {
@@ -133,6 +341,8 @@ struct TransactionOptions {
     pub gas: Option<U256>,
     /// Optional gas price to use for transaction.
     pub gas_price: Option<GasPrice>,
+    /// Optional gas policy to apply to the gas estimate and transaction fee.
+    pub gas_policy: Option<GasPolicy>,
     /// The ETH value to send with the transaction.
     pub value: Option<U256>,
     /// The data for the transaction.
@@ -141,6 +351,8 @@ struct TransactionOptions {
     pub nonce: Option<U256>,
     /// The access list
     pub access_list: Option<AccessList>,
+    /// State overrides to use for estimating gas.
+    pub state_overrides: Option<StateOverrides>,
 }
 
 /// Transaction options specific to `TransactionRequests` since they may also
@@ -299,9 +511,9 @@ async fn resolve_gas_limit<T: Transport>(
         .unwrap_or_default();
     match options.gas {
         Some(value) => Ok(value),
-        None => Ok(web3
-            .eth()
-            .estimate_gas(
+        None => {
+            let estimate = state_override::estimate_gas(
+                web3,
                 CallRequest {
                     from: Some(from),
                     to: options.to,
@@ -315,8 +527,14 @@ async fn resolve_gas_limit<T: Transport>(
                     max_priority_fee_per_gas: resolved_gas_price.max_priority_fee_per_gas,
                 },
                 None,
+                options.state_overrides.as_ref(),
             )
-            .await?),
+            .await?;
+            Ok(match options.gas_policy {
+                Some(gas_policy) => gas_policy.apply_to_estimate(estimate),
+                None => estimate,
+            })
+        }
     }
 }
 
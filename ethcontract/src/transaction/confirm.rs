@@ -7,12 +7,16 @@
 //! some of this can move upstream into the `web3` crate.
 
 use crate::errors::ExecutionError;
+use crate::trace;
 use crate::transaction::TransactionResult;
+use crate::transport::DynTransport;
+use futures::stream::StreamExt as _;
 use futures_timer::Delay;
 use std::cmp::min;
 use std::time::Duration;
-use web3::api::Web3;
-use web3::types::{TransactionReceipt, H256, U64};
+use web3::api::{SubscriptionStream, Web3};
+use web3::error::Error as Web3Error;
+use web3::types::{BlockHeader, TransactionReceipt, H256, U64};
 use web3::Transport;
 
 /// A struct with the confirmation parameters.
@@ -147,22 +151,46 @@ pub async fn wait_for_confirmation<T: Transport>(
     tx: H256,
     params: ConfirmParams,
 ) -> Result<TransactionReceipt, ExecutionError> {
-    let mut latest_block = None;
-    let mut context = ConfirmationContext {
-        web3,
-        tx,
-        params,
-        starting_block: None,
-    };
-
-    loop {
-        let target_block = match context.check(latest_block).await? {
-            Check::Confirmed(tx) => return Ok(tx),
-            Check::Pending(target_block) => target_block,
+    wait_for_confirmation_with(web3, tx, params, None).await
+}
+
+/// Waits for a transaction to be confirmed.
+///
+/// New blocks are waited for with a `newHeads` subscription if the specified
+/// subscription transport supports subscriptions, and by polling the latest
+/// block number otherwise.
+pub(crate) async fn wait_for_confirmation_with<T: Transport>(
+    web3: &Web3<T>,
+    tx: H256,
+    params: ConfirmParams,
+    subscription_transport: Option<DynTransport>,
+) -> Result<TransactionReceipt, ExecutionError> {
+    let span = trace::span!(
+        "confirm",
+        transaction_hash = ?tx,
+        confirmations = params.confirmations,
+    );
+    trace::instrument(span, async move {
+        let mut latest_block = None;
+        let mut context = ConfirmationContext {
+            web3,
+            tx,
+            params,
+            subscription_transport: subscription_transport
+                .filter(|transport| transport.supports_subscriptions()),
+            starting_block: None,
         };
 
-        latest_block = Some(context.wait_for_blocks(target_block).await?);
-    }
+        loop {
+            let target_block = match context.check(latest_block).await? {
+                Check::Confirmed(tx) => return Ok(tx),
+                Check::Pending(target_block) => target_block,
+            };
+
+            latest_block = Some(context.wait_for_blocks(target_block).await?);
+        }
+    })
+    .await
 }
 
 /// The state used for waiting for a transaction confirmation.
@@ -174,6 +202,9 @@ struct ConfirmationContext<'a, T: Transport> {
     /// The confirmation parameters (like number of confirming blocks to wait
     /// for and polling interval).
     params: ConfirmParams,
+    /// The transport used for subscribing to new heads, if it supports
+    /// subscriptions.
+    subscription_transport: Option<DynTransport>,
     /// The current block number when confirmation started. This is used for
     /// timeouts.
     starting_block: Option<U64>,
@@ -229,11 +260,18 @@ impl<T: Transport> ConfirmationContext<'_, T> {
         Ok(Check::Pending(target_block))
     }
 
-    /// Waits for blocks to be mined. This method polls the latest block number
-    /// and waits till the target block number is reached.
+    /// Waits for blocks to be mined. This method subscribes to new heads, or
+    /// polls the latest block number, and waits till the target block number
+    /// is reached.
     ///
     /// This method returns the latest block number if it is known.
-    async fn wait_for_blocks(&self, target_block: U64) -> Result<U64, ExecutionError> {
+    async fn wait_for_blocks(&mut self, target_block: U64) -> Result<U64, ExecutionError> {
+        if let Some(transport) = self.subscription_transport.clone() {
+            if let Some(latest_block) = self.wait_for_new_heads(&transport, target_block).await? {
+                return Ok(latest_block);
+            }
+        }
+
         let mut cur_delay = self.params.poll_interval_min;
 
         loop {
@@ -250,6 +288,68 @@ impl<T: Transport> ConfirmationContext<'_, T> {
             );
         }
     }
+
+    /// Waits for blocks to be mined with a `newHeads` subscription.
+    ///
+    /// This method returns `None` if the node does not support subscriptions
+    /// or the subscription ended, in which case polling should be used. Once
+    /// the node rejected the subscription, polling is used for all following
+    /// blocks.
+    async fn wait_for_new_heads(
+        &mut self,
+        transport: &DynTransport,
+        target_block: U64,
+    ) -> Result<Option<U64>, ExecutionError> {
+        let mut heads = match Web3::new(transport.clone())
+            .eth_subscribe()
+            .subscribe_new_heads()
+            .await
+        {
+            Ok(heads) => heads,
+            Err(Web3Error::Rpc(_)) => {
+                self.subscription_transport = None;
+                return Ok(None);
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let latest_block = self.next_target_head(&mut heads, target_block).await;
+
+        // NOTE: Dropping the subscription stream does not cancel the
+        //   subscription on the node, so explicitly unsubscribe, also when
+        //   waiting failed. When the stream ended, the connection was most
+        //   likely closed, so errors unsubscribing are ignored.
+        let unsubscribed = heads.unsubscribe().await;
+        match latest_block? {
+            Some(latest_block) => {
+                unsubscribed?;
+                Ok(Some(latest_block))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Waits for a new head at or after the target block, returning `None` if
+    /// the subscription ended before one was received.
+    async fn next_target_head(
+        &self,
+        heads: &mut SubscriptionStream<DynTransport, BlockHeader>,
+        target_block: U64,
+    ) -> Result<Option<U64>, ExecutionError> {
+        // NOTE: Blocks may have been mined before the subscription was
+        //   created, so check the latest block once before waiting for heads.
+        let latest_block = self.web3.eth().block_number().await?;
+        if target_block <= latest_block {
+            return Ok(Some(latest_block));
+        }
+
+        while let Some(head) = heads.next().await {
+            if let Some(number) = head?.number.filter(|number| target_block <= *number) {
+                return Ok(Some(number));
+            }
+        }
+        Ok(None)
+    }
 }
 
 /// The result of checking a transaction confirmation.
@@ -287,7 +387,8 @@ mod tests {
     use super::*;
     use crate::test::prelude::*;
     use serde_json::Value;
-    use web3::types::H2048;
+    use web3::error::TransportError;
+    use web3::types::{Block, H2048};
 
     fn generate_tx_receipt<U: Into<U64>>(hash: H256, block_num: U) -> Value {
         json!({
@@ -462,6 +563,169 @@ mod tests {
         transport.assert_no_more_requests();
     }
 
+    #[test]
+    fn confirmations_with_new_heads() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+        let subscriptions = DynTransport::new_duplex(transport.clone());
+
+        let hash = H256::repeat_byte(0xff);
+        let head = |number: u64| {
+            serde_json::to_value(Block::<H256> {
+                number: Some(number.into()),
+                logs_bloom: Some(H2048::zero()),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+
+        // transaction pending
+        transport.add_response(json!("0x1"));
+        transport.add_response(json!(null));
+        // subscribe to new heads until new block is found
+        transport.add_response(json!("0xabc"));
+        transport.add_response(json!("0x1"));
+        transport.add_notification("0xabc", head(2));
+        transport.add_notification("0xabc", head(3));
+        transport.add_response(json!(true));
+        // check transaction was mined with the block number from the new head
+        transport.add_response(generate_tx_receipt(hash, 2));
+
+        let confirm = wait_for_confirmation_with(
+            &web3,
+            hash,
+            ConfirmParams::with_confirmations(1),
+            Some(subscriptions),
+        )
+        .wait()
+        .expect("transaction confirmation failed");
+
+        assert_eq!(confirm.transaction_hash, hash);
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_request("eth_getTransactionReceipt", &[json!(hash)]);
+        transport.assert_request("eth_subscribe", &[json!("newHeads")]);
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_request("eth_unsubscribe", &[json!("0xabc")]);
+        transport.assert_request("eth_getTransactionReceipt", &[json!(hash)]);
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn confirmations_fall_back_to_polling_without_subscriptions() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+        let subscriptions = DynTransport::new_duplex(transport.clone());
+
+        let hash = H256::repeat_byte(0xff);
+
+        // transaction pending
+        transport.add_response(json!("0x1"));
+        transport.add_response(json!(null));
+        // subscriptions are not supported, poll block number instead
+        transport.add_rpc_error(-32601, "the method eth_subscribe does not exist");
+        transport.add_response(json!("0x3"));
+        transport.add_response(generate_tx_receipt(hash, 3));
+        // the rejected subscription is not retried
+        transport.add_response(json!("0x4"));
+        transport.add_response(generate_tx_receipt(hash, 3));
+
+        let confirm = wait_for_confirmation_with(
+            &web3,
+            hash,
+            ConfirmParams::with_confirmations(1),
+            Some(subscriptions),
+        )
+        .wait()
+        .expect("transaction confirmation failed");
+
+        assert_eq!(confirm.transaction_hash, hash);
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_request("eth_getTransactionReceipt", &[json!(hash)]);
+        transport.assert_request("eth_subscribe", &[json!("newHeads")]);
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_request("eth_getTransactionReceipt", &[json!(hash)]);
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_request("eth_getTransactionReceipt", &[json!(hash)]);
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn confirmations_fall_back_to_polling_when_subscription_ends() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+        let subscriptions = DynTransport::new_duplex(transport.clone());
+
+        let hash = H256::repeat_byte(0xff);
+
+        // transaction pending
+        transport.add_response(json!("0x1"));
+        transport.add_response(json!(null));
+        // the subscription ends without new heads, and unsubscribing fails
+        transport.add_response(json!("0xabc"));
+        transport.add_response(json!("0x1"));
+        transport.add_error(Web3Error::Transport(TransportError::Message(
+            "connection closed".to_owned(),
+        )));
+        // poll block number instead
+        transport.add_response(json!("0x3"));
+        transport.add_response(generate_tx_receipt(hash, 2));
+
+        let confirm = wait_for_confirmation_with(
+            &web3,
+            hash,
+            ConfirmParams::with_confirmations(1),
+            Some(subscriptions),
+        )
+        .wait()
+        .expect("transaction confirmation failed");
+
+        assert_eq!(confirm.transaction_hash, hash);
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_request("eth_getTransactionReceipt", &[json!(hash)]);
+        transport.assert_request("eth_subscribe", &[json!("newHeads")]);
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_request("eth_unsubscribe", &[json!("0xabc")]);
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_request("eth_getTransactionReceipt", &[json!(hash)]);
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn confirmations_unsubscribe_when_waiting_for_new_heads_fails() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+        let subscriptions = DynTransport::new_duplex(transport.clone());
+
+        let hash = H256::repeat_byte(0xff);
+
+        // transaction pending
+        transport.add_response(json!("0x1"));
+        transport.add_response(json!(null));
+        // querying the latest block after subscribing fails
+        transport.add_response(json!("0xabc"));
+        transport.add_error(Web3Error::Unreachable);
+        transport.add_response(json!(true));
+
+        let result = wait_for_confirmation_with(
+            &web3,
+            hash,
+            ConfirmParams::with_confirmations(1),
+            Some(subscriptions),
+        )
+        .wait();
+
+        assert!(matches!(
+            result,
+            Err(ExecutionError::Web3(Web3Error::Unreachable))
+        ));
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_request("eth_getTransactionReceipt", &[json!(hash)]);
+        transport.assert_request("eth_subscribe", &[json!("newHeads")]);
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_request("eth_unsubscribe", &[json!("0xabc")]);
+        transport.assert_no_more_requests();
+    }
+
     #[test]
     fn confirmations_with_polling_when_mining_is_slightly_ahead_of_us() {
         let mut transport = TestTransport::new();
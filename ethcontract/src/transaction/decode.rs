@@ -0,0 +1,417 @@
+//! Implementation of raw signed transaction decoding with sender recovery.
+
+use crate::transaction::{TransactionKind, UnsignedTransaction};
+use ethcontract_common::hash;
+use rlp::{DecoderError, Rlp};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use web3::signing;
+use web3::types::{AccessList, AccessListItem, Address, Bytes, H256, U256};
+
+/// A decoded raw signed transaction.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DecodedTransaction {
+    /// The transaction hash.
+    pub hash: H256,
+    /// The recovered sender of the transaction.
+    pub from: Address,
+    /// The signed transaction parameters.
+    #[serde(flatten)]
+    pub transaction: UnsignedTransaction,
+    /// Whether the transaction is only valid for its chain. Legacy transactions
+    /// without EIP-155 replay protection are valid on any chain, and have a
+    /// chain ID of 0.
+    pub replay_protected: bool,
+    /// The signature `v` value. This is EIP-155 encoded for replay protected
+    /// legacy transactions, 27 or 28 for unprotected legacy transactions and
+    /// the signature parity for typed transactions.
+    pub v: u64,
+    /// The signature `r` value.
+    pub r: H256,
+    /// The signature `s` value.
+    pub s: H256,
+}
+
+/// Error that can occur while decoding a raw signed transaction.
+#[derive(Clone, Debug, Eq, PartialEq, Error)]
+pub enum DecodeError {
+    /// The transaction is not correctly RLP encoded.
+    #[error("invalid RLP encoding: {0}")]
+    Rlp(#[from] DecoderError),
+
+    /// The EIP-2718 transaction type is not supported.
+    #[error("unsupported transaction type {0:#04x}")]
+    UnsupportedType(u8),
+
+    /// The legacy transaction is not replay protected with EIP-155, so it is
+    /// not signed for a specific chain.
+    #[error("legacy transaction is not EIP-155 replay protected")]
+    NotReplayProtected,
+
+    /// The transaction signature is invalid and the sender can't be recovered.
+    #[error("invalid transaction signature")]
+    InvalidSignature,
+
+    /// The transaction was signed for a different chain.
+    #[error("transaction chain ID {actual} does not match expected chain ID {expected}")]
+    ChainIdMismatch {
+        /// The expected chain ID.
+        expected: u64,
+        /// The chain ID the transaction was signed for.
+        actual: u64,
+    },
+}
+
+impl DecodedTransaction {
+    /// Decodes a raw signed legacy, EIP-2930 or EIP-1559 transaction and
+    /// recovers its sender.
+    pub fn decode(raw: &[u8]) -> Result<Self, DecodeError> {
+        let (mut decoded, parity, signing_hash) = parse(raw)?;
+
+        let mut signature = [0; 64];
+        signature[..32].copy_from_slice(decoded.r.as_bytes());
+        signature[32..].copy_from_slice(decoded.s.as_bytes());
+        decoded.from = signing::recover(signing_hash.as_bytes(), &signature, parity as _)
+            .map_err(|_| DecodeError::InvalidSignature)?;
+
+        Ok(decoded)
+    }
+
+    /// Decodes a raw signed transaction and verifies that it was signed for
+    /// the specified chain.
+    pub fn decode_for_chain(raw: &[u8], chain_id: u64) -> Result<Self, DecodeError> {
+        let decoded = Self::decode(raw)?;
+        if !decoded.replay_protected {
+            return Err(DecodeError::NotReplayProtected);
+        }
+        if decoded.transaction.chain_id != chain_id {
+            return Err(DecodeError::ChainIdMismatch {
+                expected: chain_id,
+                actual: decoded.transaction.chain_id,
+            });
+        }
+        Ok(decoded)
+    }
+
+    /// Decodes a raw transaction that was just signed by a known sender, so
+    /// that the sender does not need to be recovered from the signature.
+    pub(crate) fn decode_signed_by(raw: &[u8], from: Address) -> Result<Self, DecodeError> {
+        let (decoded, _, _) = parse(raw)?;
+        Ok(DecodedTransaction { from, ..decoded })
+    }
+}
+
+/// Parses a raw signed transaction without recovering its sender, returning
+/// the transaction along with its signature parity and signing hash.
+fn parse(raw: &[u8]) -> Result<(DecodedTransaction, u64, H256), DecodeError> {
+    let (kind, payload) = match raw.first() {
+        Some(&byte) if byte >= 0xc0 => (None, raw),
+        Some(&byte @ (1 | 2)) => (Some(byte), &raw[1..]),
+        Some(&byte) if byte <= 0x7f => return Err(DecodeError::UnsupportedType(byte)),
+        _ => return Err(DecoderError::RlpExpectedToBeList.into()),
+    };
+
+    let rlp = Rlp::new(payload);
+    let info = rlp.payload_info()?;
+    if info.header_len + info.value_len != payload.len() {
+        return Err(DecoderError::RlpInconsistentLengthAndData.into());
+    }
+
+    let (transaction, v, parity, replay_protected) = match kind {
+        None => decode_legacy(&rlp)?,
+        Some(1) => decode_eip2930(&rlp)?,
+        Some(_) => decode_eip1559(&rlp)?,
+    };
+    let fields = rlp.item_count()?;
+    let r = H256(rlp.val_at::<U256>(fields - 2)?.into());
+    let s = H256(rlp.val_at::<U256>(fields - 1)?.into());
+
+    // NOTE: Unprotected legacy transactions sign the first 6 fields only,
+    //   without the chain ID.
+    let signing_hash = if replay_protected {
+        transaction.signing_hash()
+    } else {
+        let mut stream = rlp::RlpStream::new_list(6);
+        for item in rlp.iter().take(6) {
+            stream.append_raw(item.as_raw(), 1);
+        }
+        H256(hash::keccak256(stream.out()))
+    };
+
+    let decoded = DecodedTransaction {
+        hash: H256(hash::keccak256(raw)),
+        from: Address::zero(),
+        transaction,
+        replay_protected,
+        v,
+        r,
+        s,
+    };
+    Ok((decoded, parity, signing_hash))
+}
+
+/// Returns the chain ID and whether the transaction is replay protected for a
+/// legacy signature `v` value.
+fn legacy_chain_id(v: u64) -> Result<(u64, bool), DecodeError> {
+    match v {
+        v if v >= 35 => Ok(((v - 35) / 2, true)),
+        27 | 28 => Ok((0, false)),
+        _ => Err(DecodeError::InvalidSignature),
+    }
+}
+
+/// Decodes the fields of a legacy transaction, returning the transaction along
+/// with its `v` value, signature parity and whether it is replay protected.
+fn decode_legacy(rlp: &Rlp) -> Result<(UnsignedTransaction, u64, u64, bool), DecodeError> {
+    check_item_count(rlp, 9)?;
+    let v: u64 = rlp.val_at(6)?;
+    let (chain_id, replay_protected) = legacy_chain_id(v)?;
+    let parity = if replay_protected {
+        (v - 35) % 2
+    } else {
+        v - 27
+    };
+
+    let transaction = UnsignedTransaction {
+        chain_id,
+        nonce: rlp.val_at(0)?,
+        gas: rlp.val_at(2)?,
+        to: decode_to(rlp, 3)?,
+        value: rlp.val_at(4)?,
+        data: Bytes(rlp.val_at(5)?),
+        kind: TransactionKind::Legacy {
+            gas_price: rlp.val_at(1)?,
+        },
+    };
+    Ok((transaction, v, parity, replay_protected))
+}
+
+/// Decodes the fields of an EIP-2930 transaction payload.
+fn decode_eip2930(rlp: &Rlp) -> Result<(UnsignedTransaction, u64, u64, bool), DecodeError> {
+    check_item_count(rlp, 11)?;
+    let parity = decode_parity(rlp, 8)?;
+
+    let transaction = UnsignedTransaction {
+        chain_id: rlp.val_at(0)?,
+        nonce: rlp.val_at(1)?,
+        gas: rlp.val_at(3)?,
+        to: decode_to(rlp, 4)?,
+        value: rlp.val_at(5)?,
+        data: Bytes(rlp.val_at(6)?),
+        kind: TransactionKind::Eip2930 {
+            gas_price: rlp.val_at(2)?,
+            access_list: decode_access_list(&rlp.at(7)?)?,
+        },
+    };
+    Ok((transaction, parity, parity, true))
+}
+
+/// Decodes the fields of an EIP-1559 transaction payload.
+fn decode_eip1559(rlp: &Rlp) -> Result<(UnsignedTransaction, u64, u64, bool), DecodeError> {
+    check_item_count(rlp, 12)?;
+    let parity = decode_parity(rlp, 9)?;
+
+    let transaction = UnsignedTransaction {
+        chain_id: rlp.val_at(0)?,
+        nonce: rlp.val_at(1)?,
+        gas: rlp.val_at(4)?,
+        to: decode_to(rlp, 5)?,
+        value: rlp.val_at(6)?,
+        data: Bytes(rlp.val_at(7)?),
+        kind: TransactionKind::Eip1559 {
+            max_priority_fee_per_gas: rlp.val_at(2)?,
+            max_fee_per_gas: rlp.val_at(3)?,
+            access_list: decode_access_list(&rlp.at(8)?)?,
+        },
+    };
+    Ok((transaction, parity, parity, true))
+}
+
+fn check_item_count(rlp: &Rlp, count: usize) -> Result<(), DecodeError> {
+    if rlp.item_count()? != count {
+        return Err(DecoderError::RlpIncorrectListLen.into());
+    }
+    Ok(())
+}
+
+fn decode_parity(rlp: &Rlp, index: usize) -> Result<u64, DecodeError> {
+    match rlp.val_at(index)? {
+        parity @ (0 | 1) => Ok(parity),
+        _ => Err(DecodeError::InvalidSignature),
+    }
+}
+
+/// Decodes the receiver of a transaction, which is empty for contract
+/// deployments.
+fn decode_to(rlp: &Rlp, index: usize) -> Result<Option<Address>, DecodeError> {
+    let to = rlp.at(index)?;
+    if to.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(to.as_val()?))
+    }
+}
+
+fn decode_access_list(rlp: &Rlp) -> Result<AccessList, DecodeError> {
+    rlp.iter()
+        .map(|item| {
+            if item.item_count()? != 2 {
+                return Err(DecoderError::RlpIncorrectListLen.into());
+            }
+            Ok(AccessListItem {
+                address: item.val_at(0)?,
+                storage_keys: item.list_at(1)?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::fixtures::key;
+    use crate::transaction::Transaction;
+    use web3::signing::Key;
+
+    fn transactions() -> Vec<UnsignedTransaction> {
+        let access_list = vec![AccessListItem {
+            address: addr!("0x0000000000000000000000000000000000000001"),
+            storage_keys: vec![H256::repeat_byte(2), H256::repeat_byte(3)],
+        }];
+        [
+            TransactionKind::Legacy {
+                gas_price: 0xbab10c.into(),
+            },
+            TransactionKind::Eip2930 {
+                gas_price: 0xbab10c.into(),
+                access_list: access_list.clone(),
+            },
+            TransactionKind::Eip1559 {
+                max_fee_per_gas: 0xbab10c.into(),
+                max_priority_fee_per_gas: 0x1337.into(),
+                access_list,
+            },
+        ]
+        .into_iter()
+        .flat_map(|kind| {
+            [
+                Some(addr!("0x0123456789012345678901234567890123456789")),
+                None,
+            ]
+            .into_iter()
+            .map(move |to| UnsignedTransaction {
+                chain_id: 77777,
+                nonce: 42.into(),
+                gas: 0x1337.into(),
+                to,
+                value: 28.into(),
+                data: Bytes(vec![0x13, 0x37]),
+                kind: kind.clone(),
+            })
+        })
+        .collect()
+    }
+
+    fn raw(tx: &UnsignedTransaction) -> (Bytes, H256) {
+        match tx.sign(&key()) {
+            Transaction::Raw { bytes, hash, .. } => (bytes, hash),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn decode_roundtrip() {
+        for tx in transactions() {
+            let (bytes, hash) = raw(&tx);
+            let decoded = DecodedTransaction::decode(&bytes.0).expect("failed to decode");
+
+            assert_eq!(decoded.transaction, tx);
+            assert_eq!(decoded.from, key().public_address());
+            assert_eq!(decoded.hash, hash);
+        }
+    }
+
+    #[test]
+    fn decode_for_chain() {
+        let (bytes, _) = raw(&transactions()[0]);
+
+        assert!(DecodedTransaction::decode_for_chain(&bytes.0, 77777).is_ok());
+        assert_eq!(
+            DecodedTransaction::decode_for_chain(&bytes.0, 1),
+            Err(DecodeError::ChainIdMismatch {
+                expected: 1,
+                actual: 77777,
+            })
+        );
+    }
+
+    #[test]
+    fn decode_errors() {
+        assert_eq!(
+            DecodedTransaction::decode(&[]),
+            Err(DecoderError::RlpExpectedToBeList.into())
+        );
+        assert_eq!(
+            DecodedTransaction::decode(&[0x03, 0xc0]),
+            Err(DecodeError::UnsupportedType(3))
+        );
+
+        let (bytes, _) = raw(&transactions()[4]);
+        assert!(matches!(
+            DecodedTransaction::decode(&bytes.0[..bytes.0.len() - 1]),
+            Err(DecodeError::Rlp(_))
+        ));
+        assert!(matches!(
+            DecodedTransaction::decode(&[&bytes.0[..], &[0x00]].concat()),
+            Err(DecodeError::Rlp(_))
+        ));
+
+        let mut tampered = bytes.0.clone();
+        *tampered.last_mut().unwrap() ^= 0xff;
+        let result = DecodedTransaction::decode(&tampered);
+        assert!(!matches!(result, Ok(tx) if tx.from == key().public_address()));
+    }
+
+    #[test]
+    fn decode_unprotected_legacy_transaction() {
+        let tx = &transactions()[0];
+        let gas_price = tx.kind.max_fee_per_gas();
+        let fields = |stream: &mut rlp::RlpStream| {
+            stream.append(&tx.nonce);
+            stream.append(&gas_price);
+            stream.append(&tx.gas);
+            stream.append(&tx.to.unwrap());
+            stream.append(&tx.value);
+            stream.append(&tx.data.0);
+        };
+
+        let mut payload = rlp::RlpStream::new_list(6);
+        fields(&mut payload);
+        let signature = key().sign_message(&hash::keccak256(payload.out())).unwrap();
+
+        let mut stream = rlp::RlpStream::new_list(9);
+        fields(&mut stream);
+        stream.append(&(signature.v + 27));
+        stream.append(&U256::from_big_endian(signature.r.as_bytes()));
+        stream.append(&U256::from_big_endian(signature.s.as_bytes()));
+        let raw = stream.out();
+
+        let decoded = DecodedTransaction::decode(&raw).expect("failed to decode");
+        assert_eq!(decoded.from, key().public_address());
+        assert!(!decoded.replay_protected);
+        assert_eq!(decoded.v, signature.v + 27);
+        assert_eq!(
+            decoded.transaction,
+            UnsignedTransaction {
+                chain_id: 0,
+                ..tx.clone()
+            }
+        );
+
+        assert_eq!(
+            DecodedTransaction::decode_for_chain(&raw, 77777),
+            Err(DecodeError::NotReplayProtected)
+        );
+    }
+}
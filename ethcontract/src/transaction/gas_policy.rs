@@ -0,0 +1,238 @@
+//! Implementation of gas limit policies for padding gas estimates and limiting
+//! the total transaction fee.
+
+use crate::errors::ExecutionError;
+use crate::transaction::gas_price::GasPrice;
+use primitive_types::U256;
+
+/// Policy applied to the gas limit of a transaction.
+///
+/// Padding, floor and ceiling are only applied to estimated gas limits, while
+/// the fee budget applies to all transactions, including ones with an
+/// explicitly specified gas limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GasPolicy {
+    /// Padding to add to gas estimates.
+    pub padding: Option<GasPadding>,
+    /// Minimum gas limit to use for estimated gas.
+    pub floor: Option<U256>,
+    /// Maximum gas limit to use for estimated gas.
+    pub ceiling: Option<U256>,
+    /// Maximum total fee, computed as the gas limit times the maximum fee per
+    /// gas, that a transaction is allowed to pay.
+    ///
+    /// The fee is checked once the transaction is built. Transactions that
+    /// are signed by the node when sending need an explicit gas price for
+    /// the fee to be known, and fail to build without querying the node
+    /// otherwise.
+    pub fee_budget: Option<U256>,
+}
+
+/// Padding that is added to gas estimates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GasPadding {
+    /// Multiply the gas estimate by a factor, for example `1.2` for 20% extra.
+    /// Factors below 1 are not applied, so estimates are never reduced.
+    Multiplier(f64),
+    /// Add a fixed amount of gas to the estimate.
+    Fixed(U256),
+}
+
+impl GasPolicy {
+    /// Multiply gas estimates by the specified factor.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the factor is not a finite number of at least 1.
+    pub fn multiplier(mut self, value: f64) -> Self {
+        assert!(
+            value.is_finite() && value >= 1.0,
+            "gas multiplier {} is not a finite number of at least 1",
+            value,
+        );
+        self.padding = Some(GasPadding::Multiplier(value));
+        self
+    }
+
+    /// Add a fixed amount of gas to gas estimates.
+    pub fn padding(mut self, value: U256) -> Self {
+        self.padding = Some(GasPadding::Fixed(value));
+        self
+    }
+
+    /// Specify the minimum gas limit to use for estimated gas.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the floor is greater than the ceiling.
+    pub fn floor(mut self, value: U256) -> Self {
+        self.floor = Some(value);
+        self.check_limits();
+        self
+    }
+
+    /// Specify the maximum gas limit to use for estimated gas.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the ceiling is less than the floor.
+    pub fn ceiling(mut self, value: U256) -> Self {
+        self.ceiling = Some(value);
+        self.check_limits();
+        self
+    }
+
+    /// Specify the maximum total fee a transaction is allowed to pay.
+    pub fn fee_budget(mut self, value: U256) -> Self {
+        self.fee_budget = Some(value);
+        self
+    }
+
+    /// Applies the padding, floor and ceiling to a gas estimate.
+    pub fn apply_to_estimate(&self, estimate: U256) -> U256 {
+        let mut gas = match self.padding {
+            // NOTE: Invalid factors, for example ones specified without the
+            //   builder method, result in 0 or less than the estimate, so the
+            //   padded gas is clamped to the estimate.
+            Some(GasPadding::Multiplier(factor)) => {
+                U256::from_f64_lossy(estimate.to_f64_lossy() * factor).max(estimate)
+            }
+            Some(GasPadding::Fixed(padding)) => estimate.saturating_add(padding),
+            None => estimate,
+        };
+        if let Some(floor) = self.floor {
+            gas = gas.max(floor);
+        }
+        if let Some(ceiling) = self.ceiling {
+            gas = gas.min(ceiling);
+        }
+        gas
+    }
+
+    /// Checks that the floor is not greater than the ceiling.
+    fn check_limits(&self) {
+        if let (Some(floor), Some(ceiling)) = (self.floor, self.ceiling) {
+            assert!(
+                floor <= ceiling,
+                "gas floor {} is greater than gas ceiling {}",
+                floor,
+                ceiling,
+            );
+        }
+    }
+
+    /// Checks that the maximum fee of a transaction with the specified gas
+    /// limit and maximum fee per gas does not exceed the fee budget.
+    pub fn check_fee_budget(&self, gas: U256, max_fee_per_gas: U256) -> Result<(), ExecutionError> {
+        let budget = match self.fee_budget {
+            Some(budget) => budget,
+            None => return Ok(()),
+        };
+        let fee = gas.full_mul(max_fee_per_gas);
+        if fee > budget.into() {
+            return Err(ExecutionError::FeeBudgetExceeded {
+                fee: fee.try_into().unwrap_or(U256::MAX),
+                budget,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl GasPrice {
+    /// Returns the maximum fee per gas a transaction with this gas price can
+    /// pay.
+    pub fn max_fee_per_gas(&self) -> U256 {
+        match self {
+            GasPrice::Legacy(gas_price) => *gas_price,
+            GasPrice::Eip1559 {
+                max_fee_per_gas, ..
+            } => *max_fee_per_gas,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_padding() {
+        let estimate = U256::from(100_000);
+
+        assert_eq!(GasPolicy::default().apply_to_estimate(estimate), estimate);
+        assert_eq!(
+            GasPolicy::default()
+                .multiplier(1.5)
+                .apply_to_estimate(estimate),
+            150_000.into()
+        );
+        assert_eq!(
+            GasPolicy::default()
+                .padding(21_000.into())
+                .apply_to_estimate(estimate),
+            121_000.into()
+        );
+    }
+
+    #[test]
+    fn apply_floor_and_ceiling() {
+        let policy = GasPolicy::default()
+            .multiplier(2.0)
+            .floor(50_000.into())
+            .ceiling(150_000.into());
+
+        assert_eq!(policy.apply_to_estimate(10_000.into()), 50_000.into());
+        assert_eq!(policy.apply_to_estimate(60_000.into()), 120_000.into());
+        assert_eq!(policy.apply_to_estimate(100_000.into()), 150_000.into());
+    }
+
+    #[test]
+    fn invalid_multipliers_never_reduce_estimate() {
+        let estimate = U256::from(100_000);
+        for factor in [0.5, -1.0, f64::NAN] {
+            let policy = GasPolicy {
+                padding: Some(GasPadding::Multiplier(factor)),
+                ..Default::default()
+            };
+            assert_eq!(policy.apply_to_estimate(estimate), estimate);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "gas multiplier 0.5 is not a finite number of at least 1")]
+    fn rejects_multiplier_below_one() {
+        GasPolicy::default().multiplier(0.5);
+    }
+
+    #[test]
+    #[should_panic(expected = "gas multiplier NaN is not a finite number of at least 1")]
+    fn rejects_nan_multiplier() {
+        GasPolicy::default().multiplier(f64::NAN);
+    }
+
+    #[test]
+    #[should_panic(expected = "gas floor 2 is greater than gas ceiling 1")]
+    fn rejects_floor_above_ceiling() {
+        GasPolicy::default().ceiling(1.into()).floor(2.into());
+    }
+
+    #[test]
+    fn check_fee_budget() {
+        let policy = GasPolicy::default().fee_budget(1_000_000.into());
+
+        assert!(policy.check_fee_budget(1_000.into(), 1_000.into()).is_ok());
+        assert!(matches!(
+            policy.check_fee_budget(1_001.into(), 1_000.into()),
+            Err(ExecutionError::FeeBudgetExceeded { fee, budget })
+                if fee == 1_001_000.into() && budget == 1_000_000.into()
+        ));
+        assert!(matches!(
+            policy.check_fee_budget(U256::MAX, U256::MAX),
+            Err(ExecutionError::FeeBudgetExceeded { fee, .. }) if fee == U256::MAX
+        ));
+        assert!(GasPolicy::default()
+            .check_fee_budget(U256::MAX, U256::MAX)
+            .is_ok());
+    }
+}
@@ -4,6 +4,8 @@
 use crate::errors::ExecutionError;
 use crate::transaction::confirm;
 use crate::transaction::{ResolveCondition, Transaction, TransactionBuilder};
+use crate::transport::DynTransport;
+use web3::api::Web3;
 use web3::types::{TransactionReceipt, H256, U64};
 use web3::Transport;
 
@@ -13,11 +15,35 @@ impl<T: Transport> TransactionBuilder<T> {
     pub async fn send(mut self) -> Result<TransactionResult, ExecutionError> {
         let web3 = self.web3.clone();
         let resolve = self.resolve.take().unwrap_or_default();
+        let subscription_transport = self.subscription_transport.take();
 
         let tx = self.build().await?;
-        let tx_hash = match tx {
+        tx.send_with(&web3, resolve, subscription_transport).await
+    }
+}
+
+impl Transaction {
+    /// Send a prepared transaction, for example a raw transaction that was
+    /// signed offline, and resolve it according to the resolve condition.
+    pub async fn send<T: Transport>(
+        self,
+        web3: &Web3<T>,
+        resolve: ResolveCondition,
+    ) -> Result<TransactionResult, ExecutionError> {
+        self.send_with(web3, resolve, None).await
+    }
+
+    /// Send a prepared transaction, waiting for confirmations with a
+    /// subscription if the specified transport supports subscriptions.
+    async fn send_with<T: Transport>(
+        self,
+        web3: &Web3<T>,
+        resolve: ResolveCondition,
+        subscription_transport: Option<DynTransport>,
+    ) -> Result<TransactionResult, ExecutionError> {
+        let tx_hash = match self {
             Transaction::Request(tx) => web3.eth().send_transaction(tx).await?,
-            Transaction::Raw { bytes, hash } => {
+            Transaction::Raw { bytes, hash, .. } => {
                 let node_hash = web3.eth().send_raw_transaction(bytes).await?;
                 if node_hash != hash {
                     return Err(ExecutionError::UnexpectedTransactionHash);
@@ -29,7 +55,8 @@ impl<T: Transport> TransactionBuilder<T> {
         let tx_receipt = match resolve {
             ResolveCondition::Pending => return Ok(TransactionResult::Hash(tx_hash)),
             ResolveCondition::Confirmed(params) => {
-                confirm::wait_for_confirmation(&web3, tx_hash, params).await
+                confirm::wait_for_confirmation_with(web3, tx_hash, params, subscription_transport)
+                    .await
             }
         }?;
 
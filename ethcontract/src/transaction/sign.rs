@@ -0,0 +1,262 @@
+//! Implementation of EIP-191 personal message and EIP-712 typed data signing
+//! for the different account types.
+
+use crate::errors::ExecutionError;
+use crate::transaction::Account;
+use ethcontract_common::hash::{self, TypedData};
+use web3::api::Web3;
+use web3::helpers;
+use web3::signing::{Key, Signature};
+use web3::types::{Bytes, H520};
+use web3::Transport;
+
+impl Account {
+    /// Signs a message according to EIP-191 (also known as `personal_sign`).
+    /// The message gets prefixed with `"\x19Ethereum Signed Message:\n"` and
+    /// its length before being hashed and signed.
+    ///
+    /// Returns the 65-byte `r ‖ s ‖ v` signature, where `v` is either 27 or
+    /// 28. Node-backed accounts sign with `eth_sign` or `personal_sign` for
+    /// local and locked accounts respectively.
+    pub async fn sign_message<T, M>(
+        &self,
+        web3: &Web3<T>,
+        message: M,
+    ) -> Result<H520, ExecutionError>
+    where
+        T: Transport,
+        M: AsRef<[u8]>,
+    {
+        let message = message.as_ref();
+        let signature = match self {
+            Account::Local(address, _) => {
+                web3.eth().sign(*address, Bytes(message.to_vec())).await?
+            }
+            Account::Locked(address, password, _) => {
+                web3.personal()
+                    .sign(Bytes(message.to_vec()), *address, password)
+                    .await?
+            }
+            Account::Offline(key, _) => sign_hash(key, hash::hash_message(message)),
+            #[cfg(feature = "aws-kms")]
+            Account::Kms(account, _) => {
+                into_bytes(account.sign(hash::hash_message(message)).await?)
+            }
+        };
+
+        Ok(normalize_v(signature))
+    }
+
+    /// Signs EIP-712 typed structured data.
+    ///
+    /// Returns the 65-byte `r ‖ s ‖ v` signature, where `v` is either 27 or
+    /// 28. Local node accounts sign with `eth_signTypedData_v4`. Note that
+    /// locked accounts are not supported as there is no standard RPC method
+    /// for signing typed data with a password.
+    pub async fn sign_typed_data<T>(
+        &self,
+        web3: &Web3<T>,
+        data: &TypedData,
+    ) -> Result<H520, ExecutionError>
+    where
+        T: Transport,
+    {
+        let signature = match self {
+            Account::Local(address, _) => {
+                // NOTE: The typed data is passed as a JSON string and not an
+                //   object, as this is what is supported by most nodes and
+                //   wallets implementing the `_v4` method.
+                let data = serde_json::to_string(data).map_err(web3::Error::from)?;
+                let result = web3
+                    .transport()
+                    .execute(
+                        "eth_signTypedData_v4",
+                        vec![helpers::serialize(address), helpers::serialize(&data)],
+                    )
+                    .await?;
+                helpers::decode(result)?
+            }
+            Account::Locked(..) => return Err(ExecutionError::UnsupportedSigningMethod),
+            Account::Offline(key, _) => sign_hash(key, hash::hash_typed_data(data)?),
+            #[cfg(feature = "aws-kms")]
+            Account::Kms(account, _) => {
+                into_bytes(account.sign(hash::hash_typed_data(data)?).await?)
+            }
+        };
+
+        Ok(normalize_v(signature))
+    }
+}
+
+/// Signs a 32-byte hash with a private key.
+fn sign_hash(key: impl Key, hash: [u8; 32]) -> H520 {
+    into_bytes(
+        key.sign_message(&hash)
+            .expect("signing a 32-byte hash never fails"),
+    )
+}
+
+/// Converts a signature into its 65-byte `r ‖ s ‖ v` representation.
+fn into_bytes(signature: Signature) -> H520 {
+    let mut bytes = H520::default();
+    bytes.0[..32].copy_from_slice(signature.r.as_bytes());
+    bytes.0[32..64].copy_from_slice(signature.s.as_bytes());
+    bytes.0[64] = signature.v as u8;
+    bytes
+}
+
+/// Normalizes the recovery byte of a signature to be either 27 or 28. Signers
+/// may either return the raw recovery ID (0 or 1) or the "Electrum" notation
+/// `v` value.
+fn normalize_v(mut signature: H520) -> H520 {
+    if signature.0[64] < 27 {
+        signature.0[64] += 27;
+    }
+    signature
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::fixtures::{cow, mail};
+    use crate::test::prelude::*;
+    use web3::signing;
+    use web3::types::H256;
+
+    fn split_signature(signature: &H520) -> (H256, H256, u8) {
+        (
+            H256::from_slice(&signature.0[..32]),
+            H256::from_slice(&signature.0[32..64]),
+            signature.0[64],
+        )
+    }
+
+    #[test]
+    fn offline_sign_message() {
+        let transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let key = cow();
+        let signature = Account::Offline(key.clone(), None)
+            .sign_message(&web3, "hello")
+            .immediate()
+            .expect("failed to sign message");
+
+        let (r, s, v) = split_signature(&signature);
+        let recovered = signing::recover(
+            &hash::hash_message("hello"),
+            &[r.as_bytes(), s.as_bytes()].concat(),
+            v as i32 - 27,
+        )
+        .unwrap();
+        assert_eq!(recovered, key.public_address());
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn offline_sign_typed_data() {
+        let transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let signature = Account::Offline(cow(), None)
+            .sign_typed_data(&web3, &mail())
+            .immediate()
+            .expect("failed to sign typed data");
+
+        // signature from the EIP-712 specification example
+        let (r, s, v) = split_signature(&signature);
+        assert_eq!(v, 28);
+        assert_eq!(
+            r,
+            hash!("0x4355c47d63924e8a72e509b65029052eb6c299d53a04e167c5775fd466751c9d")
+        );
+        assert_eq!(
+            s,
+            hash!("0x07299936d304c153f6443dfa05f40ff007d72911b6f72307f996231605b91562")
+        );
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn local_sign_message() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let address = addr!("0x9876543210987654321098765432109876543210");
+        let mut signature = H520::repeat_byte(0x42);
+        signature.0[64] = 1;
+        transport.add_response(json!(signature));
+
+        let result = Account::Local(address, None)
+            .sign_message(&web3, [0x13, 0x37])
+            .immediate()
+            .expect("failed to sign message");
+
+        transport.assert_request("eth_sign", &[json!(address), json!("0x1337")]);
+        transport.assert_no_more_requests();
+        assert_eq!(result.0[..64], signature.0[..64]);
+        assert_eq!(result.0[64], 28);
+    }
+
+    #[test]
+    fn locked_sign_message() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let address = addr!("0x9876543210987654321098765432109876543210");
+        let signature = H520::repeat_byte(0x1b);
+        transport.add_response(json!(signature));
+
+        let result = Account::Locked(address, "foobar".into(), None)
+            .sign_message(&web3, [0x13, 0x37])
+            .immediate()
+            .expect("failed to sign message");
+
+        transport.assert_request(
+            "personal_sign",
+            &[json!("0x1337"), json!(address), json!("foobar")],
+        );
+        transport.assert_no_more_requests();
+        assert_eq!(result, signature);
+    }
+
+    #[test]
+    fn local_sign_typed_data() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let address = addr!("0x9876543210987654321098765432109876543210");
+        let signature = H520::repeat_byte(0x1c);
+        transport.add_response(json!(signature));
+
+        let data = mail();
+        let result = Account::Local(address, None)
+            .sign_typed_data(&web3, &data)
+            .immediate()
+            .expect("failed to sign typed data");
+
+        transport.assert_request(
+            "eth_signTypedData_v4",
+            &[json!(address), json!(serde_json::to_string(&data).unwrap())],
+        );
+        transport.assert_no_more_requests();
+        assert_eq!(result, signature);
+    }
+
+    #[test]
+    fn locked_sign_typed_data_unsupported() {
+        let transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let address = addr!("0x9876543210987654321098765432109876543210");
+        let result = Account::Locked(address, "foobar".into(), None)
+            .sign_typed_data(&web3, &mail())
+            .immediate();
+
+        assert!(matches!(
+            result,
+            Err(ExecutionError::UnsupportedSigningMethod)
+        ));
+        transport.assert_no_more_requests();
+    }
+}
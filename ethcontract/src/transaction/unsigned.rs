@@ -0,0 +1,406 @@
+//! Implementation of unsigned transactions that can be exported, signed on a
+//! different (possibly air-gapped) machine and then imported back as raw
+//! signed transactions.
+
+use crate::secret::PrivateKey;
+use crate::transaction::{DecodeError, DecodedTransaction, Transaction};
+use ethcontract_common::hash;
+use rlp::RlpStream;
+use serde::{Deserialize, Serialize};
+use web3::signing::{Key, Signature};
+use web3::types::{AccessList, Address, Bytes, H256, U256};
+
+/// A fully specified transaction that is ready to be signed. All parameters
+/// that would otherwise be queried from the node (chain ID, nonce, gas and gas
+/// price) are resolved, so signing does not require a node connection.
+///
+/// Unsigned transactions can be serialized to JSON in order to be signed on a
+/// different machine, and the signed result can be imported back with
+/// [`Transaction::from_raw`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnsignedTransaction {
+    /// The chain ID the transaction is valid for.
+    pub chain_id: u64,
+    /// The transaction nonce.
+    pub nonce: U256,
+    /// The gas limit of the transaction.
+    pub gas: U256,
+    /// The receiver of the transaction, `None` for contract deployments.
+    pub to: Option<Address>,
+    /// The ETH value to send with the transaction.
+    pub value: U256,
+    /// The data for the transaction.
+    pub data: Bytes,
+    /// The transaction type along with its fee parameters.
+    #[serde(flatten)]
+    pub kind: TransactionKind,
+}
+
+/// The type of an unsigned transaction with its type specific parameters.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum TransactionKind {
+    /// A legacy transaction with EIP-155 replay protection.
+    #[serde(rename = "legacy", rename_all = "camelCase")]
+    Legacy {
+        /// The gas price.
+        gas_price: U256,
+    },
+    /// An EIP-2930 transaction with an access list.
+    #[serde(rename = "eip2930", rename_all = "camelCase")]
+    Eip2930 {
+        /// The gas price.
+        gas_price: U256,
+        /// The access list.
+        access_list: AccessList,
+    },
+    /// An EIP-1559 transaction with a priority fee.
+    #[serde(rename = "eip1559", rename_all = "camelCase")]
+    Eip1559 {
+        /// Maximum gas price willing to pay for the transaction.
+        max_fee_per_gas: U256,
+        /// Priority fee used to incentivize miners to include the transaction.
+        max_priority_fee_per_gas: U256,
+        /// The access list.
+        access_list: AccessList,
+    },
+}
+
+impl TransactionKind {
+    /// Returns the maximum fee per gas the transaction can pay.
+    pub fn max_fee_per_gas(&self) -> U256 {
+        match self {
+            TransactionKind::Legacy { gas_price } | TransactionKind::Eip2930 { gas_price, .. } => {
+                *gas_price
+            }
+            TransactionKind::Eip1559 {
+                max_fee_per_gas, ..
+            } => *max_fee_per_gas,
+        }
+    }
+}
+
+impl UnsignedTransaction {
+    /// Returns the hash that needs to be signed for this transaction.
+    pub fn signing_hash(&self) -> H256 {
+        H256(hash::keccak256(self.encode(None)))
+    }
+
+    /// Signs the transaction with a private key, returning the raw signed
+    /// transaction.
+    pub fn sign(&self, key: &PrivateKey) -> Transaction {
+        let signature = key
+            .sign_message(self.signing_hash().as_bytes())
+            .expect("signing a 32-byte hash never fails");
+        self.with_signature(signature)
+            .expect("locally signed transactions are always valid")
+    }
+
+    /// Encodes the transaction with a signature that was computed externally
+    /// for the [`signing_hash`](Self::signing_hash), returning the raw signed
+    /// transaction.
+    ///
+    /// The recovery byte `v` of the signature may either be a raw recovery ID
+    /// (0 or 1), in "Electrum" notation (27 or 28) or EIP-155 encoded. Returns
+    /// an error if the signature is invalid or EIP-155 encoded for a different
+    /// chain.
+    pub fn with_signature(&self, signature: Signature) -> Result<Transaction, DecodeError> {
+        let parity = match signature.v {
+            v @ (0 | 1) => v,
+            v @ (27 | 28) => v - 27,
+            v if v >= 35 && (v - 35) / 2 != self.chain_id => {
+                return Err(DecodeError::ChainIdMismatch {
+                    expected: self.chain_id,
+                    actual: (v - 35) / 2,
+                })
+            }
+            v if v >= 35 => (v - 35) % 2,
+            _ => return Err(DecodeError::InvalidSignature),
+        };
+        let v = match self.kind {
+            TransactionKind::Legacy { .. } => parity + 35 + 2 * self.chain_id,
+            _ => parity,
+        };
+
+        let bytes = Bytes(self.encode(Some((v, signature.r, signature.s))));
+        DecodedTransaction::decode(&bytes.0)?;
+        Ok(Transaction::from_raw(bytes))
+    }
+
+    /// RLP encodes the transaction with its EIP-2718 type prefix. If no
+    /// signature is specified, then this encodes the signing payload.
+    fn encode(&self, signature: Option<(u64, H256, H256)>) -> Vec<u8> {
+        // NOTE: Legacy transactions always have 9 fields as the EIP-155
+        //   signing payload includes the chain ID in place of the signature.
+        let signature_fields = if signature.is_some() { 3 } else { 0 };
+        let mut stream = RlpStream::new_list(match self.kind {
+            TransactionKind::Legacy { .. } => 9,
+            TransactionKind::Eip2930 { .. } => 8 + signature_fields,
+            TransactionKind::Eip1559 { .. } => 9 + signature_fields,
+        });
+
+        match &self.kind {
+            TransactionKind::Legacy { gas_price } => {
+                self.append_common(&mut stream, None, *gas_price);
+            }
+            TransactionKind::Eip2930 {
+                gas_price,
+                access_list,
+            } => {
+                stream.append(&self.chain_id);
+                self.append_common(&mut stream, None, *gas_price);
+                append_access_list(&mut stream, access_list);
+            }
+            TransactionKind::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+                access_list,
+            } => {
+                stream.append(&self.chain_id);
+                self.append_common(
+                    &mut stream,
+                    Some(*max_priority_fee_per_gas),
+                    *max_fee_per_gas,
+                );
+                append_access_list(&mut stream, access_list);
+            }
+        }
+
+        match (signature, &self.kind) {
+            (Some((v, r, s)), _) => {
+                stream.append(&v);
+                // NOTE: Signature values are encoded as integers, meaning that
+                //   leading zeros need to be stripped.
+                stream.append(&U256::from_big_endian(r.as_bytes()));
+                stream.append(&U256::from_big_endian(s.as_bytes()));
+            }
+            (None, TransactionKind::Legacy { .. }) => {
+                stream.append(&self.chain_id);
+                stream.append(&0u8);
+                stream.append(&0u8);
+            }
+            (None, _) => {}
+        }
+
+        match self.kind {
+            TransactionKind::Legacy { .. } => stream.out().to_vec(),
+            TransactionKind::Eip2930 { .. } => [&[1], stream.as_raw()].concat(),
+            TransactionKind::Eip1559 { .. } => [&[2], stream.as_raw()].concat(),
+        }
+    }
+
+    /// Appends the fields shared by all transaction types.
+    fn append_common(&self, stream: &mut RlpStream, priority_fee: Option<U256>, fee: U256) {
+        stream.append(&self.nonce);
+        if let Some(priority_fee) = priority_fee {
+            stream.append(&priority_fee);
+        }
+        stream.append(&fee);
+        stream.append(&self.gas);
+        match self.to {
+            Some(to) => stream.append(&to),
+            None => stream.append(&""),
+        };
+        stream.append(&self.value);
+        stream.append(&self.data.0);
+    }
+}
+
+/// Appends an RLP encoded access list.
+fn append_access_list(stream: &mut RlpStream, access_list: &AccessList) {
+    stream.begin_list(access_list.len());
+    for item in access_list {
+        stream.begin_list(2);
+        stream.append(&item.address);
+        stream.begin_list(item.storage_keys.len());
+        for key in &item.storage_keys {
+            stream.append(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::fixtures::key;
+    use crate::test::prelude::*;
+    use web3::types::{AccessListItem, TransactionParameters};
+
+    fn unsigned(kind: TransactionKind) -> UnsignedTransaction {
+        UnsignedTransaction {
+            chain_id: 77777,
+            nonce: 42.into(),
+            gas: 0x1337.into(),
+            to: Some(addr!("0x0123456789012345678901234567890123456789")),
+            value: 28.into(),
+            data: Bytes(vec![0x13, 0x37]),
+            kind,
+        }
+    }
+
+    fn access_list() -> AccessList {
+        vec![AccessListItem {
+            address: addr!("0x0000000000000000000000000000000000000001"),
+            storage_keys: vec![H256::repeat_byte(2)],
+        }]
+    }
+
+    /// Signs the transaction with `web3` for comparing the encoding.
+    fn web3_sign(tx: &UnsignedTransaction) -> Transaction {
+        let web3 = Web3::new(TestTransport::new());
+        let (gas_price, transaction_type, access_list, max_fee, priority_fee) = match &tx.kind {
+            TransactionKind::Legacy { gas_price } => (Some(*gas_price), None, None, None, None),
+            TransactionKind::Eip2930 {
+                gas_price,
+                access_list,
+            } => (
+                Some(*gas_price),
+                Some(1.into()),
+                Some(access_list.clone()),
+                None,
+                None,
+            ),
+            TransactionKind::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+                access_list,
+            } => (
+                None,
+                Some(2.into()),
+                Some(access_list.clone()),
+                Some(*max_fee_per_gas),
+                Some(*max_priority_fee_per_gas),
+            ),
+        };
+        let signed = web3
+            .accounts()
+            .sign_transaction(
+                TransactionParameters {
+                    nonce: Some(tx.nonce),
+                    to: tx.to,
+                    gas: tx.gas,
+                    gas_price,
+                    value: tx.value,
+                    data: tx.data.clone(),
+                    chain_id: Some(tx.chain_id),
+                    transaction_type,
+                    access_list,
+                    max_fee_per_gas: max_fee,
+                    max_priority_fee_per_gas: priority_fee,
+                },
+                &key(),
+            )
+            .immediate()
+            .expect("failed to sign transaction");
+        Transaction::from_raw(signed.raw_transaction)
+    }
+
+    #[test]
+    fn sign_matches_web3_encoding() {
+        for kind in [
+            TransactionKind::Legacy {
+                gas_price: 0xbab10c.into(),
+            },
+            TransactionKind::Eip2930 {
+                gas_price: 0xbab10c.into(),
+                access_list: access_list(),
+            },
+            TransactionKind::Eip1559 {
+                max_fee_per_gas: 0xbab10c.into(),
+                max_priority_fee_per_gas: 0x1337.into(),
+                access_list: access_list(),
+            },
+        ] {
+            let tx = unsigned(kind);
+            assert_eq!(tx.sign(&key()), web3_sign(&tx));
+        }
+    }
+
+    #[test]
+    fn external_signature_roundtrip() {
+        let tx = unsigned(TransactionKind::Legacy {
+            gas_price: 0xbab10c.into(),
+        });
+
+        let mut signature = key().sign_message(tx.signing_hash().as_bytes()).unwrap();
+        signature.v += 27;
+
+        assert_eq!(tx.with_signature(signature).unwrap(), tx.sign(&key()));
+    }
+
+    #[test]
+    fn external_signature_for_other_chain() {
+        let tx = unsigned(TransactionKind::Legacy {
+            gas_price: 0xbab10c.into(),
+        });
+        let signature = |chain_id: u64| {
+            let mut signature = key().sign_message(tx.signing_hash().as_bytes()).unwrap();
+            signature.v += 35 + 2 * chain_id;
+            signature
+        };
+
+        assert_eq!(
+            tx.with_signature(signature(77777)).unwrap(),
+            tx.sign(&key())
+        );
+        assert_eq!(
+            tx.with_signature(signature(1)),
+            Err(DecodeError::ChainIdMismatch {
+                expected: 77777,
+                actual: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn external_signature_with_invalid_recovery_byte() {
+        let tx = unsigned(TransactionKind::Legacy {
+            gas_price: 0xbab10c.into(),
+        });
+        let signature = |v: u64| {
+            let mut signature = key().sign_message(tx.signing_hash().as_bytes()).unwrap();
+            signature.v += v;
+            signature
+        };
+
+        assert_eq!(
+            tx.with_signature(signature(2)),
+            Err(DecodeError::InvalidSignature)
+        );
+        assert_eq!(
+            tx.with_signature(signature(30)),
+            Err(DecodeError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn serialization_roundtrip() {
+        let tx = unsigned(TransactionKind::Eip1559 {
+            max_fee_per_gas: 100.into(),
+            max_priority_fee_per_gas: 2.into(),
+            access_list: vec![],
+        });
+
+        let json = serde_json::to_value(&tx).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "chainId": 77777,
+                "nonce": "0x2a",
+                "gas": "0x1337",
+                "to": "0x0123456789012345678901234567890123456789",
+                "value": "0x1c",
+                "data": "0x1337",
+                "type": "eip1559",
+                "maxFeePerGas": "0x64",
+                "maxPriorityFeePerGas": "0x2",
+                "accessList": [],
+            })
+        );
+        assert_eq!(
+            serde_json::from_value::<UnsignedTransaction>(json).unwrap(),
+            tx
+        );
+    }
+}
@@ -5,22 +5,61 @@
 //! to help create a more ergonimic experience by making the generated struct not
 //! be generic on the underlying transport (at the small cost of some dynamic
 //! dispatch and extra allocations).
+//!
+//! `DynTransport` also supports subscriptions when the underlying transport
+//! does, so that it can be passed to event streams and confirmations for using
+//! `eth_subscribe` instead of polling.
 
 use futures::future::BoxFuture;
-use futures::FutureExt as _;
+use futures::stream::BoxStream;
+use futures::{FutureExt as _, Stream, StreamExt as _};
 use jsonrpc_core::Call;
 use serde_json::Value;
 use std::any::Any;
 use std::fmt::Debug;
 use std::future::Future;
 use std::sync::Arc;
-use web3::error::Error as Web3Error;
-use web3::{BatchTransport, RequestId, Transport};
+use web3::api::SubscriptionId;
+use web3::error::{Error as Web3Error, TransportError};
+use web3::{BatchTransport, DuplexTransport, RequestId, Transport};
+
+mod auto_batch;
+mod cache;
+mod cassette;
+mod instrument;
+mod metrics;
+mod multi_node;
+mod rate_limit;
+mod retry;
+
+pub use self::auto_batch::{
+    AutoBatchConfig, AutoBatchingTransport, DEFAULT_BATCH_WINDOW, DEFAULT_MAX_BATCH_SIZE,
+};
+pub use self::cache::{CachingTransport, DEFAULT_CACHE_CAPACITY};
+pub use self::cassette::{
+    Cassette, CassetteError, Interaction, RecordedRequest, RecordedResponse,
+    RecordedTransportError, RecordingTransport, ReplayMode, ReplayTransport,
+};
+pub use self::instrument::{InstrumentedTransport, RequestHook, RequestInfo, ResponseInfo};
+pub use self::metrics::{LatencyHistogram, MethodMetrics, RpcMetrics, DEFAULT_LATENCY_BUCKETS};
+pub use self::multi_node::{
+    MultiNodeConfig, MultiNodeTransport, NodeResponse, NodeResult, QuorumError, DEFAULT_COOLDOWN,
+    DEFAULT_QUORUM_METHODS, QUORUM_ERROR_CODE,
+};
+pub use self::rate_limit::{
+    is_rate_limited, RateLimitConfig, RateLimitTransport, DEFAULT_MAX_RATE_LIMIT_BACKOFF,
+    DEFAULT_MAX_RATE_LIMIT_RETRIES, DEFAULT_RATE_LIMIT_BACKOFF,
+};
+pub use self::retry::{
+    is_idempotent, is_transient, RetryConfig, RetryTransport, DEFAULT_INITIAL_BACKOFF,
+    DEFAULT_MAX_BACKOFF, DEFAULT_MAX_RETRIES, DEFAULT_TIMEOUT, NON_IDEMPOTENT_METHODS,
+};
 
 /// Type alias for the output future in for the `DynTransport`'s `Transport`
 /// implementation.
 type BoxedFuture = BoxFuture<'static, Result<Value, Web3Error>>;
 type BoxedBatch = BoxFuture<'static, Result<Vec<Result<Value, Web3Error>>, Web3Error>>;
+type BoxedNotifications = BoxStream<'static, Value>;
 
 /// Helper trait that wraps `Transport` trait so it can be used as a trait
 /// object. This trait is implemented for all `Transport`'s.
@@ -73,15 +112,51 @@ where
     }
 }
 
+/// Helper trait that wraps `DuplexTransport` trait so it can be used as a
+/// trait object. This trait is implemented for all `DuplexTransport`'s with
+/// sendable notification streams.
+trait DuplexTransportBoxed: Debug + Send + Sync + 'static {
+    /// Wraps `DuplexTransport::subscribe`
+    fn subscribe_boxed(&self, id: SubscriptionId) -> Result<BoxedNotifications, Web3Error>;
+
+    /// Wraps `DuplexTransport::unsubscribe`
+    fn unsubscribe_boxed(&self, id: SubscriptionId) -> Result<(), Web3Error>;
+}
+
+impl<S, T> DuplexTransportBoxed for T
+where
+    S: Stream<Item = Value> + Send + 'static,
+    T: DuplexTransport<NotificationStream = S> + Debug + Send + Sync + 'static,
+{
+    #[inline(always)]
+    fn subscribe_boxed(&self, id: SubscriptionId) -> Result<BoxedNotifications, Web3Error> {
+        Ok(self.subscribe(id)?.boxed())
+    }
+
+    #[inline(always)]
+    fn unsubscribe_boxed(&self, id: SubscriptionId) -> Result<(), Web3Error> {
+        self.unsubscribe(id)
+    }
+}
+
 /// Dynamic `Transport` implementation to allow for a generic-free contract API.
 /// This type wraps any `Transport` type and implements `Transport` itself.
+///
+/// The `DuplexTransport` implementation only supports subscriptions if the
+/// wrapped transport does, see [`DynTransport::supports_subscriptions`].
 #[derive(Debug)]
 pub struct DynTransport {
     inner: Arc<dyn TransportBoxed>,
+    duplex: Option<Arc<dyn DuplexTransportBoxed>>,
 }
 
 impl DynTransport {
     /// Wrap a `Transport` in a `DynTransport`
+    ///
+    /// Subscriptions are supported when wrapping the websocket and IPC
+    /// transports from `web3`. Use [`DynTransport::new_duplex`] for other
+    /// transports that support subscriptions, such as transport wrappers
+    /// around a websocket transport.
     pub fn new<F, B, T>(inner: T) -> Self
     where
         F: Future<Output = Result<Value, Web3Error>> + Send + 'static,
@@ -89,31 +164,102 @@ impl DynTransport {
         T: Transport<Out = F> + BatchTransport<Batch = B> + Send + Sync + 'static,
     {
         let inner_ref: &dyn Any = &inner;
-        let inner_arc = match inner_ref.downcast_ref::<DynTransport>() {
+        if let Some(dyn_transport) = inner_ref.downcast_ref::<DynTransport>() {
             // NOTE: If a `DynTransport` is being created from another
             //   `DynTransport`, then just clone its inner transport instead of
             //   re-wrapping it.
-            Some(dyn_transport) => dyn_transport.inner.clone(),
-            None => Arc::new(inner),
-        };
+            return dyn_transport.clone();
+        }
+        if let Some(dyn_transport) = duplex_transport(inner_ref) {
+            return dyn_transport;
+        }
 
-        DynTransport { inner: inner_arc }
+        DynTransport {
+            inner: Arc::new(inner),
+            duplex: None,
+        }
+    }
+
+    /// Wrap a `DuplexTransport` in a `DynTransport` that supports
+    /// subscriptions.
+    ///
+    /// The transport wrappers in this module forward subscriptions to their
+    /// inner transport, so they can be wrapped here when the inner transport
+    /// supports subscriptions.
+    pub fn new_duplex<F, B, S, T>(inner: T) -> Self
+    where
+        F: Future<Output = Result<Value, Web3Error>> + Send + 'static,
+        B: Future<Output = Result<Vec<Result<Value, Web3Error>>, Web3Error>> + Send + 'static,
+        S: Stream<Item = Value> + Send + 'static,
+        T: Transport<Out = F>
+            + BatchTransport<Batch = B>
+            + DuplexTransport<NotificationStream = S>
+            + Send
+            + Sync
+            + 'static,
+    {
+        let inner_ref: &dyn Any = &inner;
+        if let Some(dyn_transport) = inner_ref.downcast_ref::<DynTransport>() {
+            return dyn_transport.clone();
+        }
+
+        let inner = Arc::new(inner);
+        DynTransport {
+            inner: inner.clone(),
+            duplex: Some(inner),
+        }
+    }
+
+    /// Returns true if the underlying transport supports subscriptions.
+    pub fn supports_subscriptions(&self) -> bool {
+        self.duplex.is_some()
     }
 
     /// Casts this transport into the underlying type.
     pub fn downcast<T: Any + Send + Sync + 'static>(&self) -> Option<&T> {
         self.inner.inner().downcast_ref()
     }
+
+    /// Returns the underlying duplex transport, or an error if subscriptions
+    /// are not supported.
+    fn duplex(&self) -> Result<&dyn DuplexTransportBoxed, Web3Error> {
+        self.duplex.as_deref().ok_or_else(|| {
+            Web3Error::Transport(TransportError::Message(
+                "transport does not support subscriptions".to_owned(),
+            ))
+        })
+    }
 }
 
 impl Clone for DynTransport {
     fn clone(&self) -> Self {
         DynTransport {
             inner: self.inner.clone(),
+            duplex: self.duplex.clone(),
         }
     }
 }
 
+/// Returns a `DynTransport` supporting subscriptions if the specified transport
+/// is one of the `web3` transports that support subscriptions.
+#[allow(unused_variables)]
+fn duplex_transport(inner: &dyn Any) -> Option<DynTransport> {
+    #[cfg(any(
+        feature = "ws-tokio",
+        feature = "ws-async-std",
+        feature = "ws-tls-tokio",
+        feature = "ws-tls-async-std",
+    ))]
+    if let Some(ws) = inner.downcast_ref::<web3::transports::WebSocket>() {
+        return Some(DynTransport::new_duplex(ws.clone()));
+    }
+    #[cfg(feature = "ipc-tokio")]
+    if let Some(ipc) = inner.downcast_ref::<web3::transports::Ipc>() {
+        return Some(DynTransport::new_duplex(ipc.clone()));
+    }
+    None
+}
+
 impl Transport for DynTransport {
     type Out = BoxedFuture;
 
@@ -133,6 +279,18 @@ impl Transport for DynTransport {
     }
 }
 
+impl DuplexTransport for DynTransport {
+    type NotificationStream = BoxedNotifications;
+
+    fn subscribe(&self, id: SubscriptionId) -> Result<Self::NotificationStream, Web3Error> {
+        self.duplex()?.subscribe_boxed(id)
+    }
+
+    fn unsubscribe(&self, id: SubscriptionId) -> Result<(), Web3Error> {
+        self.duplex()?.unsubscribe_boxed(id)
+    }
+}
+
 impl BatchTransport for DynTransport {
     type Batch = BoxedBatch;
 
@@ -189,6 +347,42 @@ mod tests {
         assert_eq!(Arc::strong_count(&dyn_dyn_transport.inner), 2);
     }
 
+    #[test]
+    fn dyn_transport_subscriptions() {
+        let mut transport = TestTransport::new();
+        transport.add_notification("0x1", json!(1));
+
+        let dyn_transport = DynTransport::new(transport.clone());
+        assert!(!dyn_transport.supports_subscriptions());
+        assert!(dyn_transport
+            .subscribe(SubscriptionId::from("0x1".to_owned()))
+            .is_err());
+
+        let dyn_transport = DynTransport::new(DynTransport::new_duplex(transport));
+        assert!(dyn_transport.supports_subscriptions());
+        let notifications = dyn_transport
+            .subscribe(SubscriptionId::from("0x1".to_owned()))
+            .unwrap()
+            .collect::<Vec<_>>()
+            .immediate();
+        assert_eq!(notifications, [json!(1)]);
+    }
+
+    #[test]
+    fn wrapped_transport_subscriptions() {
+        let mut transport = TestTransport::new();
+        transport.add_notification("0x1", json!(1));
+
+        let dyn_transport = DynTransport::new_duplex(RetryTransport::new(transport));
+        assert!(dyn_transport.supports_subscriptions());
+        let notifications = dyn_transport
+            .subscribe(SubscriptionId::from("0x1".to_owned()))
+            .unwrap()
+            .collect::<Vec<_>>()
+            .immediate();
+        assert_eq!(notifications, [json!(1)]);
+    }
+
     #[test]
     fn dyn_transport_is_threadsafe() {
         let transport = TestTransport::new();
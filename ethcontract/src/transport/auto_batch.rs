@@ -0,0 +1,444 @@
+//! Implementation of a transport wrapper that transparently coalesces
+//! concurrent requests into JSON RPC batches.
+
+use futures::channel::oneshot;
+use futures::future::{self, BoxFuture, Shared};
+use futures::FutureExt as _;
+use futures_timer::Delay;
+use jsonrpc_core::Call;
+use serde_json::Value;
+use std::fmt::{self, Debug, Formatter};
+use std::mem;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use web3::api::SubscriptionId;
+use web3::error::{Error as Web3Error, TransportError};
+use web3::{BatchTransport, DuplexTransport, RequestId, Transport};
+
+/// The default duration to collect requests for before sending a batch.
+pub const DEFAULT_BATCH_WINDOW: Duration = Duration::from_millis(10);
+
+/// The default maximum number of requests in a batch.
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 100;
+
+/// Parameters for coalescing requests into batches.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AutoBatchConfig {
+    /// The duration to collect requests for after the first request of a
+    /// batch was issued. A zero window only coalesces requests that are
+    /// issued concurrently before any of them is polled again.
+    pub window: Duration,
+    /// The maximum number of requests in a batch. A batch is sent right away
+    /// once it reaches this size.
+    pub max_batch_size: usize,
+}
+
+impl Default for AutoBatchConfig {
+    fn default() -> Self {
+        AutoBatchConfig {
+            window: DEFAULT_BATCH_WINDOW,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+        }
+    }
+}
+
+/// Transport wrapper that collects requests issued within a short window, or
+/// up to a maximum number of requests, and sends them to the node as a single
+/// JSON RPC batch. Each request future resolves separately with its own
+/// result.
+///
+/// Batches are sent by the request futures themselves, which all drive a
+/// shared future for sending their batch, so no background task is required.
+/// Dropping a request future does not affect the other requests of its batch.
+#[derive(Clone, Debug)]
+pub struct AutoBatchingTransport<T> {
+    inner: T,
+    config: Arc<AutoBatchConfig>,
+    pending: Arc<Mutex<Option<PendingBatch>>>,
+}
+
+type PendingRequest = (RequestId, Call, oneshot::Sender<Result<Value, Web3Error>>);
+
+/// The batch that new requests are added to.
+struct PendingBatch {
+    requests: Arc<Mutex<Vec<PendingRequest>>>,
+    /// The future that sends the batch, polled by all of its requests.
+    flush: Shared<BoxFuture<'static, ()>>,
+    /// Signals that the batch is full and should be sent right away.
+    full: oneshot::Sender<()>,
+}
+
+impl Debug for PendingBatch {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("PendingBatch")
+            .field("requests", &self.requests)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> AutoBatchingTransport<T> {
+    /// Wraps a transport with the default batching configuration.
+    pub fn new(inner: T) -> Self {
+        AutoBatchingTransport::with_config(inner, AutoBatchConfig::default())
+    }
+
+    /// Wraps a transport with the specified batching configuration.
+    pub fn with_config(inner: T, config: AutoBatchConfig) -> Self {
+        AutoBatchingTransport {
+            inner,
+            config: Arc::new(config),
+            pending: Default::default(),
+        }
+    }
+
+    /// Returns a reference to the inner transport.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns the batching configuration.
+    pub fn config(&self) -> &AutoBatchConfig {
+        &self.config
+    }
+}
+
+impl<T> AutoBatchingTransport<T>
+where
+    T: BatchTransport + Clone + Send + Sync + 'static,
+    T::Out: Send,
+    T::Batch: Send,
+{
+    /// Creates a new pending batch that is sent once the window expires or
+    /// the batch is full.
+    fn new_batch(&self) -> PendingBatch {
+        let requests = Arc::<Mutex<Vec<_>>>::default();
+        let (full, full_receiver) = oneshot::channel();
+        let flush = Self::flush(
+            self.inner.clone(),
+            Arc::downgrade(&self.pending),
+            requests.clone(),
+            full_receiver,
+            self.config.window,
+        )
+        .boxed()
+        .shared();
+        PendingBatch {
+            requests,
+            flush,
+            full,
+        }
+    }
+
+    /// Waits for the batch window to expire or for the batch to be full, and
+    /// then sends the batch.
+    async fn flush(
+        inner: T,
+        pending: Weak<Mutex<Option<PendingBatch>>>,
+        requests: Arc<Mutex<Vec<PendingRequest>>>,
+        full: oneshot::Receiver<()>,
+        window: Duration,
+    ) {
+        let timer = if window.is_zero() {
+            yield_now().boxed()
+        } else {
+            Delay::new(window).boxed()
+        };
+        future::select(full, timer).await;
+
+        // NOTE: Stop adding requests to this batch before sending it. Requests
+        //   are only added while holding the pending batch lock, so none can
+        //   be added once it is replaced.
+        if let Some(pending) = pending.upgrade() {
+            let mut pending = pending.lock().unwrap();
+            if pending
+                .as_ref()
+                .is_some_and(|batch| Arc::ptr_eq(&batch.requests, &requests))
+            {
+                *pending = None;
+            }
+        }
+        let batch = mem::take(&mut *requests.lock().unwrap());
+        if !batch.is_empty() {
+            Self::send_requests(inner, batch).await;
+        }
+    }
+
+    /// Sends a batch of pending requests and resolves their futures.
+    async fn send_requests(inner: T, batch: Vec<PendingRequest>) {
+        if let [_] = &batch[..] {
+            let (id, request, sender) = batch.into_iter().next().unwrap();
+            let _ = sender.send(inner.send(id, request).await);
+            return;
+        }
+
+        let (requests, senders): (Vec<_>, Vec<_>) = batch
+            .into_iter()
+            .map(|(id, request, sender)| ((id, request), sender))
+            .unzip();
+        match inner.send_batch(requests).await {
+            Ok(results) => {
+                let mut results = results.into_iter();
+                for sender in senders {
+                    let result = results.next().unwrap_or_else(|| {
+                        Err(Web3Error::InvalidResponse(
+                            "incomplete batch response".to_owned(),
+                        ))
+                    });
+                    let _ = sender.send(result);
+                }
+            }
+            Err(err) => {
+                for sender in senders {
+                    let _ = sender.send(Err(err.clone()));
+                }
+            }
+        }
+    }
+}
+
+impl<T> Transport for AutoBatchingTransport<T>
+where
+    T: BatchTransport + Clone + Send + Sync + 'static,
+    T::Out: Send,
+    T::Batch: Send,
+{
+    type Out = BoxFuture<'static, Result<Value, Web3Error>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        self.inner.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: Call) -> Self::Out {
+        let (sender, receiver) = oneshot::channel();
+        let flush = {
+            let mut pending = self.pending.lock().unwrap();
+            let batch = pending.get_or_insert_with(|| self.new_batch());
+            let len = {
+                let mut requests = batch.requests.lock().unwrap();
+                requests.push((id, request, sender));
+                requests.len()
+            };
+            let flush = batch.flush.clone();
+            if len >= self.config.max_batch_size {
+                let batch = pending.take().expect("batch was just added");
+                let _ = batch.full.send(());
+            }
+            flush
+        };
+
+        async move {
+            let (result, ()) = future::join(receiver, flush).await;
+            canceled(result)
+        }
+        .boxed()
+    }
+}
+
+impl<T> BatchTransport for AutoBatchingTransport<T>
+where
+    T: BatchTransport + Clone + Send + Sync + 'static,
+    T::Out: Send,
+    T::Batch: Send,
+{
+    type Batch = T::Batch;
+
+    fn send_batch<I>(&self, requests: I) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, Call)>,
+    {
+        self.inner.send_batch(requests)
+    }
+}
+
+impl<T> DuplexTransport for AutoBatchingTransport<T>
+where
+    T: BatchTransport + DuplexTransport + Clone + Send + Sync + 'static,
+    T::Out: Send,
+    T::Batch: Send,
+{
+    type NotificationStream = T::NotificationStream;
+
+    fn subscribe(&self, id: SubscriptionId) -> Result<Self::NotificationStream, Web3Error> {
+        self.inner.subscribe(id)
+    }
+
+    fn unsubscribe(&self, id: SubscriptionId) -> Result<(), Web3Error> {
+        self.inner.unsubscribe(id)
+    }
+}
+
+/// Converts the result of a canceled request into an error.
+fn canceled(
+    result: Result<Result<Value, Web3Error>, oneshot::Canceled>,
+) -> Result<Value, Web3Error> {
+    result.unwrap_or_else(|_| {
+        Err(Web3Error::Transport(TransportError::Message(
+            "batched request was dropped before it was sent".to_owned(),
+        )))
+    })
+}
+
+/// Yields to the executor once, so that other concurrent futures can issue
+/// their requests.
+async fn yield_now() {
+    let mut yielded = false;
+    future::poll_fn(|cx| {
+        if yielded {
+            return std::task::Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        std::task::Poll::Pending
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::prelude::*;
+    use crate::transport::DynTransport;
+    use web3::types::U64;
+
+    /// Returns a batching transport without a window, so that batches are
+    /// sent as soon as all concurrent requests were issued.
+    fn batching(
+        transport: &TestTransport,
+        config: AutoBatchConfig,
+    ) -> AutoBatchingTransport<TestTransport> {
+        AutoBatchingTransport::with_config(
+            transport.clone(),
+            AutoBatchConfig {
+                window: Duration::from_millis(0),
+                ..config
+            },
+        )
+    }
+
+    #[test]
+    fn coalesces_concurrent_requests() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(DynTransport::new(batching(&transport, Default::default())));
+
+        transport.add_batch_response(vec![
+            Ok(json!("0x1")),
+            Err(Web3Error::Unreachable),
+            Ok(json!("0x3")),
+        ]);
+        let eth = web3.eth();
+        let (first, second, third) =
+            futures::future::join3(eth.block_number(), eth.chain_id(), eth.gas_price()).wait();
+
+        assert_eq!(first.unwrap(), U64::from(1));
+        assert!(matches!(second, Err(Web3Error::Unreachable)));
+        assert_eq!(third.unwrap(), 3.into());
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_request("eth_chainId", &[]);
+        transport.assert_request("eth_gasPrice", &[]);
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn sends_single_requests_without_batch() {
+        let mut transport = TestTransport::new();
+        let batching = batching(&transport, Default::default());
+
+        transport.add_response(json!("0x1"));
+        let result = batching.execute("eth_blockNumber", vec![]).wait();
+
+        assert_eq!(result.unwrap(), json!("0x1"));
+    }
+
+    #[test]
+    fn splits_batches_at_max_size() {
+        let mut transport = TestTransport::new();
+        let batching = batching(
+            &transport,
+            AutoBatchConfig {
+                max_batch_size: 2,
+                ..Default::default()
+            },
+        );
+
+        transport.add_response(json!(["0x1", "0x2"]));
+        transport.add_response(json!("0x3"));
+        let results =
+            futures::future::join_all((0..3).map(|_| batching.execute("eth_blockNumber", vec![])))
+                .wait();
+
+        assert_eq!(
+            results.into_iter().map(Result::unwrap).collect::<Vec<_>>(),
+            vec![json!("0x1"), json!("0x2"), json!("0x3")]
+        );
+    }
+
+    #[test]
+    fn batch_errors_fail_all_requests() {
+        let mut transport = TestTransport::new();
+        let batching = batching(&transport, Default::default());
+
+        transport.add_error(Web3Error::Transport(TransportError::Code(502)));
+        let results =
+            futures::future::join_all((0..2).map(|_| batching.execute("eth_blockNumber", vec![])))
+                .wait();
+
+        for result in results {
+            assert!(matches!(
+                result,
+                Err(Web3Error::Transport(TransportError::Code(502)))
+            ));
+        }
+    }
+
+    #[test]
+    fn waits_for_window() {
+        let mut transport = TestTransport::new();
+        let batching = AutoBatchingTransport::with_config(
+            transport.clone(),
+            AutoBatchConfig {
+                window: Duration::from_millis(10),
+                ..Default::default()
+            },
+        );
+
+        transport.add_response(json!(["0x1", "0x2"]));
+        let first = batching.execute("eth_blockNumber", vec![]);
+        let second = batching.execute("eth_chainId", vec![]);
+        let (first, second) = futures::future::join(first, second).wait();
+
+        assert_eq!(first.unwrap(), json!("0x1"));
+        assert_eq!(second.unwrap(), json!("0x2"));
+    }
+
+    #[test]
+    fn dropped_requests_do_not_fail_their_batch() {
+        let mut transport = TestTransport::new();
+        let batching = batching(
+            &transport,
+            AutoBatchConfig {
+                max_batch_size: 2,
+                ..Default::default()
+            },
+        );
+
+        // the second request fills the batch and is dropped before sending it
+        transport.add_response(json!(["0x1", "0x2"]));
+        let first = batching.execute("eth_blockNumber", vec![]);
+        drop(batching.execute("eth_chainId", vec![]));
+        assert_eq!(first.wait().unwrap(), json!("0x1"));
+
+        // the first request of a batch is dropped while waiting for the window
+        transport.add_response(json!(["0x3", "0x4"]));
+        let mut leader = batching.execute("eth_blockNumber", vec![]);
+        assert!((&mut leader).now_or_never().is_none());
+        let follower = batching.execute("eth_chainId", vec![]);
+        drop(leader);
+        assert_eq!(follower.wait().unwrap(), json!("0x4"));
+
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_request("eth_chainId", &[]);
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_request("eth_chainId", &[]);
+        transport.assert_no_more_requests();
+    }
+}
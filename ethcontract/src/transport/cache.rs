@@ -0,0 +1,620 @@
+//! Implementation of a transport wrapper that memoizes the results of requests
+//! that can't change, such as the chain ID or calls at a specific block.
+
+use futures::future::BoxFuture;
+use futures::FutureExt as _;
+use jsonrpc_core::{Call, Params};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use web3::api::SubscriptionId;
+use web3::error::Error as Web3Error;
+use web3::types::U64;
+use web3::{BatchTransport, DuplexTransport, RequestId, Transport};
+
+/// The default maximum number of cached results.
+pub const DEFAULT_CACHE_CAPACITY: usize = 1024;
+
+/// Transport wrapper that caches the results of requests that can't change in
+/// a bounded LRU cache.
+///
+/// The following requests are cached:
+/// - `net_version` and `eth_chainId`;
+/// - `eth_call`, `eth_getCode`, `eth_getBalance`, `eth_getStorageAt` and
+///   `eth_getTransactionCount` for a specific block number or block hash;
+/// - `eth_getBlockByHash` for known blocks;
+/// - `eth_getTransactionReceipt` for receipts that are a configured number of
+///   confirmations deep, see [`CachingTransport::cache_receipts`].
+///
+/// Requests for mutable block tags like `latest` or `pending` as well as
+/// errors are never cached. Neither are requests that set `requireCanonical`,
+/// since the node has to check that the block is still part of the canonical
+/// chain on every request. Receipts are not cached by default, since a reorg
+/// can move a mined transaction to a different block or drop it entirely. Note
+/// that results for a specific block number can still change in case of a
+/// reorg, so this transport should only be used for data that is deep enough
+/// in the chain.
+#[derive(Clone, Debug)]
+pub struct CachingTransport<T> {
+    inner: Arc<T>,
+    cache: Arc<Mutex<Lru>>,
+    receipt_confirmations: Option<u64>,
+}
+
+impl<T> CachingTransport<T> {
+    /// Wraps a transport with a cache of the default capacity.
+    pub fn new(inner: T) -> Self {
+        CachingTransport::with_capacity(inner, DEFAULT_CACHE_CAPACITY)
+    }
+
+    /// Wraps a transport with a cache holding at most `capacity` results.
+    pub fn with_capacity(inner: T, capacity: usize) -> Self {
+        CachingTransport {
+            inner: Arc::new(inner),
+            cache: Arc::new(Mutex::new(Lru::new(capacity))),
+            receipt_confirmations: None,
+        }
+    }
+
+    /// Also caches transaction receipts once they are at least `confirmations`
+    /// blocks deep, in the same sense as
+    /// [`ConfirmParams::confirmations`](crate::transaction::confirm::ConfirmParams::confirmations).
+    /// Checking the depth of a newly fetched receipt requires an additional
+    /// `eth_blockNumber` request.
+    pub fn cache_receipts(mut self, confirmations: u64) -> Self {
+        self.receipt_confirmations = Some(confirmations);
+        self
+    }
+
+    /// Returns a reference to the inner transport.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns the number of cached results.
+    pub fn len(&self) -> usize {
+        self.cache.lock().unwrap().entries.len()
+    }
+
+    /// Returns true if no results are cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes all cached results.
+    pub fn clear(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+
+    /// Returns the cached result for a request.
+    fn get(&self, key: &Option<CacheKey>) -> Option<Value> {
+        let key = key.as_ref()?;
+        self.cache.lock().unwrap().get(&key.key)
+    }
+}
+
+impl<T> Transport for CachingTransport<T>
+where
+    T: Transport + Send + Sync + 'static,
+    T::Out: Send,
+{
+    type Out = BoxFuture<'static, Result<Value, Web3Error>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        self.inner.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: Call) -> Self::Out {
+        let key = CacheKey::new(&request, self.receipt_confirmations);
+        if let Some(value) = self.get(&key) {
+            return futures::future::ready(Ok(value)).boxed();
+        }
+
+        let inner = self.inner.clone();
+        let cache = self.cache.clone();
+        let response = self.inner.send(id, request);
+        async move {
+            let value = response.await?;
+            if let Some(key) = key {
+                let head = match key.mined_block(&value) {
+                    Some(_) => block_number(&*inner).await,
+                    None => None,
+                };
+                key.insert(&mut cache.lock().unwrap(), &value, head);
+            }
+            Ok(value)
+        }
+        .boxed()
+    }
+}
+
+impl<T> BatchTransport for CachingTransport<T>
+where
+    T: BatchTransport + Send + Sync + 'static,
+    T::Out: Send,
+    T::Batch: Send,
+{
+    type Batch = BoxFuture<'static, Result<Vec<Result<Value, Web3Error>>, Web3Error>>;
+
+    fn send_batch<I>(&self, requests: I) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, Call)>,
+    {
+        let mut keys = Vec::new();
+        let mut results = Vec::new();
+        let mut misses = Vec::new();
+        for (id, request) in requests {
+            let key = CacheKey::new(&request, self.receipt_confirmations);
+            let cached = self.get(&key);
+            if cached.is_none() {
+                misses.push((id, request));
+            }
+            keys.push(key);
+            results.push(cached.map(Ok));
+        }
+
+        if misses.is_empty() {
+            let results = results.into_iter().flatten().collect();
+            return futures::future::ready(Ok(results)).boxed();
+        }
+
+        let inner = self.inner.clone();
+        let cache = self.cache.clone();
+        let response = self.inner.send_batch(misses);
+        async move {
+            let mut responses = response.await?.into_iter();
+            let mut fresh = Vec::new();
+            let results = results
+                .into_iter()
+                .zip(keys)
+                .map(|(cached, key)| {
+                    if let Some(cached) = cached {
+                        return cached;
+                    }
+                    let response = responses.next().unwrap_or_else(|| {
+                        Err(Web3Error::InvalidResponse(
+                            "incomplete batch response".to_owned(),
+                        ))
+                    });
+                    if let (Ok(value), Some(key)) = (&response, key) {
+                        fresh.push((key, value.clone()));
+                    }
+                    response
+                })
+                .collect();
+
+            let head = if fresh
+                .iter()
+                .any(|(key, value)| key.mined_block(value).is_some())
+            {
+                block_number(&*inner).await
+            } else {
+                None
+            };
+            let mut cache = cache.lock().unwrap();
+            for (key, value) in fresh {
+                key.insert(&mut cache, &value, head);
+            }
+
+            Ok(results)
+        }
+        .boxed()
+    }
+}
+
+impl<T> DuplexTransport for CachingTransport<T>
+where
+    T: DuplexTransport + Send + Sync + 'static,
+    T::Out: Send,
+{
+    type NotificationStream = T::NotificationStream;
+
+    fn subscribe(&self, id: SubscriptionId) -> Result<Self::NotificationStream, Web3Error> {
+        self.inner.subscribe(id)
+    }
+
+    fn unsubscribe(&self, id: SubscriptionId) -> Result<(), Web3Error> {
+        self.inner.unsubscribe(id)
+    }
+}
+
+/// The key of a cacheable request along with the condition for caching its
+/// result.
+struct CacheKey {
+    key: String,
+    condition: CacheCondition,
+}
+
+/// Condition on the result of a request for it to be cached.
+#[derive(Clone, Copy)]
+enum CacheCondition {
+    /// The result can always be cached.
+    Always,
+    /// The result can be cached if it is not `null`.
+    NotNull,
+    /// The result is a receipt that can be cached once it is mined at least
+    /// the specified number of blocks below the current block.
+    Confirmed(u64),
+}
+
+impl CacheKey {
+    /// Returns the cache key for a request, or `None` if its result can
+    /// change.
+    fn new(request: &Call, receipt_confirmations: Option<u64>) -> Option<Self> {
+        let call = match request {
+            Call::MethodCall(call) => call,
+            _ => return None,
+        };
+        let params = match &call.params {
+            Params::Array(params) => &params[..],
+            Params::None => &[],
+            Params::Map(_) => return None,
+        };
+
+        let condition = match call.method.as_str() {
+            "net_version" | "eth_chainId" => CacheCondition::Always,
+            "eth_call" | "eth_getCode" | "eth_getBalance" | "eth_getTransactionCount"
+                if is_fixed_block(params.get(1)) =>
+            {
+                CacheCondition::Always
+            }
+            "eth_getStorageAt" if is_fixed_block(params.get(2)) => CacheCondition::Always,
+            "eth_getBlockByHash" => CacheCondition::NotNull,
+            "eth_getTransactionReceipt" => CacheCondition::Confirmed(receipt_confirmations?),
+            _ => return None,
+        };
+
+        Some(CacheKey {
+            key: serde_json::to_string(&(&call.method, params)).ok()?,
+            condition,
+        })
+    }
+
+    /// Returns the block a receipt result was mined in if caching it depends
+    /// on the current block number.
+    fn mined_block(&self, value: &Value) -> Option<u64> {
+        match self.condition {
+            CacheCondition::Confirmed(_) => {
+                let block = serde_json::from_value::<U64>(value.get("blockNumber")?.clone());
+                Some(block.ok()?.as_u64())
+            }
+            _ => None,
+        }
+    }
+
+    /// Inserts a result into the cache if it satisfies the caching condition
+    /// given the current block number.
+    fn insert(self, cache: &mut Lru, value: &Value, head: Option<u64>) {
+        let cacheable = match self.condition {
+            CacheCondition::Always => true,
+            CacheCondition::NotNull => !value.is_null(),
+            CacheCondition::Confirmed(confirmations) => match (self.mined_block(value), head) {
+                (Some(block), Some(head)) => block.saturating_add(confirmations) <= head,
+                _ => false,
+            },
+        };
+        if cacheable {
+            cache.insert(self.key, value.clone());
+        }
+    }
+}
+
+/// Returns true if a block parameter refers to a specific block by number or
+/// hash instead of a block tag like `latest`, and does not require the block
+/// to be canonical.
+fn is_fixed_block(block: Option<&Value>) -> bool {
+    match block {
+        Some(Value::String(block)) => block.starts_with("0x"),
+        Some(Value::Object(block)) => {
+            block.get("requireCanonical") != Some(&Value::Bool(true))
+                && (block.contains_key("blockHash") || is_fixed_block(block.get("blockNumber")))
+        }
+        _ => false,
+    }
+}
+
+/// Retrieves the current block number, or `None` if it can't be retrieved.
+async fn block_number<T: Transport>(transport: &T) -> Option<u64> {
+    let (id, request) = transport.prepare("eth_blockNumber", vec![]);
+    let value = transport.send(id, request).await.ok()?;
+    Some(serde_json::from_value::<U64>(value).ok()?.as_u64())
+}
+
+/// A simple least recently used cache.
+#[derive(Debug)]
+struct Lru {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<String, (Value, u64)>,
+    order: BTreeMap<u64, String>,
+}
+
+impl Lru {
+    fn new(capacity: usize) -> Self {
+        Lru {
+            capacity,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Option<Value> {
+        let tick = self.next_tick();
+        let (value, used) = self.entries.get_mut(key)?;
+        let key = self.order.remove(used).expect("entry is ordered");
+        self.order.insert(tick, key);
+        *used = tick;
+        Some(value.clone())
+    }
+
+    fn insert(&mut self, key: String, value: Value) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let tick = self.next_tick();
+        if let Some((_, used)) = self.entries.insert(key.clone(), (value, tick)) {
+            self.order.remove(&used);
+        }
+        self.order.insert(tick, key);
+
+        while self.entries.len() > self.capacity {
+            let (_, key) = self.order.pop_first().expect("entries are ordered");
+            self.entries.remove(&key);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::prelude::*;
+    use crate::transport::{DynTransport, InstrumentedTransport, RpcMetrics};
+    use web3::types::{H256, U64};
+
+    #[test]
+    fn caches_chain_id() {
+        let mut transport = TestTransport::new();
+        let metrics = RpcMetrics::new();
+        let cached = CachingTransport::new(
+            InstrumentedTransport::new(transport.clone()).hook(metrics.clone()),
+        );
+
+        transport.add_response(json!("0x1"));
+        for _ in 0..3 {
+            let chain_id = cached.execute("eth_chainId", vec![]).immediate().unwrap();
+            assert_eq!(chain_id, json!("0x1"));
+        }
+
+        assert_eq!(metrics.method("eth_chainId").unwrap().requests, 1);
+        assert_eq!(metrics.snapshot().len(), 1);
+        assert_eq!(cached.len(), 1);
+    }
+
+    #[test]
+    fn caches_calls_at_fixed_blocks() {
+        let mut transport = TestTransport::new();
+        let cached = CachingTransport::new(transport.clone());
+        let call = json!({ "to": addr!("0x0123456789012345678901234567890123456789") });
+
+        transport.add_response(json!("0x01"));
+        transport.add_response(json!("0x02"));
+        for block in [json!("0x2a"), json!({ "blockHash": H256::zero() })] {
+            cached
+                .execute("eth_call", vec![call.clone(), block.clone()])
+                .immediate()
+                .unwrap();
+        }
+        assert_eq!(cached.len(), 2);
+
+        assert_eq!(
+            cached
+                .execute("eth_call", vec![call.clone(), json!("0x2a")])
+                .immediate()
+                .unwrap(),
+            json!("0x01")
+        );
+        assert_eq!(
+            cached
+                .execute("eth_call", vec![call, json!({ "blockHash": H256::zero() })])
+                .immediate()
+                .unwrap(),
+            json!("0x02")
+        );
+    }
+
+    #[test]
+    fn does_not_cache_canonical_block_calls() {
+        let mut transport = TestTransport::new();
+        let cached = CachingTransport::new(transport.clone());
+        let block = json!({ "blockHash": H256::zero(), "requireCanonical": true });
+
+        transport.add_response(json!("0x01"));
+        transport.add_error(Web3Error::Rpc(jsonrpc_core::Error::invalid_params(
+            "block is not canonical",
+        )));
+        cached
+            .execute("eth_call", vec![json!({}), block.clone()])
+            .immediate()
+            .unwrap();
+        assert!(cached
+            .execute("eth_call", vec![json!({}), block])
+            .immediate()
+            .is_err());
+        assert!(cached.is_empty());
+    }
+
+    #[test]
+    fn caches_confirmed_receipts() {
+        let mut transport = TestTransport::new();
+        let cached = CachingTransport::new(transport.clone()).cache_receipts(2);
+        let receipt = |block: u64| {
+            json!({
+                "blockHash": H256::repeat_byte(1),
+                "blockNumber": U64::from(block),
+            })
+        };
+        let get_receipt = |hash: H256| {
+            cached
+                .execute("eth_getTransactionReceipt", vec![json!(hash)])
+                .immediate()
+                .unwrap()
+        };
+
+        // Pending and missing receipts don't require the current block.
+        transport.add_response(json!(null));
+        transport.add_response(json!({ "blockHash": null, "blockNumber": null }));
+        get_receipt(H256::repeat_byte(1));
+        get_receipt(H256::repeat_byte(1));
+        transport.assert_request("eth_getTransactionReceipt", &[json!(H256::repeat_byte(1))]);
+        transport.assert_request("eth_getTransactionReceipt", &[json!(H256::repeat_byte(1))]);
+        transport.assert_no_more_requests();
+        assert!(cached.is_empty());
+
+        // Receipts that are not deep enough are not cached.
+        transport.add_response(receipt(9));
+        transport.add_response(json!("0xa"));
+        assert_eq!(get_receipt(H256::repeat_byte(2)), receipt(9));
+        transport.assert_request("eth_getTransactionReceipt", &[json!(H256::repeat_byte(2))]);
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_no_more_requests();
+        assert!(cached.is_empty());
+
+        transport.add_response(receipt(9));
+        transport.add_response(json!("0xb"));
+        assert_eq!(get_receipt(H256::repeat_byte(2)), receipt(9));
+        assert_eq!(get_receipt(H256::repeat_byte(2)), receipt(9));
+        assert_eq!(cached.len(), 1);
+    }
+
+    #[test]
+    fn does_not_cache_receipts_by_default() {
+        let mut transport = TestTransport::new();
+        let cached = CachingTransport::new(transport.clone());
+
+        transport.add_response(json!({ "blockNumber": "0x1" }));
+        cached
+            .execute("eth_getTransactionReceipt", vec![json!(H256::zero())])
+            .immediate()
+            .unwrap();
+
+        transport.assert_request("eth_getTransactionReceipt", &[json!(H256::zero())]);
+        transport.assert_no_more_requests();
+        assert!(cached.is_empty());
+    }
+
+    #[test]
+    fn does_not_cache_mutable_results() {
+        let mut transport = TestTransport::new();
+        let cached = CachingTransport::new(transport.clone());
+        let address = json!(addr!("0x0123456789012345678901234567890123456789"));
+
+        for (method, params) in [
+            ("eth_blockNumber", vec![]),
+            ("eth_call", vec![json!({}), json!("latest")]),
+            ("eth_call", vec![json!({})]),
+            ("eth_getCode", vec![address.clone(), json!("pending")]),
+            (
+                "eth_getStorageAt",
+                vec![address, json!("0x0"), json!("safe")],
+            ),
+            ("eth_getTransactionReceipt", vec![json!(H256::zero())]),
+            (
+                "eth_getBlockByHash",
+                vec![json!(H256::zero()), json!(false)],
+            ),
+            ("eth_getTransactionByHash", vec![json!(H256::zero())]),
+        ] {
+            let response = match method {
+                "eth_getTransactionByHash" | "eth_getTransactionReceipt" => {
+                    json!({ "blockHash": H256::repeat_byte(1) })
+                }
+                "eth_getBlockByHash" => json!(null),
+                _ => json!("0x1"),
+            };
+            transport.add_response(response);
+            cached.execute(method, params).immediate().unwrap();
+        }
+
+        assert!(cached.is_empty());
+    }
+
+    #[test]
+    fn does_not_cache_errors() {
+        let mut transport = TestTransport::new();
+        let cached = CachingTransport::new(transport.clone());
+
+        transport.add_error(Web3Error::Unreachable);
+        transport.add_response(json!("0x1"));
+        assert!(cached.execute("net_version", vec![]).immediate().is_err());
+        assert_eq!(
+            cached.execute("net_version", vec![]).immediate().unwrap(),
+            json!("0x1")
+        );
+        assert_eq!(cached.len(), 1);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut lru = Lru::new(2);
+        lru.insert("a".to_owned(), json!(1));
+        lru.insert("b".to_owned(), json!(2));
+        assert_eq!(lru.get("a"), Some(json!(1)));
+
+        lru.insert("c".to_owned(), json!(3));
+        assert_eq!(lru.get("b"), None);
+        assert_eq!(lru.get("a"), Some(json!(1)));
+        assert_eq!(lru.get("c"), Some(json!(3)));
+
+        lru.insert("a".to_owned(), json!(4));
+        lru.insert("d".to_owned(), json!(5));
+        assert_eq!(lru.get("c"), None);
+        assert_eq!(lru.get("a"), Some(json!(4)));
+        assert_eq!(lru.entries.len(), 2);
+        assert_eq!(lru.order.len(), 2);
+    }
+
+    #[test]
+    fn batch_only_sends_cache_misses() {
+        let mut transport = TestTransport::new();
+        let cached = CachingTransport::new(transport.clone());
+
+        transport.add_response(json!("0x1"));
+        cached.execute("eth_chainId", vec![]).immediate().unwrap();
+
+        let requests = vec![
+            cached.prepare("eth_chainId", vec![]),
+            cached.prepare("eth_blockNumber", vec![]),
+        ];
+        transport.add_response(json!(["0x2a"]));
+        let results = cached.send_batch(requests).immediate().unwrap();
+
+        assert_eq!(
+            results.into_iter().map(Result::unwrap).collect::<Vec<_>>(),
+            vec![json!("0x1"), json!("0x2a")]
+        );
+    }
+
+    #[test]
+    fn composes_with_dyn_transport() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(DynTransport::new(CachingTransport::new(transport.clone())));
+
+        transport.add_response(json!("0x1"));
+        for _ in 0..2 {
+            let chain_id = web3.eth().chain_id().immediate().unwrap();
+            assert_eq!(chain_id, 1.into());
+        }
+    }
+}
@@ -0,0 +1,649 @@
+//! Implementation of transports for recording JSON RPC exchanges with a node to
+//! a cassette file and replaying them later, so that tests written against a
+//! development node can run offline.
+
+use futures::future::{self, BoxFuture};
+use futures::FutureExt as _;
+use jsonrpc_core::{Call, Params};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+use web3::error::{Error as Web3Error, TransportError};
+use web3::{BatchTransport, RequestId, Transport};
+
+/// A recorded sequence of JSON RPC exchanges.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Cassette {
+    /// The recorded exchanges in the order they happened.
+    pub interactions: Vec<Interaction>,
+}
+
+/// A single recorded JSON RPC request and its response.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    /// The request that was sent.
+    pub request: RecordedRequest,
+    /// The response that was received.
+    pub response: RecordedResponse,
+}
+
+/// A recorded JSON RPC request.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    /// The JSON RPC method.
+    pub method: String,
+    /// The request parameters.
+    pub params: Vec<Value>,
+}
+
+/// A recorded response to a JSON RPC request.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RecordedResponse {
+    /// The request succeeded with a result.
+    Result(Value),
+    /// The node returned a JSON RPC error.
+    Error(jsonrpc_core::Error),
+    /// The request failed with a transport error.
+    TransportError(RecordedTransportError),
+}
+
+/// A recorded transport error, preserving whether it was an error code or a
+/// message so that it is replayed as the same [`TransportError`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RecordedTransportError {
+    /// A transport specific error code, such as an HTTP status code.
+    Code(u16),
+    /// An error message.
+    Message(String),
+}
+
+/// Error that can occur while loading or saving a cassette.
+#[derive(Debug, Error)]
+pub enum CassetteError {
+    /// An IO error occurred reading or writing the cassette file.
+    #[error("failed to access cassette file: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// The cassette file is not valid JSON.
+    #[error("invalid cassette: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+impl Cassette {
+    /// Loads a cassette from a JSON file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, CassetteError> {
+        let json = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    /// Saves the cassette as a JSON file.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), CassetteError> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json)?;
+        Ok(())
+    }
+}
+
+impl RecordedRequest {
+    fn from_call(request: &Call) -> Self {
+        let (method, params) = match request {
+            Call::MethodCall(call) => (&call.method, &call.params),
+            Call::Notification(notification) => (&notification.method, &notification.params),
+            Call::Invalid { .. } => {
+                return RecordedRequest {
+                    method: String::new(),
+                    params: Vec::new(),
+                }
+            }
+        };
+        let params = match params {
+            Params::Array(params) => params.clone(),
+            Params::Map(params) => vec![Value::Object(params.clone())],
+            Params::None => Vec::new(),
+        };
+        RecordedRequest {
+            method: method.clone(),
+            params,
+        }
+    }
+}
+
+impl RecordedResponse {
+    fn from_result(result: &Result<Value, Web3Error>) -> Self {
+        match result {
+            Ok(value) => RecordedResponse::Result(value.clone()),
+            Err(Web3Error::Rpc(err)) => RecordedResponse::Error(err.clone()),
+            Err(err) => RecordedResponse::TransportError(RecordedTransportError::from_error(err)),
+        }
+    }
+
+    fn into_result(self) -> Result<Value, Web3Error> {
+        match self {
+            RecordedResponse::Result(value) => Ok(value),
+            RecordedResponse::Error(err) => Err(Web3Error::Rpc(err)),
+            RecordedResponse::TransportError(err) => Err(Web3Error::Transport(err.into())),
+        }
+    }
+}
+
+impl RecordedTransportError {
+    fn from_error(err: &Web3Error) -> Self {
+        match err {
+            Web3Error::Transport(TransportError::Code(code)) => RecordedTransportError::Code(*code),
+            Web3Error::Transport(TransportError::Message(message)) => {
+                RecordedTransportError::Message(message.clone())
+            }
+            err => RecordedTransportError::Message(err.to_string()),
+        }
+    }
+}
+
+impl From<RecordedTransportError> for TransportError {
+    fn from(err: RecordedTransportError) -> Self {
+        match err {
+            RecordedTransportError::Code(code) => TransportError::Code(code),
+            RecordedTransportError::Message(message) => TransportError::Message(message),
+        }
+    }
+}
+
+/// Transport wrapper that records all requests sent through it and their
+/// responses into a [`Cassette`].
+#[derive(Clone, Debug)]
+pub struct RecordingTransport<T> {
+    inner: T,
+    cassette: Arc<Mutex<Cassette>>,
+}
+
+impl<T> RecordingTransport<T> {
+    /// Wraps a transport to record its requests into an empty cassette.
+    pub fn new(inner: T) -> Self {
+        RecordingTransport {
+            inner,
+            cassette: Default::default(),
+        }
+    }
+
+    /// Returns a reference to the inner transport.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns a copy of the cassette recorded so far.
+    pub fn cassette(&self) -> Cassette {
+        self.cassette.lock().unwrap().clone()
+    }
+
+    /// Saves the cassette recorded so far as a JSON file.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), CassetteError> {
+        self.cassette().save(path)
+    }
+}
+
+impl<T> Transport for RecordingTransport<T>
+where
+    T: Transport,
+    T::Out: Send + 'static,
+{
+    type Out = BoxFuture<'static, Result<Value, Web3Error>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        self.inner.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: Call) -> Self::Out {
+        let recorded = RecordedRequest::from_call(&request);
+        let cassette = self.cassette.clone();
+        self.inner
+            .send(id, request)
+            .map(move |result| {
+                cassette.lock().unwrap().interactions.push(Interaction {
+                    request: recorded,
+                    response: RecordedResponse::from_result(&result),
+                });
+                result
+            })
+            .boxed()
+    }
+}
+
+impl<T> BatchTransport for RecordingTransport<T>
+where
+    T: BatchTransport,
+    T::Out: Send + 'static,
+    T::Batch: Send + 'static,
+{
+    type Batch = BoxFuture<'static, Result<Vec<Result<Value, Web3Error>>, Web3Error>>;
+
+    fn send_batch<I>(&self, requests: I) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, Call)>,
+    {
+        let requests = requests.into_iter().collect::<Vec<_>>();
+        let recorded = requests
+            .iter()
+            .map(|(_, request)| RecordedRequest::from_call(request))
+            .collect::<Vec<_>>();
+        let cassette = self.cassette.clone();
+        self.inner
+            .send_batch(requests)
+            .map(move |result| {
+                // NOTE: Batched requests are recorded individually so that
+                //   they can be replayed with or without batching.
+                let mut cassette = cassette.lock().unwrap();
+                for (i, request) in recorded.into_iter().enumerate() {
+                    let response = match &result {
+                        Ok(results) => match results.get(i) {
+                            Some(result) => RecordedResponse::from_result(result),
+                            None => continue,
+                        },
+                        Err(err) => RecordedResponse::TransportError(
+                            RecordedTransportError::from_error(err),
+                        ),
+                    };
+                    cassette
+                        .interactions
+                        .push(Interaction { request, response });
+                }
+                drop(cassette);
+                result
+            })
+            .boxed()
+    }
+}
+
+/// How a [`ReplayTransport`] finds the recorded response for a request.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ReplayMode {
+    /// Requests must be sent in exactly the recorded order.
+    Ordered,
+    /// Requests are matched to the first unused recorded interaction with the
+    /// same method and parameters, regardless of order.
+    Matching,
+}
+
+/// Transport that serves responses from a recorded [`Cassette`] without
+/// connecting to a node.
+///
+/// Requests that don't match the cassette fail with a transport error
+/// containing a diff between the expected and actual request.
+#[derive(Clone, Debug)]
+pub struct ReplayTransport {
+    mode: ReplayMode,
+    state: Arc<Mutex<ReplayState>>,
+}
+
+#[derive(Debug)]
+struct ReplayState {
+    interactions: Vec<Interaction>,
+    used: Vec<bool>,
+    next_id: RequestId,
+}
+
+impl ReplayTransport {
+    /// Creates a transport replaying a cassette in the specified mode.
+    pub fn new(cassette: Cassette, mode: ReplayMode) -> Self {
+        let used = vec![false; cassette.interactions.len()];
+        ReplayTransport {
+            mode,
+            state: Arc::new(Mutex::new(ReplayState {
+                interactions: cassette.interactions,
+                used,
+                next_id: 0,
+            })),
+        }
+    }
+
+    /// Loads a cassette from a JSON file and creates a transport replaying it
+    /// in the specified mode.
+    pub fn load(path: impl AsRef<Path>, mode: ReplayMode) -> Result<Self, CassetteError> {
+        Ok(ReplayTransport::new(Cassette::load(path)?, mode))
+    }
+
+    /// Returns the recorded requests that were not replayed yet.
+    pub fn remaining(&self) -> Vec<RecordedRequest> {
+        let state = self.state.lock().unwrap();
+        state
+            .interactions
+            .iter()
+            .zip(&state.used)
+            .filter(|(_, used)| !**used)
+            .map(|(interaction, _)| interaction.request.clone())
+            .collect()
+    }
+
+    /// Asserts that all recorded interactions were replayed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are recorded requests that were not sent.
+    pub fn assert_finished(&self) {
+        let remaining = self.remaining();
+        assert!(
+            remaining.is_empty(),
+            "{} recorded requests were not replayed, next: {}",
+            remaining.len(),
+            to_pretty_json(&remaining[0]),
+        );
+    }
+
+    fn replay(&self, request: &Call) -> Result<Value, Web3Error> {
+        let request = RecordedRequest::from_call(request);
+        let mut state = self.state.lock().unwrap();
+
+        let index = match self.mode {
+            ReplayMode::Ordered => {
+                let index = state.used.iter().position(|used| !used);
+                match index {
+                    Some(index) if state.interactions[index].request == request => Ok(index),
+                    Some(index) => Err(mismatch(&state.interactions[index].request, &request)),
+                    None => Err(exhausted(&request)),
+                }
+            }
+            ReplayMode::Matching => {
+                let unused = || {
+                    state
+                        .interactions
+                        .iter()
+                        .enumerate()
+                        .filter(|(i, _)| !state.used[*i])
+                };
+                let index = unused()
+                    .find(|(_, interaction)| interaction.request == request)
+                    .map(|(i, _)| i);
+                match index {
+                    Some(index) => Ok(index),
+                    None => {
+                        let closest = unused()
+                            .find(|(_, interaction)| interaction.request.method == request.method);
+                        match closest {
+                            Some((_, interaction)) => Err(mismatch(&interaction.request, &request)),
+                            None => Err(exhausted(&request)),
+                        }
+                    }
+                }
+            }
+        }
+        .map_err(|message| Web3Error::Transport(TransportError::Message(message)))?;
+
+        state.used[index] = true;
+        state.interactions[index].response.clone().into_result()
+    }
+}
+
+impl Transport for ReplayTransport {
+    type Out = future::Ready<Result<Value, Web3Error>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+        let id = state.next_id;
+        (id, web3::helpers::build_request(id, method, params))
+    }
+
+    fn send(&self, _: RequestId, request: Call) -> Self::Out {
+        future::ready(self.replay(&request))
+    }
+}
+
+impl BatchTransport for ReplayTransport {
+    type Batch = future::Ready<Result<Vec<Result<Value, Web3Error>>, Web3Error>>;
+
+    fn send_batch<I>(&self, requests: I) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, Call)>,
+    {
+        future::ok(
+            requests
+                .into_iter()
+                .map(|(_, request)| self.replay(&request))
+                .collect(),
+        )
+    }
+}
+
+fn exhausted(request: &RecordedRequest) -> String {
+    format!(
+        "unexpected request, no matching recorded requests remaining:\n{}",
+        to_pretty_json(request),
+    )
+}
+
+fn mismatch(expected: &RecordedRequest, actual: &RecordedRequest) -> String {
+    format!(
+        "request does not match recording (-expected +actual):\n{}",
+        diff_lines(&to_pretty_json(expected), &to_pretty_json(actual)),
+    )
+}
+
+fn to_pretty_json(request: &RecordedRequest) -> String {
+    serde_json::to_string_pretty(request).expect("requests are valid JSON")
+}
+
+/// Computes a line based diff between two strings using the longest common
+/// subsequence of lines.
+fn diff_lines(expected: &str, actual: &str) -> String {
+    let expected = expected.lines().collect::<Vec<_>>();
+    let actual = actual.lines().collect::<Vec<_>>();
+
+    let mut lcs = vec![vec![0usize; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            lcs[i][j] = if expected[i] == actual[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            diff.push(format!(" {}", expected[i]));
+            i += 1;
+            j += 1;
+        } else if i < expected.len() && (j == actual.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            diff.push(format!("-{}", expected[i]));
+            i += 1;
+        } else {
+            diff.push(format!("+{}", actual[j]));
+            j += 1;
+        }
+    }
+    diff.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::prelude::*;
+    use crate::transport::DynTransport;
+
+    fn record() -> Cassette {
+        let mut transport = TestTransport::new();
+        let recording = RecordingTransport::new(transport.clone());
+        let web3 = Web3::new(DynTransport::new(recording.clone()));
+
+        transport.add_response(json!("0x2a"));
+        transport.add_error(Web3Error::Rpc(jsonrpc_core::Error::method_not_found()));
+        transport.add_response(json!("0x1"));
+        web3.eth().block_number().immediate().unwrap();
+        web3.eth().chain_id().immediate().unwrap_err();
+        web3.eth()
+            .balance(addr!("0x0123456789012345678901234567890123456789"), None)
+            .immediate()
+            .unwrap();
+
+        recording.cassette()
+    }
+
+    #[test]
+    fn records_interactions() {
+        let cassette = record();
+
+        assert_eq!(
+            serde_json::to_value(&cassette).unwrap(),
+            json!({
+                "interactions": [
+                    {
+                        "request": { "method": "eth_blockNumber", "params": [] },
+                        "response": { "result": "0x2a" },
+                    },
+                    {
+                        "request": { "method": "eth_chainId", "params": [] },
+                        "response": {
+                            "error": { "code": -32601, "message": "Method not found" },
+                        },
+                    },
+                    {
+                        "request": {
+                            "method": "eth_getBalance",
+                            "params": ["0x0123456789012345678901234567890123456789", "latest"],
+                        },
+                        "response": { "result": "0x1" },
+                    },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn replays_in_order() {
+        let replay = ReplayTransport::new(record(), ReplayMode::Ordered);
+        let web3 = Web3::new(DynTransport::new(replay.clone()));
+
+        assert_eq!(web3.eth().block_number().immediate().unwrap(), 42.into());
+        assert!(matches!(
+            web3.eth().chain_id().immediate(),
+            Err(Web3Error::Rpc(err)) if err.code == jsonrpc_core::ErrorCode::MethodNotFound
+        ));
+        assert_eq!(replay.remaining().len(), 1);
+
+        let err = web3
+            .eth()
+            .balance(addr!("0x0000000000000000000000000000000000000000"), None)
+            .immediate()
+            .unwrap_err();
+        let message = match err {
+            Web3Error::Transport(TransportError::Message(message)) => message,
+            err => panic!("unexpected error {:?}", err),
+        };
+        assert!(message.contains("-    \"0x0123456789012345678901234567890123456789\","));
+        assert!(message.contains("+    \"0x0000000000000000000000000000000000000000\","));
+        assert!(message.contains("     \"latest\""));
+    }
+
+    #[test]
+    fn replays_by_matching() {
+        let replay = ReplayTransport::new(record(), ReplayMode::Matching);
+        let web3 = Web3::new(DynTransport::new(replay.clone()));
+
+        web3.eth()
+            .balance(addr!("0x0123456789012345678901234567890123456789"), None)
+            .immediate()
+            .unwrap();
+        assert_eq!(web3.eth().block_number().immediate().unwrap(), 42.into());
+        assert!(web3.eth().block_number().immediate().is_err());
+        assert!(web3.eth().chain_id().immediate().is_err());
+        replay.assert_finished();
+    }
+
+    #[test]
+    fn replays_batches() {
+        let replay = ReplayTransport::new(record(), ReplayMode::Ordered);
+        let requests = vec![
+            replay.prepare("eth_blockNumber", vec![]),
+            replay.prepare("eth_chainId", vec![]),
+        ];
+
+        let results = replay.send_batch(requests).immediate().unwrap();
+        assert_eq!(results[0].as_ref().unwrap(), &json!("0x2a"));
+        assert!(results[1].is_err());
+    }
+
+    #[test]
+    fn replays_transport_errors() {
+        let mut transport = TestTransport::new();
+        let recording = RecordingTransport::new(transport.clone());
+
+        transport.add_error(Web3Error::Transport(TransportError::Code(429)));
+        transport.add_error(Web3Error::Transport(TransportError::Message(
+            "request timed out after 1s".into(),
+        )));
+        transport.add_error(Web3Error::Unreachable);
+        for _ in 0..3 {
+            recording
+                .execute("eth_blockNumber", vec![])
+                .immediate()
+                .unwrap_err();
+        }
+
+        let cassette = recording.cassette();
+        assert_eq!(
+            serde_json::to_value(&cassette.interactions[0].response).unwrap(),
+            json!({ "transportError": { "code": 429 } }),
+        );
+
+        let replay = ReplayTransport::new(cassette, ReplayMode::Ordered);
+        let mut replayed = (0..3).map(|_| {
+            replay
+                .execute("eth_blockNumber", vec![])
+                .immediate()
+                .unwrap_err()
+        });
+        assert!(matches!(
+            replayed.next(),
+            Some(Web3Error::Transport(TransportError::Code(429)))
+        ));
+        assert!(matches!(
+            replayed.next(),
+            Some(Web3Error::Transport(TransportError::Message(message)))
+                if message == "request timed out after 1s"
+        ));
+        assert!(matches!(
+            replayed.next(),
+            Some(Web3Error::Transport(TransportError::Message(message)))
+                if message == "Server is unreachable"
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "1 recorded requests were not replayed")]
+    fn assert_finished_panics_with_remaining_requests() {
+        let replay = ReplayTransport::new(record(), ReplayMode::Matching);
+        replay
+            .execute("eth_blockNumber", vec![])
+            .immediate()
+            .unwrap();
+        replay
+            .execute("eth_chainId", vec![])
+            .immediate()
+            .unwrap_err();
+        replay.assert_finished();
+    }
+
+    #[test]
+    fn save_and_load() {
+        let cassette = record();
+        let path =
+            std::env::temp_dir().join(format!("ethcontract-cassette-{}.json", std::process::id()));
+
+        cassette.save(&path).unwrap();
+        let loaded = Cassette::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap(), cassette);
+    }
+
+    #[test]
+    fn diff() {
+        assert_eq!(diff_lines("a\nb\nc", "a\nx\nc\nd"), " a\n-b\n+x\n c\n+d");
+    }
+}
@@ -0,0 +1,280 @@
+//! Implementation of a transport wrapper that calls hooks for each request and
+//! response, for example for collecting metrics or logging.
+
+use futures::future::BoxFuture;
+use futures::FutureExt as _;
+use jsonrpc_core::Call;
+use serde_json::Value;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use web3::api::SubscriptionId;
+use web3::error::Error as Web3Error;
+use web3::{BatchTransport, DuplexTransport, RequestId, Transport};
+
+/// Information about a request sent through an [`InstrumentedTransport`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestInfo {
+    /// The request ID.
+    pub id: RequestId,
+    /// The JSON RPC method of the request.
+    pub method: String,
+    /// The size of the batch the request is part of, or `None` if it was sent
+    /// on its own.
+    pub batch_size: Option<usize>,
+}
+
+/// Information about the response to a request sent through an
+/// [`InstrumentedTransport`].
+#[derive(Debug)]
+pub struct ResponseInfo<'a> {
+    /// The time it took to receive the response. For batched requests, this is
+    /// the duration of the whole batch.
+    pub duration: Duration,
+    /// The result of the request.
+    pub result: Result<&'a Value, &'a Web3Error>,
+}
+
+/// Hook that is called for each request sent through an
+/// [`InstrumentedTransport`] and for its response.
+///
+/// Hooks are called for each sub-request of a batch, with the batch error as
+/// the result of each sub-request if the batch as a whole failed.
+pub trait RequestHook: Debug + Send + Sync + 'static {
+    /// Called before a request is sent.
+    fn on_request(&self, request: &RequestInfo) {
+        let _ = request;
+    }
+
+    /// Called after the response to a request was received.
+    fn on_response(&self, request: &RequestInfo, response: &ResponseInfo) {
+        let _ = (request, response);
+    }
+}
+
+/// Transport wrapper that calls [`RequestHook`]s for each request and response.
+///
+/// When the `tracing` feature is enabled, each request is additionally
+/// instrumented with an `rpc` span carrying the method name and request
+/// duration.
+#[derive(Clone, Debug)]
+pub struct InstrumentedTransport<T> {
+    inner: T,
+    hooks: Arc<Vec<Arc<dyn RequestHook>>>,
+}
+
+impl<T> InstrumentedTransport<T> {
+    /// Wraps a transport without any hooks.
+    pub fn new(inner: T) -> Self {
+        InstrumentedTransport {
+            inner,
+            hooks: Default::default(),
+        }
+    }
+
+    /// Adds a hook that is called for each request and response.
+    pub fn hook(mut self, hook: impl RequestHook) -> Self {
+        Arc::make_mut(&mut self.hooks).push(Arc::new(hook));
+        self
+    }
+
+    /// Returns a reference to the inner transport.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T> Transport for InstrumentedTransport<T>
+where
+    T: Transport,
+    T::Out: Send + 'static,
+{
+    type Out = BoxFuture<'static, Result<Value, Web3Error>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        self.inner.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: Call) -> Self::Out {
+        let info = request_info(id, &request, None);
+        let span = crate::trace::span!("rpc", id = info.id, method = %info.method);
+        for hook in self.hooks.iter() {
+            hook.on_request(&info);
+        }
+
+        let hooks = self.hooks.clone();
+        let start = Instant::now();
+        let response = self.inner.send(id, request);
+        crate::trace::instrument(span, async move {
+            let result = response.await;
+            let response = ResponseInfo {
+                duration: start.elapsed(),
+                result: result.as_ref(),
+            };
+            for hook in hooks.iter() {
+                hook.on_response(&info, &response);
+            }
+            result
+        })
+        .boxed()
+    }
+}
+
+impl<T> BatchTransport for InstrumentedTransport<T>
+where
+    T: BatchTransport,
+    T::Out: Send + 'static,
+    T::Batch: Send + 'static,
+{
+    type Batch = BoxFuture<'static, Result<Vec<Result<Value, Web3Error>>, Web3Error>>;
+
+    fn send_batch<I>(&self, requests: I) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, Call)>,
+    {
+        let requests = requests.into_iter().collect::<Vec<_>>();
+        let infos = requests
+            .iter()
+            .map(|(id, request)| request_info(*id, request, Some(requests.len())))
+            .collect::<Vec<_>>();
+        let span = crate::trace::span!("rpc_batch", size = infos.len());
+        for info in &infos {
+            for hook in self.hooks.iter() {
+                hook.on_request(info);
+            }
+        }
+
+        let hooks = self.hooks.clone();
+        let start = Instant::now();
+        let response = self.inner.send_batch(requests);
+        crate::trace::instrument(span, async move {
+            let result = response.await;
+            let duration = start.elapsed();
+            for (i, info) in infos.iter().enumerate() {
+                let result = match &result {
+                    Ok(results) => match results.get(i) {
+                        Some(result) => result.as_ref(),
+                        None => continue,
+                    },
+                    Err(err) => Err(err),
+                };
+                let response = ResponseInfo { duration, result };
+                for hook in hooks.iter() {
+                    hook.on_response(info, &response);
+                }
+            }
+            result
+        })
+        .boxed()
+    }
+}
+
+impl<T> DuplexTransport for InstrumentedTransport<T>
+where
+    T: DuplexTransport,
+    T::Out: Send + 'static,
+{
+    type NotificationStream = T::NotificationStream;
+
+    fn subscribe(&self, id: SubscriptionId) -> Result<Self::NotificationStream, Web3Error> {
+        self.inner.subscribe(id)
+    }
+
+    fn unsubscribe(&self, id: SubscriptionId) -> Result<(), Web3Error> {
+        self.inner.unsubscribe(id)
+    }
+}
+
+fn request_info(id: RequestId, request: &Call, batch_size: Option<usize>) -> RequestInfo {
+    let method = match request {
+        Call::MethodCall(call) => call.method.clone(),
+        Call::Notification(notification) => notification.method.clone(),
+        Call::Invalid { .. } => String::new(),
+    };
+    RequestInfo {
+        id,
+        method,
+        batch_size,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::prelude::*;
+    use crate::transport::DynTransport;
+    use std::sync::Mutex;
+
+    #[derive(Debug, Default)]
+    struct RecordingHook {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl RequestHook for Arc<RecordingHook> {
+        fn on_request(&self, request: &RequestInfo) {
+            self.events.lock().unwrap().push(format!(
+                "request {} {:?}",
+                request.method, request.batch_size
+            ));
+        }
+
+        fn on_response(&self, request: &RequestInfo, response: &ResponseInfo) {
+            self.events.lock().unwrap().push(format!(
+                "response {} {}",
+                request.method,
+                match response.result {
+                    Ok(value) => value.to_string(),
+                    Err(_) => "error".to_owned(),
+                }
+            ));
+        }
+    }
+
+    #[test]
+    fn calls_hooks() {
+        let mut transport = TestTransport::new();
+        let hook = Arc::new(RecordingHook::default());
+        let web3 = Web3::new(DynTransport::new(
+            InstrumentedTransport::new(transport.clone()).hook(hook.clone()),
+        ));
+
+        transport.add_response(json!("0x2a"));
+        transport.add_error(Web3Error::Unreachable);
+        web3.eth().block_number().immediate().unwrap();
+        web3.eth().chain_id().immediate().unwrap_err();
+
+        assert_eq!(
+            *hook.events.lock().unwrap(),
+            [
+                "request eth_blockNumber None",
+                "response eth_blockNumber \"0x2a\"",
+                "request eth_chainId None",
+                "response eth_chainId error",
+            ]
+        );
+    }
+
+    #[test]
+    fn calls_hooks_for_batch_requests() {
+        let mut transport = TestTransport::new();
+        let hook = Arc::new(RecordingHook::default());
+        let instrumented = InstrumentedTransport::new(transport.clone()).hook(hook.clone());
+
+        let requests = vec![
+            instrumented.prepare("eth_blockNumber", vec![]),
+            instrumented.prepare("eth_chainId", vec![]),
+        ];
+        transport.add_response(json!(["0x1", "0x2"]));
+        instrumented.send_batch(requests).immediate().unwrap();
+
+        assert_eq!(
+            *hook.events.lock().unwrap(),
+            [
+                "request eth_blockNumber Some(2)",
+                "request eth_chainId Some(2)",
+                "response eth_blockNumber \"0x1\"",
+                "response eth_chainId \"0x2\"",
+            ]
+        );
+    }
+}
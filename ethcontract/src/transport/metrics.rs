@@ -0,0 +1,218 @@
+//! Implementation of a simple metrics collector for JSON RPC requests that can
+//! be used as a hook for an [`InstrumentedTransport`](super::InstrumentedTransport).
+
+use crate::transport::{RequestHook, RequestInfo, ResponseInfo};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The default upper bounds of the latency histogram buckets.
+pub const DEFAULT_LATENCY_BUCKETS: &[Duration] = &[
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(25),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(250),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_millis(2500),
+    Duration::from_secs(5),
+    Duration::from_secs(10),
+];
+
+/// Collects per JSON RPC method request counts, error counts and latency
+/// histograms.
+///
+/// Metrics are shared between clones, so a clone can be added as a hook to an
+/// [`InstrumentedTransport`](super::InstrumentedTransport) while the original
+/// is used for reading the metrics.
+#[derive(Clone, Debug, Default)]
+pub struct RpcMetrics {
+    methods: Arc<Mutex<BTreeMap<String, MethodMetrics>>>,
+}
+
+/// Metrics for a single JSON RPC method.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MethodMetrics {
+    /// The number of requests.
+    pub requests: u64,
+    /// The number of requests that resulted in an error.
+    pub errors: u64,
+    /// The latency histogram of the requests.
+    pub latency: LatencyHistogram,
+}
+
+/// A histogram of request latencies.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LatencyHistogram {
+    /// The inclusive upper bounds of the buckets.
+    pub bounds: Vec<Duration>,
+    /// The number of samples per bucket. This has one more element than
+    /// `bounds` for samples exceeding the last bound.
+    pub counts: Vec<u64>,
+    /// The sum of all samples.
+    pub sum: Duration,
+}
+
+impl RpcMetrics {
+    /// Creates a new empty metrics collector.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a snapshot of the metrics of all methods.
+    pub fn snapshot(&self) -> BTreeMap<String, MethodMetrics> {
+        self.methods.lock().unwrap().clone()
+    }
+
+    /// Returns a snapshot of the metrics of a single method.
+    pub fn method(&self, method: &str) -> Option<MethodMetrics> {
+        self.methods.lock().unwrap().get(method).cloned()
+    }
+
+    /// Resets all metrics.
+    pub fn reset(&self) {
+        self.methods.lock().unwrap().clear();
+    }
+}
+
+impl RequestHook for RpcMetrics {
+    fn on_response(&self, request: &RequestInfo, response: &ResponseInfo) {
+        let mut methods = self.methods.lock().unwrap();
+        let metrics = methods
+            .entry(request.method.clone())
+            .or_insert_with(|| MethodMetrics {
+                requests: 0,
+                errors: 0,
+                latency: LatencyHistogram::new(DEFAULT_LATENCY_BUCKETS),
+            });
+
+        metrics.requests += 1;
+        if response.result.is_err() {
+            metrics.errors += 1;
+        }
+        metrics.latency.observe(response.duration);
+    }
+}
+
+impl LatencyHistogram {
+    /// Creates a new empty histogram with the specified bucket bounds.
+    pub fn new(bounds: &[Duration]) -> Self {
+        LatencyHistogram {
+            bounds: bounds.to_vec(),
+            counts: vec![0; bounds.len() + 1],
+            sum: Duration::ZERO,
+        }
+    }
+
+    /// Records a sample.
+    pub fn observe(&mut self, latency: Duration) {
+        let bucket = self.bounds.partition_point(|bound| *bound < latency);
+        self.counts[bucket] += 1;
+        self.sum += latency;
+    }
+
+    /// Returns the total number of samples.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Returns the mean latency, or `None` if there are no samples.
+    pub fn mean(&self) -> Option<Duration> {
+        let count = self.count();
+        (count > 0).then(|| Duration::from_secs_f64(self.sum.as_secs_f64() / count as f64))
+    }
+
+    /// Returns the upper bound of the bucket containing the specified quantile
+    /// (between 0 and 1) of samples. Returns `None` if there are no samples or
+    /// if the quantile falls into the unbounded last bucket.
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+
+        let target = ((count as f64 * quantile).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, samples) in self.counts.iter().enumerate() {
+            seen += samples;
+            if seen >= target {
+                return self.bounds.get(bucket).copied();
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::prelude::*;
+    use crate::transport::InstrumentedTransport;
+    use web3::error::Error as Web3Error;
+    use web3::Transport;
+
+    #[test]
+    fn histogram_buckets() {
+        let mut histogram =
+            LatencyHistogram::new(&[Duration::from_millis(10), Duration::from_millis(100)]);
+        assert_eq!(histogram.mean(), None);
+        assert_eq!(histogram.quantile(0.5), None);
+
+        for ms in [1, 10, 50, 200] {
+            histogram.observe(Duration::from_millis(ms));
+        }
+
+        assert_eq!(histogram.counts, [2, 1, 1]);
+        assert_eq!(histogram.count(), 4);
+        assert_eq!(histogram.sum, Duration::from_millis(261));
+        assert_eq!(histogram.mean(), Some(Duration::from_micros(65_250)));
+        assert_eq!(histogram.quantile(0.5), Some(Duration::from_millis(10)));
+        assert_eq!(histogram.quantile(0.75), Some(Duration::from_millis(100)));
+        assert_eq!(histogram.quantile(1.0), None);
+    }
+
+    #[test]
+    fn histogram_mean_with_many_samples() {
+        let samples = u64::from(u32::MAX) + 1;
+        let histogram = LatencyHistogram {
+            bounds: vec![Duration::from_millis(10)],
+            counts: vec![samples, 0],
+            sum: Duration::from_millis(2) * u32::MAX + Duration::from_millis(2),
+        };
+
+        assert_eq!(histogram.mean(), Some(Duration::from_millis(2)));
+    }
+
+    #[test]
+    fn collects_per_method_metrics() {
+        let mut transport = TestTransport::new();
+        let metrics = RpcMetrics::new();
+        let instrumented = InstrumentedTransport::new(transport.clone()).hook(metrics.clone());
+
+        transport.add_response(json!("0x1"));
+        transport.add_response(json!("0x2"));
+        transport.add_error(Web3Error::Unreachable);
+        transport.add_response(json!("0x1"));
+        for method in [
+            "eth_blockNumber",
+            "eth_blockNumber",
+            "eth_blockNumber",
+            "eth_chainId",
+        ] {
+            let _ = instrumented.execute(method, vec![]).immediate();
+        }
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        let block_number = &snapshot["eth_blockNumber"];
+        assert_eq!(block_number.requests, 3);
+        assert_eq!(block_number.errors, 1);
+        assert_eq!(block_number.latency.count(), 3);
+        assert_eq!(metrics.method("eth_chainId").unwrap().requests, 1);
+
+        metrics.reset();
+        assert!(metrics.snapshot().is_empty());
+    }
+}
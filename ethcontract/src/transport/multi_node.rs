@@ -0,0 +1,1165 @@
+//! Implementation of a transport that distributes requests over multiple nodes,
+//! failing over to the next node when one is unavailable and optionally
+//! requiring a quorum of nodes to agree on the result of reads.
+
+use crate::transport::{is_idempotent, is_rate_limited, is_transient};
+use futures::future::{self, BoxFuture};
+use futures::FutureExt as _;
+use jsonrpc_core::{Call, ErrorCode, Params};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use web3::error::Error as Web3Error;
+use web3::{BatchTransport, RequestId, Transport};
+
+/// The default duration a node is skipped for after it is marked unhealthy.
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// The JSON RPC error code used for reporting quorum errors as `web3` errors.
+pub const QUORUM_ERROR_CODE: i64 = -32099;
+
+/// Methods for which a quorum is required by default when quorum mode is
+/// enabled.
+pub const DEFAULT_QUORUM_METHODS: &[&str] = &[
+    "eth_blockNumber",
+    "eth_call",
+    "eth_chainId",
+    "eth_getBalance",
+    "eth_getCode",
+    "eth_getStorageAt",
+    "eth_getTransactionCount",
+];
+
+/// Methods that use node-local state and therefore can't fail over to a
+/// different node.
+const NODE_LOCAL_METHODS: &[&str] = &[
+    "eth_newFilter",
+    "eth_newBlockFilter",
+    "eth_newPendingTransactionFilter",
+    "eth_getFilterChanges",
+    "eth_getFilterLogs",
+    "eth_uninstallFilter",
+];
+
+/// Methods that create a filter on the node they are sent to.
+const FILTER_CREATION_METHODS: &[&str] = &[
+    "eth_newFilter",
+    "eth_newBlockFilter",
+    "eth_newPendingTransactionFilter",
+];
+
+/// Parameters for distributing requests over multiple nodes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MultiNodeConfig {
+    /// The number of consecutive failures after which a node is marked
+    /// unhealthy.
+    pub failure_threshold: usize,
+    /// The duration an unhealthy node is skipped for, unless no healthy nodes
+    /// remain.
+    pub cooldown: Duration,
+    /// The number of nodes that need to agree on the result of a quorum
+    /// method, or `None` to disable quorum mode. Must be between 1 and the
+    /// number of nodes.
+    pub quorum: Option<usize>,
+    /// The methods that require a quorum when quorum mode is enabled.
+    pub quorum_methods: Vec<String>,
+    /// Whether or not to also fail over non-idempotent methods like
+    /// `eth_sendRawTransaction` when a node may have already received them.
+    pub failover_non_idempotent: bool,
+}
+
+impl Default for MultiNodeConfig {
+    fn default() -> Self {
+        MultiNodeConfig {
+            failure_threshold: 1,
+            cooldown: DEFAULT_COOLDOWN,
+            quorum: None,
+            quorum_methods: DEFAULT_QUORUM_METHODS
+                .iter()
+                .map(|method| method.to_string())
+                .collect(),
+            failover_non_idempotent: false,
+        }
+    }
+}
+
+impl MultiNodeConfig {
+    /// Specify the number of consecutive failures after which a node is marked
+    /// unhealthy.
+    pub fn failure_threshold(mut self, value: usize) -> Self {
+        self.failure_threshold = value;
+        self
+    }
+
+    /// Specify the duration unhealthy nodes are skipped for.
+    pub fn cooldown(mut self, value: Duration) -> Self {
+        self.cooldown = value;
+        self
+    }
+
+    /// Require the specified number of nodes to agree on the result of quorum
+    /// methods. The quorum must be between 1 and the number of nodes.
+    pub fn quorum(mut self, value: usize) -> Self {
+        self.quorum = Some(value);
+        self
+    }
+
+    /// Specify the methods that require a quorum.
+    pub fn quorum_methods<I, S>(mut self, methods: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.quorum_methods = methods.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Specify whether or not to fail over non-idempotent methods after errors
+    /// where the node may have already received them, at the risk of sending
+    /// transactions more than once.
+    pub fn failover_non_idempotent(mut self, value: bool) -> Self {
+        self.failover_non_idempotent = value;
+        self
+    }
+
+    /// Returns true if the requests can be sent to the next node after they
+    /// failed with the specified node failure.
+    fn can_fail_over<'a>(
+        &self,
+        mut requests: impl Iterator<Item = &'a Call>,
+        err: &Web3Error,
+    ) -> bool {
+        self.failover_non_idempotent || is_rate_limited(err) || requests.all(is_idempotent)
+    }
+
+    /// Returns the required quorum for a request, if any.
+    fn quorum_for(&self, request: &Call) -> Option<usize> {
+        let method = method(request)?;
+        self.quorum
+            .filter(|_| self.quorum_methods.iter().any(|m| m == method))
+    }
+}
+
+/// Error returned when not enough nodes agree on the result of a request in
+/// quorum mode.
+///
+/// Since transports can only return `web3` errors, this error is converted into
+/// a JSON RPC error with [`QUORUM_ERROR_CODE`] that contains the responses of
+/// all nodes as data. Use [`QuorumError::from_web3_error`] to recover it.
+#[derive(Clone, Debug, Error, PartialEq, Serialize, Deserialize)]
+#[error("{required} nodes did not agree on the result of {method}")]
+pub struct QuorumError {
+    /// The method of the request.
+    pub method: String,
+    /// The number of nodes that were required to agree.
+    pub required: usize,
+    /// The responses of each node that was queried.
+    pub responses: Vec<NodeResponse>,
+}
+
+/// The response of a single node for a request in quorum mode.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NodeResponse {
+    /// The index of the node.
+    pub node: usize,
+    /// The result returned by the node.
+    pub result: NodeResult,
+}
+
+/// The result returned by a single node.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum NodeResult {
+    /// The node returned a value.
+    Value(Value),
+    /// The node returned a JSON RPC error.
+    Rpc(jsonrpc_core::Error),
+    /// The request to the node failed.
+    Failed(String),
+}
+
+impl QuorumError {
+    /// Recovers a quorum error from a `web3` error returned by a
+    /// [`MultiNodeTransport`].
+    pub fn from_web3_error(err: &Web3Error) -> Option<Self> {
+        match err {
+            Web3Error::Rpc(err) if err.code == ErrorCode::ServerError(QUORUM_ERROR_CODE) => {
+                serde_json::from_value(err.data.clone()?).ok()
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<QuorumError> for Web3Error {
+    fn from(err: QuorumError) -> Self {
+        Web3Error::Rpc(jsonrpc_core::Error {
+            code: ErrorCode::ServerError(QUORUM_ERROR_CODE),
+            message: err.to_string(),
+            data: serde_json::to_value(&err).ok(),
+        })
+    }
+}
+
+/// Transport that sends requests to multiple nodes.
+///
+/// Requests are sent to the first healthy node, failing over to the next one
+/// in order when a node is unavailable. Nodes are marked unhealthy after
+/// [`MultiNodeConfig::failure_threshold`] consecutive failures and skipped for
+/// the [`MultiNodeConfig::cooldown`] duration. JSON RPC errors are considered
+/// valid responses and do not cause a failover, except for rate limit errors.
+///
+/// Non-idempotent methods (see
+/// [`NON_IDEMPOTENT_METHODS`](crate::transport::NON_IDEMPOTENT_METHODS)) are
+/// only failed over when the node rejected them because of a rate limit, since
+/// any other failure may happen after the node already received the request.
+/// This can be changed with [`MultiNodeConfig::failover_non_idempotent`].
+///
+/// Filter methods use node-local state and are never failed over. Filters are
+/// created on the first healthy node, and requests for an existing filter are
+/// always sent to the node that created it.
+///
+/// In quorum mode, quorum methods are sent to all healthy nodes concurrently
+/// and the result is only accepted if enough nodes agree on it; otherwise a
+/// [`QuorumError`] is returned. Batches are split so that only their quorum
+/// requests are sent to all nodes.
+#[derive(Clone, Debug)]
+pub struct MultiNodeTransport<T> {
+    nodes: Arc<[Node<T>]>,
+    config: Arc<MultiNodeConfig>,
+    /// The nodes that created each installed filter, by filter ID.
+    filters: Arc<Mutex<HashMap<String, usize>>>,
+    /// The ID of the next prepared request. Requests are prepared again by
+    /// each node they are sent to, so that they use the node's request IDs.
+    next_id: Arc<AtomicUsize>,
+}
+
+#[derive(Debug)]
+struct Node<T> {
+    transport: T,
+    health: Mutex<Health>,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    failures: usize,
+    unhealthy_until: Option<Instant>,
+}
+
+impl<T> MultiNodeTransport<T> {
+    /// Creates a transport for the specified nodes in order of preference
+    /// with the default configuration.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no nodes are specified.
+    pub fn new(nodes: impl IntoIterator<Item = T>) -> Self {
+        MultiNodeTransport::with_config(nodes, MultiNodeConfig::default())
+    }
+
+    /// Creates a transport for the specified nodes in order of preference.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no nodes are specified, or if the quorum is not between 1 and
+    /// the number of nodes.
+    pub fn with_config(nodes: impl IntoIterator<Item = T>, config: MultiNodeConfig) -> Self {
+        let nodes = nodes
+            .into_iter()
+            .map(|transport| Node {
+                transport,
+                health: Default::default(),
+            })
+            .collect::<Arc<[_]>>();
+        assert!(!nodes.is_empty(), "at least one node is required");
+        if let Some(quorum) = config.quorum {
+            assert!(
+                (1..=nodes.len()).contains(&quorum),
+                "quorum of {} is not between 1 and the number of nodes {}",
+                quorum,
+                nodes.len(),
+            );
+        }
+
+        MultiNodeTransport {
+            nodes,
+            config: Arc::new(config),
+            filters: Default::default(),
+            next_id: Default::default(),
+        }
+    }
+
+    /// Returns the transports of the nodes in order of preference.
+    pub fn nodes(&self) -> impl Iterator<Item = &T> + '_ {
+        self.nodes.iter().map(|node| &node.transport)
+    }
+
+    /// Returns whether or not the node at the specified index is currently
+    /// considered healthy.
+    pub fn is_healthy(&self, node: usize) -> bool {
+        self.nodes[node].is_healthy(Instant::now())
+    }
+
+    /// Returns the indices of the nodes in the order they should be tried:
+    /// healthy nodes first and unhealthy nodes as a last resort.
+    fn ordered_nodes(&self) -> Vec<usize> {
+        let now = Instant::now();
+        let (mut healthy, unhealthy): (Vec<_>, Vec<_>) =
+            (0..self.nodes.len()).partition(|&i| self.nodes[i].is_healthy(now));
+        healthy.extend(unhealthy);
+        healthy
+    }
+
+    /// Returns the indices of the nodes to query for a quorum.
+    fn quorum_nodes(&self, quorum: usize) -> Vec<usize> {
+        let now = Instant::now();
+        let healthy = (0..self.nodes.len())
+            .filter(|&i| self.nodes[i].is_healthy(now))
+            .collect::<Vec<_>>();
+        if healthy.len() >= quorum {
+            healthy
+        } else {
+            (0..self.nodes.len()).collect()
+        }
+    }
+
+    /// Returns the node that created the filter a request refers to, if it is
+    /// known.
+    fn filter_node(&self, request: &Call) -> Option<usize> {
+        let id = filter_id(request)?;
+        self.filters.lock().unwrap().get(id).copied()
+    }
+
+    /// Returns the nodes to send a node-local request to: the node that
+    /// created the request's filter or the first healthy node.
+    fn node_local_nodes<'a>(&self, requests: impl IntoIterator<Item = &'a Call>) -> Vec<usize> {
+        let node = requests
+            .into_iter()
+            .find_map(|request| self.filter_node(request))
+            .unwrap_or_else(|| self.ordered_nodes()[0]);
+        vec![node]
+    }
+
+    /// Pins created filters to the node that created them and forgets
+    /// uninstalled filters.
+    fn track_filter(&self, node: usize, request: &Call, result: &Result<Value, Web3Error>) {
+        let Ok(value) = result else {
+            return;
+        };
+        match method(request) {
+            Some(method) if FILTER_CREATION_METHODS.contains(&method) => {
+                if let Some(id) = value.as_str() {
+                    self.filters.lock().unwrap().insert(id.to_owned(), node);
+                }
+            }
+            Some("eth_uninstallFilter") => {
+                if let Some(id) = filter_id(request) {
+                    self.filters.lock().unwrap().remove(id);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Updates the health of a node based on the result of a request.
+    fn record<R>(&self, node: usize, result: &Result<R, Web3Error>) {
+        match result {
+            Err(err) if is_node_failure(err) => self.nodes[node].failed(&self.config),
+            _ => self.nodes[node].succeeded(),
+        }
+    }
+}
+
+impl<T: Transport> Node<T> {
+    /// Prepares a request again with the node's transport, so that it uses a
+    /// request ID assigned by the node's transport. Requests with named
+    /// parameters can not be prepared again and are sent as is.
+    fn prepare(&self, id: RequestId, request: &Call) -> (RequestId, Call) {
+        match request {
+            Call::MethodCall(call) => match &call.params {
+                Params::Array(params) => self.transport.prepare(&call.method, params.clone()),
+                Params::None => self.transport.prepare(&call.method, Vec::new()),
+                Params::Map(_) => (id, request.clone()),
+            },
+            _ => (id, request.clone()),
+        }
+    }
+
+    /// Sends a request to the node.
+    fn send(&self, id: RequestId, request: &Call) -> T::Out {
+        let (id, request) = self.prepare(id, request);
+        self.transport.send(id, request)
+    }
+}
+
+impl<T: BatchTransport> Node<T> {
+    /// Sends a batch of requests to the node.
+    fn send_batch(&self, requests: &[(RequestId, Call)]) -> T::Batch {
+        self.transport.send_batch(
+            requests
+                .iter()
+                .map(|(id, request)| self.prepare(*id, request))
+                .collect::<Vec<_>>(),
+        )
+    }
+}
+
+impl<T> Node<T> {
+    fn is_healthy(&self, now: Instant) -> bool {
+        let health = self.health.lock().unwrap();
+        !matches!(health.unhealthy_until, Some(until) if until > now)
+    }
+
+    fn failed(&self, config: &MultiNodeConfig) {
+        let mut health = self.health.lock().unwrap();
+        health.failures += 1;
+        if health.failures >= config.failure_threshold {
+            health.unhealthy_until = Some(Instant::now() + config.cooldown);
+        }
+    }
+
+    fn succeeded(&self) {
+        *self.health.lock().unwrap() = Health::default();
+    }
+}
+
+impl<T> Transport for MultiNodeTransport<T>
+where
+    T: Transport + Send + Sync + 'static,
+    T::Out: Send,
+{
+    type Out = BoxFuture<'static, Result<Value, Web3Error>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        (id, web3::helpers::build_request(id, method, params))
+    }
+
+    fn send(&self, id: RequestId, request: Call) -> Self::Out {
+        let this = self.clone();
+        async move {
+            if let Some(quorum) = this.config.quorum_for(&request) {
+                let nodes = this.quorum_nodes(quorum);
+                let results = future::join_all(nodes.iter().map(|&node| {
+                    this.nodes[node]
+                        .send(id, &request)
+                        .map(move |result| (node, result))
+                }))
+                .await;
+                for (node, result) in &results {
+                    this.record(*node, result);
+                }
+                return resolve_quorum(method(&request).unwrap_or_default(), quorum, results);
+            }
+
+            let nodes = if is_node_local(&request) {
+                this.node_local_nodes([&request])
+            } else {
+                this.ordered_nodes()
+            };
+
+            let mut last_error = None;
+            for node in nodes {
+                let result = this.nodes[node].send(id, &request).await;
+                this.record(node, &result);
+                this.track_filter(node, &request, &result);
+                match result {
+                    Err(err)
+                        if is_node_failure(&err)
+                            && this.config.can_fail_over([&request].into_iter(), &err) =>
+                    {
+                        last_error = Some(err)
+                    }
+                    result => return result,
+                }
+            }
+            Err(last_error.expect("at least one node is always tried"))
+        }
+        .boxed()
+    }
+}
+
+impl<T> BatchTransport for MultiNodeTransport<T>
+where
+    T: BatchTransport + Send + Sync + 'static,
+    T::Out: Send,
+    T::Batch: Send,
+{
+    type Batch = BoxFuture<'static, Result<Vec<Result<Value, Web3Error>>, Web3Error>>;
+
+    fn send_batch<I>(&self, requests: I) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, Call)>,
+    {
+        let this = self.clone();
+        let requests = requests.into_iter().collect::<Vec<_>>();
+        async move {
+            // NOTE: Only the requests that require a quorum are sent to the
+            //   quorum nodes. The other requests, such as transactions and
+            //   filter methods, are sent to a single node at a time so that
+            //   they are not executed more than once, and the results are
+            //   merged back in request order.
+            let (quorum_indices, other_indices): (Vec<_>, Vec<_>) = (0..requests.len())
+                .partition(|&i| this.config.quorum_for(&requests[i].1).is_some());
+            if quorum_indices.is_empty() {
+                return this.send_batch_with_failover(&requests).await;
+            }
+
+            let quorum_requests = quorum_indices
+                .iter()
+                .map(|&i| requests[i].clone())
+                .collect::<Vec<_>>();
+            let other_requests = other_indices
+                .iter()
+                .map(|&i| requests[i].clone())
+                .collect::<Vec<_>>();
+            let (quorum_results, other_results) =
+                future::join(this.send_batch_with_quorum(&quorum_requests), async {
+                    if other_requests.is_empty() {
+                        return Ok(Vec::new());
+                    }
+                    this.send_batch_with_failover(&other_requests).await
+                })
+                .await;
+
+            let mut results = requests.iter().map(|_| None).collect::<Vec<_>>();
+            let indexed_results = quorum_indices
+                .into_iter()
+                .zip(quorum_results)
+                .chain(other_indices.into_iter().zip(other_results?));
+            for (i, result) in indexed_results {
+                results[i] = Some(result);
+            }
+            Ok(results
+                .into_iter()
+                .map(|result| {
+                    result.unwrap_or_else(|| {
+                        Err(Web3Error::InvalidResponse(
+                            "batch response length mismatch".to_owned(),
+                        ))
+                    })
+                })
+                .collect())
+        }
+        .boxed()
+    }
+}
+
+impl<T> MultiNodeTransport<T>
+where
+    T: BatchTransport,
+{
+    /// Sends a batch of quorum requests to the quorum nodes, and resolves the
+    /// quorum for each request individually.
+    async fn send_batch_with_quorum(
+        &self,
+        requests: &[(RequestId, Call)],
+    ) -> Vec<Result<Value, Web3Error>> {
+        let quorum = requests
+            .iter()
+            .filter_map(|(_, request)| self.config.quorum_for(request))
+            .max()
+            .unwrap_or(1);
+        let nodes = self.quorum_nodes(quorum);
+        let batches = future::join_all(nodes.iter().map(|&node| {
+            self.nodes[node]
+                .send_batch(requests)
+                .map(move |result| (node, result))
+        }))
+        .await;
+        for (node, result) in &batches {
+            self.record(*node, result);
+        }
+
+        let mut batches = batches
+            .into_iter()
+            .map(|(node, result)| {
+                let results = match result {
+                    Ok(results) if results.len() == requests.len() => Ok(results.into_iter()),
+                    Ok(_) => Err("batch response length mismatch".to_owned()),
+                    Err(err) => Err(err.to_string()),
+                };
+                (node, results)
+            })
+            .collect::<Vec<_>>();
+
+        requests
+            .iter()
+            .map(|(_, request)| {
+                let results = batches
+                    .iter_mut()
+                    .map(|(node, results)| {
+                        let result = match results {
+                            Ok(results) => results.next().expect("batch length checked"),
+                            Err(message) => Err(Web3Error::InvalidResponse(message.clone())),
+                        };
+                        (*node, result)
+                    })
+                    .collect();
+                let quorum = self.config.quorum_for(request).unwrap_or(1);
+                resolve_quorum(method(request).unwrap_or_default(), quorum, results)
+            })
+            .collect()
+    }
+
+    /// Sends a batch of requests to a single node, failing over to the next
+    /// node when it is unavailable.
+    async fn send_batch_with_failover(
+        &self,
+        requests: &[(RequestId, Call)],
+    ) -> Result<Vec<Result<Value, Web3Error>>, Web3Error> {
+        let nodes = if requests.iter().any(|(_, request)| is_node_local(request)) {
+            self.node_local_nodes(requests.iter().map(|(_, request)| request))
+        } else {
+            self.ordered_nodes()
+        };
+
+        let mut last_error = None;
+        for node in nodes {
+            let result = self.nodes[node].send_batch(requests).await;
+            self.record(node, &result);
+            if let Ok(results) = &result {
+                for ((_, request), result) in requests.iter().zip(results) {
+                    self.track_filter(node, request, result);
+                }
+            }
+            match result {
+                Err(err)
+                    if is_node_failure(&err)
+                        && self
+                            .config
+                            .can_fail_over(requests.iter().map(|(_, request)| request), &err) =>
+                {
+                    last_error = Some(err)
+                }
+                result => return result,
+            }
+        }
+        Err(last_error.expect("at least one node is always tried"))
+    }
+}
+
+/// Returns the method of a request.
+fn method(request: &Call) -> Option<&str> {
+    match request {
+        Call::MethodCall(call) => Some(&call.method),
+        _ => None,
+    }
+}
+
+/// Returns the filter ID parameter of a request for an existing filter.
+fn filter_id(request: &Call) -> Option<&str> {
+    match request {
+        Call::MethodCall(call) if is_node_local(request) => match &call.params {
+            Params::Array(params) => params.first()?.as_str(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Returns true if the request uses node-local state.
+fn is_node_local(request: &Call) -> bool {
+    method(request).is_some_and(|method| NODE_LOCAL_METHODS.contains(&method))
+}
+
+/// Returns true if the error indicates that the node failed to handle the
+/// request, as opposed to a valid JSON RPC error response.
+fn is_node_failure(err: &Web3Error) -> bool {
+    !matches!(err, Web3Error::Rpc(_)) || is_transient(err)
+}
+
+/// Returns the result that at least `quorum` nodes agree on. JSON RPC errors
+/// are treated like values so that, for example, reverted calls are reported
+/// as such when enough nodes agree.
+fn resolve_quorum(
+    method: &str,
+    quorum: usize,
+    results: Vec<(usize, Result<Value, Web3Error>)>,
+) -> Result<Value, Web3Error> {
+    let responses = results
+        .into_iter()
+        .map(|(node, result)| NodeResponse {
+            node,
+            result: match result {
+                Ok(value) => NodeResult::Value(value),
+                Err(err) if is_node_failure(&err) => NodeResult::Failed(err.to_string()),
+                Err(Web3Error::Rpc(err)) => NodeResult::Rpc(err),
+                Err(err) => NodeResult::Failed(err.to_string()),
+            },
+        })
+        .collect::<Vec<_>>();
+
+    let agreed = responses.iter().find(|response| {
+        !matches!(response.result, NodeResult::Failed(_))
+            && responses
+                .iter()
+                .filter(|other| other.result == response.result)
+                .count()
+                >= quorum
+    });
+    match agreed.map(|response| &response.result) {
+        Some(NodeResult::Value(value)) => Ok(value.clone()),
+        Some(NodeResult::Rpc(err)) => Err(Web3Error::Rpc(err.clone())),
+        _ => Err(QuorumError {
+            method: method.to_owned(),
+            required: quorum,
+            responses,
+        }
+        .into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::prelude::*;
+    use crate::transport::DynTransport;
+    use web3::error::TransportError;
+    use web3::types::H256;
+
+    fn nodes(count: usize) -> Vec<TestTransport> {
+        (0..count).map(|_| TestTransport::new()).collect()
+    }
+
+    fn down() -> Web3Error {
+        Web3Error::Transport(TransportError::Code(503))
+    }
+
+    fn rate_limited() -> Web3Error {
+        Web3Error::Transport(TransportError::Code(429))
+    }
+
+    fn reverted() -> Web3Error {
+        Web3Error::Rpc(jsonrpc_core::Error {
+            code: ErrorCode::ServerError(3),
+            message: "execution reverted".to_owned(),
+            data: None,
+        })
+    }
+
+    /// Returns the next queued response of a test transport.
+    fn next_response(node: &TestTransport) -> Result<Value, Web3Error> {
+        node.execute("test", vec![]).immediate()
+    }
+
+    #[test]
+    fn fails_over_in_order() {
+        let mut nodes = nodes(3);
+        let transport = MultiNodeTransport::new(nodes.clone());
+
+        nodes[0].add_error(down());
+        nodes[1].add_response(json!("0x1"));
+        nodes[2].add_response(json!("0x2"));
+        let result = transport
+            .execute("eth_blockNumber", vec![])
+            .immediate()
+            .expect("request failed");
+
+        assert_eq!(result, json!("0x1"));
+        assert!(!transport.is_healthy(0));
+        assert!(transport.is_healthy(1));
+
+        // each node prepares the requests it is sent
+        nodes[0].assert_request("eth_blockNumber", &[]);
+        nodes[0].assert_no_more_requests();
+        nodes[1].assert_request("eth_blockNumber", &[]);
+        nodes[1].assert_no_more_requests();
+        nodes[2].assert_no_more_requests();
+        assert_eq!(next_response(&nodes[2]).unwrap(), json!("0x2"));
+    }
+
+    #[test]
+    fn sends_requests_with_node_request_ids() {
+        #[derive(Clone, Debug, Default)]
+        struct IdTransport {
+            next_id: Arc<AtomicUsize>,
+            sent: Arc<Mutex<Vec<(RequestId, Call)>>>,
+        }
+
+        impl Transport for IdTransport {
+            type Out = future::Ready<Result<Value, Web3Error>>;
+
+            fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+                let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 100;
+                (id, web3::helpers::build_request(id, method, params))
+            }
+
+            fn send(&self, id: RequestId, request: Call) -> Self::Out {
+                self.sent.lock().unwrap().push((id, request));
+                future::ready(Ok(json!("0x1")))
+            }
+        }
+
+        let nodes = vec![IdTransport::default(), IdTransport::default()];
+        let transport =
+            MultiNodeTransport::with_config(nodes.clone(), MultiNodeConfig::default().quorum(2));
+
+        transport
+            .execute("eth_blockNumber", vec![])
+            .immediate()
+            .unwrap();
+        for node in &nodes {
+            let sent = node.sent.lock().unwrap();
+            assert_eq!(sent.len(), 1);
+            assert_eq!(sent[0].0, 100);
+            assert_eq!(
+                sent[0].1,
+                web3::helpers::build_request(100, "eth_blockNumber", vec![])
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "quorum of 0 is not between 1 and the number of nodes 2")]
+    fn rejects_zero_quorum() {
+        MultiNodeTransport::with_config(nodes(2), MultiNodeConfig::default().quorum(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "quorum of 3 is not between 1 and the number of nodes 2")]
+    fn rejects_quorum_larger_than_nodes() {
+        MultiNodeTransport::with_config(nodes(2), MultiNodeConfig::default().quorum(3));
+    }
+
+    #[test]
+    fn skips_unhealthy_nodes_during_cooldown() {
+        let mut nodes = nodes(2);
+        let transport = MultiNodeTransport::new(nodes.clone());
+
+        nodes[0].add_error(down());
+        nodes[1].add_response(json!("0x1"));
+        transport
+            .execute("eth_blockNumber", vec![])
+            .immediate()
+            .unwrap();
+
+        nodes[0].add_response(json!("0x0"));
+        nodes[1].add_response(json!("0x2"));
+        let result = transport.execute("eth_blockNumber", vec![]).immediate();
+        assert_eq!(result.unwrap(), json!("0x2"));
+        assert_eq!(next_response(&nodes[0]).unwrap(), json!("0x0"));
+    }
+
+    #[test]
+    fn recovers_after_cooldown() {
+        let mut nodes = nodes(2);
+        let transport = MultiNodeTransport::with_config(
+            nodes.clone(),
+            MultiNodeConfig::default().cooldown(Duration::from_secs(0)),
+        );
+
+        nodes[0].add_error(down());
+        nodes[1].add_response(json!("0x1"));
+        transport
+            .execute("eth_blockNumber", vec![])
+            .immediate()
+            .unwrap();
+        assert!(transport.is_healthy(0));
+
+        nodes[0].add_response(json!("0x2"));
+        let result = transport.execute("eth_blockNumber", vec![]).immediate();
+        assert_eq!(result.unwrap(), json!("0x2"));
+    }
+
+    #[test]
+    fn tries_unhealthy_nodes_as_last_resort() {
+        let mut nodes = nodes(2);
+        let transport = MultiNodeTransport::new(nodes.clone());
+
+        nodes[0].add_error(down());
+        nodes[1].add_error(down());
+        assert!(transport
+            .execute("eth_blockNumber", vec![])
+            .immediate()
+            .is_err());
+
+        nodes[0].add_response(json!("0x1"));
+        let result = transport.execute("eth_blockNumber", vec![]).immediate();
+        assert_eq!(result.unwrap(), json!("0x1"));
+        assert!(transport.is_healthy(0));
+    }
+
+    #[test]
+    fn does_not_fail_over_on_rpc_errors() {
+        let mut nodes = nodes(2);
+        let transport = MultiNodeTransport::new(nodes.clone());
+
+        nodes[0].add_error(reverted());
+        nodes[1].add_response(json!("0x"));
+        let result = transport.execute("eth_call", vec![]).immediate();
+
+        assert!(matches!(result, Err(Web3Error::Rpc(_))));
+        assert!(transport.is_healthy(0));
+    }
+
+    #[test]
+    fn does_not_fail_over_non_idempotent_methods() {
+        let mut nodes = nodes(2);
+        let transport = MultiNodeTransport::new(nodes.clone());
+
+        nodes[0].add_error(down());
+        let result = transport
+            .execute("eth_sendRawTransaction", vec![json!("0x1337")])
+            .immediate();
+        assert!(result.is_err());
+        nodes[1].assert_no_more_requests();
+
+        // rate limited requests were never accepted by the node
+        nodes[1].add_error(rate_limited());
+        nodes[0].add_response(json!(H256::zero()));
+        let result = transport
+            .execute("eth_sendRawTransaction", vec![json!("0x1337")])
+            .immediate();
+        assert_eq!(result.unwrap(), json!(H256::zero()));
+    }
+
+    #[test]
+    fn fails_over_non_idempotent_methods_when_enabled() {
+        let mut nodes = nodes(2);
+        let transport = MultiNodeTransport::with_config(
+            nodes.clone(),
+            MultiNodeConfig::default().failover_non_idempotent(true),
+        );
+
+        nodes[0].add_error(down());
+        nodes[1].add_response(json!(H256::zero()));
+        let result = transport
+            .execute("eth_sendRawTransaction", vec![json!("0x1337")])
+            .immediate();
+        assert_eq!(result.unwrap(), json!(H256::zero()));
+    }
+
+    #[test]
+    fn does_not_fail_over_filter_methods() {
+        let mut nodes = nodes(2);
+        let transport = MultiNodeTransport::new(nodes.clone());
+
+        nodes[0].add_error(down());
+        nodes[1].add_response(json!([]));
+        let result = transport
+            .execute("eth_getFilterChanges", vec![json!("0x1")])
+            .immediate();
+
+        assert!(result.is_err());
+        assert_eq!(next_response(&nodes[1]).unwrap(), json!([]));
+    }
+
+    #[test]
+    fn pins_filters_to_the_node_that_created_them() {
+        let mut nodes = nodes(2);
+        let transport = MultiNodeTransport::new(nodes.clone());
+
+        nodes[0].add_response(json!("0xf0"));
+        transport
+            .execute("eth_newBlockFilter", vec![])
+            .immediate()
+            .unwrap();
+
+        // the first node becomes unhealthy, so new filters are created on the
+        // second node
+        nodes[0].add_error(down());
+        nodes[1].add_response(json!("0x1"));
+        transport
+            .execute("eth_blockNumber", vec![])
+            .immediate()
+            .unwrap();
+        nodes[1].add_response(json!("0xf1"));
+        transport
+            .execute("eth_newBlockFilter", vec![])
+            .immediate()
+            .unwrap();
+
+        nodes[0].add_response(json!(["0x0"]));
+        nodes[1].add_response(json!(["0x1"]));
+        let first = transport
+            .execute("eth_getFilterChanges", vec![json!("0xf0")])
+            .immediate();
+        let second = transport
+            .execute("eth_getFilterChanges", vec![json!("0xf1")])
+            .immediate();
+        assert_eq!(first.unwrap(), json!(["0x0"]));
+        assert_eq!(second.unwrap(), json!(["0x1"]));
+
+        nodes[0].add_response(json!(true));
+        transport
+            .execute("eth_uninstallFilter", vec![json!("0xf0")])
+            .immediate()
+            .unwrap();
+        assert!(transport.filters.lock().unwrap().get("0xf0").is_none());
+    }
+
+    #[test]
+    fn quorum_agreement() {
+        let mut nodes = nodes(3);
+        let transport =
+            MultiNodeTransport::with_config(nodes.clone(), MultiNodeConfig::default().quorum(2));
+
+        nodes[0].add_response(json!("0x1"));
+        nodes[1].add_response(json!("0x2"));
+        nodes[2].add_response(json!("0x2"));
+        let result = transport.execute("eth_blockNumber", vec![]).immediate();
+        assert_eq!(result.unwrap(), json!("0x2"));
+
+        nodes[0].add_error(reverted());
+        nodes[1].add_error(down());
+        nodes[2].add_error(reverted());
+        let result = transport.execute("eth_call", vec![]).immediate();
+        assert!(matches!(
+            result,
+            Err(Web3Error::Rpc(err)) if err.message == "execution reverted"
+        ));
+    }
+
+    #[test]
+    fn quorum_disagreement() {
+        let mut nodes = nodes(3);
+        let transport =
+            MultiNodeTransport::with_config(nodes.clone(), MultiNodeConfig::default().quorum(2));
+
+        nodes[0].add_response(json!("0x1"));
+        nodes[1].add_response(json!("0x2"));
+        nodes[2].add_error(down());
+        let err = transport
+            .execute("eth_blockNumber", vec![])
+            .immediate()
+            .unwrap_err();
+
+        let quorum_error = QuorumError::from_web3_error(&err).expect("not a quorum error");
+        assert_eq!(quorum_error.method, "eth_blockNumber");
+        assert_eq!(quorum_error.required, 2);
+        assert_eq!(
+            &quorum_error.responses[..2],
+            &[
+                NodeResponse {
+                    node: 0,
+                    result: NodeResult::Value(json!("0x1")),
+                },
+                NodeResponse {
+                    node: 1,
+                    result: NodeResult::Value(json!("0x2")),
+                },
+            ]
+        );
+        assert!(matches!(
+            quorum_error.responses[2],
+            NodeResponse {
+                node: 2,
+                result: NodeResult::Failed(_),
+            }
+        ));
+    }
+
+    #[test]
+    fn quorum_only_applies_to_quorum_methods() {
+        let mut nodes = nodes(3);
+        let transport =
+            MultiNodeTransport::with_config(nodes.clone(), MultiNodeConfig::default().quorum(2));
+
+        nodes[0].add_response(json!(H256::zero()));
+        let result = transport
+            .execute("eth_sendRawTransaction", vec![json!("0x1337")])
+            .immediate();
+        assert_eq!(result.unwrap(), json!(H256::zero()));
+    }
+
+    #[test]
+    fn batch_failover() {
+        let mut nodes = nodes(2);
+        let transport = MultiNodeTransport::new(nodes.clone());
+
+        let requests = vec![
+            transport.prepare("eth_getBalance", vec![]),
+            transport.prepare("eth_getCode", vec![]),
+        ];
+
+        nodes[0].add_error(down());
+        nodes[1].add_response(json!(["0x1", "0x2"]));
+        let results = transport.send_batch(requests).immediate().unwrap();
+
+        assert_eq!(
+            results.into_iter().map(Result::unwrap).collect::<Vec<_>>(),
+            vec![json!("0x1"), json!("0x2")]
+        );
+        nodes[0].assert_request("eth_getBalance", &[]);
+        nodes[0].assert_request("eth_getCode", &[]);
+
+        // batches with non-idempotent requests are not failed over to the
+        // first node, which is now unhealthy and tried last
+        let requests = vec![
+            transport.prepare("eth_getBalance", vec![]),
+            transport.prepare("eth_sendRawTransaction", vec![]),
+        ];
+
+        nodes[1].add_error(down());
+        let result = transport.send_batch(requests).immediate();
+        assert!(result.is_err());
+        nodes[0].assert_no_more_requests();
+    }
+
+    #[test]
+    fn batch_quorum() {
+        let mut nodes = nodes(3);
+        let transport =
+            MultiNodeTransport::with_config(nodes.clone(), MultiNodeConfig::default().quorum(2));
+
+        let requests = vec![
+            transport.prepare("eth_blockNumber", vec![]),
+            transport.prepare("eth_getBalance", vec![]),
+            transport.prepare("eth_gasPrice", vec![]),
+        ];
+
+        nodes[0].add_response(json!(["0x1", "0x1"]));
+        nodes[1].add_response(json!(["0x1", "0x2"]));
+        nodes[2].add_error(down());
+        nodes[0].add_response(json!(["0x3"]));
+        let results = transport.send_batch(requests).immediate().unwrap();
+
+        assert_eq!(results[0].as_ref().unwrap(), &json!("0x1"));
+        assert!(QuorumError::from_web3_error(results[1].as_ref().unwrap_err()).is_some());
+        assert_eq!(results[2].as_ref().unwrap(), &json!("0x3"));
+    }
+
+    #[test]
+    fn batch_quorum_sends_other_requests_once() {
+        let mut nodes = nodes(2);
+        let transport =
+            MultiNodeTransport::with_config(nodes.clone(), MultiNodeConfig::default().quorum(2));
+
+        let requests = vec![
+            transport.prepare("eth_sendRawTransaction", vec![json!("0x1337")]),
+            transport.prepare("eth_call", vec![json!({}), json!("latest")]),
+        ];
+
+        nodes[0].add_response(json!(["0x42"]));
+        nodes[1].add_response(json!(["0x42"]));
+        nodes[0].add_response(json!([H256::zero()]));
+        let results = transport.send_batch(requests).immediate().unwrap();
+
+        assert_eq!(
+            results.into_iter().map(Result::unwrap).collect::<Vec<_>>(),
+            vec![json!(H256::zero()), json!("0x42")]
+        );
+        nodes[0].assert_request("eth_call", &[json!({}), json!("latest")]);
+        nodes[0].assert_request("eth_sendRawTransaction", &[json!("0x1337")]);
+        nodes[0].assert_no_more_requests();
+        nodes[1].assert_request("eth_call", &[json!({}), json!("latest")]);
+        nodes[1].assert_no_more_requests();
+    }
+
+    #[test]
+    fn composes_with_dyn_transport() {
+        let mut nodes = nodes(2);
+        let web3 = Web3::new(DynTransport::new(MultiNodeTransport::new(nodes.clone())));
+
+        nodes[0].add_error(down());
+        nodes[1].add_response(json!("0x2a"));
+        let block = web3.eth().block_number().immediate().unwrap();
+
+        assert_eq!(block, 42.into());
+    }
+}
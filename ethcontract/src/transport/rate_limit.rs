@@ -0,0 +1,684 @@
+//! Implementation of a transport wrapper that limits the request rate and the
+//! number of concurrent requests to a node, and backs off when the node
+//! reports that a rate limit was exceeded.
+
+use futures::future::{self, BoxFuture};
+use futures::FutureExt as _;
+use futures_timer::Delay;
+use jsonrpc_core::Call;
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+use web3::api::SubscriptionId;
+use web3::error::{Error as Web3Error, TransportError};
+use web3::{BatchTransport, DuplexTransport, RequestId, Transport};
+
+/// The default number of times a request is retried after being rejected by
+/// the node's rate limit.
+pub const DEFAULT_MAX_RATE_LIMIT_RETRIES: usize = 5;
+
+/// The default backoff after the first rate limit error.
+pub const DEFAULT_RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The default maximum backoff after consecutive rate limit errors.
+pub const DEFAULT_MAX_RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Parameters for limiting requests.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateLimitConfig {
+    /// The sustained number of requests per second, or `None` for no rate
+    /// limit. Rates that are not positive numbers do not limit requests.
+    pub requests_per_second: Option<f64>,
+    /// The maximum number of requests that can be sent in a burst. Defaults to
+    /// the number of requests per second (rounded up) when `None`.
+    pub burst: Option<usize>,
+    /// The maximum number of requests in flight at any given time, or `None`
+    /// for no limit.
+    pub max_in_flight: Option<usize>,
+    /// The maximum number of times a request is retried after the node rejects
+    /// it with a rate limit error.
+    pub max_retries: usize,
+    /// The backoff after the first rate limit error. The backoff doubles with
+    /// each consecutive rate limit error.
+    pub initial_backoff: Duration,
+    /// The maximum backoff after consecutive rate limit errors.
+    pub max_backoff: Duration,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            requests_per_second: None,
+            burst: None,
+            max_in_flight: None,
+            max_retries: DEFAULT_MAX_RATE_LIMIT_RETRIES,
+            initial_backoff: DEFAULT_RATE_LIMIT_BACKOFF,
+            max_backoff: DEFAULT_MAX_RATE_LIMIT_BACKOFF,
+        }
+    }
+}
+
+impl RateLimitConfig {
+    /// Limit the sustained number of requests per second.
+    pub fn requests_per_second(mut self, value: f64) -> Self {
+        self.requests_per_second = Some(value);
+        self
+    }
+
+    /// Specify the maximum number of requests that can be sent in a burst.
+    pub fn burst(mut self, value: usize) -> Self {
+        self.burst = Some(value);
+        self
+    }
+
+    /// Limit the number of requests in flight at any given time.
+    pub fn max_in_flight(mut self, value: usize) -> Self {
+        self.max_in_flight = Some(value);
+        self
+    }
+
+    /// Returns the backoff after the specified number of consecutive rate
+    /// limit errors, starting at 1.
+    fn backoff(&self, errors: usize) -> Duration {
+        let factor = 1u32
+            .checked_shl(errors.saturating_sub(1) as _)
+            .unwrap_or(u32::MAX);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// Transport wrapper that enforces a token bucket request rate and a maximum
+/// number of requests in flight. Batches count as their number of
+/// sub-requests.
+///
+/// When the node rejects a request with a rate limit error (HTTP 429 or a JSON
+/// RPC "limit exceeded" error), all requests sent through this transport are
+/// paused with exponential backoff and the rejected request is retried. Since
+/// rate limited requests are never processed by the node, this is done for
+/// all methods.
+#[derive(Clone, Debug)]
+pub struct RateLimitTransport<T> {
+    inner: T,
+    limiter: Arc<Limiter>,
+}
+
+impl<T> RateLimitTransport<T> {
+    /// Wraps a transport with the specified limits.
+    pub fn new(inner: T, config: RateLimitConfig) -> Self {
+        RateLimitTransport {
+            inner,
+            limiter: Arc::new(Limiter::new(config, Instant::now())),
+        }
+    }
+
+    /// Returns a reference to the inner transport.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns the rate limit configuration.
+    pub fn config(&self) -> &RateLimitConfig {
+        &self.limiter.config
+    }
+
+    /// Returns the number of requests that are currently in flight.
+    pub fn in_flight(&self) -> usize {
+        self.limiter.state.lock().unwrap().in_flight
+    }
+}
+
+impl<T> Transport for RateLimitTransport<T>
+where
+    T: Transport + Clone + Send + Sync + 'static,
+    T::Out: Send,
+{
+    type Out = BoxFuture<'static, Result<Value, Web3Error>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        self.inner.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: Call) -> Self::Out {
+        let inner = self.inner.clone();
+        let limiter = self.limiter.clone();
+        async move {
+            let mut retry = 0;
+            loop {
+                let permit = limiter.acquire(1).await;
+                let result = inner.send(id, request.clone()).await;
+                drop(permit);
+
+                match result {
+                    Err(err) if is_rate_limited(&err) => {
+                        limiter.rate_limited();
+                        if retry >= limiter.config.max_retries {
+                            return Err(err);
+                        }
+                        retry += 1;
+                    }
+                    result => {
+                        limiter.succeeded();
+                        return result;
+                    }
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+impl<T> BatchTransport for RateLimitTransport<T>
+where
+    T: BatchTransport + Clone + Send + Sync + 'static,
+    T::Out: Send,
+    T::Batch: Send,
+{
+    type Batch = BoxFuture<'static, Result<Vec<Result<Value, Web3Error>>, Web3Error>>;
+
+    fn send_batch<I>(&self, requests: I) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, Call)>,
+    {
+        let inner = self.inner.clone();
+        let limiter = self.limiter.clone();
+        let requests = requests.into_iter().collect::<Vec<_>>();
+        async move {
+            let mut results = (0..requests.len()).map(|_| None).collect::<Vec<_>>();
+            let mut pending = (0..requests.len()).collect::<Vec<_>>();
+            let mut retry = 0;
+            while !pending.is_empty() {
+                let can_retry = retry < limiter.config.max_retries;
+
+                let permit = limiter.acquire(pending.len()).await;
+                let batch = pending.iter().map(|&i| requests[i].clone());
+                let result = inner.send_batch(batch).await;
+                drop(permit);
+
+                match result {
+                    Ok(responses) => {
+                        if responses.len() != pending.len() {
+                            return Err(Web3Error::InvalidResponse(format!(
+                                "expected {} batch responses but got {}",
+                                pending.len(),
+                                responses.len(),
+                            )));
+                        }
+
+                        let mut limited = Vec::new();
+                        for (i, response) in pending.iter().copied().zip(responses) {
+                            match response {
+                                Err(err) if can_retry && is_rate_limited(&err) => limited.push(i),
+                                response => results[i] = Some(response),
+                            }
+                        }
+                        if limited.is_empty() {
+                            limiter.succeeded();
+                        } else {
+                            limiter.rate_limited();
+                        }
+                        pending = limited;
+                    }
+                    Err(err) if is_rate_limited(&err) => {
+                        limiter.rate_limited();
+                        if !can_retry {
+                            return Err(err);
+                        }
+                    }
+                    Err(err) => return Err(err),
+                }
+                retry += 1;
+            }
+
+            Ok(results
+                .into_iter()
+                .map(|result| result.expect("all batch requests resolved"))
+                .collect())
+        }
+        .boxed()
+    }
+}
+
+impl<T> DuplexTransport for RateLimitTransport<T>
+where
+    T: DuplexTransport + Clone + Send + Sync + 'static,
+    T::Out: Send,
+{
+    type NotificationStream = T::NotificationStream;
+
+    fn subscribe(&self, id: SubscriptionId) -> Result<Self::NotificationStream, Web3Error> {
+        self.inner.subscribe(id)
+    }
+
+    fn unsubscribe(&self, id: SubscriptionId) -> Result<(), Web3Error> {
+        self.inner.unsubscribe(id)
+    }
+}
+
+/// Returns true if the error indicates that the node rejected the request
+/// because of a rate limit.
+pub fn is_rate_limited(err: &Web3Error) -> bool {
+    match err {
+        Web3Error::Transport(TransportError::Code(429)) => true,
+        Web3Error::Rpc(err) => matches!(err.code.code(), -32005 | 429),
+        _ => false,
+    }
+}
+
+/// Shared state for limiting requests across clones of a transport.
+#[derive(Debug)]
+struct Limiter {
+    config: RateLimitConfig,
+    state: Mutex<LimiterState>,
+}
+
+#[derive(Debug)]
+struct LimiterState {
+    bucket: Option<TokenBucket>,
+    in_flight: usize,
+    waiters: Vec<Waker>,
+    paused_until: Option<Instant>,
+    rate_limit_errors: usize,
+}
+
+impl Limiter {
+    fn new(config: RateLimitConfig, now: Instant) -> Self {
+        // NOTE: A rate of zero, a negative rate or NaN would never refill the
+        //   bucket, so treat them as unlimited.
+        let bucket = config
+            .requests_per_second
+            .filter(|rate| *rate > 0.0)
+            .map(|rate| {
+                let capacity = config.burst.unwrap_or_else(|| rate.ceil() as _).max(1);
+                TokenBucket::new(rate, capacity as _, now)
+            });
+        Limiter {
+            config,
+            state: Mutex::new(LimiterState {
+                bucket,
+                in_flight: 0,
+                waiters: Vec::new(),
+                paused_until: None,
+                rate_limit_errors: 0,
+            }),
+        }
+    }
+
+    /// Waits until `count` requests can be sent, returning a permit that
+    /// counts them as in flight until it is dropped.
+    async fn acquire(self: &Arc<Self>, count: usize) -> Permit {
+        loop {
+            match future::poll_fn(|cx| self.poll_acquire(cx, count, Instant::now())).await {
+                Ok(()) => {
+                    return Permit {
+                        limiter: self.clone(),
+                        count,
+                    }
+                }
+                Err(wait) => Delay::new(wait).await,
+            }
+        }
+    }
+
+    /// Attempts to acquire `count` requests. Returns the duration to wait
+    /// before trying again if the request rate is exceeded, or registers the
+    /// task to be woken up when in flight requests complete if there are too
+    /// many of them.
+    fn poll_acquire(
+        &self,
+        cx: &mut Context,
+        count: usize,
+        now: Instant,
+    ) -> Poll<Result<(), Duration>> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(paused_until) = state.paused_until {
+            if paused_until > now {
+                return Poll::Ready(Err(paused_until - now));
+            }
+            state.paused_until = None;
+        }
+
+        // NOTE: Always allow requests when nothing is in flight, so that
+        //   batches larger than the in flight limit can make progress.
+        if let Some(max_in_flight) = self.config.max_in_flight {
+            if state.in_flight > 0 && state.in_flight + count > max_in_flight {
+                state.waiters.push(cx.waker().clone());
+                return Poll::Pending;
+            }
+        }
+
+        if let Some(bucket) = &mut state.bucket {
+            if let Err(wait) = bucket.take(count as _, now) {
+                return Poll::Ready(Err(wait));
+            }
+        }
+
+        state.in_flight += count;
+        Poll::Ready(Ok(()))
+    }
+
+    fn release(&self, count: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight -= count;
+        for waker in state.waiters.drain(..) {
+            waker.wake();
+        }
+    }
+
+    /// Pauses all requests after the node rejected a request because of a
+    /// rate limit.
+    fn rate_limited(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.rate_limit_errors += 1;
+        let paused_until =
+            saturating_add(Instant::now(), self.config.backoff(state.rate_limit_errors));
+        state.paused_until = Some(match state.paused_until {
+            Some(current) => current.max(paused_until),
+            None => paused_until,
+        });
+    }
+
+    fn succeeded(&self) {
+        self.state.lock().unwrap().rate_limit_errors = 0;
+    }
+}
+
+/// Adds a duration to an instant, saturating at the latest instant that can
+/// be represented instead of panicking on overflow.
+fn saturating_add(instant: Instant, mut duration: Duration) -> Instant {
+    loop {
+        if let Some(result) = instant.checked_add(duration) {
+            return result;
+        }
+        duration /= 2;
+    }
+}
+
+/// Guard for requests that are in flight.
+struct Permit {
+    limiter: Arc<Limiter>,
+    count: usize,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.limiter.release(self.count);
+    }
+}
+
+/// A token bucket that refills at a constant rate up to its capacity.
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, capacity: f64, now: Instant) -> Self {
+        TokenBucket {
+            rate,
+            capacity,
+            tokens: capacity,
+            refilled: now,
+        }
+    }
+
+    /// Takes tokens from the bucket, or returns the duration to wait until
+    /// enough tokens are available.
+    ///
+    /// Requests for more tokens than the bucket's capacity are allowed once
+    /// the bucket is full, leaving it in debt so that the average rate is
+    /// still respected.
+    fn take(&mut self, tokens: f64, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.refilled = now;
+
+        let required = tokens.min(self.capacity);
+        if self.tokens < required {
+            // NOTE: Tiny rates can require waits that overflow a `Duration`.
+            return Err(
+                Duration::try_from_secs_f64((required - self.tokens) / self.rate)
+                    .unwrap_or(Duration::MAX),
+            );
+        }
+        self.tokens -= tokens;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::prelude::*;
+    use crate::transport::DynTransport;
+    use futures::task::noop_waker;
+
+    fn too_many_requests() -> Web3Error {
+        Web3Error::Transport(TransportError::Code(429))
+    }
+
+    /// Returns a configuration that does not back off after rate limit
+    /// errors, so that retries resolve immediately.
+    fn no_backoff() -> RateLimitConfig {
+        RateLimitConfig {
+            initial_backoff: Duration::from_millis(0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn token_bucket_limits_rate() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(10.0, 2.0, now);
+
+        assert_eq!(bucket.take(1.0, now), Ok(()));
+        assert_eq!(bucket.take(1.0, now), Ok(()));
+        assert_eq!(bucket.take(1.0, now), Err(Duration::from_millis(100)));
+
+        let later = now + Duration::from_millis(150);
+        assert_eq!(bucket.take(1.0, later), Ok(()));
+        assert!(bucket.take(1.0, later).is_err());
+    }
+
+    #[test]
+    fn token_bucket_allows_large_batches_when_full() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(10.0, 2.0, now);
+
+        assert_eq!(bucket.take(5.0, now), Ok(()));
+        // the bucket is in debt for 3 tokens, so it takes 0.4 seconds until
+        // the next request can be sent.
+        assert_eq!(bucket.take(1.0, now), Err(Duration::from_millis(400)));
+        assert_eq!(bucket.take(1.0, now + Duration::from_millis(400)), Ok(()));
+    }
+
+    #[test]
+    fn token_bucket_saturates_wait_for_tiny_rates() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(1e-300, 1.0, now);
+
+        assert_eq!(bucket.take(1.0, now), Ok(()));
+        assert_eq!(bucket.take(1.0, now), Err(Duration::MAX));
+    }
+
+    #[test]
+    fn rate_limit_pause_saturates_for_huge_backoffs() {
+        let limiter = Limiter::new(
+            RateLimitConfig {
+                initial_backoff: Duration::MAX,
+                max_backoff: Duration::MAX,
+                ..Default::default()
+            },
+            Instant::now(),
+        );
+
+        limiter.rate_limited();
+        let paused_until = limiter.state.lock().unwrap().paused_until.unwrap();
+        assert!(paused_until > Instant::now());
+    }
+
+    #[test]
+    fn invalid_rates_are_unlimited() {
+        for rate in [0.0, -1.0, f64::NAN] {
+            let limiter = Arc::new(Limiter::new(
+                RateLimitConfig::default().requests_per_second(rate),
+                Instant::now(),
+            ));
+            for _ in 0..10 {
+                limiter.acquire(1).now_or_never().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn limits_in_flight_requests() {
+        let limiter = Arc::new(Limiter::new(
+            RateLimitConfig::default().max_in_flight(3),
+            Instant::now(),
+        ));
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        let first = limiter.acquire(2).now_or_never().unwrap();
+        assert!(limiter
+            .poll_acquire(&mut cx, 2, Instant::now())
+            .is_pending());
+        let second = limiter.acquire(1).now_or_never().unwrap();
+
+        drop(first);
+        let third = limiter.acquire(2).now_or_never().unwrap();
+        assert_eq!(limiter.state.lock().unwrap().in_flight, 3);
+
+        drop(second);
+        drop(third);
+        // batches larger than the limit are allowed when nothing is in flight
+        assert!(limiter.acquire(5).now_or_never().is_some());
+    }
+
+    #[test]
+    fn rate_limit_errors_pause_requests() {
+        let limiter = Limiter::new(
+            RateLimitConfig {
+                initial_backoff: Duration::from_secs(1),
+                ..Default::default()
+            },
+            Instant::now(),
+        );
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        limiter.rate_limited();
+        assert!(matches!(
+            limiter.poll_acquire(&mut cx, 1, Instant::now()),
+            Poll::Ready(Err(_))
+        ));
+        assert!(matches!(
+            limiter.poll_acquire(&mut cx, 1, Instant::now() + Duration::from_secs(2)),
+            Poll::Ready(Ok(()))
+        ));
+    }
+
+    #[test]
+    fn backoff_is_exponential_and_capped() {
+        let config = RateLimitConfig {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+            ..Default::default()
+        };
+
+        assert_eq!(config.backoff(1), Duration::from_secs(1));
+        assert_eq!(config.backoff(2), Duration::from_secs(2));
+        assert_eq!(config.backoff(3), Duration::from_secs(4));
+        assert_eq!(config.backoff(4), Duration::from_secs(5));
+
+        let config = RateLimitConfig::default();
+        assert_eq!(config.backoff(1), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn retries_rate_limited_requests() {
+        let mut transport = TestTransport::new();
+        let limited = RateLimitTransport::new(transport.clone(), no_backoff());
+
+        transport.add_error(too_many_requests());
+        transport.add_error(Web3Error::Rpc(jsonrpc_core::Error {
+            code: jsonrpc_core::ErrorCode::ServerError(-32005),
+            message: "limit exceeded".to_owned(),
+            data: None,
+        }));
+        transport.add_response(json!("0x1"));
+        let result = limited
+            .execute("eth_sendRawTransaction", vec![json!("0x1337")])
+            .immediate()
+            .expect("request failed");
+
+        assert_eq!(result, json!("0x1"));
+        assert_eq!(limited.in_flight(), 0);
+        transport.assert_request("eth_sendRawTransaction", &[json!("0x1337")]);
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn does_not_retry_other_errors() {
+        let mut transport = TestTransport::new();
+        let limited = RateLimitTransport::new(transport.clone(), no_backoff());
+
+        transport.add_error(Web3Error::Transport(TransportError::Code(502)));
+        transport.add_response(json!("0x1"));
+        let result = limited.execute("eth_blockNumber", vec![]).immediate();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn retries_rate_limited_batch_requests() {
+        let mut transport = TestTransport::new();
+        let limited = RateLimitTransport::new(transport.clone(), no_backoff().max_in_flight(1));
+
+        let requests = vec![
+            limited.prepare("eth_blockNumber", vec![]),
+            limited.prepare("eth_chainId", vec![]),
+        ];
+
+        transport.add_error(too_many_requests());
+        transport.add_response(json!(["0x1", "0x2"]));
+        let results = limited
+            .send_batch(requests)
+            .immediate()
+            .expect("batch failed");
+
+        assert_eq!(
+            results.into_iter().map(Result::unwrap).collect::<Vec<_>>(),
+            vec![json!("0x1"), json!("0x2")]
+        );
+        assert_eq!(limited.in_flight(), 0);
+    }
+
+    #[test]
+    fn composes_with_dyn_transport() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(DynTransport::new(RateLimitTransport::new(
+            transport.clone(),
+            RateLimitConfig::default()
+                .requests_per_second(1000.0)
+                .max_in_flight(4),
+        )));
+
+        transport.add_response(json!("0x2a"));
+        let block = web3
+            .eth()
+            .block_number()
+            .immediate()
+            .expect("request failed");
+
+        assert_eq!(block, 42.into());
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_no_more_requests();
+    }
+}
@@ -0,0 +1,544 @@
+//! Implementation of a transport wrapper that adds per-request timeouts and
+//! retries transient errors with exponential backoff.
+
+use futures::future::{self, BoxFuture, Either};
+use futures::FutureExt as _;
+use futures_timer::Delay;
+use jsonrpc_core::Call;
+use serde_json::Value;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use web3::api::SubscriptionId;
+use web3::error::{Error as Web3Error, TransportError};
+use web3::{BatchTransport, DuplexTransport, RequestId, Transport};
+
+/// The default number of retries after the initial attempt.
+pub const DEFAULT_MAX_RETRIES: usize = 3;
+
+/// The default backoff before the first retry.
+pub const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+
+/// The default maximum backoff between retries.
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// The default timeout for a single request attempt.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Methods that are never retried by default since sending them more than once
+/// may have side effects.
+pub const NON_IDEMPOTENT_METHODS: &[&str] = &[
+    "eth_sendRawTransaction",
+    "eth_sendTransaction",
+    "personal_sendTransaction",
+    "eth_newFilter",
+    "eth_newBlockFilter",
+    "eth_newPendingTransactionFilter",
+    "eth_getFilterChanges",
+];
+
+/// Prefixes of transport error messages for failures to reach the node or to
+/// receive its response, which are retried. Other transport error messages,
+/// like invalid URLs or responses that can not be parsed, are not transient.
+const TRANSIENT_MESSAGES: &[&str] = &[
+    // HTTP transport
+    "failed to send request",
+    "failed to read response bytes",
+    // websocket transport
+    "Connection Error",
+    // IPC transport
+    "Send Error",
+    "Recv Error",
+    // request timeouts
+    "request timed out",
+];
+
+/// Parameters for retrying requests.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryConfig {
+    /// The maximum number of retries after the initial attempt.
+    pub max_retries: usize,
+    /// The backoff before the first retry. The backoff doubles with each
+    /// subsequent retry.
+    pub initial_backoff: Duration,
+    /// The maximum backoff between retries.
+    pub max_backoff: Duration,
+    /// The timeout for a single request attempt, or `None` for no timeout.
+    pub timeout: Option<Duration>,
+    /// Whether or not to also retry non-idempotent methods like
+    /// `eth_sendRawTransaction`.
+    pub retry_non_idempotent: bool,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_retries: DEFAULT_MAX_RETRIES,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            timeout: Some(DEFAULT_TIMEOUT),
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Returns the backoff before the specified retry, starting at 0.
+    fn backoff(&self, retry: usize) -> Duration {
+        let factor = 1u32.checked_shl(retry as _).unwrap_or(u32::MAX);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+
+    /// Returns true if the request can be retried.
+    fn can_retry(&self, request: &Call) -> bool {
+        self.retry_non_idempotent || is_idempotent(request)
+    }
+}
+
+/// Transport wrapper that adds per-request timeouts and retries requests that
+/// fail with transient errors using exponential backoff.
+///
+/// Non-idempotent methods (see [`NON_IDEMPOTENT_METHODS`]) are never retried
+/// unless explicitly enabled with [`RetryConfig::retry_non_idempotent`].
+#[derive(Clone, Debug)]
+pub struct RetryTransport<T> {
+    inner: T,
+    config: Arc<RetryConfig>,
+}
+
+impl<T> RetryTransport<T> {
+    /// Wraps a transport with the default retry configuration.
+    pub fn new(inner: T) -> Self {
+        RetryTransport::with_config(inner, RetryConfig::default())
+    }
+
+    /// Wraps a transport with the specified retry configuration.
+    pub fn with_config(inner: T, config: RetryConfig) -> Self {
+        RetryTransport {
+            inner,
+            config: Arc::new(config),
+        }
+    }
+
+    /// Returns a reference to the inner transport.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns the retry configuration.
+    pub fn config(&self) -> &RetryConfig {
+        &self.config
+    }
+}
+
+impl<T> Transport for RetryTransport<T>
+where
+    T: Transport + Clone + Send + Sync + 'static,
+    T::Out: Send,
+{
+    type Out = BoxFuture<'static, Result<Value, Web3Error>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        self.inner.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: Call) -> Self::Out {
+        let inner = self.inner.clone();
+        let config = self.config.clone();
+        async move {
+            let mut retry = 0;
+            loop {
+                let result = with_timeout(inner.send(id, request.clone()), config.timeout).await;
+                match result {
+                    Err(err)
+                        if retry < config.max_retries
+                            && config.can_retry(&request)
+                            && is_transient(&err) =>
+                    {
+                        sleep(config.backoff(retry)).await;
+                        retry += 1;
+                    }
+                    result => return result,
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+impl<T> BatchTransport for RetryTransport<T>
+where
+    T: BatchTransport + Clone + Send + Sync + 'static,
+    T::Out: Send,
+    T::Batch: Send,
+{
+    type Batch = BoxFuture<'static, Result<Vec<Result<Value, Web3Error>>, Web3Error>>;
+
+    fn send_batch<I>(&self, requests: I) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, Call)>,
+    {
+        let inner = self.inner.clone();
+        let config = self.config.clone();
+        let requests = requests.into_iter().collect::<Vec<_>>();
+        async move {
+            let mut results = (0..requests.len()).map(|_| None).collect::<Vec<_>>();
+            let mut pending = (0..requests.len()).collect::<Vec<_>>();
+            let mut retry = 0;
+            loop {
+                let can_retry =
+                    |i: usize| retry < config.max_retries && config.can_retry(&requests[i].1);
+
+                let batch = pending.iter().map(|&i| requests[i].clone());
+                match with_timeout(inner.send_batch(batch), config.timeout).await {
+                    Ok(responses) => {
+                        if responses.len() != pending.len() {
+                            return Err(Web3Error::InvalidResponse(format!(
+                                "expected {} batch responses but got {}",
+                                pending.len(),
+                                responses.len(),
+                            )));
+                        }
+
+                        // NOTE: Only retry the requests in the batch that failed
+                        //   with a transient error.
+                        let mut failed = Vec::new();
+                        for (i, response) in pending.iter().copied().zip(responses) {
+                            match response {
+                                Err(err) if can_retry(i) && is_transient(&err) => failed.push(i),
+                                response => results[i] = Some(response),
+                            }
+                        }
+                        pending = failed;
+                        if pending.is_empty() {
+                            break;
+                        }
+                    }
+                    Err(err) if pending.iter().all(|&i| can_retry(i)) && is_transient(&err) => {}
+                    Err(err) => return Err(err),
+                }
+
+                sleep(config.backoff(retry)).await;
+                retry += 1;
+            }
+
+            Ok(results
+                .into_iter()
+                .map(|result| result.expect("all batch requests resolved"))
+                .collect())
+        }
+        .boxed()
+    }
+}
+
+impl<T> DuplexTransport for RetryTransport<T>
+where
+    T: DuplexTransport + Clone + Send + Sync + 'static,
+    T::Out: Send,
+{
+    type NotificationStream = T::NotificationStream;
+
+    fn subscribe(&self, id: SubscriptionId) -> Result<Self::NotificationStream, Web3Error> {
+        self.inner.subscribe(id)
+    }
+
+    fn unsubscribe(&self, id: SubscriptionId) -> Result<(), Web3Error> {
+        self.inner.unsubscribe(id)
+    }
+}
+
+/// Returns true if the request does not have side effects when sent more than
+/// once.
+pub fn is_idempotent(request: &Call) -> bool {
+    match request {
+        Call::MethodCall(call) => !NON_IDEMPOTENT_METHODS.contains(&call.method.as_str()),
+        _ => true,
+    }
+}
+
+/// Returns true if the error is transient and the request may succeed when
+/// retried. This includes timeouts, connection errors, HTTP 408, 429 and 5xx
+/// status codes, and JSON RPC rate limit errors.
+pub fn is_transient(err: &Web3Error) -> bool {
+    match err {
+        Web3Error::Unreachable | Web3Error::Io(_) => true,
+        Web3Error::Transport(TransportError::Code(code)) => {
+            matches!(code, 408 | 429 | 500 | 502 | 503 | 504)
+        }
+        Web3Error::Transport(TransportError::Message(message)) => TRANSIENT_MESSAGES
+            .iter()
+            .any(|prefix| message.starts_with(prefix)),
+        Web3Error::Rpc(err) => matches!(err.code.code(), -32005 | 429),
+        _ => false,
+    }
+}
+
+/// Resolves to a transport error if the future does not complete within the
+/// specified timeout.
+async fn with_timeout<F, R>(future: F, timeout: Option<Duration>) -> Result<R, Web3Error>
+where
+    F: Future<Output = Result<R, Web3Error>>,
+{
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return future.await,
+    };
+
+    futures::pin_mut!(future);
+    match future::select(future, Delay::new(timeout)).await {
+        Either::Left((result, _)) => result,
+        Either::Right(_) => Err(Web3Error::Transport(TransportError::Message(format!(
+            "request timed out after {:?}",
+            timeout,
+        )))),
+    }
+}
+
+async fn sleep(duration: Duration) {
+    if !duration.is_zero() {
+        Delay::new(duration).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::prelude::*;
+    use crate::transport::DynTransport;
+    use jsonrpc_core::ErrorCode;
+    use web3::types::H256;
+
+    fn bad_gateway() -> Web3Error {
+        Web3Error::Transport(TransportError::Code(502))
+    }
+
+    /// Returns a retrying transport that does not back off, so that retries
+    /// resolve immediately.
+    fn retrying(transport: &TestTransport, config: RetryConfig) -> RetryTransport<TestTransport> {
+        RetryTransport::with_config(
+            transport.clone(),
+            RetryConfig {
+                initial_backoff: Duration::from_millis(0),
+                ..config
+            },
+        )
+    }
+
+    #[test]
+    fn retries_transient_errors() {
+        let mut transport = TestTransport::new();
+        let retry = retrying(&transport, Default::default());
+
+        transport.add_error(bad_gateway());
+        transport.add_error(Web3Error::Unreachable);
+        transport.add_response(json!("0x1"));
+        let result = retry
+            .execute("eth_blockNumber", vec![])
+            .immediate()
+            .expect("request failed");
+
+        assert_eq!(result, json!("0x1"));
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn gives_up_after_max_retries() {
+        let mut transport = TestTransport::new();
+        let retry = retrying(
+            &transport,
+            RetryConfig {
+                max_retries: 1,
+                ..Default::default()
+            },
+        );
+
+        transport.add_error(bad_gateway());
+        transport.add_error(bad_gateway());
+        transport.add_response(json!("0x1"));
+        let result = retry.execute("eth_blockNumber", vec![]).immediate();
+
+        assert!(matches!(
+            result,
+            Err(Web3Error::Transport(TransportError::Code(502)))
+        ));
+        // the last response was never consumed
+        assert_eq!(
+            transport
+                .execute("eth_chainId", vec![])
+                .immediate()
+                .unwrap(),
+            json!("0x1")
+        );
+    }
+
+    #[test]
+    fn does_not_retry_permanent_errors() {
+        let mut transport = TestTransport::new();
+        let retry = retrying(&transport, Default::default());
+
+        transport.add_error(Web3Error::Rpc(jsonrpc_core::Error::new(
+            ErrorCode::InvalidParams,
+        )));
+        let result = retry.execute("eth_call", vec![]).immediate();
+
+        assert!(matches!(result, Err(Web3Error::Rpc(_))));
+        transport.assert_request("eth_call", &[]);
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn does_not_retry_non_idempotent_methods() {
+        let mut transport = TestTransport::new();
+        let retry = retrying(&transport, Default::default());
+
+        transport.add_error(bad_gateway());
+        let result = retry
+            .execute("eth_sendRawTransaction", vec![json!("0x1337")])
+            .immediate();
+        assert!(result.is_err());
+
+        let retry = retrying(
+            &transport,
+            RetryConfig {
+                retry_non_idempotent: true,
+                ..Default::default()
+            },
+        );
+        transport.add_error(bad_gateway());
+        transport.add_response(json!(H256::zero()));
+        let result = retry
+            .execute("eth_sendRawTransaction", vec![json!("0x1337")])
+            .immediate();
+        assert_eq!(result.unwrap(), json!(H256::zero()));
+    }
+
+    #[test]
+    fn times_out_requests() {
+        #[derive(Clone, Debug)]
+        struct PendingTransport;
+
+        impl Transport for PendingTransport {
+            type Out = future::Pending<Result<Value, Web3Error>>;
+
+            fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+                (0, web3::helpers::build_request(0, method, params))
+            }
+
+            fn send(&self, _: RequestId, _: Call) -> Self::Out {
+                future::pending()
+            }
+        }
+
+        let retry = RetryTransport::with_config(
+            PendingTransport,
+            RetryConfig {
+                max_retries: 1,
+                initial_backoff: Duration::from_millis(0),
+                timeout: Some(Duration::from_millis(10)),
+                ..Default::default()
+            },
+        );
+        let result = retry.execute("eth_blockNumber", vec![]).wait();
+
+        assert!(matches!(
+            result,
+            Err(Web3Error::Transport(TransportError::Message(_)))
+        ));
+    }
+
+    #[test]
+    fn retries_failed_batch_requests() {
+        let mut transport = TestTransport::new();
+        let retry = retrying(&transport, Default::default());
+
+        let requests = vec![
+            retry.prepare("eth_blockNumber", vec![]),
+            retry.prepare("eth_chainId", vec![]),
+        ];
+
+        transport.add_error(bad_gateway());
+        transport.add_response(json!(["0x1", "0x2"]));
+        let results = retry
+            .send_batch(requests)
+            .immediate()
+            .expect("batch failed");
+
+        assert_eq!(
+            results.into_iter().map(Result::unwrap).collect::<Vec<_>>(),
+            vec![json!("0x1"), json!("0x2")]
+        );
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_request("eth_chainId", &[]);
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn does_not_retry_batches_with_non_idempotent_methods() {
+        let mut transport = TestTransport::new();
+        let retry = retrying(&transport, Default::default());
+
+        let requests = vec![
+            retry.prepare("eth_blockNumber", vec![]),
+            retry.prepare("eth_sendRawTransaction", vec![json!("0x1337")]),
+        ];
+
+        transport.add_error(bad_gateway());
+        transport.add_response(json!(["0x1", "0x2"]));
+        let result = retry.send_batch(requests).immediate();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn composes_with_dyn_transport() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(DynTransport::new(retrying(&transport, Default::default())));
+
+        transport.add_error(bad_gateway());
+        transport.add_response(json!("0x2a"));
+        let block = web3
+            .eth()
+            .block_number()
+            .immediate()
+            .expect("request failed");
+
+        assert_eq!(block, 42.into());
+        transport.assert_request("eth_blockNumber", &[]);
+        transport.assert_no_more_requests();
+    }
+
+    #[test]
+    fn detects_transient_transport_messages() {
+        let message = |message: &str| Web3Error::Transport(TransportError::Message(message.into()));
+
+        assert!(is_transient(&message(
+            "failed to send request: connection refused"
+        )));
+        assert!(is_transient(&message("request timed out after 1s")));
+        assert!(!is_transient(&message(
+            "failed to deserialize response: expected value"
+        )));
+        assert!(!is_transient(&message("failed to parse url: empty host")));
+    }
+
+    #[test]
+    fn backoff_is_exponential_and_capped() {
+        let config = RetryConfig {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+            ..Default::default()
+        };
+
+        assert_eq!(config.backoff(0), Duration::from_millis(100));
+        assert_eq!(config.backoff(1), Duration::from_millis(200));
+        assert_eq!(config.backoff(2), Duration::from_millis(400));
+        assert_eq!(config.backoff(3), Duration::from_millis(500));
+        assert_eq!(config.backoff(100), Duration::from_millis(500));
+
+        let config = RetryConfig::default();
+        assert_eq!(config.backoff(0), Duration::from_millis(250));
+    }
+}
@@ -0,0 +1,751 @@
+//! Module for verifying signatures produced by externally owned accounts as
+//! well as smart contract wallets.
+//!
+//! Signatures of externally owned accounts (EOAs) are verified by recovering
+//! the signer with `ecrecover`. Contract wallets are verified with ERC-1271
+//! `isValidSignature` calls, and ERC-6492 wrapped signatures of counterfactual
+//! (i.e. not yet deployed) wallets are verified by simulating the wallet
+//! deployment in a single `eth_call`.
+
+use crate::contract::{Instance, Signature};
+use crate::errors::ExecutionError;
+use crate::tokens::Bytes;
+use ethcontract_common::abi::{self, ParamType, Token};
+use ethcontract_common::contract::Interface;
+use ethcontract_common::errors::TypedDataError;
+use ethcontract_common::hash::{self, TypedData};
+use ethcontract_common::Abi;
+use lazy_static::lazy_static;
+use std::sync::Arc;
+use thiserror::Error;
+use web3::api::Web3;
+use web3::signing;
+use web3::types::{Address, BlockId, BlockNumber, CallRequest, H256, U256};
+use web3::Transport;
+
+/// The value returned by ERC-1271 `isValidSignature` for valid signatures.
+pub const ERC1271_MAGIC_VALUE: [u8; 4] = [0x16, 0x26, 0xba, 0x7e];
+
+/// The 32-byte suffix marking ERC-6492 wrapped signatures.
+pub const ERC6492_MAGIC_SUFFIX: [u8; 32] = [
+    0x64, 0x92, 0x64, 0x92, 0x64, 0x92, 0x64, 0x92, 0x64, 0x92, 0x64, 0x92, 0x64, 0x92, 0x64, 0x92,
+    0x64, 0x92, 0x64, 0x92, 0x64, 0x92, 0x64, 0x92, 0x64, 0x92, 0x64, 0x92, 0x64, 0x92, 0x64, 0x92,
+];
+
+/// Init code used for verifying ERC-6492 signatures of counterfactual wallets
+/// with a deployless `eth_call`. It is followed by 4 words: the signer, the
+/// factory, the length of the factory calldata and the length of the
+/// `isValidSignature` calldata, and then the two calldata buffers.
+///
+/// The constructor calls the factory if the signer has no code, then calls
+/// `isValidSignature` on the signer and returns the call's success flag,
+/// return data size and first return data word.
+const ERC6492_VALIDATOR: [u8; 82] = [
+    0x60, 0x80, 0x61, 0x00, 0x52, 0x60, 0x00, 0x39, 0x60, 0x00, 0x51, 0x3b, 0x61, 0x00, 0x2a, 0x57,
+    0x60, 0x40, 0x51, 0x61, 0x00, 0xd2, 0x60, 0x80, 0x39, 0x60, 0x00, 0x60, 0x00, 0x60, 0x40, 0x51,
+    0x60, 0x80, 0x60, 0x00, 0x60, 0x20, 0x51, 0x5a, 0xf1, 0x50, 0x5b, 0x60, 0x60, 0x51, 0x60, 0x40,
+    0x51, 0x61, 0x00, 0xd2, 0x01, 0x60, 0x80, 0x39, 0x60, 0x20, 0x60, 0x40, 0x60, 0x60, 0x51, 0x60,
+    0x80, 0x60, 0x00, 0x51, 0x5a, 0xfa, 0x60, 0x00, 0x52, 0x3d, 0x60, 0x20, 0x52, 0x60, 0x60, 0x60,
+    0x00, 0xf3,
+];
+
+lazy_static! {
+    static ref ERC1271: Arc<Interface> = {
+        let abi: Abi = serde_json::from_str(
+            r#"[{
+                "type": "function",
+                "name": "isValidSignature",
+                "inputs": [
+                    { "name": "hash", "type": "bytes32" },
+                    { "name": "signature", "type": "bytes" }
+                ],
+                "outputs": [{ "name": "magicValue", "type": "bytes4" }],
+                "stateMutability": "view"
+            }]"#,
+        )
+        .expect("valid ERC-1271 ABI");
+        Arc::new(abi.into())
+    };
+}
+
+/// Error that can occur when recovering the signer of a message.
+#[derive(Debug, Error)]
+pub enum RecoverError {
+    /// The signature is not a well formed 65-byte ECDSA signature.
+    #[error("invalid ECDSA signature")]
+    InvalidSignature,
+
+    /// An error occured while hashing EIP-712 typed data.
+    #[error("typed data error: {0}")]
+    TypedData(#[from] TypedDataError),
+}
+
+/// The result of verifying a signature.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Verification {
+    /// The expected signer of the message.
+    pub signer: Address,
+    /// The method used for verifying the signature.
+    pub method: VerificationMethod,
+    /// The outcome of the verification.
+    pub status: VerificationStatus,
+}
+
+impl Verification {
+    /// Returns `true` if the signature was successfully verified for the
+    /// expected signer.
+    pub fn is_valid(&self) -> bool {
+        self.status == VerificationStatus::Valid
+    }
+}
+
+/// The method used for verifying a signature.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum VerificationMethod {
+    /// The signer has no code and the signature was verified by recovering the
+    /// signing address with `ecrecover`.
+    Ecrecover,
+    /// The signer is a deployed contract and the signature was verified by
+    /// calling its ERC-1271 `isValidSignature` method.
+    Erc1271,
+    /// The signature was an ERC-6492 wrapped signature for a wallet that is
+    /// not deployed yet. It was verified by simulating the wallet deployment
+    /// with the specified factory and then calling `isValidSignature`.
+    Erc6492 {
+        /// The factory used for deploying the wallet.
+        factory: Address,
+        /// The calldata for the factory call deploying the wallet.
+        factory_calldata: Vec<u8>,
+    },
+}
+
+/// The outcome of a signature verification.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum VerificationStatus {
+    /// The signature is valid for the expected signer.
+    Valid,
+    /// The signature bytes are not a well formed ECDSA or ERC-6492 signature.
+    Malformed,
+    /// The ECDSA signature is valid, but was produced by a different account.
+    SignerMismatch(Address),
+    /// The contract wallet returned a value other than the ERC-1271 magic
+    /// value. This is `None` if the call did not return a `bytes4` value.
+    InvalidMagicValue(Option<[u8; 4]>),
+    /// The `isValidSignature` call reverted, with the revert reason if one
+    /// was provided.
+    Reverted(Option<String>),
+}
+
+/// Recovers the address that signed a 32-byte hash. The signature must be a
+/// 65-byte `r ‖ s ‖ v` signature, where `v` is either 0, 1, 27 or 28.
+pub fn recover(hash: H256, signature: &[u8]) -> Result<Address, RecoverError> {
+    if signature.len() != 65 {
+        return Err(RecoverError::InvalidSignature);
+    }
+    let recovery_id = match signature[64] {
+        v @ (0 | 1) => v,
+        v @ (27 | 28) => v - 27,
+        _ => return Err(RecoverError::InvalidSignature),
+    };
+
+    signing::recover(hash.as_bytes(), &signature[..64], recovery_id as _)
+        .map_err(|_| RecoverError::InvalidSignature)
+}
+
+/// Recovers the address that signed an EIP-191 personal message.
+pub fn recover_message<M>(message: M, signature: &[u8]) -> Result<Address, RecoverError>
+where
+    M: AsRef<[u8]>,
+{
+    recover(H256(hash::hash_message(message)), signature)
+}
+
+/// Recovers the address that signed EIP-712 typed data.
+pub fn recover_typed_data(data: &TypedData, signature: &[u8]) -> Result<Address, RecoverError> {
+    recover(H256(hash::hash_typed_data(data)?), signature)
+}
+
+/// Wraps a signature for a counterfactual wallet according to ERC-6492, so
+/// that it can be verified before the wallet is deployed.
+pub fn wrap_erc6492_signature(
+    factory: Address,
+    factory_calldata: Vec<u8>,
+    signature: Vec<u8>,
+) -> Vec<u8> {
+    let mut wrapped = abi::encode(&[
+        Token::Address(factory),
+        Token::Bytes(factory_calldata),
+        Token::Bytes(signature),
+    ]);
+    wrapped.extend_from_slice(&ERC6492_MAGIC_SUFFIX);
+    wrapped
+}
+
+/// Verifies that an EIP-191 personal message was signed by `signer`.
+pub async fn verify_message<T, M>(
+    web3: &Web3<T>,
+    signer: Address,
+    message: M,
+    signature: &[u8],
+) -> Result<Verification, ExecutionError>
+where
+    T: Transport,
+    M: AsRef<[u8]>,
+{
+    let hash = H256(hash::hash_message(message));
+    verify_hash(web3, signer, hash, signature, None).await
+}
+
+/// Verifies that EIP-712 typed data was signed by `signer`.
+pub async fn verify_typed_data<T: Transport>(
+    web3: &Web3<T>,
+    signer: Address,
+    data: &TypedData,
+    signature: &[u8],
+) -> Result<Verification, ExecutionError> {
+    let hash = H256(hash::hash_typed_data(data)?);
+    verify_hash(web3, signer, hash, signature, None).await
+}
+
+/// Verifies that a 32-byte hash was signed by `signer`, optionally at a
+/// specific block.
+///
+/// The verification method depends on the signature and the signer:
+/// - signatures for signers with code are verified with ERC-1271, unwrapping
+///   ERC-6492 signatures first;
+/// - ERC-6492 wrapped signatures for signers without code are verified by
+///   simulating the wallet deployment;
+/// - otherwise the signature is verified with `ecrecover`.
+///
+/// Errors are only returned for failing node requests, invalid signatures are
+/// reported with the returned [`VerificationStatus`].
+pub async fn verify_hash<T: Transport>(
+    web3: &Web3<T>,
+    signer: Address,
+    hash: H256,
+    signature: &[u8],
+    block: Option<BlockNumber>,
+) -> Result<Verification, ExecutionError> {
+    let verification = |method, status| Verification {
+        signer,
+        method,
+        status,
+    };
+
+    let wrapped = if signature.ends_with(&ERC6492_MAGIC_SUFFIX) {
+        match unwrap_erc6492_signature(signature) {
+            Some(wrapped) => Some(wrapped),
+            None => {
+                // NOTE: The signature has the ERC-6492 suffix but could not be
+                //   decoded, so there is no factory to report.
+                return Ok(verification(
+                    VerificationMethod::Erc6492 {
+                        factory: Address::zero(),
+                        factory_calldata: Vec::new(),
+                    },
+                    VerificationStatus::Malformed,
+                ));
+            }
+        }
+    } else {
+        None
+    };
+
+    let code = web3.eth().code(signer, block).await?;
+    if !code.0.is_empty() {
+        let signature = match &wrapped {
+            Some((_, _, signature)) => signature.as_slice(),
+            None => signature,
+        };
+        let status = erc1271(web3, signer, hash, signature, block).await?;
+        return Ok(verification(VerificationMethod::Erc1271, status));
+    }
+
+    if let Some((factory, factory_calldata, signature)) = wrapped {
+        let status = erc6492(
+            web3,
+            signer,
+            hash,
+            factory,
+            &factory_calldata,
+            &signature,
+            block,
+        )
+        .await?;
+        return Ok(verification(
+            VerificationMethod::Erc6492 {
+                factory,
+                factory_calldata,
+            },
+            status,
+        ));
+    }
+
+    let status = match recover(hash, signature) {
+        Ok(recovered) if recovered == signer => VerificationStatus::Valid,
+        Ok(recovered) => VerificationStatus::SignerMismatch(recovered),
+        Err(_) => VerificationStatus::Malformed,
+    };
+    Ok(verification(VerificationMethod::Ecrecover, status))
+}
+
+/// Splits an ERC-6492 wrapped signature into its factory, factory calldata
+/// and inner signature. Returns `None` if the signature is not correctly
+/// encoded.
+fn unwrap_erc6492_signature(signature: &[u8]) -> Option<(Address, Vec<u8>, Vec<u8>)> {
+    let encoded = signature.strip_suffix(&ERC6492_MAGIC_SUFFIX)?;
+    let tokens = abi::decode(
+        &[ParamType::Address, ParamType::Bytes, ParamType::Bytes],
+        encoded,
+    )
+    .ok()?;
+    match <[Token; 3]>::try_from(tokens).ok()? {
+        [Token::Address(factory), Token::Bytes(factory_calldata), Token::Bytes(signature)] => {
+            Some((factory, factory_calldata, signature))
+        }
+        _ => None,
+    }
+}
+
+/// Calls `isValidSignature` on a deployed contract wallet.
+async fn erc1271<T: Transport>(
+    web3: &Web3<T>,
+    signer: Address,
+    hash: H256,
+    signature: &[u8],
+    block: Option<BlockNumber>,
+) -> Result<VerificationStatus, ExecutionError> {
+    let instance = Instance::at(web3.clone(), ERC1271.clone(), signer);
+    let mut call = instance
+        .view_method::<_, Bytes<[u8; 4]>>(
+            Signature::new(hash::function_selector("isValidSignature(bytes32,bytes)")),
+            (Bytes(hash.0), Bytes(signature.to_vec())),
+        )
+        .expect("isValidSignature is part of the ERC-1271 ABI");
+    if let Some(block) = block {
+        call = call.block(BlockId::Number(block));
+    }
+
+    match call.call().await {
+        Ok(Bytes(value)) => Ok(magic_value_status(value)),
+        Err(err) => match err.inner {
+            ExecutionError::Revert(reason) => Ok(VerificationStatus::Reverted(reason)),
+            ExecutionError::InvalidOpcode => Ok(VerificationStatus::Reverted(None)),
+            ExecutionError::AbiDecode(_) | ExecutionError::Tokenization(_) => {
+                Ok(VerificationStatus::InvalidMagicValue(None))
+            }
+            err => Err(err),
+        },
+    }
+}
+
+/// Verifies a signature for a counterfactual wallet by executing the
+/// validator init code in a deployless `eth_call`.
+async fn erc6492<T: Transport>(
+    web3: &Web3<T>,
+    signer: Address,
+    hash: H256,
+    factory: Address,
+    factory_calldata: &[u8],
+    signature: &[u8],
+    block: Option<BlockNumber>,
+) -> Result<VerificationStatus, ExecutionError> {
+    let mut is_valid_signature =
+        hash::function_selector("isValidSignature(bytes32,bytes)").to_vec();
+    is_valid_signature.extend(abi::encode(&[
+        Token::FixedBytes(hash.0.to_vec()),
+        Token::Bytes(signature.to_vec()),
+    ]));
+
+    let mut data = ERC6492_VALIDATOR.to_vec();
+    data.extend(abi::encode(&[
+        Token::Address(signer),
+        Token::Address(factory),
+        Token::Uint(factory_calldata.len().into()),
+        Token::Uint(is_valid_signature.len().into()),
+    ]));
+    data.extend_from_slice(factory_calldata);
+    data.extend(is_valid_signature);
+
+    let call = CallRequest {
+        data: Some(data.into()),
+        ..Default::default()
+    };
+    let result = web3.eth().call(call, block.map(BlockId::Number)).await?;
+
+    // NOTE: Return data that can not be decoded is reported as an invalid
+    //   magic value, the same as for deployed ERC-1271 wallets.
+    let tokens = abi::decode(
+        &[
+            ParamType::Bool,
+            ParamType::Uint(256),
+            ParamType::FixedBytes(32),
+        ],
+        &result.0,
+    )
+    .unwrap_or_default();
+    let status = match tokens.as_slice() {
+        [Token::Bool(false), ..] => VerificationStatus::Reverted(None),
+        [Token::Bool(true), Token::Uint(size), Token::FixedBytes(word)]
+            if *size >= U256::from(32) =>
+        {
+            let mut value = [0; 4];
+            value.copy_from_slice(&word[..4]);
+            magic_value_status(value)
+        }
+        _ => VerificationStatus::InvalidMagicValue(None),
+    };
+    Ok(status)
+}
+
+/// Converts an `isValidSignature` return value to a verification status.
+fn magic_value_status(value: [u8; 4]) -> VerificationStatus {
+    if value == ERC1271_MAGIC_VALUE {
+        VerificationStatus::Valid
+    } else {
+        VerificationStatus::InvalidMagicValue(Some(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::fixtures::cow;
+    use crate::test::prelude::*;
+    use crate::transaction::Account;
+
+    fn sign(message: &str) -> Vec<u8> {
+        let transport = TestTransport::new();
+        let web3 = Web3::new(transport);
+        Account::Offline(cow(), None)
+            .sign_message(&web3, message)
+            .immediate()
+            .expect("failed to sign message")
+            .0
+            .to_vec()
+    }
+
+    fn word(value: impl Into<U256>) -> Vec<u8> {
+        abi::encode(&[Token::Uint(value.into())])
+    }
+
+    #[test]
+    fn recover_accepts_raw_and_electrum_recovery_ids() {
+        let mut signature = sign("hello");
+        let signer = cow().public_address();
+
+        assert_eq!(recover_message("hello", &signature).unwrap(), signer);
+        signature[64] -= 27;
+        assert_eq!(recover_message("hello", &signature).unwrap(), signer);
+
+        signature[64] = 42;
+        assert!(matches!(
+            recover_message("hello", &signature),
+            Err(RecoverError::InvalidSignature)
+        ));
+        assert!(matches!(
+            recover_message("hello", &signature[..64]),
+            Err(RecoverError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn erc6492_signature_roundtrip() {
+        let factory = addr!("0xfac7000000000000000000000000000000000001");
+        let wrapped = wrap_erc6492_signature(factory, vec![1, 2, 3], vec![4, 5]);
+
+        assert!(wrapped.ends_with(&ERC6492_MAGIC_SUFFIX));
+        assert_eq!(
+            unwrap_erc6492_signature(&wrapped),
+            Some((factory, vec![1, 2, 3], vec![4, 5]))
+        );
+        assert_eq!(unwrap_erc6492_signature(&ERC6492_MAGIC_SUFFIX), None);
+    }
+
+    #[test]
+    fn verify_eoa_signature() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let signer = cow().public_address();
+        let signature = sign("hello");
+        transport.add_response(json!("0x"));
+
+        let verification = verify_message(&web3, signer, "hello", &signature)
+            .immediate()
+            .expect("verification failed");
+
+        transport.assert_request("eth_getCode", &[json!(signer), json!("latest")]);
+        transport.assert_no_more_requests();
+        assert_eq!(verification.method, VerificationMethod::Ecrecover);
+        assert!(verification.is_valid());
+    }
+
+    #[test]
+    fn verify_eoa_signer_mismatch() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let signer = addr!("0x9876543210987654321098765432109876543210");
+        let signature = sign("hello");
+        transport.add_response(json!("0x"));
+
+        let verification = verify_message(&web3, signer, "hello", &signature)
+            .immediate()
+            .expect("verification failed");
+
+        assert_eq!(
+            verification.status,
+            VerificationStatus::SignerMismatch(cow().public_address())
+        );
+        assert!(!verification.is_valid());
+    }
+
+    #[test]
+    fn verify_malformed_eoa_signature() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let signer = addr!("0x9876543210987654321098765432109876543210");
+        transport.add_response(json!("0x"));
+
+        let verification = verify_message(&web3, signer, "hello", &[0x42; 12])
+            .immediate()
+            .expect("verification failed");
+
+        assert_eq!(verification.status, VerificationStatus::Malformed);
+    }
+
+    #[test]
+    fn verify_erc1271_signature() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let signer = addr!("0x9876543210987654321098765432109876543210");
+        let hash = hash!("0x1111111111111111111111111111111111111111111111111111111111111111");
+        transport.add_response(json!("0x60806040"));
+        transport.add_response(json!(
+            "0x1626ba7e00000000000000000000000000000000000000000000000000000000"
+        ));
+
+        let verification = verify_hash(
+            &web3,
+            signer,
+            hash,
+            &[0x13, 0x37],
+            Some(BlockNumber::Number(42.into())),
+        )
+        .immediate()
+        .expect("verification failed");
+
+        let mut data = hash::function_selector("isValidSignature(bytes32,bytes)").to_vec();
+        data.extend(abi::encode(&[
+            Token::FixedBytes(hash.0.to_vec()),
+            Token::Bytes(vec![0x13, 0x37]),
+        ]));
+        transport.assert_request("eth_getCode", &[json!(signer), json!("0x2a")]);
+        transport.assert_request(
+            "eth_call",
+            &[
+                json!({
+                    "to": signer,
+                    "data": web3::types::Bytes(data),
+                }),
+                json!("0x2a"),
+            ],
+        );
+        transport.assert_no_more_requests();
+        assert_eq!(verification.method, VerificationMethod::Erc1271);
+        assert!(verification.is_valid());
+    }
+
+    #[test]
+    fn verify_erc1271_invalid_magic_value() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let signer = addr!("0x9876543210987654321098765432109876543210");
+        transport.add_response(json!("0x60806040"));
+        transport.add_response(json!(
+            "0xffffffff00000000000000000000000000000000000000000000000000000000"
+        ));
+
+        let verification = verify_hash(&web3, signer, H256::zero(), &[], None)
+            .immediate()
+            .expect("verification failed");
+
+        assert_eq!(
+            verification.status,
+            VerificationStatus::InvalidMagicValue(Some([0xff; 4]))
+        );
+    }
+
+    #[test]
+    fn verify_erc1271_empty_return_data() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let signer = addr!("0x9876543210987654321098765432109876543210");
+        transport.add_response(json!("0x60806040"));
+        transport.add_response(json!("0x"));
+
+        let verification = verify_hash(&web3, signer, H256::zero(), &[], None)
+            .immediate()
+            .expect("verification failed");
+
+        assert_eq!(
+            verification.status,
+            VerificationStatus::InvalidMagicValue(None)
+        );
+    }
+
+    #[test]
+    fn verify_erc1271_unwraps_erc6492_signature_for_deployed_wallet() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let signer = addr!("0x9876543210987654321098765432109876543210");
+        let factory = addr!("0xfac7000000000000000000000000000000000001");
+        let signature = wrap_erc6492_signature(factory, vec![1, 2, 3], vec![0x13, 0x37]);
+        transport.add_response(json!("0x60806040"));
+        transport.add_response(json!(
+            "0x1626ba7e00000000000000000000000000000000000000000000000000000000"
+        ));
+
+        let verification = verify_hash(&web3, signer, H256::zero(), &signature, None)
+            .immediate()
+            .expect("verification failed");
+
+        let mut data = hash::function_selector("isValidSignature(bytes32,bytes)").to_vec();
+        data.extend(abi::encode(&[
+            Token::FixedBytes(vec![0; 32]),
+            Token::Bytes(vec![0x13, 0x37]),
+        ]));
+        transport.assert_request("eth_getCode", &[json!(signer), json!("latest")]);
+        transport.assert_request(
+            "eth_call",
+            &[
+                json!({
+                    "to": signer,
+                    "data": web3::types::Bytes(data),
+                }),
+                json!("latest"),
+            ],
+        );
+        transport.assert_no_more_requests();
+        assert_eq!(verification.method, VerificationMethod::Erc1271);
+        assert!(verification.is_valid());
+    }
+
+    #[test]
+    fn verify_erc6492_counterfactual_wallet() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let signer = addr!("0x9876543210987654321098765432109876543210");
+        let factory = addr!("0xfac7000000000000000000000000000000000001");
+        let hash = hash!("0x1111111111111111111111111111111111111111111111111111111111111111");
+        let signature = wrap_erc6492_signature(factory, vec![1, 2, 3], vec![0x13, 0x37]);
+        transport.add_response(json!("0x"));
+        transport.add_response(json!(web3::types::Bytes(
+            [
+                word(1),
+                word(32),
+                hex::decode("1626ba7e00000000000000000000000000000000000000000000000000000000")
+                    .unwrap(),
+            ]
+            .concat()
+        )));
+
+        let verification = verify_hash(&web3, signer, hash, &signature, None)
+            .immediate()
+            .expect("verification failed");
+
+        let mut is_valid_signature =
+            hash::function_selector("isValidSignature(bytes32,bytes)").to_vec();
+        is_valid_signature.extend(abi::encode(&[
+            Token::FixedBytes(hash.0.to_vec()),
+            Token::Bytes(vec![0x13, 0x37]),
+        ]));
+        let data = [
+            ERC6492_VALIDATOR.to_vec(),
+            abi::encode(&[Token::Address(signer), Token::Address(factory)]),
+            word(3),
+            word(is_valid_signature.len()),
+            vec![1, 2, 3],
+            is_valid_signature,
+        ]
+        .concat();
+        transport.assert_request("eth_getCode", &[json!(signer), json!("latest")]);
+        transport.assert_request(
+            "eth_call",
+            &[json!({ "data": web3::types::Bytes(data) }), json!("latest")],
+        );
+        transport.assert_no_more_requests();
+        assert_eq!(
+            verification.method,
+            VerificationMethod::Erc6492 {
+                factory,
+                factory_calldata: vec![1, 2, 3],
+            }
+        );
+        assert!(verification.is_valid());
+    }
+
+    #[test]
+    fn verify_erc6492_failed_validation_call() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let signer = addr!("0x9876543210987654321098765432109876543210");
+        let factory = addr!("0xfac7000000000000000000000000000000000001");
+        let signature = wrap_erc6492_signature(factory, vec![], vec![]);
+        transport.add_response(json!("0x"));
+        transport.add_response(json!(web3::types::Bytes(
+            [word(0), word(0), word(0)].concat()
+        )));
+
+        let verification = verify_hash(&web3, signer, H256::zero(), &signature, None)
+            .immediate()
+            .expect("verification failed");
+
+        assert_eq!(verification.status, VerificationStatus::Reverted(None));
+    }
+
+    #[test]
+    fn verify_erc6492_empty_return_data() {
+        let mut transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let signer = addr!("0x9876543210987654321098765432109876543210");
+        let factory = addr!("0xfac7000000000000000000000000000000000001");
+        let signature = wrap_erc6492_signature(factory, vec![], vec![]);
+        transport.add_response(json!("0x"));
+        transport.add_response(json!("0x"));
+
+        let verification = verify_hash(&web3, signer, H256::zero(), &signature, None)
+            .immediate()
+            .expect("verification failed");
+
+        assert_eq!(
+            verification.status,
+            VerificationStatus::InvalidMagicValue(None)
+        );
+    }
+
+    #[test]
+    fn verify_malformed_erc6492_signature() {
+        let transport = TestTransport::new();
+        let web3 = Web3::new(transport.clone());
+
+        let signer = addr!("0x9876543210987654321098765432109876543210");
+        let signature = [vec![0x42; 7], ERC6492_MAGIC_SUFFIX.to_vec()].concat();
+
+        let verification = verify_hash(&web3, signer, H256::zero(), &signature, None)
+            .immediate()
+            .expect("verification failed");
+
+        transport.assert_no_more_requests();
+        assert_eq!(verification.status, VerificationStatus::Malformed);
+    }
+}